@@ -31,7 +31,11 @@ async fn main() {
             let arbitrage = &arbitrage;
             return async move {
                 let tx_hash = tx_hash.clone();
-                if let Ok(Some((tx_queue, profit))) = simulate.run(tx_hash, false).await {
+                if let Ok(Some(Opportunity {
+                    tx_queue, reports, ..
+                })) = simulate.run(tx_hash, false).await
+                {
+                    let profit = ProfitReport::total_native(&reports);
                     log_profit(flashbot, arbitrage.address(), tx_hash, profit, || async {
                         for tx_list in tx_queue {
                             // Without priority fee, all simulations will fail
@@ -20,7 +20,9 @@ pub async fn log_profit<M: Middleware, S: Signer, Fut: Future<Output = ()>, F: F
     let balance_after = client.get_balance(address, None).await.unwrap();
 
     let profit = format_units(profit, "eth").unwrap();
-    let balance_diff = format_units(balance_after - balance_before, "eth").unwrap();
+    // Negative when the gas cost more than the profit.
+    let balance_diff = I256::from_raw(balance_after) - I256::from_raw(balance_before);
+    let balance_diff = format_units(balance_diff, "eth").unwrap();
     let balance_before = format_units(balance_before, "eth").unwrap();
     let balance_after = format_units(balance_after, "eth").unwrap();
 
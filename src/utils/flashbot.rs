@@ -1,3 +1,4 @@
+use crate::utils::{SimulateError, TxQueue};
 use ethers::core::rand::thread_rng;
 use ethers::prelude::*;
 use ethers::types::transaction::eip2718::TypedTransaction;
@@ -10,6 +11,9 @@ type Singer = SignerMiddleware<FlashbotsMiddleware<Provider<Http>, LocalWallet>,
 
 pub struct FlashBotUtil {
     pub inner: Singer,
+    // Check the simulation of a queue before sending it, see `skip_gate`.
+    gate: bool,
+    refuse_unprofitable: bool,
 }
 
 impl Deref for FlashBotUtil {
@@ -35,7 +39,11 @@ impl FlashBotUtil {
                 ),
                 wallet,
             );
-            return Some(Self { inner: flashbot });
+            return Some(Self {
+                inner: flashbot,
+                gate: true,
+                refuse_unprofitable: true,
+            });
         }
 
         None
@@ -50,6 +58,38 @@ impl FlashBotUtil {
         Ok(self.inner().send_bundle(&bundle).await?.await?)
     }
 
+    // Send `run_queue` bundles whatever their simulation shows.
+    pub fn skip_gate(mut self) -> Self {
+        self.gate = false;
+        self
+    }
+
+    // Whether `run_queue` refuses queues that net no profit, on by default.
+    pub fn refuse_unprofitable(mut self, refuse_unprofitable: bool) -> Self {
+        self.refuse_unprofitable = refuse_unprofitable;
+        self
+    }
+
+    // Send the queue as a bundle for the next block, refused once the queue expired so the target
+    // block always lies within its validity, with `refuse_unprofitable` when its economics net no
+    // profit, or when the simulation doesn't pass `gate_simulated`. A signer on a chain ethers
+    // doesn't know is refused too, its tx types can't be told.
+    pub async fn run_queue(&self, tx_queue: &TxQueue) -> Result<TxHash, Box<dyn Error>> {
+        if self.refuse_unprofitable {
+            tx_queue.ensure_profitable()?;
+        }
+        let current_block = self.get_block_number().await?;
+        tx_queue.ensure_valid(current_block)?;
+        let chain_id = self.signer().chain_id();
+        let chain = Chain::try_from(chain_id).map_err(|_| SimulateError::UnknownChain(chain_id))?;
+        let bundle = self.to_bundle(tx_queue.to_typed(chain)).await?;
+        let simulated = self.inner().simulate_bundle(&bundle).await?;
+        if self.gate {
+            gate_simulated(tx_queue, &simulated)?;
+        }
+        Ok(self.inner().send_bundle(&bundle).await?.await?)
+    }
+
     async fn to_bundle<T: Into<TypedTransaction>>(
         &self,
         tx_list: Vec<T>,
@@ -70,3 +110,26 @@ impl FlashBotUtil {
         Ok(bundle)
     }
 }
+
+// The gate of `BundleSubmitter::submit_all` on the simulation of a bundle without reverting txs:
+// none of them may revert, and the coinbase must gain at least the queue's bribe.
+fn gate_simulated(tx_queue: &TxQueue, simulated: &SimulatedBundle) -> Result<(), SimulateError> {
+    let refuse = |reason: String| SimulateError::GateRefused {
+        block: simulated.simulation_block,
+        reason,
+    };
+    for (index, tx) in simulated.transactions.iter().enumerate() {
+        if let Some(error) = tx.revert.as_ref().or(tx.error.as_ref()) {
+            return Err(refuse(format!("entry {index} reverts: {error}")));
+        }
+    }
+    let bribe = tx_queue.economics.unwrap_or_default().bribe;
+    if simulated.coinbase_diff < bribe {
+        return Err(refuse(format!(
+            "coinbase gains {}, less than the bribe {bribe}",
+            simulated.coinbase_diff
+        )));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,500 @@
+use crate::utils::{Simulate, SimulateError, TxQueue};
+use ethers::{
+    abi::{self, ParamType},
+    prelude::*,
+    utils::{id, keccak256},
+};
+use serde_json::{json, Value};
+use url::Url;
+
+// A tx hinted on the MEV-Share event stream: only what its sender chose to share, the calldata
+// and logs may be missing.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MevShareHint {
+    pub hash: TxHash,
+    pub logs: Vec<Log>,
+    pub txs: Vec<HintTx>,
+    pub mev_gas_price: Option<U256>,
+    pub gas_used: Option<U256>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HintTx {
+    pub to: Option<Address>,
+    pub function_selector: Option<[u8; 4]>,
+    pub calldata: Option<Bytes>,
+}
+
+impl MevShareHint {
+    // The `data` of an event as the stream sends it.
+    pub fn from_json(event: &Value) -> Result<Self, SimulateError> {
+        let field =
+            |value: &Value, name: &str| value.get(name).filter(|value| !value.is_null()).cloned();
+
+        let hash = field(event, "hash").ok_or(SimulateError::MevShareHintWithoutHash)?;
+        let txs = match field(event, "txs") {
+            Some(Value::Array(txs)) => txs
+                .iter()
+                .map(|tx| {
+                    let function_selector = field(tx, "functionSelector")
+                        .map(serde_json::from_value::<Bytes>)
+                        .transpose()?;
+                    Ok(HintTx {
+                        to: field(tx, "to").map(serde_json::from_value).transpose()?,
+                        function_selector: function_selector
+                            .and_then(|selector| selector.to_vec().try_into().ok()),
+                        calldata: field(tx, "callData")
+                            .map(serde_json::from_value)
+                            .transpose()?,
+                    })
+                })
+                .collect::<Result<_, serde_json::Error>>()
+                .map_err(SimulateError::middleware)?,
+            _ => vec![],
+        };
+
+        Ok(Self {
+            hash: serde_json::from_value(hash).map_err(SimulateError::middleware)?,
+            logs: field(event, "logs")
+                .map(serde_json::from_value)
+                .transpose()
+                .map_err(SimulateError::middleware)?
+                .unwrap_or_default(),
+            txs,
+            mev_gas_price: field(event, "mevGasPrice")
+                .map(serde_json::from_value)
+                .transpose()
+                .map_err(SimulateError::middleware)?,
+            gas_used: field(event, "gasUsed")
+                .map(serde_json::from_value)
+                .transpose()
+                .map_err(SimulateError::middleware)?,
+        })
+    }
+
+    // The reserves the hinted tx leaves uniswap v2 style pairs with, from their `Sync` logs. The
+    // last log of a pair wins.
+    pub fn synced_reserves(&self) -> Vec<(Address, U256, U256)> {
+        let sync = H256(keccak256("Sync(uint112,uint112)"));
+        let mut reserves: Vec<(Address, U256, U256)> = Vec::new();
+        for log in &self.logs {
+            if log.topics.first() != Some(&sync) {
+                continue;
+            }
+            let decoded =
+                match abi::decode(&[ParamType::Uint(112), ParamType::Uint(112)], &log.data) {
+                    Ok(decoded) => decoded,
+                    Err(_) => continue,
+                };
+            let (reserve0, reserve1) = (
+                decoded[0].clone().into_uint().unwrap_or_default(),
+                decoded[1].clone().into_uint().unwrap_or_default(),
+            );
+            reserves.retain(|(pair, ..)| *pair != log.address);
+            reserves.push((log.address, reserve0, reserve1));
+        }
+        reserves
+    }
+}
+
+// One `data:` event of a server-sent event stream, `None` for keep-alives and comments.
+fn parse_event(event: &str) -> Option<Result<MevShareHint, SimulateError>> {
+    let data = event
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(str::trim_start)
+        .collect::<Vec<_>>()
+        .join("\n");
+    if data.is_empty() {
+        return None;
+    }
+    Some(
+        serde_json::from_str(&data)
+            .map_err(SimulateError::MevShareEventDecode)
+            .and_then(|event| MevShareHint::from_json(&event)),
+    )
+}
+
+// The bytes of an event stream, cut into events on complete lines only: a chunk may end within
+// a line, even within a character of it.
+#[derive(Debug, Default)]
+struct EventBuffer {
+    bytes: Vec<u8>,
+    // Of the event being read.
+    lines: Vec<String>,
+}
+
+impl EventBuffer {
+    fn push(&mut self, chunk: &[u8]) {
+        self.bytes.extend_from_slice(chunk);
+    }
+
+    // The lines of the next event ended by an empty line, `None` until one is complete.
+    fn next_event(&mut self) -> Option<String> {
+        while let Some(end) = self.bytes.iter().position(|byte| *byte == b'\n') {
+            let bytes = self.bytes.drain(..=end).collect::<Vec<_>>();
+            let text = String::from_utf8_lossy(&bytes[..end]);
+            let line = text.strip_suffix('\r').unwrap_or(&text);
+            match line.is_empty() {
+                true if !self.lines.is_empty() => {
+                    return Some(self.lines.drain(..).collect::<Vec<_>>().join("\n"))
+                }
+                true => {}
+                false => self.lines.push(line.to_string()),
+            }
+        }
+        None
+    }
+}
+
+// The MEV-Share event stream (e.g. `https://mev-share.flashbots.net`), read hint by hint.
+pub struct MevShareEvents {
+    response: reqwest::Response,
+    buffer: EventBuffer,
+}
+
+impl MevShareEvents {
+    pub async fn connect(url: Url) -> Result<Self, SimulateError> {
+        let response = reqwest::Client::new()
+            .get(url)
+            .header("Accept", "text/event-stream")
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(SimulateError::MevShareStream)?;
+        Ok(Self {
+            response,
+            buffer: EventBuffer::default(),
+        })
+    }
+
+    // The next hint, `None` once the server closed the stream.
+    pub async fn next_hint(&mut self) -> Result<Option<MevShareHint>, SimulateError> {
+        loop {
+            while let Some(event) = self.buffer.next_event() {
+                if let Some(hint) = parse_event(&event) {
+                    return hint.map(Some);
+                }
+            }
+
+            match self
+                .response
+                .chunk()
+                .await
+                .map_err(SimulateError::MevShareStream)?
+            {
+                Some(chunk) => self.buffer.push(&chunk),
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+// A v0.1 `mev_sendBundle` backrunning a hinted tx, see `BundleSubmitter::submit_mev_share`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MevShareBundle {
+    pub backrun: TxHash,
+    pub block: U64,
+    // The last block the bundle may land in, only `block` if unset.
+    pub max_block: Option<U64>,
+    // Of the profit, what goes back to the hinted tx's sender.
+    pub refund_percent: Option<u64>,
+    // What of our txs the matchmaker may share on, e.g. "calldata", "logs", "hash".
+    pub hints: Vec<String>,
+    // Builders the bundle may be sent to, only the matchmaker's default if empty.
+    pub builders: Vec<String>,
+    // Our txs (by index) sent with `canRevert`, see `QueueEntry::revertible`.
+    pub reverting: Vec<usize>,
+}
+
+impl MevShareBundle {
+    pub fn new(backrun: TxHash, block: U64) -> Self {
+        Self {
+            backrun,
+            block,
+            ..Default::default()
+        }
+    }
+
+    pub fn max_block(mut self, max_block: U64) -> Self {
+        self.max_block = Some(max_block);
+        self
+    }
+
+    pub fn refund_percent(mut self, refund_percent: u64) -> Self {
+        self.refund_percent = Some(refund_percent);
+        self
+    }
+
+    pub fn hint(mut self, hint: &str) -> Self {
+        self.hints.push(hint.into());
+        self
+    }
+
+    pub fn builder(mut self, builder: &str) -> Self {
+        self.builders.push(builder.into());
+        self
+    }
+
+    pub fn reverting(mut self, index: usize) -> Self {
+        self.reverting.push(index);
+        self
+    }
+
+    // `reverting` for every revertible entry of `tx_queue`.
+    pub fn reverting_entries(mut self, tx_queue: &TxQueue) -> Self {
+        for index in tx_queue.revertible() {
+            if !self.reverting.contains(&index) {
+                self.reverting.push(index);
+            }
+        }
+        self
+    }
+
+    // The `mev_sendBundle` params with the signed backrun txs after the hinted one, none of them
+    // may revert but the `reverting` ones: without the hinted tx before them they'd only lose the
+    // gas.
+    pub fn to_params(&self, raw_tx_list: &[Bytes]) -> Value {
+        let mut inclusion = json!({ "block": self.block });
+        if let Some(max_block) = self.max_block {
+            inclusion["maxBlock"] = json!(max_block);
+        }
+        let body = [json!({ "hash": self.backrun })]
+            .into_iter()
+            .chain(
+                raw_tx_list.iter().enumerate().map(|(index, raw_tx)| {
+                    json!({ "tx": raw_tx, "canRevert": self.reverting.contains(&index) })
+                }),
+            )
+            .collect::<Vec<_>>();
+        let mut bundle = json!({
+            "version": "v0.1",
+            "inclusion": inclusion,
+            "body": body,
+        });
+        if let Some(percent) = self.refund_percent {
+            bundle["validity"] = json!({ "refund": [{ "bodyIdx": 0, "percent": percent }] });
+        }
+        if !self.hints.is_empty() || !self.builders.is_empty() {
+            let mut privacy = json!({});
+            if !self.hints.is_empty() {
+                privacy["hints"] = json!(self.hints);
+            }
+            if !self.builders.is_empty() {
+                privacy["builders"] = json!(self.builders);
+            }
+            bundle["privacy"] = privacy;
+        }
+        json!([bundle])
+    }
+}
+
+// A pair a hinted tx moves: its reserves now and after the tx.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolMove {
+    pub pair: Address,
+    pub reserves_before: (U256, U256),
+    pub reserves_after: (U256, U256),
+}
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    // The reduced simulation of a hint without calldata: the tx can't be traced, but the `Sync`
+    // logs it shares tell how it moves the pairs, against their reserves on the latest block.
+    pub async fn hint_pool_moves(
+        &self,
+        hint: &MevShareHint,
+    ) -> Result<Vec<PoolMove>, SimulateError> {
+        let mut moves = Vec::new();
+        for (pair, reserve0, reserve1) in hint.synced_reserves() {
+            let tx = TransactionRequest::new()
+                .to(pair)
+                .data(id("getReserves()").to_vec());
+            let output = self
+                .call(&tx.into(), None)
+                .await
+                .map_err(SimulateError::middleware)?;
+            let reserves = abi::decode(
+                &[
+                    ParamType::Uint(112),
+                    ParamType::Uint(112),
+                    ParamType::Uint(32),
+                ],
+                &output,
+            )
+            .map_err(SimulateError::middleware)?;
+            let reserves_before = (
+                reserves[0].clone().into_uint().unwrap_or_default(),
+                reserves[1].clone().into_uint().unwrap_or_default(),
+            );
+            if reserves_before != (reserve0, reserve1) {
+                moves.push(PoolMove {
+                    pair,
+                    reserves_before,
+                    reserves_after: (reserve0, reserve1),
+                });
+            }
+        }
+        Ok(moves)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_event, EventBuffer, HintTx, MevShareBundle, MevShareHint, PoolMove};
+    use crate::utils::Simulate;
+    use ethers::{
+        abi::{self, Token},
+        prelude::*,
+        utils::keccak256,
+    };
+    use serde_json::json;
+
+    fn sync_log(pair: Address, reserve0: u64, reserve1: u64) -> Log {
+        Log {
+            address: pair,
+            topics: vec![H256(keccak256("Sync(uint112,uint112)"))],
+            data: abi::encode(&[Token::Uint(reserve0.into()), Token::Uint(reserve1.into())]).into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parse_event_of_partial_hint() {
+        let (hash, pair, router) = (H256::random(), Address::random(), Address::random());
+        let log = sync_log(pair, 100, 200);
+        // As the stream sends it: only what the sender shares, the calldata left out.
+        let event = format!(
+            "data: {}",
+            json!({
+                "hash": hash,
+                "logs": [{ "address": pair, "topics": log.topics, "data": log.data }],
+                "txs": [{ "to": router, "functionSelector": "0x38ed1739" }],
+                "mevGasPrice": "0x3b9aca00",
+                "gasUsed": "0x2dc6c0",
+            })
+        );
+
+        let hint = parse_event(&event).unwrap().unwrap();
+        assert_eq!(hint.hash, hash);
+        assert_eq!(
+            hint.txs,
+            vec![HintTx {
+                to: Some(router),
+                function_selector: Some([0x38, 0xed, 0x17, 0x39]),
+                calldata: None,
+            }]
+        );
+        assert_eq!(hint.mev_gas_price, Some(U256::from(1_000_000_000)));
+        assert_eq!(hint.gas_used, Some(U256::from(3_000_000)));
+        assert_eq!(
+            hint.synced_reserves(),
+            vec![(pair, U256::from(100), U256::from(200))]
+        );
+
+        // Only a hash is shared.
+        let event = json!({ "hash": hash, "logs": null, "txs": null });
+        let hint = parse_event(&format!("data: {event}")).unwrap().unwrap();
+        assert_eq!(
+            hint,
+            MevShareHint {
+                hash,
+                ..Default::default()
+            }
+        );
+        assert!(parse_event(": keep-alive").is_none());
+    }
+
+    #[test]
+    fn event_buffer_split_on_complete_lines() {
+        let mut buffer = EventBuffer::default();
+        // A chunk ends within a line, and within the 3 bytes of its last character.
+        let stream = ": ping \u{2713}\r\n\r\ndata: {\"hash\":\n\ndata: next\n\n".as_bytes();
+        let cut = stream.iter().position(|byte| *byte == 0xe2).unwrap() + 1;
+        buffer.push(&stream[..cut]);
+        assert_eq!(buffer.next_event(), None);
+        buffer.push(&stream[cut..cut + 12]);
+        assert_eq!(buffer.next_event().as_deref(), Some(": ping \u{2713}"));
+        assert_eq!(buffer.next_event(), None);
+        buffer.push(&stream[cut + 12..]);
+        assert_eq!(buffer.next_event().as_deref(), Some("data: {\"hash\":"));
+        assert_eq!(buffer.next_event().as_deref(), Some("data: next"));
+        assert_eq!(buffer.next_event(), None);
+    }
+
+    // The shape of `mev_sendBundle` in the MEV-Share spec, v0.1.
+    #[test]
+    fn mev_share_bundle_match_schema() {
+        let backrun = H256::random();
+        let raw_tx_list = vec![Bytes::from(vec![0x02, 0x01])];
+        let bundle = MevShareBundle::new(backrun, U64::from(100))
+            .max_block(U64::from(102))
+            .refund_percent(90)
+            .hint("calldata")
+            .hint("logs")
+            .builder("flashbots");
+
+        assert_eq!(
+            bundle.to_params(&raw_tx_list),
+            json!([{
+                "version": "v0.1",
+                "inclusion": { "block": "0x64", "maxBlock": "0x66" },
+                "body": [
+                    { "hash": backrun },
+                    { "tx": "0x0201", "canRevert": false },
+                ],
+                "validity": { "refund": [{ "bodyIdx": 0, "percent": 90 }] },
+                "privacy": { "hints": ["calldata", "logs"], "builders": ["flashbots"] },
+            }])
+        );
+
+        // The optional sections are left out.
+        let params = MevShareBundle::new(backrun, U64::from(100)).to_params(&raw_tx_list);
+        let mut fields = params[0]
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        fields.sort();
+        assert_eq!(fields, vec!["body", "inclusion", "version"]);
+        assert_eq!(params[0]["inclusion"], json!({ "block": "0x64" }));
+    }
+
+    #[tokio::test]
+    async fn hint_pool_moves_compare_with_latest_reserves() {
+        let (provider, mock) = Provider::mocked();
+        let client = SignerMiddleware::new(
+            provider,
+            LocalWallet::new(&mut ethers::core::rand::thread_rng()),
+        );
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let (moved, untouched) = (Address::random(), Address::random());
+        let hint = MevShareHint {
+            logs: vec![
+                sync_log(moved, 1, 1),
+                sync_log(moved, 900, 1100),
+                sync_log(untouched, 500, 500),
+            ],
+            ..Default::default()
+        };
+        let reserves = |reserve0: u64, reserve1: u64| {
+            Bytes::from(abi::encode(&[
+                Token::Uint(reserve0.into()),
+                Token::Uint(reserve1.into()),
+                Token::Uint(0.into()),
+            ]))
+        };
+
+        // The last log of a pair counts.
+        mock.push(reserves(500, 500)).unwrap();
+        mock.push(reserves(1000, 1000)).unwrap();
+        assert_eq!(
+            simulate.hint_pool_moves(&hint).await.unwrap(),
+            vec![PoolMove {
+                pair: moved,
+                reserves_before: (U256::from(1000), U256::from(1000)),
+                reserves_after: (U256::from(900), U256::from(1100)),
+            }]
+        );
+    }
+}
@@ -2,10 +2,26 @@ mod base;
 mod contract;
 mod flashbot;
 mod listen;
+mod mev_share;
+mod rate_limit;
+mod relay_adapter;
 mod simulate;
+mod stats;
+mod store;
+mod submission;
+mod submit;
+mod watch;
 
 pub use base::*;
 pub use contract::*;
 pub use flashbot::*;
 pub use listen::*;
+pub use mev_share::*;
+pub use rate_limit::*;
+pub use relay_adapter::*;
 pub use simulate::*;
+pub use stats::*;
+pub use store::*;
+pub use submission::*;
+pub use submit::*;
+pub use watch::*;
@@ -0,0 +1,305 @@
+use crate::utils::SimulateError;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::{sleep, Instant};
+use url::Url;
+
+// The pace a relay accepts requests from one reputation key at, past it the key is throttled or
+// banned for a while.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    // Positive, see `RateLimit::new`.
+    per_second: f64,
+    // Requests sent at once after a quiet period.
+    pub burst: u32,
+    // How long a request waits for its turn, it's dropped if the turn comes later.
+    pub max_wait: Duration,
+    // The pause after the relay answered HTTP 429 without a `Retry-After`.
+    pub cool_down: Duration,
+    // The longest pause a relay's `Retry-After` gets, the relay controls it.
+    pub max_cool_down: Duration,
+}
+
+impl RateLimit {
+    // An error unless `per_second` is positive, no token would ever come otherwise.
+    pub fn new(per_second: f64, burst: u32) -> Result<Self, SimulateError> {
+        if !(per_second > 0.0 && per_second.is_finite()) {
+            return Err(SimulateError::InvalidRateLimit { per_second });
+        }
+        Ok(Self {
+            per_second,
+            burst: burst.max(1),
+            max_wait: Duration::from_secs(2),
+            cool_down: Duration::from_secs(10),
+            max_cool_down: Duration::from_secs(60),
+        })
+    }
+
+    pub fn per_second(&self) -> f64 {
+        self.per_second
+    }
+
+    pub fn max_wait(mut self, max_wait: Duration) -> Self {
+        self.max_wait = max_wait;
+        self
+    }
+
+    pub fn cool_down(mut self, cool_down: Duration) -> Self {
+        self.cool_down = cool_down;
+        self
+    }
+
+    pub fn max_cool_down(mut self, max_cool_down: Duration) -> Self {
+        self.max_cool_down = max_cool_down;
+        self
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    // When `tokens` was counted, in the future while the relay is cooling down.
+    updated: Instant,
+}
+
+// A token bucket per relay, shared by every request going through it.
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    buckets: Mutex<HashMap<Url, Bucket>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Wait for a turn at `relay`. If it doesn't come within `max_wait` nothing is waited for,
+    // the error is how long it would have taken.
+    pub(crate) async fn acquire(&self, relay: &Url) -> Result<(), Duration> {
+        let deadline = Instant::now() + self.limit.max_wait;
+        loop {
+            let now = Instant::now();
+            let wait = match self.try_acquire(relay, now) {
+                Some(wait) => wait,
+                None => return Ok(()),
+            };
+            if now + wait > deadline {
+                return Err(wait);
+            }
+            sleep(wait).await;
+        }
+    }
+
+    // Nothing is sent to `relay` for `duration`, `cool_down` if the relay didn't say, never more
+    // than `max_cool_down`.
+    pub(crate) fn cool_down(&self, relay: &Url, duration: Option<Duration>) {
+        let now = Instant::now();
+        let duration = duration
+            .unwrap_or(self.limit.cool_down)
+            .min(self.limit.max_cool_down);
+        let updated = now.checked_add(duration).unwrap_or(now);
+        let mut buckets = self.buckets.lock().unwrap();
+        buckets.insert(
+            relay.clone(),
+            Bucket {
+                tokens: 0.0,
+                updated,
+            },
+        );
+    }
+
+    // Take a token, `None` if there was one, the wait for the next one otherwise.
+    fn try_acquire(&self, relay: &Url, now: Instant) -> Option<Duration> {
+        let burst = f64::from(self.limit.burst);
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(relay.clone()).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        if bucket.updated > now {
+            return Some(bucket.updated - now);
+        }
+
+        let elapsed = (now - bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.limit.per_second).min(burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return None;
+        }
+        Some(Duration::from_secs_f64(
+            (1.0 - bucket.tokens) / self.limit.per_second,
+        ))
+    }
+}
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+// The wait a `Retry-After` header asks for, in seconds or until an HTTP date (e.g. `Wed, 21 Oct
+// 2015 07:28:00 GMT`), none once the date passed.
+pub(crate) fn retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+    Some(http_date(value)?.duration_since(now).unwrap_or_default())
+}
+
+// An IMF-fixdate, the only form of HTTP date a sender may use.
+fn http_date(value: &str) -> Option<SystemTime> {
+    let (_, date) = value.split_once(", ")?;
+    let (day, month, year, time) = match date.split(' ').collect::<Vec<_>>()[..] {
+        [day, month, year, time, "GMT"] => (day, month, year, time),
+        _ => return None,
+    };
+    let day = day.parse::<u64>().ok()?;
+    let month = MONTHS.iter().position(|name| *name == month)? as u64 + 1;
+    let year = year.parse::<u64>().ok()?;
+    let clock = time
+        .split(':')
+        .map(|part| part.parse::<u64>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    let (hour, minute, second) = match clock[..] {
+        [hour, minute, second] if hour < 24 && minute < 60 && second <= 60 => {
+            (hour, minute, second)
+        }
+        _ => return None,
+    };
+    if year < 1970 || !(1..=31).contains(&day) {
+        return None;
+    }
+    let seconds = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    Some(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+// The days from 1970-01-01 to a date from then on, Howard Hinnant's `days_from_civil`.
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    // Years start in March, the leap day is the last.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{retry_after, RateLimit, RateLimiter};
+    use futures::future::join_all;
+    use std::time::{Duration, UNIX_EPOCH};
+    use tokio::time::Instant;
+    use url::Url;
+
+    fn relay(port: u16) -> Url {
+        Url::parse(&format!("http://127.0.0.1:{port}")).unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn acquire_pace_after_burst() {
+        let limiter = RateLimiter::new(
+            RateLimit::new(2.0, 2)
+                .unwrap()
+                .max_wait(Duration::from_secs(5)),
+        );
+        let (relay, other) = (relay(1), relay(2));
+        let start = Instant::now();
+
+        // Shared by the concurrent requests, the burst goes out at once and the rest every 500ms.
+        let times = join_all((0..4).map(|_| async {
+            limiter.acquire(&relay).await.unwrap();
+            start.elapsed()
+        }))
+        .await;
+        assert_eq!(
+            times,
+            vec![
+                Duration::ZERO,
+                Duration::ZERO,
+                Duration::from_millis(500),
+                Duration::from_secs(1),
+            ]
+        );
+        // Another relay has its own bucket.
+        limiter.acquire(&other).await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn acquire_time_out_past_max_wait() {
+        let limiter = RateLimiter::new(
+            RateLimit::new(1.0, 1)
+                .unwrap()
+                .max_wait(Duration::from_millis(500)),
+        );
+        let relay = relay(1);
+        let start = Instant::now();
+
+        limiter.acquire(&relay).await.unwrap();
+        // The next token is a second away, the request is dropped right away.
+        assert_eq!(limiter.acquire(&relay).await, Err(Duration::from_secs(1)));
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        limiter.acquire(&relay).await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn acquire_wait_out_cool_down() {
+        let limiter = RateLimiter::new(
+            RateLimit::new(10.0, 5)
+                .unwrap()
+                .max_wait(Duration::from_secs(5))
+                .cool_down(Duration::from_secs(30)),
+        );
+        let relay = relay(1);
+        let start = Instant::now();
+
+        // The relay asked for 3s.
+        limiter.cool_down(&relay, Some(Duration::from_secs(3)));
+        limiter.acquire(&relay).await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_millis(3100));
+
+        // The default cool-down is longer than a request waits.
+        limiter.cool_down(&relay, None);
+        assert_eq!(limiter.acquire(&relay).await, Err(Duration::from_secs(30)));
+
+        // Whatever the relay asks for, the pause stays within `max_cool_down`.
+        let forever = retry_after("18446744073709551615", UNIX_EPOCH);
+        assert_eq!(forever, Some(Duration::from_secs(u64::MAX)));
+        limiter.cool_down(&relay, forever);
+        assert_eq!(limiter.acquire(&relay).await, Err(Duration::from_secs(60)));
+        limiter.cool_down(&relay, Some(Duration::MAX));
+        assert_eq!(limiter.acquire(&relay).await, Err(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn rate_limit_refuse_no_rate() {
+        assert!(RateLimit::new(0.0, 5).is_err());
+        assert!(RateLimit::new(-1.0, 5).is_err());
+        assert!(RateLimit::new(f64::NAN, 5).is_err());
+        assert_eq!(RateLimit::new(0.5, 0).unwrap().burst, 1);
+    }
+
+    #[test]
+    fn retry_after_seconds_or_http_date() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_445_412_470);
+        assert_eq!(retry_after("7", now), Some(Duration::from_secs(7)));
+        assert_eq!(
+            retry_after("Wed, 21 Oct 2015 07:28:00 GMT", now),
+            Some(Duration::from_secs(10))
+        );
+        // Passed already, nothing to wait for.
+        assert_eq!(
+            retry_after("Wed, 21 Oct 2015 07:27:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(retry_after("Wed, 21 Oct 2015 07:28:00 CET", now), None);
+        assert_eq!(retry_after("soon", now), None);
+    }
+}
@@ -0,0 +1,495 @@
+use crate::utils::{BundleOptions, RelayOutcome, SimulateError};
+use ethers::{
+    prelude::*,
+    utils::{hex, keccak256},
+};
+use serde_json::{json, Map, Value};
+use std::fmt;
+
+// What a bundle carries whichever relay it goes to, taken from the signed queue and its options.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BundlePayload {
+    // The victim's tx first when backrunning.
+    pub txs: Vec<Bytes>,
+    pub target_block: U64,
+    pub min_timestamp: Option<u64>,
+    pub max_timestamp: Option<u64>,
+    pub reverting_tx_hashes: Vec<H256>,
+    pub replacement_uuid: Option<String>,
+}
+
+impl BundlePayload {
+    pub fn new(raw_tx_list: &[Bytes], options: &BundleOptions) -> Self {
+        // Without the victim's tx there's nothing to backrun, whatever of ours fails after it.
+        let reverting_tx_hashes = match options.backrun {
+            Some(_) => raw_tx_list.iter().collect::<Vec<_>>(),
+            None => options
+                .reverting
+                .iter()
+                .filter_map(|index| raw_tx_list.get(*index))
+                .collect(),
+        }
+        .into_iter()
+        .map(|raw_tx| H256(keccak256(raw_tx)))
+        .collect();
+        Self {
+            txs: options.backrun.iter().chain(raw_tx_list).cloned().collect(),
+            target_block: options.target_block,
+            min_timestamp: options.min_timestamp,
+            max_timestamp: options.max_timestamp,
+            reverting_tx_hashes,
+            replacement_uuid: options.replacement_uuid.clone(),
+        }
+    }
+
+    // The `eth_sendBundle` object, the fields not set left out.
+    fn flashbots_object(&self) -> Map<String, Value> {
+        let mut bundle = Map::new();
+        bundle.insert("txs".into(), json!(self.txs));
+        bundle.insert("blockNumber".into(), json!(self.target_block));
+        if let Some(min_timestamp) = self.min_timestamp {
+            bundle.insert("minTimestamp".into(), json!(min_timestamp));
+        }
+        if let Some(max_timestamp) = self.max_timestamp {
+            bundle.insert("maxTimestamp".into(), json!(max_timestamp));
+        }
+        if let Some(replacement_uuid) = &self.replacement_uuid {
+            bundle.insert("replacementUuid".into(), json!(replacement_uuid));
+        }
+        if !self.reverting_tx_hashes.is_empty() {
+            bundle.insert("revertingTxHashes".into(), json!(self.reverting_tx_hashes));
+        }
+        bundle
+    }
+
+    // The `eth_sendBundle` params as sent to a relay of the flashbots dialect, to replay by hand
+    // (e.g. with curl) or parse back with `from_relay_json`.
+    pub fn to_relay_json(&self) -> String {
+        FlashbotsAdapter::default()
+            .bundle_request(self)
+            .1
+            .to_string()
+    }
+
+    // The bundle of `eth_sendBundle` params, e.g. from `to_relay_json`.
+    pub fn from_relay_json(json: &str) -> Result<Self, SimulateError> {
+        let params = serde_json::from_str::<Value>(json)
+            .map_err(|e| SimulateError::RelayJson(e.to_string()))?;
+        let bundle = &params[0];
+        let invalid = |name: &'static str| {
+            move |e: serde_json::Error| SimulateError::RelayJson(format!("{name}: {e}"))
+        };
+        Ok(Self {
+            txs: serde_json::from_value(bundle["txs"].clone()).map_err(invalid("txs"))?,
+            target_block: serde_json::from_value(bundle["blockNumber"].clone())
+                .map_err(invalid("blockNumber"))?,
+            min_timestamp: serde_json::from_value(bundle["minTimestamp"].clone())
+                .map_err(invalid("minTimestamp"))?,
+            max_timestamp: serde_json::from_value(bundle["maxTimestamp"].clone())
+                .map_err(invalid("maxTimestamp"))?,
+            reverting_tx_hashes: serde_json::from_value::<Option<_>>(
+                bundle["revertingTxHashes"].clone(),
+            )
+            .map_err(invalid("revertingTxHashes"))?
+            .unwrap_or_default(),
+            replacement_uuid: serde_json::from_value(bundle["replacementUuid"].clone())
+                .map_err(invalid("replacementUuid"))?,
+        })
+    }
+}
+
+// How requests to one kind of relay are built and its answers read. The signing of the queue,
+// the target block and the uuid are the submitter's, the adapter only shapes them.
+pub trait RelayAdapter: fmt::Debug + Send + Sync {
+    // The method and params the bundle is sent with.
+    fn bundle_request(&self, bundle: &BundlePayload) -> (String, Value);
+
+    // Whether the body is signed with the reputation key in `X-Flashbots-Signature`.
+    fn signs_requests(&self) -> bool;
+
+    // Sent with every request besides the signature, e.g. an api key.
+    fn headers(&self) -> Vec<(String, String)> {
+        Vec::new()
+    }
+
+    // The bundle hash in the `result` of an accepted bundle.
+    fn bundle_hash(&self, result: &Value) -> Result<H256, String> {
+        serde_json::from_value(result["bundleHash"].clone()).map_err(|e| e.to_string())
+    }
+
+    // The outcome of a json-rpc `error`.
+    fn map_error(&self, error: &Value) -> RelayOutcome {
+        RelayOutcome::Rejected(error.to_string())
+    }
+
+    // Whether the relay speaks more than the bundle method: private txs, cancellation, MEV-Share
+    // and stats.
+    fn flashbots_methods(&self) -> bool {
+        false
+    }
+}
+
+// How a relay authenticates the sender of a bundle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelayAuth {
+    // `X-Flashbots-Signature` with the submitter's reputation key.
+    FlashbotsSignature,
+    None,
+}
+
+// What of `BundleOptions` a relay understands, the others reject unknown fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelayDialect {
+    Flashbots,
+    // Only `txs` and `blockNumber`.
+    Minimal,
+}
+
+// `eth_sendBundle` of the flashbots relay and the builders copying it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlashbotsAdapter {
+    pub auth: RelayAuth,
+    pub dialect: RelayDialect,
+}
+
+impl FlashbotsAdapter {
+    pub fn new(auth: RelayAuth, dialect: RelayDialect) -> Self {
+        Self { auth, dialect }
+    }
+}
+
+impl Default for FlashbotsAdapter {
+    fn default() -> Self {
+        Self::new(RelayAuth::FlashbotsSignature, RelayDialect::Flashbots)
+    }
+}
+
+impl RelayAdapter for FlashbotsAdapter {
+    fn bundle_request(&self, bundle: &BundlePayload) -> (String, Value) {
+        let params = match self.dialect {
+            RelayDialect::Flashbots => Value::Object(bundle.flashbots_object()),
+            RelayDialect::Minimal => json!({
+                "txs": bundle.txs,
+                "blockNumber": bundle.target_block,
+            }),
+        };
+        ("eth_sendBundle".into(), json!([params]))
+    }
+
+    fn signs_requests(&self) -> bool {
+        self.auth == RelayAuth::FlashbotsSignature
+    }
+
+    fn flashbots_methods(&self) -> bool {
+        self.dialect == RelayDialect::Flashbots
+    }
+}
+
+// bloXroute's `blxr_submit_bundle`, authenticated by the account's auth header and with the
+// params by name. Its txs go without the `0x`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloxrouteAdapter {
+    auth_header: String,
+    // The builders bloXroute forwards the bundle to, all of them if empty.
+    builders: Vec<String>,
+}
+
+impl BloxrouteAdapter {
+    pub fn new(auth_header: impl Into<String>) -> Self {
+        Self {
+            auth_header: auth_header.into(),
+            builders: Vec::new(),
+        }
+    }
+
+    pub fn builder(mut self, builder: impl Into<String>) -> Self {
+        self.builders.push(builder.into());
+        self
+    }
+}
+
+impl RelayAdapter for BloxrouteAdapter {
+    fn bundle_request(&self, bundle: &BundlePayload) -> (String, Value) {
+        let mut params = json!({
+            "transaction": bundle.txs.iter().map(hex::encode).collect::<Vec<_>>(),
+            "block_number": bundle.target_block,
+        });
+        if let Some(min_timestamp) = bundle.min_timestamp {
+            params["min_timestamp"] = json!(min_timestamp);
+        }
+        if let Some(max_timestamp) = bundle.max_timestamp {
+            params["max_timestamp"] = json!(max_timestamp);
+        }
+        if !bundle.reverting_tx_hashes.is_empty() {
+            params["reverting_hashes"] = json!(bundle.reverting_tx_hashes);
+        }
+        if let Some(replacement_uuid) = &bundle.replacement_uuid {
+            params["uuid"] = json!(replacement_uuid);
+        }
+        let builders = match self.builders.as_slice() {
+            [] => json!({ "all": "" }),
+            builders => Value::Object(
+                builders
+                    .iter()
+                    .map(|builder| (builder.clone(), json!("")))
+                    .collect(),
+            ),
+        };
+        params["mev_builders"] = builders;
+        ("blxr_submit_bundle".into(), params)
+    }
+
+    fn signs_requests(&self) -> bool {
+        false
+    }
+
+    fn headers(&self) -> Vec<(String, String)> {
+        vec![("Authorization".into(), self.auth_header.clone())]
+    }
+
+    // Its rate limit comes as an error, not an HTTP 429.
+    fn map_error(&self, error: &Value) -> RelayOutcome {
+        let message = error["message"].as_str().unwrap_or_default();
+        if message.to_lowercase().contains("rate limit") {
+            RelayOutcome::RateLimited(message.into())
+        } else {
+            RelayOutcome::Rejected(error.to_string())
+        }
+    }
+}
+
+// A builder's own bundle method: the `eth_sendBundle` object under another method and with the
+// builder's extra fields, e.g. Eden's or a refund setting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonRpcAdapter {
+    method: String,
+    fields: Map<String, Value>,
+    headers: Vec<(String, String)>,
+    signed: bool,
+}
+
+impl JsonRpcAdapter {
+    pub fn new(method: impl Into<String>) -> Self {
+        Self {
+            method: method.into(),
+            fields: Map::new(),
+            headers: Vec::new(),
+            signed: false,
+        }
+    }
+
+    // Added to the bundle object, over a field of the same name.
+    pub fn field(mut self, name: impl Into<String>, value: Value) -> Self {
+        self.fields.insert(name.into(), value);
+        self
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    // Sign the body like the flashbots relay.
+    pub fn signed(mut self) -> Self {
+        self.signed = true;
+        self
+    }
+}
+
+impl RelayAdapter for JsonRpcAdapter {
+    fn bundle_request(&self, bundle: &BundlePayload) -> (String, Value) {
+        let mut params = bundle.flashbots_object();
+        params.extend(self.fields.clone());
+        (self.method.clone(), json!([params]))
+    }
+
+    fn signs_requests(&self) -> bool {
+        self.signed
+    }
+
+    fn headers(&self) -> Vec<(String, String)> {
+        self.headers.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        BloxrouteAdapter, BundlePayload, FlashbotsAdapter, JsonRpcAdapter, RelayAdapter, RelayAuth,
+        RelayDialect,
+    };
+    use crate::utils::{BundleOptions, RelayOutcome, SimulateError};
+    use ethers::prelude::*;
+    use serde_json::json;
+
+    fn payload() -> BundlePayload {
+        BundlePayload {
+            txs: vec![Bytes::from(vec![0xf8, 0x01]), Bytes::from(vec![0x02, 0xf8])],
+            target_block: U64::from(100),
+            min_timestamp: Some(1000),
+            max_timestamp: None,
+            reverting_tx_hashes: vec![H256::repeat_byte(0x11)],
+            replacement_uuid: Some("2b0c5b8e-4c3a-4d6f-9a1e-0f1e2d3c4b5a".into()),
+        }
+    }
+
+    #[test]
+    fn bundle_payload_put_backrun_first() {
+        let raw_tx_list = [Bytes::from(vec![0x02, 0xf8])];
+        let options = BundleOptions::new(U64::from(100))
+            .reverting(0)
+            .backrun(Bytes::from(vec![0xf8, 0x01]));
+        let bundle = BundlePayload::new(&raw_tx_list, &options);
+        assert_eq!(
+            bundle.txs,
+            vec![Bytes::from(vec![0xf8, 0x01]), raw_tx_list[0].clone()]
+        );
+        // Only ours may revert.
+        assert_eq!(
+            bundle.reverting_tx_hashes,
+            vec![H256(ethers::utils::keccak256(&raw_tx_list[0]))]
+        );
+    }
+
+    #[test]
+    fn flashbots_request_shape() {
+        let adapter = FlashbotsAdapter::default();
+        assert!(adapter.signs_requests() && adapter.flashbots_methods());
+        assert_eq!(
+            adapter.bundle_request(&payload()),
+            (
+                "eth_sendBundle".to_string(),
+                json!([{
+                    "txs": ["0xf801", "0x02f8"],
+                    "blockNumber": "0x64",
+                    "minTimestamp": 1000,
+                    "replacementUuid": "2b0c5b8e-4c3a-4d6f-9a1e-0f1e2d3c4b5a",
+                    "revertingTxHashes": [
+                        "0x1111111111111111111111111111111111111111111111111111111111111111"
+                    ],
+                }])
+            )
+        );
+
+        let minimal = FlashbotsAdapter::new(RelayAuth::None, RelayDialect::Minimal);
+        assert!(!minimal.signs_requests() && !minimal.flashbots_methods());
+        assert_eq!(
+            minimal.bundle_request(&payload()).1,
+            json!([{ "txs": ["0xf801", "0x02f8"], "blockNumber": "0x64" }])
+        );
+    }
+
+    #[test]
+    fn bloxroute_request_shape() {
+        let adapter = BloxrouteAdapter::new("YWNjb3VudDpzZWNyZXQ=");
+        assert!(!adapter.signs_requests() && !adapter.flashbots_methods());
+        assert_eq!(
+            adapter.headers(),
+            vec![(
+                "Authorization".to_string(),
+                "YWNjb3VudDpzZWNyZXQ=".to_string()
+            )]
+        );
+        assert_eq!(
+            adapter.bundle_request(&payload()),
+            (
+                "blxr_submit_bundle".to_string(),
+                json!({
+                    "transaction": ["f801", "02f8"],
+                    "block_number": "0x64",
+                    "min_timestamp": 1000,
+                    "reverting_hashes": [
+                        "0x1111111111111111111111111111111111111111111111111111111111111111"
+                    ],
+                    "uuid": "2b0c5b8e-4c3a-4d6f-9a1e-0f1e2d3c4b5a",
+                    "mev_builders": { "all": "" },
+                })
+            )
+        );
+        let adapter = adapter.builder("flashbots").builder("beaverbuild");
+        assert_eq!(
+            adapter.bundle_request(&payload()).1["mev_builders"],
+            json!({ "flashbots": "", "beaverbuild": "" })
+        );
+
+        assert_eq!(
+            adapter.map_error(&json!({ "code": -32000, "message": "Rate limit exceeded" })),
+            RelayOutcome::RateLimited("Rate limit exceeded".into())
+        );
+        assert!(matches!(
+            adapter.map_error(&json!({ "code": -32000, "message": "bundle too old" })),
+            RelayOutcome::Rejected(_)
+        ));
+    }
+
+    #[test]
+    fn json_rpc_request_shape() {
+        let adapter = JsonRpcAdapter::new("eth_sendBundle")
+            .field("refundPercent", json!(90))
+            .field("builders", json!(["rsync", "titan"]))
+            .header("X-Api-Key", "secret")
+            .signed();
+        assert!(adapter.signs_requests() && !adapter.flashbots_methods());
+        assert_eq!(
+            adapter.headers(),
+            vec![("X-Api-Key".to_string(), "secret".to_string())]
+        );
+        assert_eq!(
+            adapter.bundle_request(&BundlePayload {
+                reverting_tx_hashes: vec![],
+                replacement_uuid: None,
+                ..payload()
+            }),
+            (
+                "eth_sendBundle".to_string(),
+                json!([{
+                    "txs": ["0xf801", "0x02f8"],
+                    "blockNumber": "0x64",
+                    "minTimestamp": 1000,
+                    "refundPercent": 90,
+                    "builders": ["rsync", "titan"],
+                }])
+            )
+        );
+        assert_eq!(
+            adapter.bundle_hash(&json!({ "bundleHash": H256::zero() })),
+            Ok(H256::zero())
+        );
+    }
+
+    #[test]
+    fn relay_json_pin_send_bundle_params() {
+        let json = payload().to_relay_json();
+        // Compared as json, the key order is serde_json's.
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&json).unwrap(),
+            serde_json::from_str::<serde_json::Value>(
+                r#"[{
+                    "txs": ["0xf801", "0x02f8"],
+                    "blockNumber": "0x64",
+                    "minTimestamp": 1000,
+                    "replacementUuid": "2b0c5b8e-4c3a-4d6f-9a1e-0f1e2d3c4b5a",
+                    "revertingTxHashes": [
+                        "0x1111111111111111111111111111111111111111111111111111111111111111"
+                    ]
+                }]"#
+            )
+            .unwrap()
+        );
+        assert_eq!(BundlePayload::from_relay_json(&json).unwrap(), payload());
+
+        let minimal = r#"[{"txs":["0xf801"],"blockNumber":"0x64"}]"#;
+        assert_eq!(
+            BundlePayload::from_relay_json(minimal).unwrap(),
+            BundlePayload {
+                txs: vec![Bytes::from(vec![0xf8, 0x01])],
+                target_block: U64::from(100),
+                ..Default::default()
+            }
+        );
+        assert!(matches!(
+            BundlePayload::from_relay_json(r#"[{"txs":["0xf801"]}]"#),
+            Err(SimulateError::RelayJson(_))
+        ));
+    }
+}
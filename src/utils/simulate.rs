@@ -1,27 +1,215 @@
+mod backrun;
+mod batch;
+mod block;
+mod bribe;
+mod circular;
+mod compare;
+mod cross_block;
+mod cross_check;
+mod deadline;
+mod derived;
+mod dialect;
+mod economics;
+mod error;
+mod fees;
+mod foundry;
+mod funding;
+mod gas;
+mod impersonate;
+mod l1_fee;
+mod logs;
+mod manipulation;
+mod metadata;
+#[cfg(feature = "metrics")]
+mod metrics;
+#[cfg(test)]
+pub(crate) mod mock;
+mod offline;
+mod pipeline;
+mod plan;
+mod raw;
+mod replace;
+mod report;
+mod scan;
+mod selector;
+mod sequential;
+mod signer_pool;
 mod state;
 mod strategy;
+mod target;
+mod timings;
+mod touched;
+mod trace_file;
+mod tracer;
+mod tx_queue;
+mod verify;
+
+pub use batch::{BatchTransport, HttpBatch};
+pub use block::{BlockMevReport, TxMevReport};
+pub use bribe::BribeMethod;
+pub use circular::{is_circular, native_flows, native_flows_within, token_flows};
+pub use compare::{
+    best, rank_by_profit, Candidate, QueueStrategy, StrategyComparison, StrategyOutcome,
+};
+pub use deadline::DeadlineOutcome;
+pub use derived::{is_sender_derived, sender_derived_calls};
+pub use dialect::TraceDialect;
+pub(crate) use economics::decimal;
+pub use economics::QueueEconomics;
+pub use error::SimulateError;
+pub use fees::{FeeBudget, FeeEstimate, FeeEstimator, FeeHistoryEstimator, FeeMode, Urgency};
+pub use gas::{effective_gas_price, gas_estimate_from_trace, GasSource};
+pub use l1_fee::{is_op_stack, L1FeeEstimator, GAS_PRICE_ORACLE};
+pub use manipulation::{detect_manipulation, ManipulationDetected};
+pub use offline::UNSIGNED_JSON_VERSION;
+pub use pipeline::{ExecutionPolicy, ExecutionReport, PipelineInput, PipelineStage};
+pub use plan::{ExecutionPlan, PlanStep};
+pub use raw::decode_raw_tx;
+pub use report::{ProfitCurrency, ProfitKind, ProfitReport, TokenMeta, TokenRegistry};
+pub use scan::Checkpoint;
+pub use selector::selector_of;
+pub use signer_pool::SignerPool;
+pub use state::{
+    base::{Aggregation, AnalyzerFlags, NoncePolicy},
+    lp::LpToken,
+};
+pub use strategy::{queue::QueueOverflow, sandwich};
+pub use target::{ResolvedBlock, SimulateTarget};
+pub use timings::SimulateTimings;
+pub use touched::touched_addresses;
+pub use trace_file::{read_trace_file, TRACE_FILE_VERSION};
+pub use tracer::TraceClient;
+pub use tx_queue::{GasBudget, QueueEntry, TxQueue};
+pub use verify::{QueueOutcome, Verification};
 
-use ethers::prelude::*;
+use crate::utils::{OpportunityId, OpportunityStore, SimulationSummary};
+use error::is_method_unavailable;
+use ethers::{abi::Function, prelude::*};
 use futures::future::join_all;
-use state::{base::AnalyzeState, eth::AnalyzeEth, token::AnalyzeToken};
+use gas::origin_call_status;
+use state::{
+    base::{AnalyzeState, DiffAnalysis},
+    eth::AnalyzeEth,
+    lp::AnalyzeLp,
+    rebate::AnalyzeRebate,
+    token::AnalyzeToken,
+};
 use std::collections::HashMap;
-use std::error::Error;
-use std::iter::Sum;
 use std::ops::Deref;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tracing::{info_span, warn, Instrument};
 
-struct SumU256(U256);
-impl Sum for SumU256 {
-    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
-        iter.fold(Self(U256::zero()), |a, b| Self(a.0 + b.0))
+pub type SimulateTrace = BlockTrace;
+
+#[derive(Debug, Clone)]
+pub struct Opportunity {
+    // Carried by the queues built from it and their submission, see `OpportunityStore`.
+    pub id: OpportunityId,
+    // Hash of the tx the opportunity was found in, the trigger of a cross block one. `None` for a
+    // trace analyzed without its tx.
+    pub victim: Option<TxHash>,
+    pub tx_queue: Vec<Vec<TransactionRequest>>,
+    pub reports: Vec<ProfitReport>,
+    pub timings: SimulateTimings,
+    // The block the trace was taken at, reconstructed queue should be verified on it too.
+    pub block: Option<BlockNumber>,
+    // Hash of the block a `Safe` / `Finalized` target resolved to, to detect reorgs later.
+    pub block_hash: Option<H256>,
+    pub trace: SimulateTrace,
+    // Of the victim's own top level call.
+    pub original_gas_used: Option<U256>,
+    pub original_success: bool,
+    // The victim's signed tx as it came from the mempool feed (`run_raw`), for a backrun bundle.
+    pub victim_raw_tx: Option<Bytes>,
+    // Of the victim's calldata, zeros for a tx without one.
+    pub selector: [u8; 4],
+    // `name(args)` of the call, with the abi of the function registered, see `Simulate::abi`.
+    pub decoded: Option<String>,
+    // A pool moved and read within the tx, see `detect_manipulation`.
+    pub manipulation: Option<ManipulationDetected>,
+}
+
+impl From<Opportunity> for (Vec<Vec<TransactionRequest>>, Vec<ProfitReport>) {
+    fn from(opportunity: Opportunity) -> Self {
+        (opportunity.tx_queue, opportunity.reports)
     }
 }
 
-pub type SimulateTrace = BlockTrace;
+// Where the value of a reconstructed call comes from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ValueSource {
+    // Attach the traced value to the tx itself.
+    #[default]
+    Attach,
+    // The searcher contract pays the value from its own funds, so the tx carries none.
+    ContractFunds,
+}
+
+// What happens to the queue when some calls of the trace can't be turned into txs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PartialPolicy {
+    // Leave the calls out and keep the rest.
+    #[default]
+    BestEffort,
+    // No queue unless every call is reconstructed, a partial replay may not earn the same.
+    AllOrNothing,
+}
 
 pub struct Simulate<'a, M, S> {
     inner: &'a SignerMiddleware<M, S>,
     contract: Option<Address>,
+    // Taken once in `init`, signers that fetch it (e.g. hardware wallets) aren't asked per tx.
+    signer_address: Address,
+    // The original sender the reconstructed calls are sent from instead, see `impersonate`.
+    impersonate: Option<Address>,
     state_analysis: Vec<Box<dyn AnalyzeState<'a, M, S>>>,
+    analyzers: AnalyzerFlags,
+    nonce_policy: NoncePolicy,
+    aggregation: Aggregation,
+    // Registered with `abi`, to decode the victim's call in the opportunity.
+    functions: HashMap<[u8; 4], Function>,
+    beneficiaries: Vec<Address>,
+    infer_beneficiary: bool,
+    value_source: ValueSource,
+    partial_policy: PartialPolicy,
+    store: Option<&'a dyn OpportunityStore>,
+    max_queue_len: Option<usize>,
+    queue_overflow: QueueOverflow,
+    // Whether the rpc supports the `trace_` namespace, known after the first trace call.
+    trace_supported: OnceLock<bool>,
+    trace_dialect: OnceLock<TraceDialect>,
+    // Fetched with the first opportunity, set on every generated tx.
+    chain_id: OnceLock<U256>,
+    trace_provider: Option<Box<dyn TraceClient + 'a>>,
+    allow_reverted: bool,
+    reject_circular: bool,
+    // The move of a pool's reserves `detect_manipulation` takes for a swap, in bps.
+    manipulation_min_move_bps: u32,
+    max_trace_entries: Option<usize>,
+    verify_tolerance_bps: u64,
+    batch_transport: Option<Box<dyn BatchTransport + 'a>>,
+    batch_size: usize,
+    // Pause (plus up to the jitter) between the calls of a sequential scan, none by default.
+    call_delay: Duration,
+    call_jitter: Duration,
+    gas_estimation: GasSource,
+    max_total_gas: Option<U256>,
+    estimate_timeout: Option<Duration>,
+    include_logs: bool,
+    // Blocks after the simulation block a built queue stays valid for.
+    validity_horizon: u64,
+    // Don't send queues whose `QueueEconomics` net nothing, see `send_queue`.
+    refuse_unprofitable: bool,
+    // Optional senders besides the client's signer, see `pooled_tx_queue`.
+    signer_pool: Option<SignerPool<S>>,
+    // Replacement tx hash to the original tx of its chain, not the replacement it replaced last,
+    // see `track_replacement`.
+    replacements: Mutex<HashMap<TxHash, TxHash>>,
+    // Fetched on demand for reporting, see `token_metadata`.
+    token_metadata: Mutex<HashMap<Address, TokenMeta>>,
+    // What native profit is reported in, ETH unless the chain's gas token is another.
+    native_token: TokenMeta,
 }
 
 impl<'a, M, S> Deref for Simulate<'a, M, S> {
@@ -37,35 +225,369 @@ impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
     pub async fn init(
         client: &'a SignerMiddleware<M, S>,
         contract: Option<Address>,
-    ) -> Result<Simulate<'a, M, S>, Box<dyn Error + 'a>> {
+    ) -> Result<Simulate<'a, M, S>, SimulateError> {
         Ok(Self {
             inner: client,
             contract,
+            signer_address: client.address(),
+            impersonate: None,
             state_analysis: vec![
-                Box::new(AnalyzeEth::init(client).await?),
-                Box::new(AnalyzeToken::init(client).await?),
+                Box::new(
+                    AnalyzeEth::init(client)
+                        .await
+                        .map_err(|e| SimulateError::analyze(&e))?,
+                ),
+                Box::new(
+                    AnalyzeToken::init(client)
+                        .await
+                        .map_err(|e| SimulateError::analyze(&e))?,
+                ),
             ],
+            analyzers: AnalyzerFlags::default(),
+            nonce_policy: NoncePolicy::default(),
+            aggregation: Aggregation::default(),
+            functions: HashMap::new(),
+            beneficiaries: vec![],
+            infer_beneficiary: false,
+            value_source: ValueSource::default(),
+            partial_policy: PartialPolicy::default(),
+            store: None,
+            max_queue_len: None,
+            queue_overflow: QueueOverflow::default(),
+            trace_supported: OnceLock::new(),
+            trace_dialect: OnceLock::new(),
+            chain_id: OnceLock::new(),
+            trace_provider: None,
+            allow_reverted: false,
+            reject_circular: false,
+            manipulation_min_move_bps: 0,
+            max_trace_entries: None,
+            verify_tolerance_bps: 500,
+            batch_transport: None,
+            batch_size: batch::DEFAULT_BATCH_SIZE,
+            call_delay: Duration::ZERO,
+            call_jitter: Duration::ZERO,
+            gas_estimation: GasSource::default(),
+            max_total_gas: None,
+            estimate_timeout: None,
+            include_logs: false,
+            validity_horizon: 2,
+            refuse_unprofitable: true,
+            signer_pool: None,
+            replacements: Mutex::default(),
+            token_metadata: Mutex::default(),
+            native_token: TokenMeta::eth(),
         })
     }
 
+    // Accounts whose gains count as profit, default to the tx's `from` / `to`.
+    pub fn beneficiaries(mut self, beneficiaries: Vec<Address>) -> Self {
+        self.beneficiaries = beneficiaries;
+        self
+    }
+
+    // Only run these of the built-in analyzers, the others are skipped. All of them by default.
+    pub fn analyzers(mut self, analyzers: AnalyzerFlags) -> Self {
+        self.analyzers = analyzers;
+        self
+    }
+
+    // How the native analyzer treats a diff with a mismatching sender nonce, the tx is dropped
+    // by default.
+    pub fn nonce_policy(mut self, nonce_policy: NoncePolicy) -> Self {
+        self.nonce_policy = nonce_policy;
+        self.rebuild_native_analyzer();
+        self
+    }
+
+    // How the native analyzer combines the gains of several accounts, summed by default.
+    pub fn aggregation(mut self, aggregation: Aggregation) -> Self {
+        self.aggregation = aggregation;
+        self.rebuild_native_analyzer();
+        self
+    }
+
+    fn rebuild_native_analyzer(&mut self) {
+        for analyzer in &mut self.state_analysis {
+            if analyzer.flag() == Some(AnalyzerFlags::NATIVE) {
+                *analyzer =
+                    Box::new(AnalyzeEth::new(self.nonce_policy).aggregation(self.aggregation));
+            }
+        }
+    }
+
+    // Also value gains of these pairs' LP tokens by their share of the native reserve.
+    pub fn lp_tokens(mut self, lp_tokens: Vec<LpToken>) -> Self {
+        self.state_analysis
+            .push(Box::new(AnalyzeLp::new(self.inner, lp_tokens)));
+        self
+    }
+
+    // Also report what these contracts pay out to the beneficiaries as fee rebates, on top of the
+    // balance gains that include them.
+    pub fn fee_collectors(mut self, fee_collectors: Vec<Address>) -> Self {
+        self.state_analysis
+            .push(Box::new(AnalyzeRebate::new(fee_collectors)));
+        self
+    }
+
+    // How many blocks after the simulation block a queue may still be sent, see `TxQueue::is_expired`.
+    pub fn validity_horizon(mut self, blocks: u64) -> Self {
+        self.validity_horizon = blocks;
+        self
+    }
+
+    // Also count the biggest native gainer of the trace (but the coinbase) as a beneficiary, for
+    // profit that lands in a contract nobody listed.
+    pub fn infer_beneficiary(mut self, infer_beneficiary: bool) -> Self {
+        self.infer_beneficiary = infer_beneficiary;
+        self
+    }
+
+    pub fn value_source(mut self, value_source: ValueSource) -> Self {
+        self.value_source = value_source;
+        self
+    }
+
+    pub fn partial_policy(mut self, partial_policy: PartialPolicy) -> Self {
+        self.partial_policy = partial_policy;
+        self
+    }
+
+    // Send the reconstructed calls from `sender` with their calldata as is, on a node that can
+    // impersonate it (anvil, hardhat). For debugging: a queue failing only when rewritten points
+    // at `mock_tx_data`.
+    pub fn impersonate(mut self, sender: Address) -> Self {
+        self.impersonate = Some(sender);
+        self
+    }
+
+    // The symbol of the chain's native token in reports, e.g. `MATIC` or `BNB`.
+    pub fn native_symbol(mut self, native_symbol: impl Into<String>) -> Self {
+        self.native_token.symbol = native_symbol.into();
+        self
+    }
+
+    pub fn native_decimals(mut self, native_decimals: u8) -> Self {
+        self.native_token.decimals = native_decimals;
+        self
+    }
+
+    // Record the summary of every opportunity found under its id, nothing is kept without it.
+    pub fn store(mut self, store: &'a dyn OpportunityStore) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    // Cap the total number of reconstructed txs, see `queue_overflow` for what happens beyond it.
+    pub fn max_queue_len(mut self, max_queue_len: usize) -> Self {
+        self.max_queue_len = Some(max_queue_len);
+        self
+    }
+
+    pub fn queue_overflow(mut self, queue_overflow: QueueOverflow) -> Self {
+        self.queue_overflow = queue_overflow;
+        self
+    }
+
+    // Keep analyzing txs whose top level call reverted, for MEV that survives a partial revert.
+    pub fn allow_reverted(mut self, allow_reverted: bool) -> Self {
+        self.allow_reverted = allow_reverted;
+        self
+    }
+
+    // `None` until the first trace call has been answered by the rpc.
+    pub fn trace_supported(&self) -> Option<bool> {
+        self.trace_supported.get().copied()
+    }
+
+    pub(crate) async fn chain_id(&self) -> Result<U256, SimulateError> {
+        if let Some(chain_id) = self.chain_id.get() {
+            return Ok(*chain_id);
+        }
+        let chain_id = self
+            .get_chainid()
+            .await
+            .map_err(SimulateError::middleware)?;
+        let _ = self.chain_id.set(chain_id);
+        Ok(chain_id)
+    }
+
+    // Refuse to rebuild a queue from a pathological trace (e.g. a tx looping over calls).
+    pub fn max_trace_entries(mut self, max_trace_entries: usize) -> Self {
+        self.max_trace_entries = Some(max_trace_entries);
+        self
+    }
+
+    fn check_trace_size(&self, trace: &SimulateTrace) -> Result<(), SimulateError> {
+        let entries = trace.trace.as_ref().map_or(0, Vec::len);
+        match self.max_trace_entries {
+            Some(max_trace_entries) if entries > max_trace_entries => {
+                Err(SimulateError::TraceTooLarge {
+                    entries,
+                    max_trace_entries,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Don't build a queue for a tx of another chain, it would be replayed on the wrong one.
+    pub(crate) async fn check_chain_id(
+        &self,
+        tx_chain_id: Option<U256>,
+    ) -> Result<(), SimulateError> {
+        let chain_id = self.chain_id().await?;
+        match tx_chain_id {
+            Some(tx_chain_id) if tx_chain_id != chain_id => Err(SimulateError::ChainIdMismatch {
+                tx: tx_chain_id,
+                provider: chain_id,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    // A store that fails is logged, the opportunity is still returned.
+    pub(crate) async fn record_simulation(&self, opportunity: &Opportunity) {
+        if let Some(store) = self.store {
+            let summary = SimulationSummary::new(opportunity);
+            if let Err(e) = store.record_simulation(opportunity.id, &summary).await {
+                warn!(id = %opportunity.id, "simulation not recorded: {e}");
+            }
+        }
+    }
+
+    // Open the connections before the scan loop, the first calls otherwise pay the setup. Fails
+    // like the scan would if the node doesn't answer or has no `trace_` namespace.
+    pub async fn warmup(&self) -> Result<(), SimulateError> {
+        self.chain_id().await?;
+        self.get_block_number()
+            .await
+            .map_err(SimulateError::middleware)?;
+        // An empty creation from the zero address, about the cheapest trace there is.
+        self.to_trace(&Transaction::default(), Some(BlockNumber::Latest))
+            .await?;
+        Ok(())
+    }
+
     pub async fn run(
         &self,
         tx_hash: TxHash,
-        rewind: bool,
-    ) -> Result<Option<(Vec<Vec<TransactionRequest>>, U256)>, Box<dyn Error + 'a>> {
-        if let Some(tx) = self.get_transaction(tx_hash).await? {
-            let block: Option<BlockNumber> = match tx.block_number {
-                Some(block_number) if rewind => Some((block_number - 1).into()),
-                Some(block_number) if !rewind => Some(block_number.into()),
-                _ => None,
-            };
-            if let Some((trace, profit)) = self.is_valuable(tx, block).await? {
-                let tx_queue = self.to_tx_queue(&trace);
-                if tx_queue.len() > 0 {
-                    return Ok(Some((tx_queue, profit)));
-                }
-            };
+        target: impl Into<SimulateTarget>,
+    ) -> Result<Option<Opportunity>, SimulateError> {
+        let target = target.into();
+        let result = match (&self.batch_transport, target) {
+            (Some(transport), SimulateTarget::Rewind) => self
+                .run_batch(transport.as_ref(), &[tx_hash])
+                .await
+                .pop()
+                .unwrap_or(Ok(None)),
+            _ => self.fetch_and_run(tx_hash, target).await,
+        };
+        #[cfg(feature = "metrics")]
+        metrics::METRICS.record(&result);
+
+        result
+    }
+
+    // Like `run` for a tx already fetched, e.g. from a pending tx subscription. Not mined yet, it
+    // is simulated on the latest state.
+    pub async fn run_pending(&self, tx: Transaction) -> Result<Option<Opportunity>, SimulateError> {
+        let result = self
+            .run_tx(tx, SimulateTarget::Rewind, None, SimulateTimings::default())
+            .await;
+        #[cfg(feature = "metrics")]
+        metrics::METRICS.record(&result);
+
+        result
+    }
+
+    async fn fetch_and_run(
+        &self,
+        tx_hash: TxHash,
+        target: SimulateTarget,
+    ) -> Result<Option<Opportunity>, SimulateError> {
+        let mut timings = SimulateTimings::default();
+
+        let start = Instant::now();
+        let tx = self
+            .get_transaction(tx_hash)
+            .instrument(info_span!("fetch"))
+            .await
+            .map_err(SimulateError::middleware)?;
+        timings.fetch = start.elapsed();
+        timings.round_trips += 1;
+
+        match tx {
+            Some(tx) => self.run_tx(tx, target, None, timings).await,
+            None => Ok(None),
         }
+    }
+
+    // The part of `run` after the tx is fetched, `trace` is passed when it came with the tx.
+    async fn run_tx(
+        &self,
+        tx: Transaction,
+        target: SimulateTarget,
+        trace: Option<SimulateTrace>,
+        mut timings: SimulateTimings,
+    ) -> Result<Option<Opportunity>, SimulateError> {
+        let block = self.resolve_block(&tx, target).await?;
+        // The hash is only known when a tag was resolved through the rpc.
+        if block.hash.is_some() {
+            timings.round_trips += 1;
+        }
+        // Tracing on top of the prior txs goes through `trace_callMany` instead of `to_trace`.
+        let trace = match (trace, target) {
+            (None, SimulateTarget::BeforeTx(_)) => {
+                let start = Instant::now();
+                let trace = self
+                    .to_target_trace(&tx, target, block.number)
+                    .instrument(info_span!("trace"))
+                    .await?;
+                timings.trace = start.elapsed();
+                timings.round_trips += 2;
+                Some(trace)
+            }
+            (trace, _) => trace,
+        };
+        let (tx_hash, tx_chain_id) = (tx.hash, tx.chain_id);
+        let (selector, decoded) = (selector_of(&tx.input), self.decode_call(&tx.input));
+        if let Some((trace, reports)) = self
+            .is_valuable(tx, block.number, trace, &mut timings)
+            .await?
+        {
+            self.check_chain_id(tx_chain_id).await?;
+
+            let start = Instant::now();
+            let mut tx_queue = info_span!("build_queue").in_scope(|| self.to_tx_queue(&trace));
+            timings.build_queue = start.elapsed();
+
+            if tx_queue.len() > 0
+                && strategy::queue::run(&mut tx_queue, self.max_queue_len, self.queue_overflow)
+            {
+                let (original_gas_used, original_success) = origin_call_status(&trace);
+                let manipulation = self.manipulation(&trace, &reports);
+                let opportunity = Opportunity {
+                    id: OpportunityId::new(),
+                    victim: Some(tx_hash),
+                    tx_queue,
+                    reports,
+                    timings,
+                    block: block.number,
+                    block_hash: block.hash,
+                    trace,
+                    original_gas_used,
+                    original_success,
+                    victim_raw_tx: None,
+                    selector,
+                    decoded,
+                    manipulation,
+                };
+                self.record_simulation(&opportunity).await;
+                return Ok(Some(opportunity));
+            }
+        };
 
         Ok(None)
     }
@@ -76,29 +598,41 @@ impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
         &self,
         tx: Transaction,
         block: Option<BlockNumber>,
-    ) -> Result<Option<(SimulateTrace, U256)>, Box<dyn Error + 'a>> {
+        trace: Option<SimulateTrace>,
+        timings: &mut SimulateTimings,
+    ) -> Result<Option<(SimulateTrace, Vec<ProfitReport>)>, SimulateError> {
         // e.g., prune for native token transfer.
         if strategy::transfer::run(&tx) {
             // e.g., for flashloan, loan first to ensure sufficient tokens.
             if strategy::flashloan::run(&tx) {
-                let trace = self.to_trace(&tx, block).await?;
+                let trace = match trace {
+                    Some(trace) => trace,
+                    None => {
+                        let start = Instant::now();
+                        let trace = self
+                            .to_trace(&tx, block)
+                            .instrument(info_span!("trace"))
+                            .await?;
+                        timings.trace = start.elapsed();
+                        timings.round_trips += 1;
+                        trace
+                    }
+                };
+                self.check_trace_size(&trace)?;
+                // A reverted tx may still shuffle balances in the trace, it's no real profit.
+                if !self.allow_reverted && !origin_call_status(&trace).1 {
+                    return Ok(None);
+                }
 
-                let analysis = self.state_analysis.iter().map(|a| async {
-                    a.run(&tx, &trace)
-                        .await
-                        .ok()
-                        .unwrap_or_default()
-                        .unwrap_or_default()
-                });
-                let profit = join_all(analysis)
-                    .await
-                    .into_iter()
-                    .map(|p| SumU256(p))
-                    .sum::<SumU256>()
-                    .0;
+                let start = Instant::now();
+                let reports = self
+                    .analyze(&tx, &trace, block)
+                    .instrument(info_span!("analyze"))
+                    .await;
+                timings.analyze = start.elapsed();
 
-                if !profit.is_zero() {
-                    return Ok(Some((trace, profit)));
+                if !reports.is_empty() {
+                    return Ok(Some((trace, reports)));
                 }
             }
         }
@@ -106,15 +640,131 @@ impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
         Ok(None)
     }
 
+    pub(crate) async fn analyze(
+        &self,
+        tx: &Transaction,
+        trace: &SimulateTrace,
+        block: Option<BlockNumber>,
+    ) -> Vec<ProfitReport> {
+        let mut beneficiaries = self.beneficiaries.clone();
+        if self.infer_beneficiary {
+            if let Some(inferred) = self.inferred_beneficiary(trace, block).await {
+                if !beneficiaries.contains(&inferred) {
+                    beneficiaries.push(inferred);
+                }
+            }
+        }
+
+        // Without logs the analyzers still run, like on a node without `debug_traceCall`.
+        let logs = match self.include_logs {
+            true => Some(self.trace_logs(tx, block).await.unwrap_or_default()),
+            false => None,
+        };
+
+        self.run_analyzers(tx, trace, block, &beneficiaries, &logs, false)
+            .await
+    }
+
+    // The enabled analyzers on `trace`, traced on the state of `block`. `offline` leaves out those
+    // that read from the node.
+    pub(crate) async fn run_analyzers(
+        &self,
+        tx: &Transaction,
+        trace: &SimulateTrace,
+        block: Option<BlockNumber>,
+        beneficiaries: &[Address],
+        logs: &Option<Vec<Log>>,
+        offline: bool,
+    ) -> Vec<ProfitReport> {
+        let analysis = self
+            .state_analysis
+            .iter()
+            .filter(|a| match a.flag() {
+                Some(flag) => self.analyzers.contains(flag),
+                None => true,
+            })
+            .filter(|a| !offline || !a.reads_node())
+            .map(|a| async move {
+                a.run_at(tx, trace, block, logs.as_deref(), beneficiaries)
+                    .await
+                    .ok()
+                    .unwrap_or_default()
+            });
+        join_all(analysis)
+            .await
+            .into_iter()
+            .flatten()
+            .filter(|r| !r.amount.is_zero())
+            .filter(|r| !self.reject_circular || !is_circular(r, trace, logs.as_deref()))
+            .collect()
+    }
+
+    // The account with the biggest native balance gain, the coinbase of `block` is left out as its
+    // gain is the tip. `None` if nobody gained or the block can't be fetched.
+    async fn inferred_beneficiary(
+        &self,
+        trace: &SimulateTrace,
+        block: Option<BlockNumber>,
+    ) -> Option<Address> {
+        let coinbase = self
+            .get_block(block.unwrap_or(BlockNumber::Latest))
+            .await
+            .ok()??
+            .author;
+        trace
+            .state_diff
+            .as_ref()?
+            .0
+            .iter()
+            .filter(|(address, _)| Some(**address) != coinbase)
+            .map(|(address, diff)| (address, DiffAnalysis::init(diff, None)))
+            .filter(|(_, diff)| diff.increase_balance)
+            .max_by_key(|(_, diff)| diff.balance_diff)
+            .map(|(address, _)| *address)
+    }
+
     async fn to_trace(
         &self,
         tx: &Transaction,
         block: Option<BlockNumber>,
-    ) -> Result<SimulateTrace, Box<dyn Error + 'a>> {
+    ) -> Result<SimulateTrace, SimulateError> {
+        if let Some(false) = self.trace_supported() {
+            return Err(SimulateError::TraceApiUnsupported {
+                provider_hint: "trace namespace unavailable (cached)".into(),
+            });
+        }
+
+        let trace_type = vec![TraceType::Trace, TraceType::StateDiff];
         // only parity node support `trace_call`, recommend `ankr` rpc. (Sometimes it fails, need to retry)
-        let trace = self
-            .trace_call(tx, vec![TraceType::Trace, TraceType::StateDiff], block)
-            .await?;
+        let trace = match self.trace_dialect().filter(TraceDialect::needs_compat) {
+            Some(dialect) => {
+                self.trace_call_compat(tx, trace_type, block, dialect)
+                    .await?
+            }
+            None => match self
+                .tracer()
+                .trace_call(tx.into(), trace_type.clone(), block)
+                .await
+            {
+                Ok(trace) => trace,
+                Err(e) if is_method_unavailable(&e.to_string()) => {
+                    let _ = self.trace_supported.set(false);
+                    return Err(SimulateError::TraceApiUnsupported {
+                        provider_hint: e.to_string(),
+                    });
+                }
+                // Erigon / Nethermind responses may not deserialize as is, retry through the compat layer.
+                Err(e) => {
+                    let dialect = self.detect_trace_dialect().await;
+                    if !dialect.needs_compat() {
+                        return Err(e);
+                    }
+                    self.trace_call_compat(tx, trace_type, block, dialect)
+                        .await?
+                }
+            },
+        };
+        let _ = self.trace_supported.set(true);
 
         // only geth node support `debug_traceCall`
         // let mut opts = GethDebugTracingOptions::default();
@@ -127,71 +777,95 @@ impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
     }
 
     fn to_tx_queue(&self, trace: &SimulateTrace) -> Vec<Vec<TransactionRequest>> {
+        self.to_strategy_queue(trace)
+            .into_iter()
+            .map(|(_, tx_list)| tx_list)
+            .collect()
+    }
+
+    fn to_strategy_queue(
+        &self,
+        trace: &SimulateTrace,
+    ) -> Vec<(QueueStrategy, Vec<TransactionRequest>)> {
+        self.to_strategy_traces(trace, self.queue_sender())
+            .into_iter()
+            .map(|(strategy, tx_list)| (strategy, tx_list.into_iter().map(|(tx, _)| tx).collect()))
+            .collect()
+    }
+
+    // Every reconstructed tx along with the call it comes from, sent by `sender`.
+    fn to_strategy_traces<'t>(
+        &self,
+        trace: &'t SimulateTrace,
+        sender: Address,
+    ) -> Vec<(
+        QueueStrategy,
+        Vec<(TransactionRequest, &'t TransactionTrace)>,
+    )> {
         let mut tx_queue = Vec::new();
+        // Calls working on an address keyed on their sender can't be sent by us.
+        let sender_derived = sender_derived_calls(trace);
         if let Some(trace_list) = &trace.trace {
-            let mut trace_map = HashMap::new();
-            for trace in trace_list {
-                let mut trace_key = 0;
-                for (i, v) in trace.trace_address.iter().rev().enumerate() {
-                    trace_key += v * 2_usize.pow(i.try_into().unwrap()) + 1;
-                }
-                trace_map.insert(trace_key, trace);
-            }
+            let trace_list = flatten_traces(trace_list);
 
+            let mut unparsed = false;
             // origin call
-            let origin_call = trace_map.get(&0).unwrap();
-            if let Some(tx) = self.to_tx(origin_call) {
-                tx_queue.push(vec![tx]);
+            if let Some(origin_call) = trace_list
+                .iter()
+                .find(|t| t.trace_address.is_empty())
+                .filter(|t| !sender_derived.contains(&t.trace_address))
+            {
+                match self.to_tx(origin_call, sender) {
+                    Some(tx) => tx_queue.push((QueueStrategy::Origin, vec![(tx, *origin_call)])),
+                    None => unparsed = true,
+                }
             }
             // internal call
             let mut internal_tx_list = Vec::new();
-            for i in 1..=origin_call.subtraces {
-                if let Some(tx) = self.to_tx(trace_map.get(&i).unwrap()) {
-                    internal_tx_list.push(tx);
-                } else {
-                    // Part of the trace simulation failed, can still going?
-                    // break;
+            for trace in trace_list.iter().filter(|t| t.trace_address.len() == 1) {
+                match self.to_tx(trace, sender) {
+                    Some(tx) => internal_tx_list.push((tx, *trace)),
+                    None => unparsed = true,
                 }
             }
-            if internal_tx_list.len() > 0 {
-                tx_queue.push(internal_tx_list);
+            if unparsed && self.partial_policy == PartialPolicy::AllOrNothing {
+                return Vec::new();
+            }
+            // Each internal call becomes its own tx, a contract destructed in one is gone for the next.
+            if !internal_tx_list.is_empty()
+                && !call_after_selfdestruct(&trace_list)
+                && !internal_tx_list
+                    .iter()
+                    .any(|(_, trace)| sender_derived.contains(&trace.trace_address))
+            {
+                tx_queue.push((QueueStrategy::Internal, internal_tx_list));
             }
         }
 
         tx_queue
     }
 
-    fn to_tx(&self, trace: &TransactionTrace) -> Option<TransactionRequest> {
+    fn to_tx(&self, trace: &TransactionTrace, sender: Address) -> Option<TransactionRequest> {
         match &trace.action {
             Action::Call(data) => {
                 return Some(TransactionRequest {
-                    chain_id: None,
-                    from: Some(self.signer().address()),
+                    chain_id: self.chain_id.get().map(|chain_id| chain_id.as_u64().into()),
+                    from: Some(sender),
                     to: Some(NameOrAddress::Address(data.to)),
-                    data: Some(mock_tx_data(
-                        &data.input,
-                        data.from,
-                        self.contract.unwrap_or(self.signer().address()),
-                    )),
-                    value: Some(data.value),
+                    data: Some(self.rewrite_data(&data.input, data.from, sender)),
+                    value: self.to_value(data.value),
                     // Why is the gas obtained from the debug less than the original tx's gas limit?
                     gas: None,
-                    // Due to EIP-1559, the minimum base fee must be sent, so please ensure that the wallet has enough gas fee.
-                    // Only base fee here, change later or send priority fee to coinbase in contract to ensure that tx is packaged for priority.
                     gas_price: None,
                     nonce: None,
                 });
             }
             Action::Create(data) => Some(TransactionRequest {
-                chain_id: None,
-                from: Some(self.signer().address()),
+                chain_id: self.chain_id.get().map(|chain_id| chain_id.as_u64().into()),
+                from: Some(sender),
                 to: None,
-                data: Some(mock_tx_data(
-                    &data.init,
-                    data.from,
-                    self.contract.unwrap_or(self.signer().address()),
-                )),
-                value: Some(data.value),
+                data: Some(self.rewrite_data(&data.init, data.from, sender)),
+                value: self.to_value(data.value),
                 gas: None,
                 gas_price: None,
                 nonce: None,
@@ -199,6 +873,53 @@ impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
             _ => None,
         }
     }
+
+    // The caller in the calldata swapped for us, left alone when impersonating the caller.
+    fn rewrite_data(&self, data: &Bytes, from: Address, sender: Address) -> Bytes {
+        match self.impersonate {
+            Some(_) => data.clone(),
+            None => mock_tx_data(data, from, self.contract.unwrap_or(sender)),
+        }
+    }
+
+    fn to_value(&self, value: U256) -> Option<U256> {
+        match self.value_source {
+            ValueSource::Attach => Some(value),
+            ValueSource::ContractFunds => None,
+        }
+    }
+}
+
+// Order a trace tree depth first, every parent before its children and siblings by index.
+// Comparing `trace_address` lexicographically is exactly that order, no key encoding to collide.
+pub fn flatten_traces(traces: &[TransactionTrace]) -> Vec<&TransactionTrace> {
+    let mut flattened = traces.iter().collect::<Vec<_>>();
+    flattened.sort_by(|a, b| a.trace_address.cmp(&b.trace_address));
+    flattened
+}
+
+// Whether a call of the flattened trace targets a contract that destructed in an earlier top level
+// call. Inside the victim's own tx the code lives until the tx ends, so the origin call is fine.
+fn call_after_selfdestruct(trace_list: &[&TransactionTrace]) -> bool {
+    let mut destructed: Vec<(Address, usize)> = Vec::new();
+    for trace in trace_list {
+        let top_level = match trace.trace_address.first() {
+            Some(index) => *index,
+            None => continue,
+        };
+        match &trace.action {
+            Action::Suicide(data) => destructed.push((data.address, top_level)),
+            Action::Call(data)
+                if destructed
+                    .iter()
+                    .any(|(address, index)| *address == data.to && *index < top_level) =>
+            {
+                return true
+            }
+            _ => {}
+        }
+    }
+    false
 }
 
 fn mock_tx_data(data: &Bytes, from: Address, to: Address) -> Bytes {
@@ -210,8 +931,338 @@ fn mock_tx_data(data: &Bytes, from: Address, to: Address) -> Bytes {
 
 #[cfg(test)]
 mod tests {
-    use super::mock_tx_data;
-    use ethers::prelude::*;
+    use super::{
+        flatten_traces,
+        mock::{balance_diff, block_trace, call_trace, mock_client},
+        mock_tx_data,
+        state::base::AnalyzeState,
+        AnalyzerFlags, PartialPolicy, ProfitReport, QueueOverflow, QueueStrategy, Simulate,
+        SimulateError, SimulateTimings, SimulateTrace, ValueSource,
+    };
+    use async_trait::async_trait;
+    use ethers::types::transaction::{eip2718::TypedTransaction, eip712::Eip712};
+    use ethers::{
+        prelude::*,
+        utils::{keccak256, parse_ether},
+    };
+    use proptest::prelude::*;
+    use std::collections::BTreeMap;
+    use std::error::Error;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    // A wallet counting how often its address is asked, like a hardware wallet that fetches it.
+    #[derive(Debug)]
+    struct CountingSigner(LocalWallet, Arc<AtomicUsize>);
+
+    #[async_trait]
+    impl Signer for CountingSigner {
+        type Error = WalletError;
+
+        async fn sign_message<T: Send + Sync + AsRef<[u8]>>(
+            &self,
+            message: T,
+        ) -> Result<Signature, Self::Error> {
+            self.0.sign_message(message).await
+        }
+
+        async fn sign_transaction(&self, tx: &TypedTransaction) -> Result<Signature, Self::Error> {
+            self.0.sign_transaction(tx).await
+        }
+
+        async fn sign_typed_data<T: Eip712 + Send + Sync>(
+            &self,
+            payload: &T,
+        ) -> Result<Signature, Self::Error> {
+            self.0.sign_typed_data(payload).await
+        }
+
+        fn address(&self) -> Address {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            self.0.address()
+        }
+
+        fn chain_id(&self) -> u64 {
+            self.0.chain_id()
+        }
+
+        fn with_chain_id<T: Into<u64>>(self, chain_id: T) -> Self {
+            Self(self.0.with_chain_id(chain_id), self.1)
+        }
+    }
+
+    // Report a fixed token gain, stand in for a token analyzer.
+    struct AnalyzeTokenGain(Address, Address, U256);
+
+    #[async_trait]
+    impl<'a, M, S> AnalyzeState<'a, M, S> for AnalyzeTokenGain {
+        async fn init(_client: &'a SignerMiddleware<M, S>) -> Result<Self, Box<dyn Error + 'a>> {
+            Ok(Self(Address::zero(), Address::zero(), U256::zero()))
+        }
+
+        async fn run(
+            &self,
+            _tx: &Transaction,
+            _trace: &SimulateTrace,
+            _beneficiaries: &[Address],
+        ) -> Result<Vec<ProfitReport>, Box<dyn Error + 'a>> {
+            Ok(vec![ProfitReport::token(self.0, self.1, self.2)])
+        }
+    }
+
+    #[tokio::test]
+    async fn is_valuable_skip_disabled_analyzer() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None)
+            .await
+            .unwrap()
+            .analyzers(AnalyzerFlags::ERC20);
+        let (token, tx) = (
+            Address::random(),
+            Transaction {
+                from: Address::random(),
+                input: "0x00000001".parse().unwrap(),
+                ..Default::default()
+            },
+        );
+        let token_diff = AccountDiff {
+            balance: Diff::Same,
+            nonce: Diff::Same,
+            code: Diff::Same,
+            // `balanceOf[tx.from]` of an OpenZeppelin token.
+            storage: BTreeMap::from([(
+                H256(keccak256(
+                    [H256::from(tx.from).as_bytes(), H256::zero().as_bytes()].concat(),
+                )),
+                Diff::Born(H256::from_low_u64_be(100)),
+            )]),
+        };
+        let state_diff = StateDiff(BTreeMap::from([
+            (tx.from, balance_diff(U256::zero(), parse_ether(1).unwrap())),
+            (token, token_diff),
+        ]));
+        mock.push(block_trace(vec![], Some(state_diff))).unwrap();
+
+        // The native gain goes unseen.
+        let (_, reports) = simulate
+            .is_valuable(tx.clone(), None, None, &mut SimulateTimings::default())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            reports,
+            vec![ProfitReport::token(tx.from, token, U256::from(100))]
+        );
+    }
+
+    #[tokio::test]
+    async fn is_valuable_report_profit_per_beneficiary() {
+        let (client, mock) = mock_client();
+        let eth_beneficiary = Address::random();
+        let token_beneficiary = Address::random();
+        let token = Address::random();
+        let mut simulate = Simulate::init(&client, None)
+            .await
+            .unwrap()
+            .beneficiaries(vec![eth_beneficiary, token_beneficiary]);
+        simulate.state_analysis.push(Box::new(AnalyzeTokenGain(
+            token_beneficiary,
+            token,
+            U256::from(100),
+        )));
+
+        let state_diff = StateDiff(BTreeMap::from([(
+            eth_beneficiary,
+            balance_diff(U256::zero(), parse_ether(1).unwrap()),
+        )]));
+        mock.push(block_trace(vec![], Some(state_diff))).unwrap();
+        let tx = Transaction {
+            input: "0x00000001".parse().unwrap(),
+            ..Default::default()
+        };
+        let (_, reports) = simulate
+            .is_valuable(tx, None, None, &mut SimulateTimings::default())
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(reports.len(), 2);
+        assert!(reports.contains(&ProfitReport::native(
+            eth_beneficiary,
+            parse_ether(1).unwrap()
+        )));
+        assert!(reports.contains(&ProfitReport::token(
+            token_beneficiary,
+            token,
+            U256::from(100)
+        )));
+        assert_eq!(
+            ProfitReport::total_native(&reports),
+            parse_ether(1).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn is_valuable_infer_biggest_gainer_as_beneficiary() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None)
+            .await
+            .unwrap()
+            .infer_beneficiary(true);
+        let coinbase = Address::random();
+        let contract = Address::random();
+        let state_diff = StateDiff(BTreeMap::from([
+            (
+                coinbase,
+                balance_diff(U256::zero(), parse_ether(5).unwrap()),
+            ),
+            (
+                contract,
+                balance_diff(U256::zero(), parse_ether(2).unwrap()),
+            ),
+            (
+                Address::random(),
+                balance_diff(U256::zero(), parse_ether(1).unwrap()),
+            ),
+        ]));
+
+        mock.push(Block::<TxHash> {
+            author: Some(coinbase),
+            ..Default::default()
+        })
+        .unwrap();
+        mock.push(block_trace(vec![], Some(state_diff))).unwrap();
+        let tx = Transaction {
+            input: "0x00000001".parse().unwrap(),
+            ..Default::default()
+        };
+        let (_, reports) = simulate
+            .is_valuable(tx, None, None, &mut SimulateTimings::default())
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            reports,
+            vec![ProfitReport::native(contract, parse_ether(2).unwrap())]
+        );
+    }
+
+    #[tokio::test]
+    async fn is_valuable_skip_reverted_origin_call() {
+        let reverted: SimulateTrace = serde_json::from_str(include_str!(
+            "../../tests/fixtures/trace_call/reverted.json"
+        ))
+        .unwrap();
+        let tx = Transaction {
+            from: "0x1111111111111111111111111111111111111111"
+                .parse()
+                .unwrap(),
+            nonce: U256::one(),
+            input: "0x00000001".parse().unwrap(),
+            ..Default::default()
+        };
+
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        mock.push(reverted.clone()).unwrap();
+        assert!(simulate
+            .is_valuable(tx.clone(), None, None, &mut SimulateTimings::default())
+            .await
+            .unwrap()
+            .is_none());
+
+        let simulate = simulate.allow_reverted(true);
+        mock.push(reverted).unwrap();
+        assert!(simulate
+            .is_valuable(tx, None, None, &mut SimulateTimings::default())
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn is_valuable_refuse_oversized_trace() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None)
+            .await
+            .unwrap()
+            .max_trace_entries(100);
+        let mut trace = vec![call_trace(vec![], 1000, U256::zero())];
+        trace.extend((0..1000).map(|call| call_trace(vec![call], 0, U256::zero())));
+        mock.push(block_trace(trace, None)).unwrap();
+        let tx = Transaction {
+            input: "0x00000001".parse().unwrap(),
+            ..Default::default()
+        };
+
+        let err = simulate
+            .is_valuable(tx, None, None, &mut SimulateTimings::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            SimulateError::TraceTooLarge {
+                entries: 1001,
+                max_trace_entries: 100
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn to_trace_cache_trace_supported() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        assert_eq!(simulate.trace_supported(), None);
+
+        mock.push(block_trace(vec![], None)).unwrap();
+        simulate
+            .to_trace(&Transaction::default(), None)
+            .await
+            .unwrap();
+        assert_eq!(simulate.trace_supported(), Some(true));
+    }
+
+    #[tokio::test]
+    async fn to_trace_skip_rpc_when_trace_unsupported() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        simulate.trace_supported.set(false).unwrap();
+
+        let err = simulate
+            .to_trace(&Transaction::default(), None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SimulateError::TraceApiUnsupported { .. }));
+        assert!(mock.assert_request("trace_call", ()).is_err());
+    }
+
+    #[tokio::test]
+    async fn warmup_probe_chain_id_block_and_trace() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+
+        mock.push(block_trace(vec![], None)).unwrap();
+        mock.push(U64::from(100)).unwrap();
+        mock.push(U256::one()).unwrap();
+        simulate.warmup().await.unwrap();
+
+        mock.assert_request("eth_chainId", ()).unwrap();
+        mock.assert_request("eth_blockNumber", ()).unwrap();
+        mock.assert_request(
+            "trace_call",
+            serde_json::json!([
+                TypedTransaction::from(&Transaction::default()),
+                ["trace", "stateDiff"],
+                "latest"
+            ]),
+        )
+        .unwrap();
+        assert_eq!(simulate.trace_supported(), Some(true));
+        assert_eq!(simulate.chain_id().await.unwrap(), U256::one());
+    }
 
     #[tokio::test]
     async fn mock_tx_data_return_origin_data() {
@@ -234,4 +1285,238 @@ mod tests {
             format!("0x00000001{}", &format!("{contract:x}"))
         );
     }
+
+    #[tokio::test]
+    async fn to_strategy_queue_fetch_signer_address_once() {
+        let (provider, _) = Provider::mocked();
+        let fetched = Arc::new(AtomicUsize::new(0));
+        let wallet = LocalWallet::new(&mut ethers::core::rand::thread_rng());
+        let signer_address = wallet.address();
+        let client = SignerMiddleware::new(provider, CountingSigner(wallet, fetched.clone()));
+        let simulate = Simulate::init(&client, None).await.unwrap();
+
+        let trace = block_trace(
+            vec![
+                call_trace(vec![], 2, U256::zero()),
+                call_trace(vec![0], 0, U256::zero()),
+                call_trace(vec![1], 0, U256::zero()),
+            ],
+            None,
+        );
+        for _ in 0..2 {
+            for (_, tx_list) in simulate.to_strategy_queue(&trace) {
+                assert!(tx_list.iter().all(|tx| tx.from == Some(signer_address)));
+            }
+        }
+        // Only `SignerMiddleware::new` asked the signer.
+        assert_eq!(fetched.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn to_strategy_queue_skip_internal_calls_after_selfdestruct() {
+        let (client, _) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let destructed = Address::random();
+        let call_to = |trace_address: Vec<usize>, subtraces, to| {
+            let mut trace = call_trace(trace_address, subtraces, U256::zero());
+            if let Action::Call(call) = &mut trace.action {
+                call.to = to;
+            }
+            trace
+        };
+        let selfdestruct = TransactionTrace {
+            action: Action::Suicide(Suicide {
+                address: destructed,
+                refund_address: Address::random(),
+                balance: U256::zero(),
+            }),
+            action_type: ActionType::Suicide,
+            ..call_trace(vec![0, 0], 0, U256::zero())
+        };
+
+        // The second internal call targets the contract the first one destructed.
+        let trace = block_trace(
+            vec![
+                call_to(vec![], 2, Address::random()),
+                call_to(vec![0], 1, destructed),
+                selfdestruct.clone(),
+                call_to(vec![1], 0, destructed),
+            ],
+            None,
+        );
+        let strategies = simulate
+            .to_strategy_queue(&trace)
+            .into_iter()
+            .map(|(strategy, _)| strategy)
+            .collect::<Vec<_>>();
+        assert_eq!(strategies, vec![QueueStrategy::Origin]);
+
+        let trace = block_trace(
+            vec![
+                call_to(vec![], 2, Address::random()),
+                call_to(vec![0], 1, destructed),
+                selfdestruct,
+                call_to(vec![1], 0, Address::random()),
+            ],
+            None,
+        );
+        assert_eq!(simulate.to_strategy_queue(&trace).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn to_strategy_queue_apply_partial_policy() {
+        let (client, _) = mock_client();
+        // No tx sends a selfdestruct.
+        let selfdestruct = TransactionTrace {
+            action: Action::Suicide(Suicide {
+                address: Address::random(),
+                refund_address: Address::random(),
+                balance: U256::zero(),
+            }),
+            action_type: ActionType::Suicide,
+            ..call_trace(vec![1], 0, U256::zero())
+        };
+        let trace = block_trace(
+            vec![
+                call_trace(vec![], 2, U256::zero()),
+                call_trace(vec![0], 0, U256::zero()),
+                selfdestruct,
+            ],
+            None,
+        );
+
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let queue = simulate.to_strategy_queue(&trace);
+        let strategies = queue
+            .iter()
+            .map(|(strategy, tx_list)| (*strategy, tx_list.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            strategies,
+            vec![(QueueStrategy::Origin, 1), (QueueStrategy::Internal, 1)]
+        );
+
+        let simulate = simulate.partial_policy(PartialPolicy::AllOrNothing);
+        assert!(simulate.to_strategy_queue(&trace).is_empty());
+    }
+
+    #[tokio::test]
+    async fn to_tx_attach_value() {
+        let (client, _) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let tx = simulate
+            .to_tx(
+                &call_trace(vec![], 0, parse_ether(1).unwrap()),
+                simulate.signer_address,
+            )
+            .unwrap();
+        assert_eq!(tx.value, Some(parse_ether(1).unwrap()));
+    }
+
+    #[tokio::test]
+    async fn to_tx_leave_value_to_contract_funds() {
+        let (client, _) = mock_client();
+        let simulate = Simulate::init(&client, Some(Address::random()))
+            .await
+            .unwrap()
+            .value_source(ValueSource::ContractFunds);
+        let tx = simulate
+            .to_tx(
+                &call_trace(vec![], 0, parse_ether(1).unwrap()),
+                simulate.signer_address,
+            )
+            .unwrap();
+        assert_eq!(tx.value, None);
+    }
+
+    #[tokio::test]
+    async fn run_skip_queue_over_cap() {
+        let (client, mock) = mock_client();
+        let tx = Transaction {
+            hash: TxHash::random(),
+            input: "0x00000001".parse().unwrap(),
+            ..Default::default()
+        };
+        let mut trace = vec![call_trace(vec![], 200, U256::zero())];
+        trace.extend((0..200).map(|i| call_trace(vec![i], 0, U256::zero())));
+        let state_diff = StateDiff(BTreeMap::from([(
+            tx.from,
+            balance_diff(U256::zero(), U256::from(1)),
+        )]));
+
+        for overflow in [QueueOverflow::Reject, QueueOverflow::Truncate] {
+            let simulate = Simulate::init(&client, None)
+                .await
+                .unwrap()
+                .max_queue_len(50)
+                .queue_overflow(overflow);
+            mock.push(U256::one()).unwrap();
+            mock.push(block_trace(trace.clone(), Some(state_diff.clone())))
+                .unwrap();
+            mock.push(tx.clone()).unwrap();
+
+            let opportunity = simulate.run(tx.hash, false).await.unwrap();
+            match overflow {
+                QueueOverflow::Reject => assert!(opportunity.is_none()),
+                QueueOverflow::Truncate => {
+                    let tx_queue = opportunity.unwrap().tx_queue;
+                    assert_eq!(tx_queue.iter().map(Vec::len).sum::<usize>(), 50);
+                    assert_eq!(tx_queue[0][0].chain_id, Some(U64::one()));
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn run_reject_tx_of_another_chain() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let tx = Transaction {
+            hash: TxHash::random(),
+            input: "0x00000001".parse().unwrap(),
+            chain_id: Some(U256::from(56)),
+            ..Default::default()
+        };
+        let state_diff = StateDiff(BTreeMap::from([(
+            tx.from,
+            balance_diff(U256::zero(), U256::from(1)),
+        )]));
+
+        mock.push(U256::one()).unwrap();
+        mock.push(block_trace(
+            vec![call_trace(vec![], 0, U256::zero())],
+            Some(state_diff),
+        ))
+        .unwrap();
+        mock.push(tx.clone()).unwrap();
+
+        assert!(matches!(
+            simulate.run(tx.hash, false).await,
+            Err(SimulateError::ChainIdMismatch { .. })
+        ));
+    }
+
+    proptest! {
+        #[test]
+        fn flatten_traces_keep_every_trace_once_parents_first(
+            trace_address_list in prop::collection::vec(prop::collection::vec(0_usize..4, 0..4), 0..32)
+        ) {
+            let traces = trace_address_list
+                .into_iter()
+                .map(|trace_address| call_trace(trace_address, 0, U256::zero()))
+                .collect::<Vec<_>>();
+            let flattened = flatten_traces(&traces);
+
+            prop_assert_eq!(flattened.len(), traces.len());
+            for trace in &traces {
+                prop_assert_eq!(flattened.iter().filter(|t| std::ptr::eq(**t, trace)).count(), 1);
+            }
+            for (i, parent) in flattened.iter().enumerate() {
+                for child in &flattened[..i] {
+                    prop_assert!(!(child.trace_address.len() > parent.trace_address.len()
+                        && child.trace_address.starts_with(&parent.trace_address)));
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,121 @@
+use super::{Simulate, SimulateError, TxQueue};
+use ethers::{prelude::*, utils::keccak256};
+use ethers_flashbots::BundleRequest;
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    // The victim's signed tx, to put first in a backrun bundle (`BundleOptions::backrun`). Taken
+    // from the node, or re-encoded from the signature of `tx` if it doesn't serve raw txs. Prefer
+    // `Opportunity::victim_raw_tx` when it is held already.
+    pub async fn victim_raw_tx(&self, tx: &Transaction) -> Result<Bytes, SimulateError> {
+        let raw = self
+            .inner
+            .provider()
+            .request::<_, Option<Bytes>>("eth_getRawTransactionByHash", [tx.hash])
+            .await;
+        if let Ok(Some(raw)) = raw {
+            if !raw.is_empty() {
+                return Ok(raw);
+            }
+        }
+
+        // An unsigned or altered tx wouldn't be the one the chain knows.
+        let raw = tx.rlp();
+        if H256(keccak256(&raw)) != tx.hash {
+            return Err(SimulateError::RawTxDecode(format!(
+                "re-encoded tx doesn't hash to {:?}",
+                tx.hash
+            )));
+        }
+        Ok(raw)
+    }
+
+    // A bundle of the victim's signed tx, byte for byte, followed by the queue signed with the
+    // client's signer, for a victim seen before it is mined. The caller sets the target block.
+    pub async fn bundle_with_victim(
+        &self,
+        victim: &Transaction,
+        queue: &TxQueue,
+    ) -> Result<BundleRequest, SimulateError> {
+        let mut bundle = BundleRequest::new().push_transaction(self.victim_raw_tx(victim).await?);
+        for raw_tx in queue.sign_with(self.inner.signer()).await? {
+            bundle = bundle.push_transaction(raw_tx);
+        }
+        Ok(bundle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{decode_raw_tx, mock::mock_client, Simulate, SimulateError, TxQueue};
+    use ethers::{
+        core::rand::thread_rng, prelude::*, types::transaction::eip2718::TypedTransaction,
+    };
+
+    #[tokio::test]
+    async fn victim_raw_tx_fall_back_to_re_encoding() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let wallet = LocalWallet::new(&mut thread_rng()).with_chain_id(1_u64);
+        let tx: TypedTransaction = TransactionRequest::new()
+            .from(wallet.address())
+            .to(Address::random())
+            .nonce(3)
+            .gas(21000)
+            .gas_price(10)
+            .value(1)
+            .chain_id(1)
+            .into();
+        let raw = tx.rlp_signed(&wallet.sign_transaction(&tx).await.unwrap());
+        let victim = decode_raw_tx(&raw).unwrap();
+
+        mock.push(raw.clone()).unwrap();
+        assert_eq!(simulate.victim_raw_tx(&victim).await.unwrap(), raw);
+        mock.assert_request("eth_getRawTransactionByHash", [victim.hash])
+            .unwrap();
+
+        // Not known to the node.
+        mock.push(Option::<Bytes>::None).unwrap();
+        assert_eq!(simulate.victim_raw_tx(&victim).await.unwrap(), raw);
+
+        // Without its signature the tx can't be rebuilt.
+        mock.push(Option::<Bytes>::None).unwrap();
+        let unsigned = Transaction {
+            r: U256::zero(),
+            s: U256::zero(),
+            ..victim
+        };
+        assert!(matches!(
+            simulate.victim_raw_tx(&unsigned).await,
+            Err(SimulateError::RawTxDecode(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn bundle_with_victim_put_victim_raw_tx_first() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let wallet = LocalWallet::new(&mut thread_rng()).with_chain_id(1_u64);
+        let tx: TypedTransaction = TransactionRequest::new()
+            .from(wallet.address())
+            .to(Address::random())
+            .nonce(7)
+            .gas(21000)
+            .gas_price(10)
+            .chain_id(1)
+            .into();
+        let raw = tx.rlp_signed(&wallet.sign_transaction(&tx).await.unwrap());
+        let victim = decode_raw_tx(&raw).unwrap();
+        let queue = TxQueue::from(vec![TransactionRequest::new()
+            .to(Address::random())
+            .nonce(0)
+            .gas(100000)
+            .gas_price(10)]);
+
+        mock.push(raw.clone()).unwrap();
+        let bundle = simulate.bundle_with_victim(&victim, &queue).await.unwrap();
+
+        let txs = serde_json::to_value(&bundle).unwrap()["txs"].clone();
+        let own = queue.sign_with(client.signer()).await.unwrap();
+        assert_eq!(txs, serde_json::json!([raw, own[0]]));
+    }
+}
@@ -0,0 +1,633 @@
+use super::{
+    dialect::normalize_trace, error::is_method_unavailable, Opportunity, Simulate, SimulateError,
+    SimulateTarget, SimulateTimings, SimulateTrace,
+};
+use crate::utils::retry_after;
+use async_trait::async_trait;
+use ethers::{
+    core::rand::{thread_rng, Rng},
+    prelude::*,
+};
+use reqwest::{header::HeaderMap, StatusCode};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{info_span, Instrument};
+use url::Url;
+
+pub(crate) const DEFAULT_BATCH_SIZE: usize = 10;
+
+// Sends several json-rpc requests in one round trip, `Middleware` has no batch support.
+#[async_trait]
+pub trait BatchTransport: Send + Sync {
+    // The responses may come back in any order, they are matched by `id`.
+    async fn send_batch(&self, requests: Vec<Value>) -> Result<Vec<Value>, SimulateError>;
+}
+
+// Batch over plain http, most rpc providers accept a json array as body. Also a `JsonRpcClient`,
+// so single requests through a `Provider` over it are retried on a 429 the same way.
+#[derive(Debug)]
+pub struct HttpBatch {
+    client: reqwest::Client,
+    url: Url,
+    max_retries: u32,
+    backoff: Duration,
+    max_delay: Duration,
+}
+
+impl HttpBatch {
+    pub fn new(url: Url) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url,
+            max_retries: 3,
+            backoff: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+
+    // How often a rate limited (429) batch is sent again before giving up.
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    // First delay after a 429 without `Retry-After`, doubled on every further attempt.
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    // The longest wait before a retry, whatever `Retry-After` asks for.
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    // Post `body`, again after the delay of every 429 up to `max_retries` times.
+    async fn post<B: Serialize + ?Sized>(
+        &self,
+        body: &B,
+    ) -> Result<reqwest::Response, SimulateError> {
+        let mut attempt = 0;
+        loop {
+            let response = self
+                .client
+                .post(self.url.clone())
+                .json(body)
+                .send()
+                .await
+                .map_err(SimulateError::middleware)?;
+            if response.status() == StatusCode::TOO_MANY_REQUESTS && attempt < self.max_retries {
+                let delay = retry_delay(response.headers(), attempt, self.backoff, self.max_delay);
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+                continue;
+            }
+            return response
+                .error_for_status()
+                .map_err(SimulateError::middleware);
+        }
+    }
+}
+
+#[async_trait]
+impl BatchTransport for HttpBatch {
+    async fn send_batch(&self, requests: Vec<Value>) -> Result<Vec<Value>, SimulateError> {
+        self.post(&requests)
+            .await?
+            // A node without batch support answers with a single error object, which fails here.
+            .json::<Vec<Value>>()
+            .await
+            .map_err(SimulateError::middleware)
+    }
+}
+
+#[async_trait]
+impl JsonRpcClient for HttpBatch {
+    type Error = SimulateError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, SimulateError>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned,
+    {
+        let params = serde_json::to_value(params).map_err(SimulateError::middleware)?;
+        let response = self
+            .post(&request(0, method, params))
+            .await?
+            .json::<Value>()
+            .await
+            .map_err(SimulateError::middleware)?;
+        let result = into_result(response).map_err(SimulateError::Middleware)?;
+        serde_json::from_value(result).map_err(SimulateError::middleware)
+    }
+}
+
+// The delay the provider asked for in `Retry-After`, in seconds or as an http-date, the
+// exponential backoff if it didn't say. Never more than `max_delay`.
+fn retry_delay(
+    headers: &HeaderMap,
+    attempt: u32,
+    backoff: Duration,
+    max_delay: Duration,
+) -> Duration {
+    headers
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| retry_after(value, SystemTime::now()))
+        .or_else(|| backoff.checked_mul(2_u32.saturating_pow(attempt)))
+        .unwrap_or(max_delay)
+        .min(max_delay)
+}
+
+fn request(id: usize, method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+}
+
+fn into_result(mut response: Value) -> Result<Value, String> {
+    match response.get_mut("error") {
+        Some(error) => Err(error.to_string()),
+        None => Ok(response
+            .get_mut("result")
+            .map(Value::take)
+            .unwrap_or_default()),
+    }
+}
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    // Fetch the tx and its trace in one json-rpc batch instead of two sequential round trips.
+    // Only used for `SimulateTarget::Rewind`, falls back to sequential calls on any batch error.
+    pub fn batch_transport<T: BatchTransport + 'a>(mut self, transport: T) -> Self {
+        self.batch_transport = Some(Box::new(transport));
+        self
+    }
+
+    // How many candidates `run_many` puts in one batch.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    // Wait `delay` and a random part of `jitter` between successive traces of `run_many` when it
+    // doesn't batch, to stay below the rate limit of the node.
+    pub fn call_delay(mut self, delay: Duration, jitter: Duration) -> Self {
+        self.call_delay = delay;
+        self.call_jitter = jitter;
+        self
+    }
+
+    // Nothing before the first call of a scan.
+    pub(super) async fn pace(&self, call_index: usize) {
+        if call_index == 0 || (self.call_delay.is_zero() && self.call_jitter.is_zero()) {
+            return;
+        }
+        let jitter = thread_rng().gen_range(0..=self.call_jitter.as_nanos() as u64);
+        tokio::time::sleep(self.call_delay + Duration::from_nanos(jitter)).await;
+    }
+
+    // `run` for every tx hash, results are in the same order as `tx_hashes`.
+    pub async fn run_many(
+        &self,
+        tx_hashes: &[TxHash],
+        target: impl Into<SimulateTarget>,
+    ) -> Vec<Result<Option<Opportunity>, SimulateError>> {
+        let target = target.into();
+        let mut results = Vec::with_capacity(tx_hashes.len());
+        match (&self.batch_transport, target) {
+            (Some(transport), SimulateTarget::Rewind) => {
+                for chunk in tx_hashes.chunks(self.batch_size) {
+                    let chunk_results = self.run_batch(transport.as_ref(), chunk).await;
+                    #[cfg(feature = "metrics")]
+                    chunk_results
+                        .iter()
+                        .for_each(|result| super::metrics::METRICS.record(result));
+                    results.extend(chunk_results);
+                }
+            }
+            _ => {
+                for (i, tx_hash) in tx_hashes.iter().enumerate() {
+                    self.pace(i).await;
+                    results.push(self.run(*tx_hash, target).await);
+                }
+            }
+        }
+        results
+    }
+
+    // `trace_replayTransaction` traces the tx on the state right before it, so unlike `trace_call`
+    // it doesn't need the tx body and can go in the same batch as `eth_getTransactionByHash`.
+    // That state is `Rewind`'s (the block before) only for the first tx of its block, a later one
+    // ran after the txs ahead of it and is traced again with `trace_call`, see `run_batched_tx`.
+    pub(crate) async fn run_batch(
+        &self,
+        transport: &(dyn BatchTransport + 'a),
+        tx_hashes: &[TxHash],
+    ) -> Vec<Result<Option<Opportunity>, SimulateError>> {
+        let requests = tx_hashes
+            .iter()
+            .enumerate()
+            .flat_map(|(i, tx_hash)| {
+                [
+                    request(2 * i, "eth_getTransactionByHash", json!([tx_hash])),
+                    request(
+                        2 * i + 1,
+                        "trace_replayTransaction",
+                        json!([tx_hash, ["trace", "stateDiff"]]),
+                    ),
+                ]
+            })
+            .collect();
+
+        let start = Instant::now();
+        let responses = match transport
+            .send_batch(requests)
+            .instrument(info_span!("fetch"))
+            .await
+        {
+            Ok(responses) => responses,
+            Err(_) => return self.run_sequential(tx_hashes).await,
+        };
+        let elapsed = start.elapsed();
+
+        let mut responses = responses
+            .into_iter()
+            .filter_map(|response| {
+                let id = response.get("id")?.as_u64()? as usize;
+                Some((id, into_result(response)))
+            })
+            .collect::<HashMap<_, _>>();
+
+        let mut results = Vec::with_capacity(tx_hashes.len());
+        for (i, tx_hash) in tx_hashes.iter().enumerate() {
+            let (tx, trace) = match (responses.remove(&(2 * i)), responses.remove(&(2 * i + 1))) {
+                (Some(tx), Some(trace)) => (tx, trace),
+                // The batch was cut short, get this one on its own.
+                _ => {
+                    results.extend(self.run_sequential(&[*tx_hash]).await);
+                    continue;
+                }
+            };
+            let timings = SimulateTimings {
+                fetch: elapsed,
+                round_trips: 1,
+                ..Default::default()
+            };
+            results.push(self.run_batched_tx(tx, trace, timings).await);
+        }
+        results
+    }
+
+    async fn run_batched_tx(
+        &self,
+        tx: Result<Value, String>,
+        trace: Result<Value, String>,
+        timings: SimulateTimings,
+    ) -> Result<Option<Opportunity>, SimulateError> {
+        let tx = match tx.map_err(SimulateError::Middleware)? {
+            Value::Null => return Ok(None),
+            tx => serde_json::from_value::<Transaction>(tx).map_err(SimulateError::middleware)?,
+        };
+
+        match trace {
+            // Another round trip, but the opportunity is on the block it reports.
+            Ok(_) if tx.transaction_index != Some(U64::zero()) => {
+                self.run_tx(tx, SimulateTarget::Rewind, None, timings).await
+            }
+            Ok(mut trace) => {
+                normalize_trace(&mut trace);
+                let trace = serde_json::from_value::<SimulateTrace>(trace)
+                    .map_err(SimulateError::middleware)?;
+                let _ = self.trace_supported.set(true);
+                self.run_tx(tx, SimulateTarget::Rewind, Some(trace), timings)
+                    .await
+            }
+            Err(e) if is_method_unavailable(&e) => {
+                let _ = self.trace_supported.set(false);
+                Err(SimulateError::TraceApiUnsupported { provider_hint: e })
+            }
+            // e.g. a pending tx can't be replayed, trace it with `trace_call` instead.
+            Err(_) => self.run_tx(tx, SimulateTarget::Rewind, None, timings).await,
+        }
+    }
+
+    async fn run_sequential(
+        &self,
+        tx_hashes: &[TxHash],
+    ) -> Vec<Result<Option<Opportunity>, SimulateError>> {
+        let mut results = Vec::with_capacity(tx_hashes.len());
+        for (i, tx_hash) in tx_hashes.iter().enumerate() {
+            self.pace(i).await;
+            results.push(self.fetch_and_run(*tx_hash, SimulateTarget::Rewind).await);
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        mock::{balance_diff, block_trace, call_trace, mock_client},
+        ProfitReport, Simulate, SimulateError,
+    };
+    use super::{retry_delay, BatchTransport, HttpBatch};
+    use async_trait::async_trait;
+    use ethers::prelude::*;
+    use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+    use serde_json::{json, Value};
+    use std::collections::BTreeMap;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+    use url::Url;
+
+    // The answers of a node that has mined `tx_list`, each trace credits the tx's sender with 1.
+    fn batch_responses(tx_list: &[Transaction], requests: &[Value]) -> Vec<Value> {
+        requests
+            .iter()
+            .map(|request| {
+                let tx_hash =
+                    serde_json::from_value::<TxHash>(request["params"][0].clone()).unwrap();
+                let tx = tx_list.iter().find(|tx| tx.hash == tx_hash).unwrap();
+                let result = match request["method"].as_str().unwrap() {
+                    "eth_getTransactionByHash" => json!(tx),
+                    _ => json!(block_trace(
+                        vec![call_trace(vec![], 0, U256::zero())],
+                        Some(StateDiff(BTreeMap::from([(
+                            tx.from,
+                            balance_diff(U256::zero(), U256::from(1)),
+                        )]))),
+                    )),
+                };
+                json!({ "jsonrpc": "2.0", "id": request["id"], "result": result })
+            })
+            .collect()
+    }
+
+    // Answers every batch from the given tx list, records each batch it is sent.
+    #[derive(Clone, Default)]
+    struct MockBatch {
+        tx_list: Vec<Transaction>,
+        sent: Arc<Mutex<Vec<Vec<Value>>>>,
+    }
+
+    #[async_trait]
+    impl BatchTransport for MockBatch {
+        async fn send_batch(&self, requests: Vec<Value>) -> Result<Vec<Value>, SimulateError> {
+            let mut responses = batch_responses(&self.tx_list, &requests);
+            // Out of order on purpose.
+            responses.reverse();
+            self.sent.lock().unwrap().push(requests);
+            Ok(responses)
+        }
+    }
+
+    // The body of the http request on `reader`, after its headers.
+    fn read_body(reader: &mut BufReader<TcpStream>) -> Vec<u8> {
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap();
+                }
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+        body
+    }
+
+    // Serve a single http request with the answers to the batch in its body. The listener is
+    // handed back with the batch, a connection waiting on it is another request.
+    fn http_batch(
+        tx_list: Vec<Transaction>,
+    ) -> (Url, thread::JoinHandle<(TcpListener, Vec<Value>)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let requests = serde_json::from_slice::<Vec<Value>>(&read_body(&mut reader)).unwrap();
+
+            let response = json!(batch_responses(&tx_list, &requests)).to_string();
+            write!(
+                reader.get_mut(),
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{response}",
+                response.len()
+            )
+            .unwrap();
+            (listener, requests)
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn retry_delay_honor_retry_after() {
+        let (backoff, max_delay) = (Duration::from_millis(500), Duration::from_secs(30));
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_delay(&headers, 0, backoff, max_delay), backoff);
+        assert_eq!(
+            retry_delay(&headers, 2, backoff, max_delay),
+            Duration::from_secs(2)
+        );
+        assert_eq!(retry_delay(&headers, 40, backoff, max_delay), max_delay);
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("7"));
+        assert_eq!(
+            retry_delay(&headers, 2, backoff, max_delay),
+            Duration::from_secs(7)
+        );
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("86400"));
+        assert_eq!(retry_delay(&headers, 2, backoff, max_delay), max_delay);
+
+        // Passed already, retried at once.
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(retry_delay(&headers, 1, backoff, max_delay), Duration::ZERO);
+    }
+
+    // Answer the first request with a 429 and `Retry-After: 0`, the second with `result`.
+    fn rate_limited_node(result: Value) -> (Url, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+        let handle = thread::spawn(move || {
+            for attempt in 0..2 {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let request = serde_json::from_slice::<Value>(&read_body(&mut reader)).unwrap();
+
+                let response = match attempt {
+                    0 => "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 0\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+                    _ => {
+                        let body = json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }).to_string();
+                        format!(
+                            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                            body.len()
+                        )
+                    }
+                };
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+        });
+        (url, handle)
+    }
+
+    #[tokio::test]
+    async fn request_retry_after_rate_limit() {
+        let (url, node) = rate_limited_node(json!("0x2a"));
+        let provider = Provider::new(HttpBatch::new(url).backoff(Duration::from_secs(60)));
+
+        // `Retry-After` says go on at once, not after the minute of backoff.
+        let block_number = provider.get_block_number().await.unwrap();
+        assert_eq!(block_number, U64::from(42));
+        node.join().unwrap();
+    }
+
+    fn mined_tx() -> Transaction {
+        Transaction {
+            hash: TxHash::random(),
+            from: Address::random(),
+            block_number: Some(U64::from(100)),
+            transaction_index: Some(U64::zero()),
+            input: "0x00000001".parse().unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn run_fetch_and_trace_in_one_batch() {
+        let (client, mock) = mock_client();
+        let tx = mined_tx();
+        let (url, node) = http_batch(vec![tx.clone()]);
+        let simulate = Simulate::init(&client, None)
+            .await
+            .unwrap()
+            .batch_transport(HttpBatch::new(url));
+        mock.push(U256::one()).unwrap();
+
+        let opportunity = simulate.run(tx.hash, true).await.unwrap().unwrap();
+        assert_eq!(opportunity.timings.round_trips, 1);
+        assert_eq!(opportunity.block, Some(BlockNumber::Number(U64::from(99))));
+        let (listener, batch) = node.join().unwrap();
+        let methods = batch
+            .iter()
+            .map(|request| request["method"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            methods,
+            ["eth_getTransactionByHash", "trace_replayTransaction"]
+        );
+        listener.set_nonblocking(true).unwrap();
+        assert!(listener.accept().is_err());
+        // Nothing went through the regular client.
+        assert!(mock
+            .assert_request("eth_getTransactionByHash", [tx.hash])
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn run_batch_retrace_later_tx_of_block() {
+        let (client, mock) = mock_client();
+        let tx = Transaction {
+            transaction_index: Some(U64::from(3)),
+            ..mined_tx()
+        };
+        let transport = MockBatch {
+            tx_list: vec![tx.clone()],
+            ..Default::default()
+        };
+        let simulate = Simulate::init(&client, None)
+            .await
+            .unwrap()
+            .batch_transport(transport);
+
+        // The replay ran after the three txs ahead of it, `trace_call` on the block before
+        // credits the sender with 5 instead.
+        mock.push(U256::one()).unwrap();
+        mock.push(block_trace(
+            vec![call_trace(vec![], 0, U256::zero())],
+            Some(StateDiff(BTreeMap::from([(
+                tx.from,
+                balance_diff(U256::zero(), U256::from(5)),
+            )]))),
+        ))
+        .unwrap();
+
+        let opportunity = simulate.run(tx.hash, true).await.unwrap().unwrap();
+        assert_eq!(opportunity.block, Some(BlockNumber::Number(U64::from(99))));
+        assert_eq!(
+            opportunity.reports,
+            vec![ProfitReport::native(tx.from, U256::from(5))]
+        );
+        assert_eq!(opportunity.timings.round_trips, 2);
+    }
+
+    #[tokio::test]
+    async fn run_many_chunk_by_batch_size() {
+        let (client, mock) = mock_client();
+        let tx_list = vec![mined_tx(), mined_tx(), mined_tx()];
+        let transport = MockBatch {
+            tx_list: tx_list.clone(),
+            ..Default::default()
+        };
+        let simulate = Simulate::init(&client, None)
+            .await
+            .unwrap()
+            .batch_transport(transport.clone())
+            .batch_size(2);
+        // The chain id is fetched once for all of them.
+        mock.push(U256::one()).unwrap();
+
+        let tx_hashes = tx_list.iter().map(|tx| tx.hash).collect::<Vec<_>>();
+        let results = simulate.run_many(&tx_hashes, true).await;
+
+        assert_eq!(results.len(), 3);
+        for (result, tx) in results.into_iter().zip(&tx_list) {
+            let opportunity = result.unwrap().unwrap();
+            assert_eq!(opportunity.reports[0].beneficiary, tx.from);
+        }
+        let sent = transport.sent.lock().unwrap();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].len(), 4);
+        assert_eq!(sent[1].len(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn run_many_pace_sequential_calls() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None)
+            .await
+            .unwrap()
+            .call_delay(Duration::from_millis(100), Duration::from_millis(50));
+        // None of them is known.
+        for _ in 0..3 {
+            mock.push(Option::<Transaction>::None).unwrap();
+        }
+
+        // The paused clock only moves with the sleeps.
+        let start = tokio::time::Instant::now();
+        let results = simulate
+            .run_many(
+                &[TxHash::random(), TxHash::random(), TxHash::random()],
+                true,
+            )
+            .await;
+        let elapsed = start.elapsed();
+
+        assert!(results.iter().all(|result| matches!(result, Ok(None))));
+        // Between the three calls, not before the first.
+        assert!(elapsed >= Duration::from_millis(200));
+        assert!(elapsed <= Duration::from_millis(300));
+    }
+}
@@ -0,0 +1,193 @@
+use super::{ProfitReport, Simulate, SimulateError, SimulateTimings, SimulateTrace};
+use ethers::prelude::*;
+use serde_json::json;
+
+// What one tx of the block made, see `Simulate::analyze_block`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxMevReport {
+    pub tx_hash: TxHash,
+    // As `run` would report them, empty for a tx without profit.
+    pub reports: Vec<ProfitReport>,
+    // Sent straight to the coinbase by the tx's calls, the priority fee not included.
+    pub coinbase_bribe: U256,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockMevReport {
+    pub block: U64,
+    pub coinbase: Address,
+    // In block order, one per tx.
+    pub transactions: Vec<TxMevReport>,
+    pub total_bribes: U256,
+    // The native profit of every tx.
+    pub total_profit: U256,
+}
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    // The MEV of a whole block: every tx analyzed on the state the txs ahead of it left, from a
+    // single `trace_replayBlockTransactions`, with what they paid the coinbase directly.
+    pub async fn analyze_block(&self, block: BlockNumber) -> Result<BlockMevReport, SimulateError> {
+        let block = self
+            .get_block_with_txs(block)
+            .await
+            .map_err(SimulateError::middleware)?
+            .ok_or(SimulateError::BlockNotFound(block.into()))?;
+        let number = block
+            .number
+            .ok_or(SimulateError::MissingBlockField("number"))?;
+        let coinbase = block.author.unwrap_or_default();
+
+        let traces = self
+            .tracer()
+            .request(
+                "trace_replayBlockTransactions",
+                json!([BlockNumber::Number(number), ["trace", "stateDiff"]]),
+            )
+            .await?;
+        let traces = serde_json::from_value::<Vec<SimulateTrace>>(traces)
+            .map_err(SimulateError::middleware)?;
+        if traces.len() != block.transactions.len() {
+            return Err(SimulateError::TraceCountMismatch {
+                expected: block.transactions.len(),
+                traces: traces.len(),
+            });
+        }
+
+        let parent = Some(BlockNumber::Number(number.saturating_sub(U64::one())));
+        let mut transactions = Vec::with_capacity(traces.len());
+        for (tx, trace) in block.transactions.into_iter().zip(traces) {
+            let tx_hash = tx.hash;
+            let coinbase_bribe = coinbase_transfers(&trace, coinbase);
+            // A tx whose trace can't be analyzed (e.g. too large) just has no profit.
+            let reports = self
+                .is_valuable(tx, parent, Some(trace), &mut SimulateTimings::default())
+                .await
+                .ok()
+                .flatten()
+                .map(|(_, reports)| reports)
+                .unwrap_or_default();
+            transactions.push(TxMevReport {
+                tx_hash,
+                reports,
+                coinbase_bribe,
+            });
+        }
+
+        Ok(BlockMevReport {
+            block: number,
+            coinbase,
+            total_bribes: transactions.iter().fold(U256::zero(), |total, tx| {
+                total.saturating_add(tx.coinbase_bribe)
+            }),
+            total_profit: transactions.iter().fold(U256::zero(), |total, tx| {
+                total.saturating_add(ProfitReport::total_native(&tx.reports))
+            }),
+            transactions,
+        })
+    }
+
+    // The trace of a mined tx on the state it was included at.
+    pub(crate) async fn replay_transaction(
+        &self,
+        tx_hash: TxHash,
+    ) -> Result<SimulateTrace, SimulateError> {
+        let trace = self
+            .tracer()
+            .request(
+                "trace_replayTransaction",
+                json!([tx_hash, ["trace", "stateDiff"]]),
+            )
+            .await?;
+        serde_json::from_value(trace).map_err(SimulateError::middleware)
+    }
+}
+
+// Value the successful calls of the trace sent to `coinbase`.
+fn coinbase_transfers(trace: &SimulateTrace, coinbase: Address) -> U256 {
+    trace
+        .trace
+        .iter()
+        .flatten()
+        .filter(|call| call.error.is_none())
+        .filter_map(|call| match &call.action {
+            Action::Call(data) if data.to == coinbase => Some(data.value),
+            _ => None,
+        })
+        .fold(U256::zero(), |total, value| total.saturating_add(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        mock::{balance_diff, block_trace, call_trace, mock_client},
+        ProfitReport, Simulate,
+    };
+    use ethers::prelude::*;
+    use std::collections::BTreeMap;
+
+    #[tokio::test]
+    async fn analyze_block_sum_profits_and_bribes() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let coinbase = Address::random();
+        let tx_list = (0..3)
+            .map(|_| Transaction {
+                hash: TxHash::random(),
+                from: Address::random(),
+                block_number: Some(U64::from(100)),
+                input: "0x00000001".parse().unwrap(),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        let gain = |tx: &Transaction, amount: u64| {
+            Some(StateDiff(BTreeMap::from([(
+                tx.from,
+                balance_diff(U256::zero(), U256::from(amount)),
+            )])))
+        };
+        let mut bribe = call_trace(vec![0], 0, U256::from(30));
+        if let Action::Call(call) = &mut bribe.action {
+            call.to = coinbase;
+        }
+        let traces = vec![
+            block_trace(
+                vec![call_trace(vec![], 1, U256::zero()), bribe],
+                gain(&tx_list[0], 1000),
+            ),
+            // A plain tx without profit.
+            block_trace(vec![call_trace(vec![], 0, U256::zero())], None),
+            block_trace(
+                vec![call_trace(vec![], 0, U256::zero())],
+                gain(&tx_list[2], 500),
+            ),
+        ];
+        let block = Block::<Transaction> {
+            number: Some(U64::from(100)),
+            author: Some(coinbase),
+            transactions: tx_list.clone(),
+            ..Default::default()
+        };
+
+        mock.push::<Vec<BlockTrace>, _>(traces).unwrap();
+        mock.push(block).unwrap();
+
+        let report = simulate
+            .analyze_block(BlockNumber::Number(U64::from(100)))
+            .await
+            .unwrap();
+        assert_eq!(report.block, U64::from(100));
+        assert_eq!(report.total_profit, U256::from(1500));
+        assert_eq!(report.total_bribes, U256::from(30));
+        assert_eq!(report.transactions.len(), 3);
+        assert_eq!(
+            report.transactions[0].reports,
+            vec![ProfitReport::native(tx_list[0].from, U256::from(1000))]
+        );
+        assert_eq!(report.transactions[0].coinbase_bribe, U256::from(30));
+        assert!(report.transactions[1].reports.is_empty());
+        assert_eq!(report.transactions[2].tx_hash, tx_list[2].hash);
+
+        mock.assert_request("eth_getBlockByNumber", ("0x64", true))
+            .unwrap();
+    }
+}
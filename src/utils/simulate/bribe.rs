@@ -0,0 +1,218 @@
+use super::{FeeBudget, QueueEntry, TxQueue};
+use ethers::prelude::*;
+
+// Gas of a call to the helper contract that forwards its value to `block.coinbase`,
+// the coinbase is warm since EIP-3651 so it stays close to a plain transfer.
+const COINBASE_BRIBE_GAS: u64 = 35000;
+
+// How the builder is paid for including the queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BribeMethod {
+    // A tx to `contract`, whose fallback sends the attached value to `block.coinbase`.
+    Coinbase { contract: Address },
+    // A higher tip on the last entry, no extra tx but the builder only sees it per gas.
+    PriorityFee,
+}
+
+impl TxQueue {
+    // Pay the builder `percent_of_profit` percent of `profit`, minus what the gas of the queue
+    // already costs at its fees, so bribe and gas together never exceed that share, nor the
+    // `budget` of the queue when given. Run it after `fill_gas` / `fill_fees`, the bribe goes
+    // after the last (profit-realizing) entry.
+    // Returns the bribe, `None` if the gas alone eats up the share, or the nonce or fees would
+    // overflow, and nothing was added.
+    pub fn append_bribe(
+        &mut self,
+        percent_of_profit: u32,
+        profit: U256,
+        method: BribeMethod,
+        budget: Option<&FeeBudget>,
+    ) -> Option<U256> {
+        let last = self.entries.last()?;
+        // The bribe tx pays the fees of the last entry.
+        let bribe_fee_per_gas = fee_per_gas(last);
+        let share = profit.checked_mul(U256::from(percent_of_profit.min(100)))? / 100;
+        let share = match budget {
+            Some(budget) => share.min(budget.budget),
+            None => share,
+        };
+        let gas_cost = self
+            .entries
+            .iter()
+            .filter_map(|entry| {
+                Some(
+                    entry
+                        .tx
+                        .gas
+                        .or(entry.trace_gas)?
+                        .saturating_mul(fee_per_gas(entry)),
+                )
+            })
+            .fold(U256::zero(), |total, cost| total.saturating_add(cost));
+
+        match method {
+            BribeMethod::Coinbase { contract } => {
+                let gas = U256::from(COINBASE_BRIBE_GAS);
+                let bribe = share
+                    .checked_sub(gas_cost.saturating_add(gas.saturating_mul(bribe_fee_per_gas)))?;
+                if bribe.is_zero() {
+                    return None;
+                }
+
+                let mut tx = TransactionRequest::new().to(contract).value(bribe).gas(gas);
+                tx.from = last.tx.from;
+                tx.chain_id = last.tx.chain_id;
+                tx.gas_price = last.tx.gas_price;
+                tx.nonce = match last.tx.nonce {
+                    Some(nonce) => Some(nonce.checked_add(U256::one())?),
+                    None => None,
+                };
+                let entry = QueueEntry {
+                    max_fee_per_gas: last.max_fee_per_gas,
+                    max_priority_fee_per_gas: last.max_priority_fee_per_gas,
+                    ..QueueEntry::from(tx)
+                };
+                self.entries.push(entry);
+                Some(bribe)
+            }
+            BribeMethod::PriorityFee => {
+                let last_gas = last
+                    .tx
+                    .gas
+                    .or(last.trace_gas)
+                    .filter(|gas| !gas.is_zero())?;
+                // Rounded down, the tip is paid per gas of the last entry.
+                let tip = share.checked_sub(gas_cost)? / last_gas;
+                if tip.is_zero() {
+                    return None;
+                }
+
+                let last = self.entries.last_mut()?;
+                match last.max_fee_per_gas {
+                    Some(max_fee_per_gas) => {
+                        let max_fee_per_gas = max_fee_per_gas.checked_add(tip)?;
+                        let max_priority_fee_per_gas = last
+                            .max_priority_fee_per_gas
+                            .unwrap_or_default()
+                            .checked_add(tip)?;
+                        last.max_fee_per_gas = Some(max_fee_per_gas);
+                        last.max_priority_fee_per_gas = Some(max_priority_fee_per_gas);
+                    }
+                    None => {
+                        last.tx.gas_price =
+                            Some(last.tx.gas_price.unwrap_or_default().checked_add(tip)?)
+                    }
+                }
+                Some(tip * last_gas)
+            }
+        }
+    }
+}
+
+// The most an entry can pay per gas at its fees.
+pub(crate) fn fee_per_gas(entry: &QueueEntry) -> U256 {
+    entry
+        .max_fee_per_gas
+        .or(entry.tx.gas_price)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BribeMethod, COINBASE_BRIBE_GAS};
+    use crate::utils::TxQueue;
+    use ethers::prelude::*;
+
+    fn tx_queue(from: Address) -> TxQueue {
+        TxQueue::from(vec![
+            TransactionRequest::new()
+                .from(from)
+                .nonce(1)
+                .gas(100000)
+                .gas_price(10),
+            TransactionRequest::new()
+                .from(from)
+                .nonce(2)
+                .gas(200000)
+                .gas_price(10),
+        ])
+    }
+
+    #[test]
+    fn append_coinbase_bribe_after_last_entry() {
+        let from = Address::random();
+        let contract = Address::random();
+        let mut tx_queue = tx_queue(from);
+
+        // 50% of 10000000 wei, less 300000 gas at 10 for the queue and the bribe tx's own gas.
+        let bribe = tx_queue
+            .append_bribe(
+                50,
+                U256::from(10_000_000),
+                BribeMethod::Coinbase { contract },
+                None,
+            )
+            .unwrap();
+        assert_eq!(
+            bribe,
+            U256::from(5_000_000 - 3_000_000 - COINBASE_BRIBE_GAS * 10)
+        );
+
+        assert_eq!(tx_queue.entries.len(), 3);
+        let entry = &tx_queue.entries[2];
+        assert_eq!(entry.tx.to, Some(contract.into()));
+        assert_eq!(entry.tx.value, Some(bribe));
+        assert_eq!(entry.tx.from, Some(from));
+        assert_eq!(entry.tx.nonce, Some(U256::from(3)));
+        assert_eq!(entry.tx.gas_price, Some(U256::from(10)));
+    }
+
+    #[test]
+    fn append_priority_fee_bribe_within_share() {
+        let mut tx_queue = tx_queue(Address::random());
+
+        // 5000000 wei share, 3000000 for the gas, 2000000 over the 200000 gas of the last entry.
+        let bribe = tx_queue
+            .append_bribe(50, U256::from(10_000_000), BribeMethod::PriorityFee, None)
+            .unwrap();
+        assert_eq!(bribe, U256::from(2_000_000));
+        assert_eq!(tx_queue.entries.len(), 2);
+        assert_eq!(tx_queue.entries[0].tx.gas_price, Some(U256::from(10)));
+        assert_eq!(tx_queue.entries[1].tx.gas_price, Some(U256::from(20)));
+
+        // The gas already costs more than 20% of the profit.
+        let mut tx_queue = TxQueue::from(tx_queue.tx_list());
+        assert!(tx_queue
+            .append_bribe(20, U256::from(10_000_000), BribeMethod::PriorityFee, None)
+            .is_none());
+        assert!(tx_queue
+            .append_bribe(
+                20,
+                U256::from(10_000_000),
+                BribeMethod::Coinbase {
+                    contract: Address::random()
+                },
+                None,
+            )
+            .is_none());
+        assert_eq!(tx_queue.entries.len(), 2);
+    }
+
+    #[test]
+    fn append_bribe_within_fee_budget() {
+        let mut tx_queue = tx_queue(Address::random());
+        let budget = tx_queue.break_even_fees(U256::from(4_000_000), 0).unwrap();
+
+        // The 5000000 wei share is held to the 4000000 budget, 1000000 left after the gas.
+        let bribe = tx_queue
+            .append_bribe(
+                50,
+                U256::from(10_000_000),
+                BribeMethod::PriorityFee,
+                Some(&budget),
+            )
+            .unwrap();
+        assert_eq!(bribe, U256::from(1_000_000));
+        assert_eq!(tx_queue.entries[1].tx.gas_price, Some(U256::from(15)));
+    }
+}
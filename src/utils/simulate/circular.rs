@@ -0,0 +1,219 @@
+use super::{ProfitCurrency, ProfitReport, Simulate, SimulateTrace};
+use ethers::{prelude::*, utils::keccak256};
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    // Drop a report whose beneficiary sent out at least what it received over the call tree
+    // (or, for a token, the `Transfer` logs with `include_logs`), a circular trade that nets
+    // nothing whatever the diff shows. A beneficiary with no flows at all is kept, its gain
+    // isn't from a transfer (e.g. the coinbase tip).
+    pub fn reject_circular(mut self, reject_circular: bool) -> Self {
+        self.reject_circular = reject_circular;
+        self
+    }
+}
+
+// Native value `account` received and sent over the trace, as (inbound, outbound). Calls that
+// don't move value (delegate, static, callcode) and reverted calls with their sub calls are
+// left out.
+pub fn native_flows(trace: &SimulateTrace, account: Address) -> (U256, U256) {
+    native_flows_within(trace, account, &[])
+}
+
+// `native_flows` over the call at `trace_address` and its sub calls only.
+pub fn native_flows_within(
+    trace: &SimulateTrace,
+    account: Address,
+    trace_address: &[usize],
+) -> (U256, U256) {
+    let calls = trace.trace.as_deref().unwrap_or_default();
+    let reverted = calls
+        .iter()
+        .filter(|call| call.error.is_some())
+        .map(|call| &call.trace_address)
+        .collect::<Vec<_>>();
+    let (mut inbound, mut outbound) = (U256::zero(), U256::zero());
+    for call in calls {
+        if !call.trace_address.starts_with(trace_address) {
+            continue;
+        }
+        if reverted
+            .iter()
+            .any(|address| call.trace_address.starts_with(address))
+        {
+            continue;
+        }
+        let (from, to, value) = match (&call.action, &call.result) {
+            (Action::Call(action), _) => match action.call_type {
+                CallType::DelegateCall | CallType::StaticCall | CallType::CallCode => continue,
+                _ => (Some(action.from), Some(action.to), action.value),
+            },
+            (Action::Create(action), Some(Res::Create(result))) => {
+                (Some(action.from), Some(result.address), action.value)
+            }
+            (Action::Create(action), _) => (Some(action.from), None, action.value),
+            (Action::Suicide(action), _) => (
+                Some(action.address),
+                Some(action.refund_address),
+                action.balance,
+            ),
+            (Action::Reward(action), _) => (None, Some(action.author), action.value),
+        };
+        if from == to {
+            continue;
+        }
+        if to == Some(account) {
+            inbound = inbound.saturating_add(value);
+        }
+        if from == Some(account) {
+            outbound = outbound.saturating_add(value);
+        }
+    }
+    (inbound, outbound)
+}
+
+// `token` that `account` received and sent in the ERC-20 `Transfer` logs, as (inbound, outbound).
+pub fn token_flows(logs: &[Log], token: Address, account: Address) -> (U256, U256) {
+    let transfer = H256(keccak256("Transfer(address,address,uint256)"));
+    let (mut inbound, mut outbound) = (U256::zero(), U256::zero());
+    for log in logs {
+        // A malformed log (e.g. an ERC-721 `Transfer`, the id indexed) isn't an ERC-20 amount.
+        if log.address != token
+            || log.topics.len() != 3
+            || log.topics[0] != transfer
+            || log.data.len() != 32
+        {
+            continue;
+        }
+        let (from, to) = (Address::from(log.topics[1]), Address::from(log.topics[2]));
+        let amount = U256::from_big_endian(&log.data);
+        if from == to {
+            continue;
+        }
+        if to == account {
+            inbound = inbound.saturating_add(amount);
+        }
+        if from == account {
+            outbound = outbound.saturating_add(amount);
+        }
+    }
+    (inbound, outbound)
+}
+
+// Whether the flows of the report's beneficiary net to nothing, see `Simulate::reject_circular`.
+// A token report without logs can't tell and isn't circular.
+pub fn is_circular(report: &ProfitReport, trace: &SimulateTrace, logs: Option<&[Log]>) -> bool {
+    let (inbound, outbound) = match (report.currency, logs) {
+        (ProfitCurrency::Native, _) => native_flows(trace, report.beneficiary),
+        (ProfitCurrency::Token(token), Some(logs)) => token_flows(logs, token, report.beneficiary),
+        (ProfitCurrency::Token(_), None) => return false,
+    };
+    !(inbound.is_zero() && outbound.is_zero()) && inbound <= outbound
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        mock::{balance_diff, block_trace, call_trace, mock_client},
+        ProfitReport, Simulate, SimulateTimings,
+    };
+    use super::{native_flows, token_flows};
+    use ethers::{prelude::*, utils::keccak256};
+    use std::collections::BTreeMap;
+
+    #[tokio::test]
+    async fn reject_circular_drop_net_zero_gain() {
+        let (client, mock) = mock_client();
+        let beneficiary = Address::random();
+        let (pool, router) = (Address::random(), Address::random());
+        let simulate = Simulate::init(&client, None)
+            .await
+            .unwrap()
+            .beneficiaries(vec![beneficiary])
+            .reject_circular(true);
+
+        // 100 goes round from the beneficiary through the pool and the router back to it.
+        let call = |trace_address: Vec<usize>, subtraces, from, to| {
+            let mut call = call_trace(trace_address, subtraces, U256::from(100));
+            if let Action::Call(action) = &mut call.action {
+                action.from = from;
+                action.to = to;
+            }
+            call
+        };
+        let mut reverted = call(vec![0, 1], 0, pool, beneficiary);
+        reverted.error = Some("Reverted".into());
+        let trace = block_trace(
+            vec![
+                call(vec![], 1, beneficiary, pool),
+                call(vec![0], 2, pool, router),
+                call(vec![0, 0], 0, router, beneficiary),
+                reverted,
+            ],
+            Some(StateDiff(BTreeMap::from([(
+                beneficiary,
+                balance_diff(U256::zero(), U256::from(1000)),
+            )]))),
+        );
+        assert_eq!(
+            native_flows(&trace, beneficiary),
+            (U256::from(100), U256::from(100))
+        );
+
+        mock.push(trace.clone()).unwrap();
+        let tx = Transaction {
+            input: "0x00000001".parse().unwrap(),
+            ..Default::default()
+        };
+        assert!(simulate
+            .is_valuable(tx.clone(), None, None, &mut SimulateTimings::default())
+            .await
+            .unwrap()
+            .is_none());
+
+        // Off by default, the diff alone counts.
+        let simulate = Simulate::init(&client, None)
+            .await
+            .unwrap()
+            .beneficiaries(vec![beneficiary]);
+        mock.push(trace).unwrap();
+        let (_, reports) = simulate
+            .is_valuable(tx, None, None, &mut SimulateTimings::default())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            reports,
+            vec![ProfitReport::native(beneficiary, U256::from(1000))]
+        );
+    }
+
+    #[test]
+    fn token_flows_sum_transfer_logs() {
+        let (token, account, other) = (Address::random(), Address::random(), Address::random());
+        let transfer = |token, from: Address, to: Address, amount: u64| {
+            let mut data = [0; 32];
+            U256::from(amount).to_big_endian(&mut data);
+            Log {
+                address: token,
+                topics: vec![
+                    H256(keccak256("Transfer(address,address,uint256)")),
+                    H256::from(from),
+                    H256::from(to),
+                ],
+                data: Bytes::from(data.to_vec()),
+                ..Default::default()
+            }
+        };
+        let logs = [
+            transfer(token, account, other, 50),
+            transfer(token, other, account, 80),
+            // Another token, and a no-op transfer to itself.
+            transfer(Address::random(), other, account, 1000),
+            transfer(token, account, account, 1000),
+        ];
+        assert_eq!(
+            token_flows(&logs, token, account),
+            (U256::from(80), U256::from(50))
+        );
+    }
+}
@@ -0,0 +1,284 @@
+use super::{Opportunity, Simulate, SimulateError, SimulateTarget, Verification};
+use ethers::prelude::*;
+use std::cmp::Ordering;
+
+// How the queue reproduces the opportunity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QueueStrategy {
+    // Replay the victim's origin call as is.
+    Origin,
+    // Replay the internal calls of the origin call one by one.
+    Internal,
+}
+
+#[derive(Debug, Clone)]
+pub struct StrategyOutcome {
+    pub strategy: QueueStrategy,
+    pub tx_list: Vec<TransactionRequest>,
+    pub verification: Verification,
+}
+
+#[derive(Debug, Clone)]
+pub struct StrategyComparison {
+    // Best first, according to the rank function.
+    pub ranked: Vec<StrategyOutcome>,
+    // Index into `ranked`, `None` if every strategy reverted.
+    pub recommended: Option<usize>,
+}
+
+impl StrategyComparison {
+    pub fn recommended(&self) -> Option<&StrategyOutcome> {
+        self.recommended.map(|i| &self.ranked[i])
+    }
+}
+
+// One way to reconstruct the opportunity, verified on the traced block.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub strategy: QueueStrategy,
+    pub tx_list: Vec<TransactionRequest>,
+    pub verification: Verification,
+    // The verified gas at the rpc's gas price when the candidates were built.
+    pub gas_cost: U256,
+}
+
+impl Candidate {
+    // Negative when the gas costs more than the queue earns.
+    pub fn net_profit(&self) -> I256 {
+        let gas_cost = I256::try_from(self.gas_cost).unwrap_or(I256::MAX);
+        self.verification.profit.saturating_sub(gas_cost)
+    }
+}
+
+// The successful candidate with the highest net profit, the first one on a tie.
+pub fn best(candidates: &[Candidate]) -> Option<&Candidate> {
+    candidates
+        .iter()
+        .filter(|candidate| candidate.verification.is_success())
+        .fold(None, |best: Option<&Candidate>, candidate| match best {
+            Some(best) if best.net_profit() >= candidate.net_profit() => Some(best),
+            _ => Some(candidate),
+        })
+}
+
+// Default rank: successful strategy first, then higher profit.
+// Profit within `noise` counts as a tie, the one with fewer txs wins since it's cheaper and less fragile.
+pub fn rank_by_profit(noise: U256) -> impl Fn(&StrategyOutcome, &StrategyOutcome) -> Ordering {
+    move |a, b| match (a.verification.is_success(), b.verification.is_success()) {
+        (true, false) => Ordering::Less,
+        (false, true) => Ordering::Greater,
+        _ => {
+            let diff = a.verification.profit.saturating_sub(b.verification.profit);
+            if diff.abs() <= I256::from_raw(noise) {
+                a.tx_list.len().cmp(&b.tx_list.len())
+            } else if diff > I256::zero() {
+                Ordering::Less
+            } else {
+                Ordering::Greater
+            }
+        }
+    }
+}
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    pub async fn compare_strategies(
+        &self,
+        opportunity: &Opportunity,
+        noise: U256,
+    ) -> Result<StrategyComparison, SimulateError> {
+        self.compare_strategies_by(opportunity, rank_by_profit(noise))
+            .await
+    }
+
+    // `run` then verify every reconstruction of the opportunity, in strategy order, for callers
+    // that pick one themselves (see `best`). Empty if the tx isn't an opportunity.
+    pub async fn run_candidates(
+        &self,
+        tx_hash: TxHash,
+        target: impl Into<SimulateTarget>,
+    ) -> Result<Vec<Candidate>, SimulateError> {
+        let opportunity = match self.run(tx_hash, target).await? {
+            Some(opportunity) => opportunity,
+            None => return Ok(vec![]),
+        };
+        let gas_price = self
+            .get_gas_price()
+            .await
+            .map_err(SimulateError::middleware)?;
+
+        let mut candidates = Vec::new();
+        for (strategy, tx_list) in self.to_strategy_queue(&opportunity.trace) {
+            let verification = self.verify(&tx_list, opportunity.block).await?;
+            candidates.push(Candidate {
+                strategy,
+                tx_list,
+                gas_cost: verification.gas_used.saturating_mul(gas_price),
+                verification,
+            });
+        }
+        Ok(candidates)
+    }
+
+    // Verify every strategy of the opportunity and rank them with `rank`, best is `Ordering::Less`.
+    pub async fn compare_strategies_by<F: Fn(&StrategyOutcome, &StrategyOutcome) -> Ordering>(
+        &self,
+        opportunity: &Opportunity,
+        rank: F,
+    ) -> Result<StrategyComparison, SimulateError> {
+        let mut ranked = Vec::new();
+        for (strategy, tx_list) in self.to_strategy_queue(&opportunity.trace) {
+            let verification = self.verify(&tx_list, opportunity.block).await?;
+            ranked.push(StrategyOutcome {
+                strategy,
+                tx_list,
+                verification,
+            });
+        }
+        ranked.sort_by(|a, b| rank(a, b));
+
+        let recommended = ranked
+            .first()
+            .filter(|outcome| outcome.verification.is_success())
+            .map(|_| 0);
+        Ok(StrategyComparison {
+            ranked,
+            recommended,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        mock::{balance_diff, block_trace, call_trace, mock_client},
+        Simulate,
+    };
+    use super::{best, rank_by_profit, Candidate, QueueStrategy, StrategyOutcome};
+    use crate::utils::Verification;
+    use ethers::prelude::*;
+    use std::cmp::Ordering;
+    use std::collections::BTreeMap;
+
+    fn outcome(
+        strategy: QueueStrategy,
+        tx_count: usize,
+        profit: i64,
+        reverted: bool,
+    ) -> StrategyOutcome {
+        StrategyOutcome {
+            strategy,
+            tx_list: vec![TransactionRequest::new(); tx_count],
+            verification: Verification {
+                profit: I256::from(profit),
+                gas_used: U256::zero(),
+                reverted: reverted.then(|| (0, "Reverted".into())),
+                warnings: vec![],
+            },
+        }
+    }
+
+    #[test]
+    fn rank_successful_strategy_first() {
+        let rank = rank_by_profit(U256::zero());
+        let origin = outcome(QueueStrategy::Origin, 1, 100, true);
+        let internal = outcome(QueueStrategy::Internal, 3, 10, false);
+        assert_eq!(rank(&internal, &origin), Ordering::Less);
+        assert_eq!(rank(&origin, &internal), Ordering::Greater);
+    }
+
+    #[test]
+    fn rank_higher_profit_first() {
+        let rank = rank_by_profit(U256::from(5));
+        let origin = outcome(QueueStrategy::Origin, 1, 100, false);
+        let internal = outcome(QueueStrategy::Internal, 3, 120, false);
+        assert_eq!(rank(&internal, &origin), Ordering::Less);
+    }
+
+    #[test]
+    fn rank_fewer_txs_first_within_noise() {
+        let rank = rank_by_profit(U256::from(5));
+        let origin = outcome(QueueStrategy::Origin, 1, 100, false);
+        let internal = outcome(QueueStrategy::Internal, 3, 104, false);
+        assert_eq!(rank(&origin, &internal), Ordering::Less);
+    }
+
+    #[test]
+    fn net_profit_negative_when_gas_exceeds_profit() {
+        let candidate = |profit: i64, gas_cost: U256| Candidate {
+            strategy: QueueStrategy::Origin,
+            tx_list: vec![TransactionRequest::new()],
+            verification: Verification {
+                profit: I256::from(profit),
+                gas_used: U256::from(21000),
+                reverted: None,
+                warnings: vec![],
+            },
+            gas_cost,
+        };
+        assert_eq!(
+            candidate(100, U256::from(150)).net_profit(),
+            I256::from(-50)
+        );
+        assert_eq!(candidate(-10, U256::MAX).net_profit(), I256::MIN);
+
+        let candidates = [
+            candidate(100, U256::from(150)),
+            candidate(100, U256::from(120)),
+        ];
+        assert_eq!(best(&candidates).unwrap().net_profit(), I256::from(-20));
+    }
+
+    #[tokio::test]
+    async fn best_pick_higher_net_profit() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let tx = Transaction {
+            hash: TxHash::random(),
+            input: "0x00000001".parse().unwrap(),
+            ..Default::default()
+        };
+        let analyzed = block_trace(
+            vec![
+                call_trace(vec![], 2, U256::zero()),
+                call_trace(vec![0], 0, U256::zero()),
+                call_trace(vec![1], 0, U256::zero()),
+            ],
+            Some(StateDiff(BTreeMap::from([(
+                tx.from,
+                balance_diff(U256::zero(), U256::from(1000)),
+            )]))),
+        );
+        let verified = |profit: u64| {
+            let mut call = call_trace(vec![], 0, U256::zero());
+            call.result = Some(Res::Call(CallResult {
+                gas_used: U256::from(10),
+                output: Bytes::default(),
+            }));
+            block_trace(
+                vec![call],
+                Some(StateDiff(BTreeMap::from([(
+                    client.address(),
+                    balance_diff(U256::zero(), U256::from(profit)),
+                )]))),
+            )
+        };
+
+        // The internal calls earn more even after paying
+        // for their extra gas.
+        mock.push::<Vec<BlockTrace>, _>(vec![verified(600), verified(600)])
+            .unwrap();
+        mock.push::<Vec<BlockTrace>, _>(vec![verified(900)])
+            .unwrap();
+        mock.push(U256::from(2)).unwrap();
+        mock.push(U256::one()).unwrap();
+        mock.push(analyzed).unwrap();
+        mock.push(tx.clone()).unwrap();
+
+        let candidates = simulate.run_candidates(tx.hash, true).await.unwrap();
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(candidates[0].strategy, QueueStrategy::Origin);
+        assert_eq!(candidates[0].net_profit(), I256::from(880));
+        assert_eq!(candidates[1].net_profit(), I256::from(1160));
+        assert_eq!(best(&candidates).unwrap().strategy, QueueStrategy::Internal);
+    }
+}
@@ -0,0 +1,199 @@
+use super::{
+    gas::origin_call_status, state::base::merge_state_diff, Opportunity, OpportunityId, Simulate,
+    SimulateError, SimulateTimings, SimulateTrace,
+};
+use ethers::prelude::*;
+use std::time::Instant;
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    // Analyze MEV that only realizes across blocks, e.g. an oracle update in block N used in block N + 1.
+    // The setup tx is traced on the state before its block and the trigger tx on the state after it,
+    // so the setup (and the rest of its block) is already applied when the trigger runs. A trigger
+    // mined in any block but N + 1, or reverted (unless `allow_reverted`), gives None.
+    pub async fn run_cross_block(
+        &self,
+        setup_tx: TxHash,
+        trigger_tx: TxHash,
+    ) -> Result<Option<Opportunity>, SimulateError> {
+        let mut timings = SimulateTimings::default();
+
+        let start = Instant::now();
+        let setup = self
+            .get_transaction(setup_tx)
+            .await
+            .map_err(SimulateError::middleware)?;
+        let trigger = self
+            .get_transaction(trigger_tx)
+            .await
+            .map_err(SimulateError::middleware)?;
+        timings.fetch = start.elapsed();
+        timings.round_trips += 2;
+
+        let (setup, trigger) = match (setup, trigger) {
+            (Some(setup), Some(trigger)) => (setup, trigger),
+            _ => return Ok(None),
+        };
+        // A pending setup has no block boundary to chain the state across.
+        let setup_block = match setup.block_number {
+            Some(block_number) => block_number,
+            None => return Ok(None),
+        };
+        if matches!(trigger.block_number, Some(trigger_block) if trigger_block != setup_block + 1) {
+            return Ok(None);
+        }
+
+        let start = Instant::now();
+        let setup_trace = self
+            .to_trace(&setup, Some((setup_block - 1).into()))
+            .await?;
+        let trigger_trace = self.to_trace(&trigger, Some(setup_block.into())).await?;
+        timings.trace = start.elapsed();
+        timings.round_trips += 2;
+        if !self.allow_reverted && !origin_call_status(&trigger_trace).1 {
+            return Ok(None);
+        }
+
+        let start = Instant::now();
+        let state_diff = match (&setup_trace.state_diff, &trigger_trace.state_diff) {
+            (Some(setup_diff), Some(trigger_diff)) => {
+                Some(merge_state_diff(setup_diff, trigger_diff))
+            }
+            (setup_diff, trigger_diff) => trigger_diff.clone().or(setup_diff.clone()),
+        };
+        let combined_trace = SimulateTrace {
+            state_diff,
+            ..trigger_trace.clone()
+        };
+        let reports = self
+            .analyze(&trigger, &combined_trace, Some(setup_block.into()))
+            .await;
+        timings.analyze = start.elapsed();
+        if reports.is_empty() {
+            return Ok(None);
+        }
+        self.check_chain_id(setup.chain_id).await?;
+        self.check_chain_id(trigger.chain_id).await?;
+
+        let start = Instant::now();
+        let mut tx_queue = self.to_tx_queue(&setup_trace);
+        tx_queue.extend(self.to_tx_queue(&trigger_trace));
+        timings.build_queue = start.elapsed();
+
+        let (original_gas_used, original_success) = origin_call_status(&trigger_trace);
+        let manipulation = self.manipulation(&combined_trace, &reports);
+        let opportunity = Opportunity {
+            id: OpportunityId::new(),
+            victim: Some(trigger.hash),
+            tx_queue,
+            reports,
+            timings,
+            block: Some((setup_block - 1).into()),
+            block_hash: None,
+            trace: combined_trace,
+            original_gas_used,
+            original_success,
+            victim_raw_tx: None,
+            selector: selector_of(&trigger.input),
+            decoded: self.decode_call(&trigger.input),
+            manipulation,
+        };
+        self.record_simulation(&opportunity).await;
+        Ok(Some(opportunity))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        mock::{balance_diff, block_trace, call_trace, mock_client},
+        ProfitReport, Simulate,
+    };
+    use ethers::prelude::*;
+    use std::collections::BTreeMap;
+
+    #[tokio::test]
+    async fn run_cross_block_combine_profit_of_both_blocks() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let searcher = Address::random();
+        let setup = Transaction {
+            hash: TxHash::random(),
+            from: searcher,
+            block_number: Some(U64::from(100)),
+            ..Default::default()
+        };
+        let trigger = Transaction {
+            hash: TxHash::random(),
+            from: searcher,
+            nonce: U256::from(1),
+            block_number: Some(U64::from(101)),
+            ..Default::default()
+        };
+
+        // The setup costs 1 wei in block 100, the trigger earns 3 wei in block 101.
+        let setup_diff = StateDiff(BTreeMap::from([(
+            searcher,
+            balance_diff(U256::from(10), U256::from(9)),
+        )]));
+        let trigger_diff = StateDiff(BTreeMap::from([(
+            searcher,
+            balance_diff(U256::from(20), U256::from(23)),
+        )]));
+
+        mock.push(U256::one()).unwrap();
+        mock.push(block_trace(
+            vec![call_trace(vec![], 0, U256::zero())],
+            Some(trigger_diff.clone()),
+        ))
+        .unwrap();
+        mock.push(block_trace(
+            vec![call_trace(vec![], 0, U256::zero())],
+            Some(setup_diff.clone()),
+        ))
+        .unwrap();
+        mock.push(trigger.clone()).unwrap();
+        mock.push(setup.clone()).unwrap();
+
+        let opportunity = simulate
+            .run_cross_block(setup.hash, trigger.hash)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            opportunity.reports,
+            vec![ProfitReport::native(searcher, U256::from(2))]
+        );
+        assert_eq!(opportunity.tx_queue.len(), 2);
+
+        // Mined in the setup's block, the state the trigger ran on isn't the one after it.
+        mock.push(Transaction {
+            block_number: Some(U64::from(100)),
+            ..trigger.clone()
+        })
+        .unwrap();
+        mock.push(setup.clone()).unwrap();
+        assert!(simulate
+            .run_cross_block(setup.hash, trigger.hash)
+            .await
+            .unwrap()
+            .is_none());
+
+        // A reverted trigger earns nothing.
+        let mut reverted = call_trace(vec![], 0, U256::zero());
+        reverted.error = Some("Reverted".into());
+        mock.push(block_trace(vec![reverted], Some(trigger_diff)))
+            .unwrap();
+        mock.push(block_trace(
+            vec![call_trace(vec![], 0, U256::zero())],
+            Some(setup_diff),
+        ))
+        .unwrap();
+        mock.push(trigger.clone()).unwrap();
+        mock.push(setup.clone()).unwrap();
+        assert!(simulate
+            .run_cross_block(setup.hash, trigger.hash)
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,130 @@
+use super::{
+    ProfitCurrency, ProfitKind, ProfitReport, Simulate, SimulateError, SimulateTarget, TraceClient,
+};
+use ethers::prelude::*;
+use std::collections::HashMap;
+
+type ProfitByHolder = HashMap<(Address, ProfitCurrency, ProfitKind), U256>;
+
+fn profit_by_holder(reports: &[ProfitReport]) -> ProfitByHolder {
+    let mut profits = ProfitByHolder::new();
+    for report in reports {
+        *profits
+            .entry((report.beneficiary, report.currency, report.kind))
+            .or_default() += report.amount;
+    }
+    profits
+}
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    // Trace the tx on the client's tracer and every one of `providers`, at the block `run` rewinds
+    // to, and check the analyzers find the same profits in all the traces, each amount within
+    // `verify_tolerance_bps`. A node on a stale or forked state shows up as a mismatch. Without any
+    // of `providers` there's nothing to compare the client's trace to.
+    pub async fn cross_check_trace(
+        &self,
+        tx_hash: TxHash,
+        providers: &[M],
+    ) -> Result<bool, SimulateError> {
+        if providers.is_empty() {
+            return Err(SimulateError::CrossCheckSingleSource);
+        }
+        let tx = self
+            .get_transaction(tx_hash)
+            .await
+            .map_err(SimulateError::middleware)?
+            .ok_or(SimulateError::TxNotFound(tx_hash))?;
+        let block = self
+            .resolve_block(&tx, SimulateTarget::Rewind)
+            .await?
+            .number;
+
+        let trace_type = vec![TraceType::Trace, TraceType::StateDiff];
+        let mut profits = Vec::with_capacity(providers.len() + 1);
+        let trace = self.to_trace(&tx, block).await?;
+        profits.push(profit_by_holder(&self.analyze(&tx, &trace, block).await));
+        for provider in providers {
+            let trace =
+                TraceClient::trace_call(provider, (&tx).into(), trace_type.clone(), block).await?;
+            profits.push(profit_by_holder(&self.analyze(&tx, &trace, block).await));
+        }
+
+        Ok(profits
+            .windows(2)
+            .all(|pair| self.profits_agree(&pair[0], &pair[1])))
+    }
+
+    fn profits_agree(&self, a: &ProfitByHolder, b: &ProfitByHolder) -> bool {
+        a.len() == b.len()
+            && a.iter().all(|(key, amount_a)| match b.get(key) {
+                Some(amount_b) => {
+                    let (high, low) = (*amount_a.max(amount_b), *amount_a.min(amount_b));
+                    (high - low) * 10000 <= high * self.verify_tolerance_bps
+                }
+                None => false,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        mock::{balance_diff, block_trace, mock_client},
+        Simulate, SimulateError,
+    };
+    use ethers::prelude::*;
+    use std::collections::BTreeMap;
+
+    fn trace_gaining(to: Address, gain: u64) -> BlockTrace {
+        let state_diff = StateDiff(BTreeMap::from([(
+            to,
+            balance_diff(U256::from(1000), U256::from(1000 + gain)),
+        )]));
+        block_trace(vec![], Some(state_diff))
+    }
+
+    fn provider_gaining(to: Address, gain: u64) -> Provider<MockProvider> {
+        let (provider, mock) = Provider::mocked();
+        mock.push(trace_gaining(to, gain)).unwrap();
+        provider
+    }
+
+    #[tokio::test]
+    async fn cross_check_trace_report_mismatch() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let tx = Transaction {
+            hash: TxHash::random(),
+            to: Some(Address::random()),
+            block_number: Some(U64::from(100)),
+            ..Default::default()
+        };
+        let to = tx.to.unwrap();
+
+        // 10% apart, past the default 5% tolerance.
+        mock.push(trace_gaining(to, 1000)).unwrap();
+        mock.push(tx.clone()).unwrap();
+        let providers = [provider_gaining(to, 900)];
+        assert!(!simulate
+            .cross_check_trace(tx.hash, &providers)
+            .await
+            .unwrap());
+
+        // 1% apart, rounding between nodes.
+        mock.push(trace_gaining(to, 1000)).unwrap();
+        mock.push(tx.clone()).unwrap();
+        let providers = [provider_gaining(to, 990)];
+        assert!(simulate
+            .cross_check_trace(tx.hash, &providers)
+            .await
+            .unwrap());
+
+        // The client's trace can't be compared to anything.
+        assert!(matches!(
+            simulate
+                .cross_check_trace(tx.hash, &[] as &[Provider<MockProvider>])
+                .await,
+            Err(SimulateError::CrossCheckSingleSource)
+        ));
+    }
+}
@@ -0,0 +1,268 @@
+use super::{
+    gas::origin_call_status, strategy, Opportunity, OpportunityId, ProfitReport, Simulate,
+    SimulateError, SimulateTarget, SimulateTimings, SimulateTrace, Verification,
+};
+use ethers::prelude::*;
+use std::future::Future;
+use std::time::Instant;
+
+// Whatever `run_with_deadline` computed before it finished or ran out of time.
+#[derive(Debug, Clone, Default)]
+pub struct DeadlineOutcome {
+    pub tx: Option<Transaction>,
+    pub block: Option<BlockNumber>,
+    // Only known when the block was resolved from a tag, see `ResolvedBlock`.
+    pub block_hash: Option<H256>,
+    pub trace: Option<SimulateTrace>,
+    pub reports: Vec<ProfitReport>,
+    pub tx_queue: Vec<Vec<TransactionRequest>>,
+    // One per tx list of the queue, in order, may be shorter if the deadline hit.
+    pub verification: Vec<Verification>,
+    pub timings: SimulateTimings,
+    pub deadline_exceeded: bool,
+}
+
+impl DeadlineOutcome {
+    fn exceeded(mut self) -> Self {
+        self.deadline_exceeded = true;
+        self
+    }
+
+    // The opportunity, only if every stage finished and every tx list verified.
+    pub fn opportunity(&self) -> Option<Opportunity> {
+        let verified = self.verification.len() == self.tx_queue.len()
+            && self.verification.iter().all(Verification::is_success);
+        match (&self.trace, self.deadline_exceeded, verified) {
+            (Some(trace), false, true) if !self.tx_queue.is_empty() => {
+                let (original_gas_used, original_success) = origin_call_status(trace);
+                Some(Opportunity {
+                    id: OpportunityId::new(),
+                    victim: self.tx.as_ref().map(|tx| tx.hash),
+                    tx_queue: self.tx_queue.clone(),
+                    reports: self.reports.clone(),
+                    timings: self.timings,
+                    block: self.block,
+                    block_hash: self.block_hash,
+                    trace: trace.clone(),
+                    original_gas_used,
+                    original_success,
+                    victim_raw_tx: None,
+                    selector: self
+                        .tx
+                        .as_ref()
+                        .map(|tx| selector_of(&tx.input))
+                        .unwrap_or_default(),
+                    decoded: None,
+                    manipulation: None,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+// Don't even start `fut` once the deadline passed, `timeout_at` would still poll it once.
+async fn within<F: Future>(deadline: Instant, fut: F) -> Option<F::Output> {
+    if Instant::now() >= deadline {
+        return None;
+    }
+    tokio::time::timeout_at(tokio::time::Instant::from_std(deadline), fut)
+        .await
+        .ok()
+}
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    // Like `run` then `verify` for every tx list, but every stage is bounded by `deadline`.
+    // An opportunity that takes longer than about a block to process is worthless for backrunning.
+    pub async fn run_with_deadline(
+        &self,
+        tx_hash: TxHash,
+        target: impl Into<SimulateTarget>,
+        deadline: Instant,
+    ) -> Result<DeadlineOutcome, SimulateError> {
+        let mut outcome = DeadlineOutcome::default();
+
+        let start = Instant::now();
+        let tx = match within(deadline, self.get_transaction(tx_hash)).await {
+            Some(tx) => tx.map_err(SimulateError::middleware)?,
+            None => return Ok(outcome.exceeded()),
+        };
+        outcome.timings.fetch = start.elapsed();
+        outcome.timings.round_trips += 1;
+        let tx = match tx {
+            Some(tx) => tx,
+            None => return Ok(outcome),
+        };
+
+        let target = target.into();
+        let block = match within(deadline, self.resolve_block(&tx, target)).await {
+            Some(block) => block?,
+            None => return Ok(outcome.exceeded()),
+        };
+        outcome.tx = Some(tx.clone());
+        outcome.block = block.number;
+        outcome.block_hash = block.hash;
+        if !strategy::transfer::run(&tx) || !strategy::flashloan::run(&tx) {
+            return Ok(outcome);
+        }
+
+        let start = Instant::now();
+        let trace = match within(deadline, self.to_target_trace(&tx, target, block.number)).await {
+            Some(trace) => trace?,
+            None => return Ok(outcome.exceeded()),
+        };
+        outcome.timings.trace = start.elapsed();
+        outcome.timings.round_trips += 1;
+        if !self.allow_reverted && !origin_call_status(&trace).1 {
+            outcome.trace = Some(trace);
+            return Ok(outcome);
+        }
+        // A verification costs about as much as the trace, don't start one that can't finish.
+        let verify_estimate = outcome.timings.trace;
+
+        let start = Instant::now();
+        let reports = match within(deadline, self.analyze(&tx, &trace, block.number)).await {
+            Some(reports) => reports,
+            None => {
+                outcome.trace = Some(trace);
+                return Ok(outcome.exceeded());
+            }
+        };
+        outcome.timings.analyze = start.elapsed();
+        outcome.reports = reports;
+        if outcome.reports.is_empty() {
+            outcome.trace = Some(trace);
+            return Ok(outcome);
+        }
+        self.check_chain_id(tx.chain_id).await?;
+
+        let start = Instant::now();
+        let mut tx_queue = self.to_tx_queue(&trace);
+        if !strategy::queue::run(&mut tx_queue, self.max_queue_len, self.queue_overflow) {
+            tx_queue.clear();
+        }
+        outcome.timings.build_queue = start.elapsed();
+        outcome.trace = Some(trace);
+        outcome.tx_queue = tx_queue;
+
+        let start = Instant::now();
+        for tx_list in &outcome.tx_queue {
+            if Instant::now() + verify_estimate >= deadline {
+                outcome.timings.verify = start.elapsed();
+                return Ok(outcome.exceeded());
+            }
+            match within(deadline, self.verify(tx_list, block.number)).await {
+                Some(verification) => outcome.verification.push(verification?),
+                None => {
+                    outcome.timings.verify = start.elapsed();
+                    return Ok(outcome.exceeded());
+                }
+            }
+        }
+        outcome.timings.verify = start.elapsed();
+
+        Ok(outcome)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        mock::{balance_diff, block_trace, call_trace, mock_client},
+        Simulate,
+    };
+    use ethers::prelude::*;
+    use std::collections::BTreeMap;
+    use std::time::{Duration, Instant};
+
+    #[tokio::test]
+    async fn run_with_deadline_abort_when_exceeded() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+
+        let outcome = simulate
+            .run_with_deadline(TxHash::random(), true, Instant::now())
+            .await
+            .unwrap();
+        assert!(outcome.deadline_exceeded);
+        assert!(outcome.tx.is_none());
+        assert!(outcome.opportunity().is_none());
+        assert!(mock.assert_request("eth_getTransactionByHash", ()).is_err());
+    }
+
+    #[tokio::test]
+    async fn run_with_deadline_finish_every_stage() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let tx = Transaction {
+            hash: TxHash::random(),
+            input: "0x00000001".parse().unwrap(),
+            ..Default::default()
+        };
+        let state_diff = StateDiff(BTreeMap::from([(
+            tx.from,
+            balance_diff(U256::zero(), U256::from(1)),
+        )]));
+
+        mock.push::<Vec<BlockTrace>, _>(vec![block_trace(vec![], None)])
+            .unwrap();
+        mock.push(U256::one()).unwrap();
+        mock.push(block_trace(
+            vec![call_trace(vec![], 0, U256::zero())],
+            Some(state_diff),
+        ))
+        .unwrap();
+        mock.push(tx.clone()).unwrap();
+
+        let outcome = simulate
+            .run_with_deadline(tx.hash, false, Instant::now() + Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert!(!outcome.deadline_exceeded);
+        assert_eq!(outcome.tx_queue.len(), 1);
+        assert_eq!(outcome.verification.len(), 1);
+        assert!(outcome.opportunity().is_some());
+    }
+
+    #[tokio::test]
+    async fn run_with_deadline_keep_resolved_block_hash() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let tx = Transaction {
+            hash: TxHash::random(),
+            input: "0x00000001".parse().unwrap(),
+            ..Default::default()
+        };
+        let safe = Block::<TxHash> {
+            number: Some(U64::from(90)),
+            hash: Some(H256::random()),
+            ..Default::default()
+        };
+
+        mock.push::<Vec<BlockTrace>, _>(vec![block_trace(vec![], None)])
+            .unwrap();
+        mock.push(U256::one()).unwrap();
+        mock.push(block_trace(
+            vec![call_trace(vec![], 0, U256::zero())],
+            Some(StateDiff(BTreeMap::from([(
+                tx.from,
+                balance_diff(U256::zero(), U256::from(1)),
+            )]))),
+        ))
+        .unwrap();
+        mock.push(safe.clone()).unwrap();
+        mock.push(tx.clone()).unwrap();
+
+        let outcome = simulate
+            .run_with_deadline(
+                tx.hash,
+                BlockNumber::Safe,
+                Instant::now() + Duration::from_secs(10),
+            )
+            .await
+            .unwrap();
+        let opportunity = outcome.opportunity().unwrap();
+        assert_eq!(opportunity.block, Some(BlockNumber::Number(U64::from(90))));
+        assert_eq!(opportunity.block_hash, safe.hash);
+    }
+}
@@ -0,0 +1,129 @@
+use super::{flatten_traces, SimulateTrace};
+use ethers::{
+    prelude::*,
+    utils::{get_create2_address, keccak256},
+};
+
+// The CREATE2 salts contracts commonly key on `msg.sender`: the padded address, and its hash
+// both abi encoded (`keccak256(abi.encode(sender))`) and packed.
+fn sender_salts(sender: Address) -> [H256; 3] {
+    let padded = H256::from(sender);
+    [padded, H256(keccak256(padded)), H256(keccak256(sender))]
+}
+
+// Whether `creator` deployed `created` at a CREATE2 address salted with `sender`.
+pub fn is_sender_derived(
+    creator: Address,
+    init: &Bytes,
+    created: Address,
+    sender: Address,
+) -> bool {
+    sender_salts(sender)
+        .into_iter()
+        .any(|salt| get_create2_address(creator, salt.as_bytes().to_vec(), init.clone()) == created)
+}
+
+// `trace_address` of the calls a queue is rebuilt from (the origin call and the top level calls)
+// whose sub calls deploy a contract keyed on the call's sender, e.g. a clone per user. The bytes
+// of such an address aren't in the calldata, so `mock_tx_data` can't swap it, and sent by anyone
+// else the call works on another address. Only clones deployed within the tx are detected.
+pub fn sender_derived_calls(trace: &SimulateTrace) -> Vec<Vec<usize>> {
+    let trace_list = match &trace.trace {
+        Some(trace_list) => flatten_traces(trace_list),
+        None => return vec![],
+    };
+
+    trace_list
+        .iter()
+        .filter(|call| call.trace_address.len() <= 1)
+        .filter(|call| {
+            let sender = match &call.action {
+                Action::Call(data) => data.from,
+                Action::Create(data) => data.from,
+                _ => return false,
+            };
+            trace_list.iter().any(|sub_call| {
+                if sub_call.trace_address.len() <= call.trace_address.len()
+                    || !sub_call.trace_address.starts_with(&call.trace_address)
+                {
+                    return false;
+                }
+                match (&sub_call.action, &sub_call.result) {
+                    (Action::Create(data), Some(Res::Create(result))) => {
+                        is_sender_derived(data.from, &data.init, result.address, sender)
+                    }
+                    _ => false,
+                }
+            })
+        })
+        .map(|call| call.trace_address.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        mock::{block_trace, call_trace, mock_client},
+        QueueStrategy, Simulate,
+    };
+    use super::sender_derived_calls;
+    use ethers::{
+        prelude::*,
+        utils::{get_create2_address, keccak256},
+    };
+
+    #[tokio::test]
+    async fn to_strategy_queue_skip_call_with_sender_derived_target() {
+        let (client, _) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let (sender, factory) = (Address::random(), Address::random());
+        let init = Bytes::from(vec![0x60, 0x00, 0x60, 0x00, 0xf3]);
+        // `Clones.cloneDeterministic(implementation, keccak256(abi.encode(msg.sender)))`.
+        let clone = get_create2_address(
+            factory,
+            keccak256(H256::from(sender)).to_vec(),
+            init.clone(),
+        );
+
+        let mut origin_call = call_trace(vec![], 1, U256::zero());
+        if let Action::Call(call) = &mut origin_call.action {
+            call.from = sender;
+            call.to = factory;
+        }
+        let create = TransactionTrace {
+            action: Action::Create(Create {
+                from: factory,
+                value: U256::zero(),
+                gas: U256::from(100000),
+                init,
+            }),
+            action_type: ActionType::Create,
+            result: Some(Res::Create(CreateResult {
+                gas_used: U256::zero(),
+                code: Bytes::default(),
+                address: clone,
+            })),
+            ..call_trace(vec![0], 0, U256::zero())
+        };
+        let trace = block_trace(vec![origin_call, create], None);
+
+        assert_eq!(sender_derived_calls(&trace), vec![Vec::<usize>::new()]);
+        // Only the clone's deployment itself stays, sent from us it isn't keyed on anyone.
+        let strategies = simulate
+            .to_strategy_queue(&trace)
+            .into_iter()
+            .map(|(strategy, _)| strategy)
+            .collect::<Vec<_>>();
+        assert_eq!(strategies, vec![QueueStrategy::Internal]);
+
+        // Salted with anything else, the origin call is rebuilt.
+        let trace = block_trace(
+            vec![
+                call_trace(vec![], 1, U256::zero()),
+                trace.trace.unwrap()[1].clone(),
+            ],
+            None,
+        );
+        assert!(sender_derived_calls(&trace).is_empty());
+    }
+}
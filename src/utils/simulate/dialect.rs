@@ -0,0 +1,178 @@
+use super::{Simulate, SimulateError, SimulateTrace};
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use serde_json::{json, Map, Value};
+
+// The node client answering trace calls, detected from `web3_clientVersion`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceDialect {
+    OpenEthereum,
+    Erigon,
+    Nethermind,
+    Reth,
+    Geth,
+    Unknown,
+}
+
+impl TraceDialect {
+    pub fn from_client_version(version: &str) -> Self {
+        let version = version.to_lowercase();
+        if version.starts_with("erigon") {
+            Self::Erigon
+        } else if version.starts_with("nethermind") {
+            Self::Nethermind
+        } else if version.starts_with("openethereum") || version.starts_with("parity") {
+            Self::OpenEthereum
+        } else if version.starts_with("reth") {
+            Self::Reth
+        } else if version.starts_with("geth") {
+            Self::Geth
+        } else {
+            Self::Unknown
+        }
+    }
+
+    // Whether the responses of the client need normalizing before ethers can deserialize them.
+    pub fn needs_compat(&self) -> bool {
+        matches!(self, Self::Erigon | Self::Nethermind)
+    }
+
+    fn patch_request(&self, req: &mut Value) {
+        // Both read the calldata from `input` in some versions and `data` in others.
+        if let (true, Some(req)) = (self.needs_compat(), req.as_object_mut()) {
+            if let Some(data) = req.get("data").cloned() {
+                req.entry("input").or_insert(data);
+            }
+        }
+    }
+}
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    pub fn trace_dialect(&self) -> Option<TraceDialect> {
+        self.trace_dialect.get().copied()
+    }
+
+    pub(crate) async fn detect_trace_dialect(&self) -> TraceDialect {
+        if let Some(dialect) = self.trace_dialect() {
+            return dialect;
+        }
+        let dialect = match self.tracer().client_version().await {
+            Ok(version) => TraceDialect::from_client_version(&version),
+            Err(_) => TraceDialect::Unknown,
+        };
+        *self.trace_dialect.get_or_init(|| dialect)
+    }
+
+    pub(crate) async fn trace_call_compat(
+        &self,
+        tx: &Transaction,
+        trace_type: Vec<TraceType>,
+        block: Option<BlockNumber>,
+        dialect: TraceDialect,
+    ) -> Result<SimulateTrace, SimulateError> {
+        let tx: TypedTransaction = tx.into();
+        let mut req = serde_json::to_value(&tx).map_err(SimulateError::middleware)?;
+        dialect.patch_request(&mut req);
+
+        let params = serde_json::to_value((req, trace_type, block.unwrap_or(BlockNumber::Latest)))
+            .map_err(SimulateError::middleware)?;
+        let mut trace = self.tracer().request("trace_call", params).await?;
+        normalize_trace(&mut trace);
+        serde_json::from_value(trace).map_err(SimulateError::middleware)
+    }
+}
+
+// Fill the fields some clients leave null or missing, and lowercase the enum tags.
+pub(crate) fn normalize_trace(trace: &mut Value) {
+    let trace = match trace.as_object_mut() {
+        Some(trace) => trace,
+        None => return,
+    };
+    fill(trace, "output", json!("0x"));
+
+    if let Some(Value::Array(trace_list)) = trace.get_mut("trace") {
+        for entry in trace_list.iter_mut().filter_map(Value::as_object_mut) {
+            fill(entry, "traceAddress", json!([]));
+            fill(entry, "subtraces", json!(0));
+            lowercase(entry.get_mut("type"));
+            if let Some(action) = entry.get_mut("action").and_then(Value::as_object_mut) {
+                lowercase(action.get_mut("callType"));
+            }
+        }
+    }
+
+    if let Some(Value::Object(state_diff)) = trace.get_mut("stateDiff") {
+        for account in state_diff.values_mut().filter_map(Value::as_object_mut) {
+            for key in ["balance", "nonce", "code"] {
+                fill(account, key, json!("="));
+            }
+            fill(account, "storage", json!({}));
+        }
+    }
+}
+
+fn fill(object: &mut Map<String, Value>, key: &str, default: Value) {
+    match object.get(key) {
+        None | Some(Value::Null) => {
+            object.insert(key.to_string(), default);
+        }
+        _ => {}
+    }
+}
+
+fn lowercase(value: Option<&mut Value>) {
+    if let Some(value) = value {
+        if let Some(tag) = value.as_str() {
+            *value = Value::String(tag.to_lowercase());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize_trace, TraceDialect};
+    use crate::utils::SimulateTrace;
+    use serde_json::Value;
+
+    fn parse_fixture(fixture: &str) -> SimulateTrace {
+        let mut trace: Value = serde_json::from_str(fixture).unwrap();
+        normalize_trace(&mut trace);
+        serde_json::from_value(trace).unwrap()
+    }
+
+    #[test]
+    fn detect_dialect_from_client_version() {
+        assert_eq!(
+            TraceDialect::from_client_version("erigon/2.38.1/linux-amd64/go1.19.5"),
+            TraceDialect::Erigon
+        );
+        assert_eq!(
+            TraceDialect::from_client_version("Nethermind/v1.16.1+644b2bcd/linux-x64/dotnet7.0.2"),
+            TraceDialect::Nethermind
+        );
+        assert_eq!(
+            TraceDialect::from_client_version("Geth/v1.10.26-stable/linux-amd64/go1.18.5"),
+            TraceDialect::Geth
+        );
+        assert_eq!(
+            TraceDialect::from_client_version("anvil/v0.1.0"),
+            TraceDialect::Unknown
+        );
+    }
+
+    #[test]
+    fn normalize_trace_of_every_dialect() {
+        let expected = parse_fixture(include_str!(
+            "../../../tests/fixtures/trace_call/openethereum.json"
+        ));
+        assert_eq!(expected.trace.as_ref().unwrap().len(), 2);
+        assert_eq!(expected.state_diff.as_ref().unwrap().0.len(), 2);
+
+        for fixture in [
+            include_str!("../../../tests/fixtures/trace_call/erigon.json"),
+            include_str!("../../../tests/fixtures/trace_call/nethermind.json"),
+        ] {
+            assert_eq!(parse_fixture(fixture), expected);
+        }
+    }
+}
@@ -0,0 +1,263 @@
+use super::{bribe::fee_per_gas, BribeMethod, Simulate, SimulateError, TokenMeta, TxQueue};
+use ethers::{prelude::*, utils::format_units};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// What a built queue earns and costs, from `TxQueue::fill_economics`. Serialized with the
+// amounts in wei as decimal strings.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueueEconomics {
+    // The native profit the analysis expects.
+    #[serde(with = "decimal")]
+    pub expected_profit: U256,
+    // Gas of every entry at its max fee, the bribe tx's included, a priority fee bribe not.
+    #[serde(with = "decimal")]
+    pub gas_cost: U256,
+    // The L1 data fee on OP-stack chains.
+    #[serde(with = "decimal")]
+    pub l1_fee: U256,
+    #[serde(with = "decimal")]
+    pub bribe: U256,
+    // What has to be at hand before sending: the values, the contract funded ones included, and
+    // all the fees.
+    #[serde(with = "decimal")]
+    pub upfront_capital: U256,
+}
+
+// `serde(with)` of an amount as a decimal string, what the analytics pipeline reads.
+pub(crate) mod decimal {
+    use ethers::prelude::{I256, U256};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+    use std::fmt::Display;
+
+    pub trait FromDecimal: Sized {
+        fn from_decimal(amount: &str) -> Option<Self>;
+    }
+
+    impl FromDecimal for U256 {
+        fn from_decimal(amount: &str) -> Option<Self> {
+            U256::from_dec_str(amount).ok()
+        }
+    }
+
+    impl FromDecimal for I256 {
+        fn from_decimal(amount: &str) -> Option<Self> {
+            I256::from_dec_str(amount).ok()
+        }
+    }
+
+    pub fn serialize<T: Display, S: Serializer>(
+        amount: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(amount)
+    }
+
+    pub fn deserialize<'de, T: FromDecimal, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<T, D::Error> {
+        let amount = String::deserialize(deserializer)?;
+        T::from_decimal(&amount)
+            .ok_or_else(|| D::Error::custom(format!("invalid amount {amount:?}")))
+    }
+}
+
+impl QueueEconomics {
+    // The profit left after gas, L1 fee and bribe, negative when the queue loses money.
+    pub fn net_profit(&self) -> I256 {
+        let cost = self
+            .gas_cost
+            .saturating_add(self.l1_fee)
+            .saturating_add(self.bribe);
+        I256::try_from(self.expected_profit)
+            .unwrap_or(I256::MAX)
+            .saturating_sub(I256::try_from(cost).unwrap_or(I256::MAX))
+    }
+}
+
+impl QueueEconomics {
+    // The summary of `Display` in the chain's native token, e.g. MATIC on Polygon.
+    pub fn format_with(&self, native: &TokenMeta) -> String {
+        let format = |amount: U256| {
+            format_units(amount, u32::from(native.decimals)).unwrap_or_else(|_| amount.to_string())
+        };
+        let net_profit = self.net_profit();
+        format!(
+            "profit {} - gas {} - l1 fee {} - bribe {} = net {}{} {symbol}, capital {} {symbol}",
+            format(self.expected_profit),
+            format(self.gas_cost),
+            format(self.l1_fee),
+            format(self.bribe),
+            if net_profit.is_negative() { "-" } else { "" },
+            format(net_profit.into_sign_and_abs().1),
+            format(self.upfront_capital),
+            symbol = native.symbol,
+        )
+    }
+}
+
+impl fmt::Display for QueueEconomics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.format_with(&TokenMeta::eth()))
+    }
+}
+
+impl TxQueue {
+    // Work out and attach the economics of the queue, run it last: after `fill_gas`,
+    // `fill_fees` and `append_bribe`, whose result and method are `bribe`.
+    pub fn fill_economics(
+        &mut self,
+        expected_profit: U256,
+        bribe: Option<(BribeMethod, U256)>,
+        l1_fee: U256,
+    ) -> QueueEconomics {
+        let gross_gas_cost = self
+            .entries
+            .iter()
+            .filter_map(|entry| {
+                Some(
+                    entry
+                        .tx
+                        .gas
+                        .or(entry.trace_gas)?
+                        .saturating_mul(fee_per_gas(entry)),
+                )
+            })
+            .fold(U256::zero(), |total, cost| total.saturating_add(cost));
+        let value = self
+            .entries
+            .iter()
+            .flat_map(|entry| [entry.tx.value, entry.contract_value])
+            .flatten()
+            .fold(U256::zero(), |total, value| total.saturating_add(value));
+
+        let bribe_amount = bribe.map(|(_, bribe)| bribe).unwrap_or_default();
+        let gas_cost = match bribe {
+            // The tip is paid through the gas of the last entry.
+            Some((BribeMethod::PriorityFee, bribe)) => gross_gas_cost.saturating_sub(bribe),
+            _ => gross_gas_cost,
+        };
+        // A coinbase bribe is already in the value of its tx.
+        let upfront_capital = value.saturating_add(gross_gas_cost).saturating_add(l1_fee);
+
+        let economics = QueueEconomics {
+            expected_profit,
+            gas_cost,
+            l1_fee,
+            bribe: bribe_amount,
+            upfront_capital,
+        };
+        self.economics = Some(economics);
+        economics
+    }
+
+    // Refuse a queue that nets nothing, one without economics passes.
+    pub fn ensure_profitable(&self) -> Result<(), SimulateError> {
+        match self.economics.map(|economics| economics.net_profit()) {
+            Some(net_profit) if net_profit <= I256::zero() => {
+                Err(SimulateError::Unprofitable { net_profit })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    // Whether `send_queue` refuses queues that net no profit, on by default.
+    pub fn refuse_unprofitable(mut self, refuse_unprofitable: bool) -> Self {
+        self.refuse_unprofitable = refuse_unprofitable;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{mock::mock_client, BribeMethod, Simulate, SimulateError, TxQueue};
+    use super::QueueEconomics;
+    use ethers::{prelude::*, utils::parse_ether};
+
+    #[test]
+    fn fill_economics_sum_components() {
+        let contract = Address::random();
+        let mut tx_queue = TxQueue::from(vec![
+            TransactionRequest::new()
+                .gas(100000)
+                .gas_price(10)
+                .value(500),
+            TransactionRequest::new().gas(50000).gas_price(10),
+        ]);
+        let bribe = tx_queue
+            .append_bribe(
+                50,
+                U256::from(10_000_000),
+                BribeMethod::Coinbase { contract },
+                None,
+            )
+            .unwrap();
+
+        let economics = tx_queue.fill_economics(
+            U256::from(10_000_000),
+            Some((BribeMethod::Coinbase { contract }, bribe)),
+            U256::from(7),
+        );
+        // 150000 gas of the queue plus the 35000 of the bribe tx, at 10 per gas.
+        assert_eq!(economics.gas_cost, U256::from(1_850_000));
+        assert_eq!(bribe + economics.gas_cost, U256::from(5_000_000));
+        assert_eq!(
+            economics.upfront_capital,
+            U256::from(500) + bribe + economics.gas_cost + 7
+        );
+        assert_eq!(economics.net_profit(), I256::from(5_000_000 - 7));
+        assert_eq!(tx_queue.economics, Some(economics));
+        assert_eq!(
+            serde_json::to_value(economics).unwrap()["bribe"],
+            serde_json::json!(bribe.to_string())
+        );
+    }
+
+    #[test]
+    fn net_profit_edge_values() {
+        // No gas known, the queue costs nothing.
+        let mut tx_queue = TxQueue::from(vec![TransactionRequest::new()]);
+        let economics = tx_queue.fill_economics(U256::from(100), None, U256::zero());
+        assert_eq!(economics.gas_cost, U256::zero());
+        assert_eq!(economics.net_profit(), I256::from(100));
+
+        // A bribe above the profit.
+        let economics = QueueEconomics {
+            expected_profit: parse_ether(1).unwrap(),
+            bribe: parse_ether(2).unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(
+            economics.net_profit(),
+            -I256::from_raw(parse_ether(1).unwrap())
+        );
+        assert!(economics
+            .to_string()
+            .contains("net -1.000000000000000000 ETH"));
+
+        let economics = QueueEconomics {
+            gas_cost: U256::MAX,
+            ..Default::default()
+        };
+        assert_eq!(economics.net_profit(), I256::MIN + I256::one());
+    }
+
+    #[tokio::test]
+    async fn send_queue_refuse_non_positive_net() {
+        let (client, _) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let mut tx_queue = TxQueue::from(vec![TransactionRequest::new().gas(100000).gas_price(10)]);
+        // Exactly the gas cost, nothing left.
+        tx_queue.fill_economics(U256::from(1_000_000), None, U256::zero());
+
+        let err = simulate.send_queue(&tx_queue).await.unwrap_err();
+        assert!(matches!(err, SimulateError::Unprofitable { net_profit } if net_profit.is_zero()));
+
+        // Let through, it only fails on the empty mock.
+        let simulate = simulate.refuse_unprofitable(false);
+        let err = simulate.send_queue(&tx_queue).await.unwrap_err();
+        assert!(matches!(err, SimulateError::Middleware(_)));
+    }
+}
@@ -0,0 +1,461 @@
+use super::PipelineStage;
+use crate::utils::RelayOutcome;
+use ethers::prelude::{Address, BlockId, Bytes, ProviderError, TxHash, I256, U256, U64};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+use url::Url;
+
+#[derive(Debug)]
+pub enum SimulateError {
+    // The node does not expose the `trace_` namespace (geth, free-tier infura/alchemy, etc.)
+    TraceApiUnsupported {
+        provider_hint: String,
+    },
+    Middleware(String),
+    Analyze(String),
+    // The node doesn't know the block, e.g. one it pruned or not yet mined.
+    BlockNotFound(BlockId),
+    // A block the node served without `field`, e.g. the number of the pending one.
+    MissingBlockField(&'static str),
+    // A `symbol()` or `decimals()` answer of `token` that doesn't decode, e.g. from a contract
+    // that isn't an erc20.
+    TokenMetadataDecode {
+        token: Address,
+        field: &'static str,
+    },
+    // A `getL1Fee` answer of the gas price oracle that isn't a uint256.
+    L1FeeDecode(Bytes),
+    // The node doesn't know the tx.
+    TxNotFound(TxHash),
+    // A pending tx the node announced but didn't serve in `tries`, e.g. already dropped.
+    PendingTxNotFound {
+        tx_hash: TxHash,
+        tries: u32,
+    },
+    // A tx the node knows with no receipt, i.e. not mined yet.
+    ReceiptNotFound(TxHash),
+    // Raw tx bytes that are not a valid rlp / typed envelope.
+    RawTxDecode(String),
+    // Typed envelope other than 2930 / 1559.
+    UnsupportedTxType(u8),
+    SignatureRecovery(String),
+    // The replayed queue didn't earn what the analysis of the victim's trace promised.
+    VerificationMismatch {
+        analyzed: U256,
+        verified: I256,
+    },
+    // The victim tx was signed for another chain than the one the rpc serves.
+    ChainIdMismatch {
+        tx: U256,
+        provider: U256,
+    },
+    // Signing the queue entry at `index` failed, nothing of the queue is signed then.
+    Signing {
+        index: usize,
+        reason: String,
+    },
+    // Not even the signer holds the value the queue needs.
+    InsufficientFunds {
+        address: Address,
+        required: U256,
+        balance: U256,
+    },
+    // The chain passed the last block the queue was valid for, sending it would only lose the gas.
+    QueueExpired {
+        valid_until_block: U64,
+        current_block: U64,
+    },
+    // Fields an export for an external signer needs but the queue doesn't set, as `{index}.{field}`.
+    IncompleteTx(Vec<String>),
+    // An unsigned json export of another `version` than this build reads, `None` without one.
+    // A chain id ethers doesn't know, so there's no telling which tx types it takes.
+    UnknownChain(u64),
+    // No entry of the queues has a `from` to assign nonces for.
+    NoSender,
+    UnsupportedUnsignedJson(Option<u64>),
+    // An unsigned json export that isn't json, or whose txs don't decode.
+    UnsignedJsonDecode(serde_json::Error),
+    // Another count of signatures than entries given to `TxQueue::import_signatures`.
+    SignatureCountMismatch {
+        signatures: usize,
+        entries: usize,
+    },
+    // Fees the node would reject, caught before signing.
+    FeeBelowBaseFee {
+        max_fee_per_gas: U256,
+        base_fee: U256,
+    },
+    ZeroGasPrice,
+    // A `FeeBudget` over no gas caps nothing, `fill_gas` has to run before `break_even_fees`.
+    NoGasBudget,
+    // More trace entries than `Simulate::max_trace_entries`, the queue isn't rebuilt from it.
+    TraceTooLarge {
+        entries: usize,
+        max_trace_entries: usize,
+    },
+    // The queue's costs eat up its expected profit, see `QueueEconomics::net_profit`.
+    Unprofitable {
+        net_profit: I256,
+    },
+    // The replay on the target block's parent state says the bundle must not be sent, see
+    // `Simulate::gate_bundle` and the relay's simulation of `BundleSubmitter::submit_all`.
+    GateRefused {
+        block: U64,
+        reason: String,
+    },
+    // The victim was mined by `block`, and the queue replayed without it on top of its state
+    // didn't pass the gate either.
+    VictimAlreadyIncluded {
+        block: U64,
+        reason: String,
+    },
+    // The relay requests would be signed with the tx signer's key, see
+    // `BundleSubmitter::allow_shared_key`.
+    SharedReputationKey {
+        address: Address,
+    },
+    // The entry at `index` was mined with status 0, see `TxQueue::execute_sequential`.
+    TxReverted {
+        index: usize,
+        tx_hash: TxHash,
+        reason: String,
+    },
+    // The entry at `index` got no receipt with enough confirmations in time, it may still land.
+    ReceiptTimeout {
+        index: usize,
+        tx_hash: TxHash,
+        timeout: Duration,
+    },
+    // Reading or writing an `OpportunityStore` failed.
+    Store(String),
+    // The node has neither `anvil_impersonateAccount` nor `hardhat_impersonateAccount`, see
+    // `Simulate::impersonate`.
+    ImpersonationUnsupported(Address),
+    // Reading or writing the saved trace at `path` failed, see `Simulate::trace_to_file`.
+    TraceFileIo {
+        path: PathBuf,
+        source: io::Error,
+    },
+    // A saved trace that isn't json, or whose tx or trace doesn't decode.
+    TraceFileDecode {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    // A saved trace of another `version` than `TRACE_FILE_VERSION`, `None` without one.
+    UnsupportedTraceFile {
+        path: PathBuf,
+        version: Option<u64>,
+    },
+    // `eth_sendBundle` params that don't parse, see `BundlePayload::from_relay_json`.
+    RelayJson(String),
+    // `BundleSubmitter::submit_private` sends a single tx, not a queue of `entries`.
+    PrivateTxNotSingle {
+        entries: usize,
+    },
+    // Fewer relays than `BundleSubmitter::min_accepted` took the bundle, with every relay's answer.
+    BundleNotAccepted {
+        accepted: usize,
+        min_accepted: usize,
+        outcomes: HashMap<Url, RelayOutcome>,
+    },
+    // The reputation key didn't sign a relay request.
+    AuthSigning(String),
+    // No relay of the flashbots dialect to ask for stats, or `relay` isn't one.
+    NoStatsRelay {
+        relay: Option<Url>,
+    },
+    // The relay didn't answer the stats request `method`.
+    StatsRequest {
+        method: String,
+        outcome: RelayOutcome,
+    },
+    // Stats that don't read as the relay's schema.
+    StatsDecode(String),
+    // The MEV-Share event stream didn't connect or broke off.
+    MevShareStream(reqwest::Error),
+    // A MEV-Share event whose data isn't json.
+    MevShareEventDecode(serde_json::Error),
+    // A MEV-Share event without the hash of the hinted tx.
+    MevShareHintWithoutHash,
+    // The analyzed profit doesn't reach `ExecutionPolicy::min_profit`.
+    BelowMinProfit {
+        profit: U256,
+        min_profit: U256,
+    },
+    // The trace holds no call a queue could be rebuilt from.
+    NoQueue,
+    // No tx list of the opportunity replays without a revert, with the first that reverted.
+    EveryQueueReverts(String),
+    // The gas the queue may use is exceeded by `excess`, see `Simulate::estimate_queue_gas`.
+    OverGasBudget {
+        excess: U256,
+    },
+    // A `RateLimit` whose tokens never come, it has to be positive.
+    InvalidRateLimit {
+        per_second: f64,
+    },
+    // `Simulate::pooled_tx_queue` / `sign_pooled` without `Simulate::signer_pool`.
+    NoSignerPool,
+    // No signer of the pool holds the value and gas of the queue on top of what it has in flight.
+    NoSignerCanPay,
+    SignerNotInPool {
+        address: Address,
+    },
+    // An amount past `U256`, e.g. from a node's wild numbers, with what it was.
+    Overflow(&'static str),
+    // A `trace_callMany` answer with another count of traces than the txs sent.
+    TraceCountMismatch {
+        expected: usize,
+        traces: usize,
+    },
+    // The tx of a `SimulateTarget::BeforeTx` isn't mined, so there's no block to rewind to.
+    TargetTxNotMined(TxHash),
+    // The tx of a `SimulateTarget::BeforeTx` isn't in the block its receipt points to, e.g.
+    // after a reorg.
+    TargetTxNotInBlock {
+        tx_hash: TxHash,
+        block: U64,
+    },
+    // `Simulate::cross_check_trace` got no provider to compare the client's trace to.
+    CrossCheckSingleSource,
+    // A stage of `Simulate::run_and_execute` failed, nothing after it ran.
+    PipelineStopped {
+        stage: PipelineStage,
+        error: Box<SimulateError>,
+    },
+}
+
+impl SimulateError {
+    pub(crate) fn middleware<E: Error>(err: E) -> Self {
+        Self::Middleware(err.to_string())
+    }
+
+    pub(crate) fn analyze<E: fmt::Display + ?Sized>(err: &E) -> Self {
+        Self::Analyze(err.to_string())
+    }
+}
+
+impl fmt::Display for SimulateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TraceApiUnsupported { provider_hint } => write!(
+                f,
+                "trace_call is not supported by the rpc ({provider_hint}), \
+                 use a node with the `trace_` namespace enabled (erigon, nethermind, reth, openethereum) \
+                 or a provider that exposes it (e.g. ankr)"
+            ),
+            Self::Middleware(err) => write!(f, "middleware error: {err}"),
+            Self::Analyze(err) => write!(f, "analyze error: {err}"),
+            Self::BlockNotFound(block) => write!(f, "block {block:?} not found"),
+            Self::MissingBlockField(field) => write!(f, "block without {field}"),
+            Self::TokenMetadataDecode { token, field } => write!(f, "invalid {field} of {token:?}"),
+            Self::L1FeeDecode(output) => write!(f, "invalid getL1Fee output {output}"),
+            Self::TxNotFound(tx_hash) => write!(f, "tx {tx_hash:?} not found"),
+            Self::PendingTxNotFound { tx_hash, tries } => {
+                write!(f, "tx {tx_hash:?} not found after {tries} tries")
+            }
+            Self::ReceiptNotFound(tx_hash) => write!(f, "tx {tx_hash:?} has no receipt"),
+            Self::RawTxDecode(err) => write!(f, "raw tx decode error: {err}"),
+            Self::UnsupportedTxType(tx_type) => write!(f, "unsupported tx type: {tx_type:#04x}"),
+            Self::SignatureRecovery(err) => write!(f, "signature recovery error: {err}"),
+            Self::VerificationMismatch { analyzed, verified } => write!(
+                f,
+                "verified profit {verified} diverges from analyzed profit {analyzed}"
+            ),
+            Self::ChainIdMismatch { tx, provider } => {
+                write!(f, "tx chain id {tx} differs from the rpc chain id {provider}")
+            }
+            Self::Signing { index, reason } => {
+                write!(f, "signing queue entry {index} failed: {reason}")
+            }
+            Self::InsufficientFunds {
+                address,
+                required,
+                balance,
+            } => write!(
+                f,
+                "{address:?} holds {balance} but the queue needs {required}"
+            ),
+            Self::QueueExpired {
+                valid_until_block,
+                current_block,
+            } => write!(
+                f,
+                "queue expired at block {valid_until_block}, the chain is at {current_block}"
+            ),
+            Self::IncompleteTx(missing) => {
+                write!(f, "queue is missing {}", missing.join(", "))
+            }
+            Self::UnknownChain(chain_id) => write!(f, "unknown chain id {chain_id}"),
+            Self::NoSender => write!(f, "no sender to assign nonces for"),
+            Self::UnsupportedUnsignedJson(version) => {
+                write!(f, "unsupported unsigned json version {version:?}")
+            }
+            Self::UnsignedJsonDecode(err) => write!(f, "invalid unsigned json: {err}"),
+            Self::SignatureCountMismatch {
+                signatures,
+                entries,
+            } => write!(f, "{signatures} signatures for {entries} entries"),
+            Self::FeeBelowBaseFee {
+                max_fee_per_gas,
+                base_fee,
+            } => write!(
+                f,
+                "max fee per gas {max_fee_per_gas} is below the base fee {base_fee}"
+            ),
+            Self::ZeroGasPrice => write!(f, "gas price is zero"),
+            Self::NoGasBudget => write!(f, "fee budget covers no gas, fill the gas first"),
+            Self::TraceTooLarge {
+                entries,
+                max_trace_entries,
+            } => write!(
+                f,
+                "trace has {entries} entries, more than the {max_trace_entries} allowed"
+            ),
+            Self::Unprofitable { net_profit } => {
+                write!(f, "queue nets {net_profit} after its costs")
+            }
+            Self::GateRefused { block, reason } => {
+                write!(f, "bundle refused by its replay on block {block}: {reason}")
+            }
+            Self::VictimAlreadyIncluded { block, reason } => write!(
+                f,
+                "victim already included by block {block}, refused after it: {reason}"
+            ),
+            Self::SharedReputationKey { address } => write!(
+                f,
+                "{address:?} signs both the txs and the relay requests, use a separate reputation key"
+            ),
+            Self::TxReverted {
+                index,
+                tx_hash,
+                reason,
+            } => write!(f, "queue entry {index} ({tx_hash:?}) reverted: {reason}"),
+            Self::ReceiptTimeout {
+                index,
+                tx_hash,
+                timeout,
+            } => write!(
+                f,
+                "queue entry {index} ({tx_hash:?}) not confirmed within {timeout:?}"
+            ),
+            Self::Store(err) => write!(f, "opportunity store error: {err}"),
+            Self::ImpersonationUnsupported(address) => {
+                write!(f, "node can't impersonate {address:?}")
+            }
+            Self::TraceFileIo { path, source } => {
+                write!(f, "trace file {}: {source}", path.display())
+            }
+            Self::TraceFileDecode { path, source } => {
+                write!(f, "invalid trace file {}: {source}", path.display())
+            }
+            Self::UnsupportedTraceFile { path, version } => write!(
+                f,
+                "{}: unsupported trace file version {version:?}",
+                path.display()
+            ),
+            Self::RelayJson(err) => write!(f, "invalid bundle json: {err}"),
+            Self::PrivateTxNotSingle { entries } => {
+                write!(f, "private tx for a queue of {entries} entries")
+            }
+            Self::BundleNotAccepted {
+                accepted,
+                min_accepted,
+                outcomes,
+            } => write!(
+                f,
+                "bundle accepted by {accepted} of {} relays, {min_accepted} required: {outcomes:?}",
+                outcomes.len()
+            ),
+            Self::AuthSigning(err) => write!(f, "relay request signing failed: {err}"),
+            Self::NoStatsRelay { relay: Some(relay) } => {
+                write!(f, "{relay} isn't a relay of the flashbots dialect for stats")
+            }
+            Self::NoStatsRelay { relay: None } => {
+                write!(f, "no relay of the flashbots dialect for stats")
+            }
+            Self::StatsRequest { method, outcome } => write!(f, "{method}: {outcome:?}"),
+            Self::StatsDecode(err) => write!(f, "invalid stats: {err}"),
+            Self::MevShareStream(err) => write!(f, "mev-share stream: {err}"),
+            Self::MevShareEventDecode(err) => write!(f, "invalid mev-share event: {err}"),
+            Self::MevShareHintWithoutHash => write!(f, "mev-share event without hash"),
+            Self::BelowMinProfit { profit, min_profit } => {
+                write!(f, "profit {profit} below the minimum {min_profit}")
+            }
+            Self::NoQueue => write!(f, "no queue reconstructed from the trace"),
+            Self::EveryQueueReverts(first) => write!(f, "every tx list reverts, {first}"),
+            Self::OverGasBudget { excess } => {
+                write!(f, "queue over the gas budget by {excess}")
+            }
+            Self::InvalidRateLimit { per_second } => {
+                write!(f, "rate limit of {per_second} per second, it must be positive")
+            }
+            Self::NoSignerPool => write!(f, "no signer pool set"),
+            Self::NoSignerCanPay => write!(f, "no signer of the pool can pay the queue"),
+            Self::SignerNotInPool { address } => write!(f, "{address:?} not in the signer pool"),
+            Self::Overflow(what) => write!(f, "{what} overflows"),
+            Self::TargetTxNotMined(tx_hash) => write!(f, "{tx_hash:?} is not mined"),
+            Self::TargetTxNotInBlock { tx_hash, block } => {
+                write!(f, "{tx_hash:?} not in block {block}")
+            }
+            Self::TraceCountMismatch { expected, traces } => {
+                write!(f, "{traces} traces for {expected} txs")
+            }
+            Self::CrossCheckSingleSource => {
+                write!(f, "a single trace source, a cross check needs two")
+            }
+            Self::PipelineStopped { stage, error } => {
+                write!(f, "pipeline stopped at {stage:?}: {error}")
+            }
+        }
+    }
+}
+
+impl Error for SimulateError {}
+
+// For a `Provider` over `HttpBatch`.
+impl From<SimulateError> for ProviderError {
+    fn from(err: SimulateError) -> Self {
+        Self::JsonRpcClientError(Box::new(err))
+    }
+}
+
+// Providers word "method not found" differently, match the known variants.
+pub(crate) fn is_method_unavailable(message: &str) -> bool {
+    let message = message.to_lowercase();
+    [
+        "-32601",
+        "method not found",
+        "does not exist/is not available",
+        "is not available on",
+        "unsupported method",
+    ]
+    .iter()
+    .any(|pattern| message.contains(pattern))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_method_unavailable;
+
+    #[test]
+    fn detect_method_unavailable() {
+        assert!(is_method_unavailable(
+            "(code: -32601, message: the method trace_call does not exist/is not available, data: None)"
+        ));
+        assert!(is_method_unavailable("Method not found"));
+        assert!(is_method_unavailable(
+            "trace_call is not available on the Free tier"
+        ));
+    }
+
+    #[test]
+    fn not_detect_other_errors() {
+        assert!(!is_method_unavailable("header not found"));
+        assert!(!is_method_unavailable("execution reverted"));
+    }
+}
@@ -0,0 +1,564 @@
+use super::{SimulateError, TxQueue};
+use async_trait::async_trait;
+use ethers::prelude::*;
+use std::sync::Mutex;
+
+// How soon the queue should be included, trades the tip against the chance to land.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Urgency {
+    // The next block only, its base fee is already known so the max fee has no headroom.
+    NextBlock,
+    #[default]
+    Fast,
+    // Stays valid for several blocks of rising base fee.
+    Standard,
+}
+
+impl Urgency {
+    fn reward_percentile(&self) -> f64 {
+        match self {
+            Self::NextBlock => 90.0,
+            Self::Fast => 60.0,
+            Self::Standard => 30.0,
+        }
+    }
+
+    // The base fee can rise 12.5% per block.
+    pub(crate) fn max_base_fee(&self, next_base_fee: U256) -> Result<U256, SimulateError> {
+        match self {
+            Self::NextBlock => Some(next_base_fee),
+            Self::Fast => next_base_fee.checked_mul(U256::from(9)).map(|fee| fee / 8),
+            Self::Standard => next_base_fee.checked_mul(U256::from(2)),
+        }
+        .ok_or(SimulateError::Overflow("max base fee"))
+    }
+
+    fn gas_price_pct(&self) -> u64 {
+        match self {
+            Self::NextBlock => 120,
+            Self::Fast => 110,
+            Self::Standard => 100,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeeEstimate {
+    Eip1559 {
+        max_fee_per_gas: U256,
+        max_priority_fee_per_gas: U256,
+    },
+    // Chains without EIP-1559.
+    Legacy {
+        gas_price: U256,
+    },
+}
+
+impl FeeEstimate {
+    // Never pay more than `max_fee` per gas.
+    pub fn capped(self, max_fee: U256) -> Self {
+        match self {
+            Self::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            } => {
+                let max_fee_per_gas = max_fee_per_gas.min(max_fee);
+                Self::Eip1559 {
+                    max_fee_per_gas,
+                    max_priority_fee_per_gas: max_priority_fee_per_gas.min(max_fee_per_gas),
+                }
+            }
+            Self::Legacy { gas_price } => Self::Legacy {
+                gas_price: gas_price.min(max_fee),
+            },
+        }
+    }
+}
+
+// Which fee fields a chain accepts. BSC and the other chains ethers marks as legacy reject the
+// 1559 fields, on the rest a legacy `gas_price` overpays the base fee.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeeMode {
+    Eip1559,
+    Legacy,
+}
+
+impl FeeMode {
+    // Chains ethers doesn't know are assumed to have EIP-1559, `eth_feeHistory` tells otherwise.
+    pub fn for_chain(chain_id: u64) -> Self {
+        match Chain::try_from(chain_id) {
+            Ok(chain) if chain.is_legacy() => Self::Legacy,
+            _ => Self::Eip1559,
+        }
+    }
+}
+
+impl FeeEstimate {
+    // Refuse fees the node would reject or that can't be included: a max fee below the base fee
+    // of the next block, or no gas price at all.
+    pub fn check(&self, base_fee: Option<U256>) -> Result<(), SimulateError> {
+        match *self {
+            Self::Eip1559 {
+                max_fee_per_gas, ..
+            } => match base_fee {
+                Some(base_fee) if max_fee_per_gas < base_fee => {
+                    Err(SimulateError::FeeBelowBaseFee {
+                        max_fee_per_gas,
+                        base_fee,
+                    })
+                }
+                _ => Ok(()),
+            },
+            Self::Legacy { gas_price } if gas_price.is_zero() => Err(SimulateError::ZeroGasPrice),
+            Self::Legacy { .. } => Ok(()),
+        }
+    }
+}
+
+// What a queue can pay for its gas without losing money, from `TxQueue::break_even_fees`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeeBudget {
+    pub total_gas: U256,
+    // The profit left after the margin, the most the whole queue may cost.
+    pub budget: U256,
+    // Base fee plus tip per gas, rounded down so the queue never pays more than `budget`.
+    pub max_fee_per_gas: U256,
+    // The L1 data fee on OP-stack chains, already taken out of `budget`.
+    pub l1_fee: U256,
+}
+
+impl FeeBudget {
+    // What the queue costs at `fee_per_gas`, the L1 fee included.
+    pub fn required_cost(&self, fee_per_gas: U256) -> U256 {
+        fee_per_gas
+            .saturating_mul(self.total_gas)
+            .saturating_add(self.l1_fee)
+    }
+
+    // The tip left per gas once the projected base fee is burnt.
+    pub fn max_priority_fee_per_gas(&self, base_fee: U256) -> U256 {
+        self.max_fee_per_gas.saturating_sub(base_fee)
+    }
+
+    // The most a bribe tx can send to the coinbase, the queue itself only pays the base fee then.
+    pub fn max_coinbase_tip(&self, base_fee: U256) -> U256 {
+        self.budget
+            .saturating_sub(base_fee.saturating_mul(self.total_gas))
+    }
+}
+
+#[async_trait]
+pub trait FeeEstimator: Send + Sync {
+    async fn estimate(&self, urgency: Urgency) -> Result<FeeEstimate, SimulateError>;
+
+    // The next block's base fee seen by the last `estimate`, for `FeeEstimate::check`.
+    fn last_base_fee(&self) -> Option<U256> {
+        None
+    }
+}
+
+// Tip from the `eth_feeHistory` reward percentiles of the last blocks, `eth_gasPrice` on
+// chains that don't support it or are known to be legacy.
+pub struct FeeHistoryEstimator<'c, M> {
+    client: &'c M,
+    block_count: u64,
+    mode: Option<FeeMode>,
+    last_base_fee: Mutex<Option<U256>>,
+}
+
+impl<'c, M: Middleware> FeeHistoryEstimator<'c, M> {
+    pub fn new(client: &'c M) -> Self {
+        Self {
+            client,
+            block_count: 10,
+            mode: None,
+            last_base_fee: Mutex::new(None),
+        }
+    }
+
+    pub fn block_count(mut self, block_count: u64) -> Self {
+        self.block_count = block_count;
+        self
+    }
+
+    // Go straight to `eth_gasPrice` on a legacy chain instead of asking for a fee history.
+    pub fn chain_id(mut self, chain_id: u64) -> Self {
+        self.mode = Some(FeeMode::for_chain(chain_id));
+        self
+    }
+}
+
+#[async_trait]
+impl<'c, M: Middleware> FeeEstimator for FeeHistoryEstimator<'c, M> {
+    async fn estimate(&self, urgency: Urgency) -> Result<FeeEstimate, SimulateError> {
+        let fee_history = match self.mode {
+            Some(FeeMode::Legacy) => None,
+            _ => self
+                .client
+                .fee_history(
+                    self.block_count,
+                    BlockNumber::Latest,
+                    &[urgency.reward_percentile()],
+                )
+                .await
+                .ok(),
+        };
+
+        // The last base fee is the one of the next block.
+        match fee_history {
+            Some(fee_history) if !fee_history.base_fee_per_gas.is_empty() => {
+                let next_base_fee = *fee_history.base_fee_per_gas.last().unwrap();
+                *self.last_base_fee.lock().unwrap() = Some(next_base_fee);
+                let mut rewards = fee_history
+                    .reward
+                    .iter()
+                    .filter_map(|reward| reward.first().copied())
+                    .collect::<Vec<_>>();
+                rewards.sort();
+                let max_priority_fee_per_gas =
+                    rewards.get(rewards.len() / 2).copied().unwrap_or_default();
+
+                Ok(FeeEstimate::Eip1559 {
+                    max_fee_per_gas: urgency
+                        .max_base_fee(next_base_fee)?
+                        .checked_add(max_priority_fee_per_gas)
+                        .ok_or(SimulateError::Overflow("max fee per gas"))?,
+                    max_priority_fee_per_gas,
+                })
+            }
+            _ => {
+                *self.last_base_fee.lock().unwrap() = None;
+                let gas_price = self
+                    .client
+                    .get_gas_price()
+                    .await
+                    .map_err(SimulateError::middleware)?;
+                Ok(FeeEstimate::Legacy {
+                    gas_price: gas_price
+                        .checked_mul(urgency.gas_price_pct().into())
+                        .ok_or(SimulateError::Overflow("gas price"))?
+                        / 100,
+                })
+            }
+        }
+    }
+
+    fn last_base_fee(&self) -> Option<U256> {
+        *self.last_base_fee.lock().unwrap()
+    }
+}
+
+impl TxQueue {
+    // Gas of the whole queue, the trace-derived gas for entries without one.
+    pub fn total_gas(&self) -> Result<U256, SimulateError> {
+        self.entries
+            .iter()
+            .filter_map(|entry| entry.tx.gas.or(entry.trace_gas))
+            .try_fold(U256::zero(), |total, gas| total.checked_add(gas))
+            .ok_or(SimulateError::Overflow("total gas"))
+    }
+
+    // What the gas of the queue costs in a block at `base_fee`: the base fee and the tip of every
+    // EIP-1559 entry, the gas price (at least the base fee) of a legacy one.
+    pub fn gas_cost_at(&self, base_fee: U256) -> U256 {
+        self.entries.iter().fold(U256::zero(), |cost, entry| {
+            let gas = entry.tx.gas.or(entry.trace_gas).unwrap_or_default();
+            let fee_per_gas = match (entry.max_priority_fee_per_gas, entry.tx.gas_price) {
+                (Some(tip), _) => base_fee.saturating_add(tip),
+                (None, Some(gas_price)) => gas_price.max(base_fee),
+                (None, None) => base_fee,
+            };
+            cost.saturating_add(gas.saturating_mul(fee_per_gas))
+        })
+    }
+
+    // Keep `margin_bps` of `profit` and spread the rest over the gas of the queue. Every division
+    // rounds down, rounding up would pay a wei more than the profit. Run it after `fill_gas`.
+    pub fn break_even_fees(
+        &self,
+        profit: U256,
+        margin_bps: u32,
+    ) -> Result<FeeBudget, SimulateError> {
+        let total_gas = self.total_gas()?;
+        let budget = profit
+            .checked_mul(U256::from(10_000_u32.saturating_sub(margin_bps)))
+            .ok_or(SimulateError::Overflow("fee budget"))?
+            / 10_000;
+        let max_fee_per_gas = if total_gas.is_zero() {
+            U256::zero()
+        } else {
+            budget / total_gas
+        };
+        Ok(FeeBudget {
+            total_gas,
+            budget,
+            max_fee_per_gas,
+            l1_fee: U256::zero(),
+        })
+    }
+
+    // Set the fees of every entry with `estimator`, but never more per gas than `budget` allows,
+    // so the gas can't cost more than it earns. A budget below the base fee is refused here rather
+    // than by the node once signed, as is one over no gas.
+    pub async fn fill_fees<E: FeeEstimator + ?Sized>(
+        &mut self,
+        estimator: &E,
+        urgency: Urgency,
+        budget: &FeeBudget,
+    ) -> Result<FeeEstimate, SimulateError> {
+        if budget.total_gas.is_zero() {
+            return Err(SimulateError::NoGasBudget);
+        }
+        let fees = estimator
+            .estimate(urgency)
+            .await?
+            .capped(budget.max_fee_per_gas);
+        fees.check(estimator.last_base_fee())?;
+
+        for entry in &mut self.entries {
+            match fees {
+                FeeEstimate::Eip1559 {
+                    max_fee_per_gas,
+                    max_priority_fee_per_gas,
+                } => {
+                    entry.tx.gas_price = None;
+                    entry.max_fee_per_gas = Some(max_fee_per_gas);
+                    entry.max_priority_fee_per_gas = Some(max_priority_fee_per_gas);
+                }
+                FeeEstimate::Legacy { gas_price } => {
+                    entry.tx.gas_price = Some(gas_price);
+                    entry.max_fee_per_gas = None;
+                    entry.max_priority_fee_per_gas = None;
+                }
+            }
+        }
+
+        Ok(fees)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FeeBudget, FeeEstimate, FeeEstimator, FeeHistoryEstimator, FeeMode, Urgency};
+    use crate::utils::{SimulateError, TxQueue};
+    use async_trait::async_trait;
+    use ethers::{prelude::*, utils::parse_ether};
+
+    struct FixedFees(FeeEstimate);
+
+    #[async_trait]
+    impl FeeEstimator for FixedFees {
+        async fn estimate(&self, _urgency: Urgency) -> Result<FeeEstimate, SimulateError> {
+            Ok(self.0)
+        }
+    }
+
+    #[tokio::test]
+    async fn fee_history_estimate_median_tip() {
+        let (provider, mock) = Provider::mocked();
+        mock.push(FeeHistory {
+            base_fee_per_gas: vec![U256::from(90), U256::from(100)],
+            gas_used_ratio: vec![0.5],
+            oldest_block: U256::from(1),
+            reward: vec![
+                vec![U256::from(1)],
+                vec![U256::from(3)],
+                vec![U256::from(2)],
+            ],
+        })
+        .unwrap();
+
+        let fees = FeeHistoryEstimator::new(&provider)
+            .estimate(Urgency::Standard)
+            .await
+            .unwrap();
+        assert_eq!(
+            fees,
+            FeeEstimate::Eip1559 {
+                max_fee_per_gas: U256::from(202),
+                max_priority_fee_per_gas: U256::from(2),
+            }
+        );
+    }
+
+    fn fee_history(base_fee: u64, tip: u64) -> FeeHistory {
+        FeeHistory {
+            base_fee_per_gas: vec![U256::from(base_fee)],
+            gas_used_ratio: vec![0.5],
+            oldest_block: U256::from(1),
+            reward: vec![vec![U256::from(tip)]],
+        }
+    }
+
+    #[tokio::test]
+    async fn fee_history_estimate_pick_fee_mode_of_chain() {
+        assert_eq!(FeeMode::for_chain(1), FeeMode::Eip1559);
+        assert_eq!(FeeMode::for_chain(56), FeeMode::Legacy);
+
+        // Mainnet asks for the fee history.
+        let (provider, mock) = Provider::mocked();
+        mock.push(fee_history(100, 2)).unwrap();
+        let estimator = FeeHistoryEstimator::new(&provider).chain_id(1);
+        assert!(matches!(
+            estimator.estimate(Urgency::NextBlock).await.unwrap(),
+            FeeEstimate::Eip1559 { .. }
+        ));
+        assert_eq!(estimator.last_base_fee(), Some(U256::from(100)));
+
+        // BSC goes straight to the gas price.
+        let (provider, mock) = Provider::mocked();
+        mock.push(U256::from(5)).unwrap();
+        let estimator = FeeHistoryEstimator::new(&provider).chain_id(56);
+        assert_eq!(
+            estimator.estimate(Urgency::Standard).await.unwrap(),
+            FeeEstimate::Legacy {
+                gas_price: U256::from(5)
+            }
+        );
+        mock.assert_request("eth_gasPrice", ()).unwrap();
+        assert_eq!(estimator.last_base_fee(), None);
+
+        // A chain whose node has no `eth_feeHistory`, the response doesn't parse.
+        let (provider, mock) = Provider::mocked();
+        mock.push(U256::from(7)).unwrap();
+        mock.push(serde_json::json!({ "error": "method not found" }))
+            .unwrap();
+        let estimator = FeeHistoryEstimator::new(&provider).chain_id(123456);
+        assert_eq!(
+            estimator.estimate(Urgency::Standard).await.unwrap(),
+            FeeEstimate::Legacy {
+                gas_price: U256::from(7)
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn fill_fees_refuse_max_fee_below_base_fee() {
+        let mut tx_queue = TxQueue::from(vec![TransactionRequest::new().gas(100000)]);
+        let (provider, mock) = Provider::mocked();
+        mock.push(fee_history(100, 2)).unwrap();
+        let estimator = FeeHistoryEstimator::new(&provider).chain_id(1);
+
+        // 5000000 wei over 100000 gas is 50 per gas, half the base fee.
+        let budget = tx_queue.break_even_fees(U256::from(5_000_000), 0).unwrap();
+        let err = tx_queue
+            .fill_fees(&estimator, Urgency::NextBlock, &budget)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            SimulateError::FeeBelowBaseFee { max_fee_per_gas, base_fee }
+                if max_fee_per_gas == U256::from(50) && base_fee == U256::from(100)
+        ));
+        assert_eq!(tx_queue.entries[0].max_fee_per_gas, None);
+
+        let estimator = FixedFees(FeeEstimate::Legacy {
+            gas_price: U256::zero(),
+        });
+        let err = tx_queue
+            .fill_fees(&estimator, Urgency::Fast, &budget)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SimulateError::ZeroGasPrice));
+
+        // No gas filled, nothing to spread the budget over.
+        let mut tx_queue = TxQueue::from(vec![TransactionRequest::new()]);
+        let budget = tx_queue.break_even_fees(U256::from(5_000_000), 0).unwrap();
+        let err = tx_queue
+            .fill_fees(&estimator, Urgency::Fast, &budget)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SimulateError::NoGasBudget));
+    }
+
+    #[tokio::test]
+    async fn fill_fees_cap_by_expected_profit() {
+        let mut tx_queue = TxQueue::from(vec![
+            TransactionRequest::new().gas(100000),
+            TransactionRequest::new().gas(100000),
+        ]);
+        let estimator = FixedFees(FeeEstimate::Eip1559 {
+            max_fee_per_gas: U256::from(50),
+            max_priority_fee_per_gas: U256::from(40),
+        });
+
+        // 200000 gas for 6000000 wei, at most 30 per gas.
+        let budget = tx_queue.break_even_fees(U256::from(6_000_000), 0).unwrap();
+        tx_queue
+            .fill_fees(&estimator, Urgency::Fast, &budget)
+            .await
+            .unwrap();
+        for entry in &tx_queue.entries {
+            assert_eq!(entry.max_fee_per_gas, Some(U256::from(30)));
+            assert_eq!(entry.max_priority_fee_per_gas, Some(U256::from(30)));
+        }
+
+        let estimator = FixedFees(FeeEstimate::Legacy {
+            gas_price: U256::from(20),
+        });
+        tx_queue
+            .fill_fees(&estimator, Urgency::Fast, &budget)
+            .await
+            .unwrap();
+        assert_eq!(tx_queue.entries[0].tx.gas_price, Some(U256::from(20)));
+        assert_eq!(tx_queue.entries[0].max_fee_per_gas, None);
+    }
+
+    #[test]
+    fn break_even_fees_of_queue() {
+        let gwei = |amount: u64| U256::from(amount) * U256::exp10(9);
+        let tx_queue = TxQueue::from(vec![
+            TransactionRequest::new().gas(150000),
+            TransactionRequest::new().gas(250000),
+        ]);
+
+        // 0.05 ETH over 400k gas is 125 gwei per gas.
+        let budget = tx_queue
+            .break_even_fees(parse_ether("0.05").unwrap(), 0)
+            .unwrap();
+        assert_eq!(
+            budget,
+            FeeBudget {
+                total_gas: U256::from(400000),
+                budget: parse_ether("0.05").unwrap(),
+                max_fee_per_gas: gwei(125),
+                l1_fee: U256::zero(),
+            }
+        );
+        assert_eq!(budget.max_priority_fee_per_gas(gwei(100)), gwei(25));
+        assert_eq!(budget.max_priority_fee_per_gas(gwei(130)), U256::zero());
+        // 0.05 ETH minus the 0.04 ETH burnt.
+        assert_eq!(
+            budget.max_coinbase_tip(gwei(100)),
+            parse_ether("0.01").unwrap()
+        );
+
+        // 10% margin, the wei that doesn't divide evenly is kept.
+        let budget = tx_queue
+            .break_even_fees(parse_ether("0.05").unwrap() + 1, 1000)
+            .unwrap();
+        assert_eq!(budget.budget, parse_ether("0.045").unwrap());
+        assert_eq!(budget.max_fee_per_gas, U256::from(112_500_000_000_u64));
+
+        // 0.045 ETH over 300001 gas is 149999500001.66, rounded down.
+        let tx_queue = TxQueue::from(vec![TransactionRequest::new().gas(300001)]);
+        let budget = tx_queue
+            .break_even_fees(parse_ether("0.05").unwrap(), 1000)
+            .unwrap();
+        assert_eq!(budget.max_fee_per_gas, U256::from(149_999_500_001_u64));
+
+        // A profit too large to take the margin of, or gas past `U256`.
+        assert!(matches!(
+            tx_queue.break_even_fees(U256::MAX, 1000),
+            Err(SimulateError::Overflow(_))
+        ));
+        let tx_queue = TxQueue::from(vec![
+            TransactionRequest::new().gas(U256::MAX),
+            TransactionRequest::new().gas(1),
+        ]);
+        assert!(matches!(
+            tx_queue.total_gas(),
+            Err(SimulateError::Overflow(_))
+        ));
+    }
+}
@@ -0,0 +1,148 @@
+use super::{ProfitReport, Simulate, SimulateError, SimulateTarget};
+use ethers::{
+    prelude::*,
+    utils::{hex, to_checksum},
+};
+use std::fmt::Write;
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    // A `forge` test replaying the opportunity of the tx on a fork of its simulation block, one
+    // test function per reconstructed queue, each asserting the native profit of the analysis.
+    // The fork rpc is read from `ETH_RPC_URL`. `None` if the tx isn't an opportunity.
+    pub async fn to_foundry_test(
+        &self,
+        tx_hash: TxHash,
+        target: impl Into<SimulateTarget>,
+    ) -> Result<Option<String>, SimulateError> {
+        let opportunity = match self.run(tx_hash, target).await? {
+            Some(opportunity) => opportunity,
+            None => return Ok(None),
+        };
+        let fork_block = match opportunity.block {
+            Some(BlockNumber::Number(block)) => block,
+            _ => self
+                .get_block_number()
+                .await
+                .map_err(SimulateError::middleware)?,
+        };
+
+        // The rewritten calldata sends the profit here, see `mock_tx_data`.
+        let receiver = to_checksum(&self.contract.unwrap_or(self.signer_address), None);
+        let profit = ProfitReport::total_native(&opportunity.reports);
+
+        let mut test = format!(
+            "// SPDX-License-Identifier: UNLICENSED\n\
+             pragma solidity ^0.8.13;\n\n\
+             import \"forge-std/Test.sol\";\n\n\
+             // Replays the opportunity of {tx_hash:?}.\n\
+             contract OpportunityTest is Test {{\n    \
+                 address constant RECEIVER = {receiver};\n\n    \
+                 function setUp() public {{\n        \
+                     vm.createSelectFork(vm.envString(\"ETH_RPC_URL\"), {fork_block});\n    \
+                 }}\n"
+        );
+        for (index, tx_list) in opportunity.tx_queue.iter().enumerate() {
+            let _ = writeln!(
+                test,
+                "\n    function testQueue{index}() public {{\n        \
+                     uint256 balanceBefore = RECEIVER.balance;"
+            );
+            for (call, tx) in tx_list.iter().enumerate() {
+                push_call(&mut test, call, tx);
+            }
+            let _ = writeln!(
+                test,
+                "        assertGe(RECEIVER.balance - balanceBefore, {profit});\n    }}"
+            );
+        }
+        test.push_str("}\n");
+
+        Ok(Some(test))
+    }
+}
+
+// One queued tx as a pranked call from its sender, contract creations go through `create`.
+fn push_call(test: &mut String, call: usize, tx: &TransactionRequest) {
+    let from = to_checksum(&tx.from.unwrap_or_default(), None);
+    let value = tx.value.unwrap_or_default();
+    let data = hex::encode(tx.data.clone().unwrap_or_default());
+    let _ = writeln!(test, "        vm.deal({from}, {from}.balance + {value});");
+    let _ = match &tx.to {
+        Some(to) => {
+            let to = match to {
+                NameOrAddress::Address(to) => to_checksum(to, None),
+                NameOrAddress::Name(name) => name.clone(),
+            };
+            writeln!(
+                test,
+                "        vm.prank({from});\n        \
+                 (bool success{call},) = address({to}).call{{value: {value}}}(hex\"{data}\");\n        \
+                 require(success{call}, \"call {call} reverted\");"
+            )
+        }
+        None => writeln!(
+            test,
+            "        bytes memory init{call} = hex\"{data}\";\n        \
+             address created{call};\n        \
+             vm.prank({from});\n        \
+             assembly {{\n            \
+                 created{call} := create({value}, add(init{call}, 0x20), mload(init{call}))\n        \
+             }}\n        \
+             require(created{call} != address(0), \"create {call} failed\");"
+        ),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        mock::{balance_diff, block_trace, call_trace, mock_client},
+        Simulate,
+    };
+    use ethers::prelude::*;
+    use std::collections::BTreeMap;
+
+    #[tokio::test]
+    async fn to_foundry_test_fork_block_and_replay_queue() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let tx = Transaction {
+            hash: TxHash::random(),
+            block_number: Some(U64::from(100)),
+            input: "0x00000001".parse().unwrap(),
+            ..Default::default()
+        };
+        let state_diff = StateDiff(BTreeMap::from([(
+            tx.from,
+            balance_diff(U256::zero(), U256::from(1000)),
+        )]));
+
+        mock.push(U256::one()).unwrap();
+        mock.push(block_trace(
+            vec![
+                call_trace(vec![], 2, U256::zero()),
+                call_trace(vec![0], 0, U256::zero()),
+                call_trace(vec![1], 0, U256::zero()),
+            ],
+            Some(state_diff),
+        ))
+        .unwrap();
+        mock.push(tx.clone()).unwrap();
+
+        let test = simulate
+            .to_foundry_test(tx.hash, true)
+            .await
+            .unwrap()
+            .unwrap();
+        // Rewound onto the parent block.
+        assert!(test.contains("vm.createSelectFork(vm.envString(\"ETH_RPC_URL\"), 99);"));
+        // The origin call, then the two internal calls.
+        assert_eq!(test.matches("function testQueue").count(), 2);
+        assert_eq!(test.matches(").call{value: 0}").count(), 3);
+        assert_eq!(
+            test.matches("assertGe(RECEIVER.balance - balanceBefore, 1000);")
+                .count(),
+            2
+        );
+    }
+}
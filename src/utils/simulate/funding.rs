@@ -0,0 +1,152 @@
+use super::{QueueEntry, Simulate, SimulateError, TxQueue};
+use ethers::prelude::*;
+use std::collections::BTreeMap;
+
+// Add `value` to what `address` has to hold.
+fn require(
+    required: &mut BTreeMap<Address, U256>,
+    address: Address,
+    value: U256,
+) -> Result<(), SimulateError> {
+    let total = required.entry(address).or_default();
+    *total = total
+        .checked_add(value)
+        .ok_or(SimulateError::Overflow("required value"))?;
+    Ok(())
+}
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    // Prepend a transfer from the signer to the searcher contract when the contract holds less than
+    // the queue pays from its funds. Run it before `assign_nonces` / `fill_gas`, so the transfer gets
+    // a nonce and gas like any other entry. Returns what was transferred, `None` if nothing had to.
+    pub async fn prepend_funding(
+        &self,
+        tx_queue: &mut TxQueue,
+    ) -> Result<Option<U256>, SimulateError> {
+        // The value each address has to hold, the gas comes on top.
+        let mut required = BTreeMap::<Address, U256>::new();
+        for entry in &tx_queue.entries {
+            if let (Some(from), Some(value)) = (entry.tx.from, entry.tx.value) {
+                require(&mut required, from, value)?;
+            }
+            if let (Some(contract), Some(value)) = (self.contract, entry.contract_value) {
+                require(&mut required, contract, value)?;
+            }
+        }
+
+        let signer = self.signer_address;
+        let mut funding = None;
+        if let Some(contract) = self.contract {
+            if let Some(contract_required) = required.remove(&contract) {
+                let balance = self.balance(contract).await?;
+                if contract_required > balance {
+                    let shortfall = contract_required - balance;
+                    require(&mut required, signer, shortfall)?;
+                    funding = Some((contract, shortfall));
+                }
+            }
+        }
+
+        for (address, required) in required {
+            let balance = self.balance(address).await?;
+            if required > balance {
+                return Err(SimulateError::InsufficientFunds {
+                    address,
+                    required,
+                    balance,
+                });
+            }
+        }
+
+        let (contract, shortfall) = match funding {
+            Some(funding) => funding,
+            None => return Ok(None),
+        };
+        let mut tx = TransactionRequest::new()
+            .from(signer)
+            .to(contract)
+            .value(shortfall);
+        tx.chain_id = self.chain_id.get().map(|chain_id| chain_id.as_u64().into());
+        tx_queue.entries.insert(
+            0,
+            QueueEntry {
+                trace_gas: Some(U256::from(21000)),
+                ..QueueEntry::from(tx)
+            },
+        );
+        Ok(Some(shortfall))
+    }
+
+    async fn balance(&self, address: Address) -> Result<U256, SimulateError> {
+        self.get_balance(address, None)
+            .await
+            .map_err(SimulateError::middleware)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        mock::mock_client, QueueEntry, Simulate, SimulateError, TxQueue, ValueSource,
+    };
+    use ethers::{prelude::*, utils::parse_ether};
+
+    fn contract_funded_queue(signer: Address) -> TxQueue {
+        TxQueue {
+            entries: (0..2)
+                .map(|_| QueueEntry {
+                    contract_value: Some(parse_ether(1).unwrap()),
+                    ..TransactionRequest::new().from(signer).into()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn prepend_funding_for_contract_value() {
+        let (client, mock) = mock_client();
+        let contract = Address::random();
+        let simulate = Simulate::init(&client, Some(contract))
+            .await
+            .unwrap()
+            .value_source(ValueSource::ContractFunds);
+        let signer = client.signer().address();
+        let mut tx_queue = contract_funded_queue(signer);
+
+        mock.push(parse_ether(5).unwrap()).unwrap();
+        mock.push(U256::zero()).unwrap();
+
+        let funding = simulate.prepend_funding(&mut tx_queue).await.unwrap();
+        assert_eq!(funding, Some(parse_ether(2).unwrap()));
+        assert_eq!(tx_queue.entries.len(), 3);
+        let funding_tx = &tx_queue.entries[0].tx;
+        assert_eq!(funding_tx.from, Some(signer));
+        assert_eq!(funding_tx.to, Some(contract.into()));
+        assert_eq!(funding_tx.value, Some(parse_ether(2).unwrap()));
+
+        mock.assert_request("eth_getBalance", (contract, "latest"))
+            .unwrap();
+        mock.assert_request("eth_getBalance", (signer, "latest"))
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn prepend_funding_reject_short_signer() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, Some(Address::random()))
+            .await
+            .unwrap()
+            .value_source(ValueSource::ContractFunds);
+        let mut tx_queue = contract_funded_queue(client.signer().address());
+
+        mock.push(parse_ether(1).unwrap()).unwrap();
+        mock.push(U256::zero()).unwrap();
+
+        assert!(matches!(
+            simulate.prepend_funding(&mut tx_queue).await,
+            Err(SimulateError::InsufficientFunds { .. })
+        ));
+        assert_eq!(tx_queue.entries.len(), 2);
+    }
+}
@@ -0,0 +1,316 @@
+use super::{
+    tx_queue::buffered_gas, GasBudget, QueueEntry, Simulate, SimulateError, SimulateTrace, TxQueue,
+};
+use ethers::prelude::*;
+use std::time::Duration;
+
+// Where `estimate_queue_gas` takes the gas of each entry from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GasSource {
+    // `eth_estimateGas` per entry, see `TxQueue::fill_gas`.
+    #[default]
+    Estimate,
+    // The `gas_used` of the entry's call in the victim's trace, no rpc call at all.
+    TraceGasUsed,
+}
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    pub fn gas_estimation(mut self, gas_estimation: GasSource) -> Self {
+        self.gas_estimation = gas_estimation;
+        self
+    }
+
+    // Give up on `eth_estimateGas` of an entry after `timeout`, it then gets the trace-derived gas.
+    pub fn estimate_timeout(mut self, timeout: Duration) -> Self {
+        self.estimate_timeout = Some(timeout);
+        self
+    }
+
+    // Cap the gas of a queue, `estimate_queue_gas` trims optional entries beyond it.
+    pub fn max_total_gas(mut self, max_total_gas: U256) -> Self {
+        self.max_total_gas = Some(max_total_gas);
+        self
+    }
+
+    // Set `gas` of every entry from the configured `GasSource` plus `buffer_pct` percent,
+    // then hold the queue to `max_total_gas` (if set).
+    pub async fn estimate_queue_gas(
+        &self,
+        tx_queue: &mut TxQueue,
+        buffer_pct: u64,
+    ) -> Result<GasBudget, SimulateError> {
+        match self.gas_estimation {
+            GasSource::Estimate => {
+                tx_queue
+                    .fill_gas_within(self.inner, buffer_pct, self.estimate_timeout)
+                    .await?
+            }
+            GasSource::TraceGasUsed => {
+                for entry in &mut tx_queue.entries {
+                    entry.tx.gas = entry
+                        .trace_gas
+                        .map(|gas| buffered_gas(gas, buffer_pct, None));
+                }
+            }
+        }
+
+        match self.max_total_gas {
+            Some(max_total_gas) => tx_queue.enforce_gas_budget(max_total_gas),
+            None => Ok(GasBudget {
+                total_gas: tx_queue.total_gas()?,
+                ..Default::default()
+            }),
+        }
+    }
+
+    // Gas price the tx actually pays per gas, `block` defaults to the tx's own block (latest if pending).
+    pub async fn effective_gas_price(
+        &self,
+        tx: &Transaction,
+        block: Option<BlockNumber>,
+    ) -> Result<U256, SimulateError> {
+        if tx.max_fee_per_gas.is_none() {
+            return Ok(effective_gas_price(tx, None));
+        }
+
+        let block = block
+            .or(tx.block_number.map(BlockNumber::Number))
+            .unwrap_or(BlockNumber::Latest);
+        let base_fee = self
+            .get_block(block)
+            .await
+            .map_err(SimulateError::middleware)?
+            .and_then(|block| block.base_fee_per_gas);
+        Ok(effective_gas_price(tx, base_fee))
+    }
+}
+
+// EIP-1559 tx pays `min(max_fee, base_fee + max_priority_fee)`, legacy tx pays its `gas_price`.
+pub fn effective_gas_price(tx: &Transaction, base_fee: Option<U256>) -> U256 {
+    match (tx.max_fee_per_gas, base_fee) {
+        (Some(max_fee), Some(base_fee)) => {
+            let priority_fee = tx.max_priority_fee_per_gas.unwrap_or_default();
+            max_fee.min(base_fee.saturating_add(priority_fee))
+        }
+        // Without the base fee the best guess is the max fee.
+        (Some(max_fee), None) => max_fee,
+        (None, _) => tx.gas_price.unwrap_or_default(),
+    }
+}
+
+// Execution gas of a single call, excluding the intrinsic cost of a tx.
+pub(crate) fn trace_gas_used(trace: &TransactionTrace) -> U256 {
+    match &trace.result {
+        Some(Res::Call(result)) => result.gas_used,
+        Some(Res::Create(result)) => result.gas_used,
+        _ => U256::zero(),
+    }
+}
+
+// The gas limit an entry needs from its trace alone: the intrinsic cost (calldata at the
+// Istanbul 16 / 4 gas per nonzero / zero byte) plus the traced execution gas, plus a quarter of
+// that for the refund, a refunded tx needs more gas while running than it ends up using and
+// London caps the refund at a fifth. Entries without a traced execution fall back to `trace_gas`.
+pub fn gas_estimate_from_trace(entry: &QueueEntry) -> Option<U256> {
+    match entry.execution_gas {
+        Some(execution_gas) => Some(gas_limit_for(&entry.tx, execution_gas)),
+        None => entry.trace_gas,
+    }
+}
+
+// The gas limit of `tx` whose call traced `execution_gas`, see `gas_estimate_from_trace`.
+pub(crate) fn gas_limit_for(tx: &TransactionRequest, execution_gas: U256) -> U256 {
+    let gas_used = intrinsic_gas(tx).saturating_add(execution_gas);
+    gas_used.saturating_add(gas_used / 4)
+}
+
+// Base cost of a tx before any execution: 21000, 32000 more for a create, and the calldata.
+pub(crate) fn intrinsic_gas(tx: &TransactionRequest) -> U256 {
+    let create = if tx.to.is_none() { 32000 } else { 0 };
+    let calldata = tx
+        .data
+        .iter()
+        .flat_map(|data| data.iter())
+        .map(|byte| if *byte == 0 { 4 } else { 16 })
+        .sum::<u64>();
+    U256::from(21000 + create + calldata)
+}
+
+// Gas used by the top level call and whether it succeeded, the gas is unknown when it reverted.
+// A trace without a top level call carries no revert, count it as a success.
+pub(crate) fn origin_call_status(trace: &SimulateTrace) -> (Option<U256>, bool) {
+    let origin_call = trace
+        .trace
+        .iter()
+        .flatten()
+        .find(|trace| trace.trace_address.is_empty());
+    match origin_call {
+        Some(call) => (
+            call.result.as_ref().map(|_| trace_gas_used(call)),
+            call.error.is_none(),
+        ),
+        None => (None, true),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        mock::{block_trace, call_trace, mock_client, opportunity},
+        QueueStrategy, Simulate,
+    };
+    use super::{effective_gas_price, gas_estimate_from_trace, origin_call_status, GasSource};
+    use crate::utils::SimulateTrace;
+    use ethers::{prelude::*, utils::parse_units};
+
+    fn gwei(amount: u64) -> U256 {
+        parse_units(amount, "gwei").unwrap().into()
+    }
+
+    #[test]
+    fn effective_gas_price_of_legacy_tx() {
+        let tx = Transaction {
+            gas_price: Some(gwei(30)),
+            ..Default::default()
+        };
+        assert_eq!(effective_gas_price(&tx, Some(gwei(20))), gwei(30));
+    }
+
+    #[test]
+    fn effective_gas_price_of_1559_tx() {
+        let tx = Transaction {
+            transaction_type: Some(U64::from(2)),
+            max_fee_per_gas: Some(gwei(50)),
+            max_priority_fee_per_gas: Some(gwei(2)),
+            ..Default::default()
+        };
+        assert_eq!(effective_gas_price(&tx, Some(gwei(20))), gwei(22));
+        // Capped by the max fee when the base fee spikes.
+        assert_eq!(effective_gas_price(&tx, Some(gwei(49))), gwei(50));
+    }
+
+    #[tokio::test]
+    async fn effective_gas_price_use_block_base_fee() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let tx = Transaction {
+            block_number: Some(U64::from(10)),
+            transaction_type: Some(U64::from(2)),
+            max_fee_per_gas: Some(gwei(50)),
+            max_priority_fee_per_gas: Some(gwei(2)),
+            ..Default::default()
+        };
+        mock.push(Block::<TxHash> {
+            base_fee_per_gas: Some(gwei(15)),
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(
+            simulate.effective_gas_price(&tx, None).await.unwrap(),
+            gwei(17)
+        );
+    }
+
+    #[tokio::test]
+    async fn gas_estimate_from_trace_of_queue_entry() {
+        let (client, _) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let entry_of = |input: Vec<u8>, gas_used: u64| {
+            let mut call = call_trace(vec![], 0, U256::zero());
+            if let Action::Call(action) = &mut call.action {
+                action.input = input.into();
+            }
+            call.result = Some(Res::Call(CallResult {
+                gas_used: U256::from(gas_used),
+                output: Bytes::default(),
+            }));
+            let (_, tx_queue) = simulate
+                .tx_queues(&opportunity(block_trace(vec![call], None)))
+                .unwrap()
+                .remove(0);
+            tx_queue.entries[0].clone()
+        };
+
+        // A plain transfer, the 21000 a receipt shows and a quarter of it on top.
+        assert_eq!(
+            gas_estimate_from_trace(&entry_of(vec![], 0)),
+            Some(U256::from(26250))
+        );
+
+        // `transfer(address,uint256)` of 1000 USDC: 28 nonzero and 40 zero calldata bytes over
+        // the 21000, and the 30000 the call traced, 51608 in the receipt.
+        let data = [
+            vec![0xa9, 0x05, 0x9c, 0xbb],
+            vec![0; 12],
+            vec![0x11; 20],
+            vec![0; 28],
+            vec![0x3b, 0x9a, 0xca, 0x11],
+        ]
+        .concat();
+        assert_eq!(
+            gas_estimate_from_trace(&entry_of(data, 30000)),
+            Some(U256::from(64510))
+        );
+    }
+
+    #[tokio::test]
+    async fn estimate_queue_gas_sum_trace_gas_used() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None)
+            .await
+            .unwrap()
+            .gas_estimation(GasSource::TraceGasUsed);
+        let trace = [
+            (vec![], 2, 100000),
+            (vec![0], 0, 30000),
+            (vec![1], 0, 50000),
+        ]
+        .into_iter()
+        .map(|(trace_address, subtraces, gas_used)| TransactionTrace {
+            result: Some(Res::Call(CallResult {
+                gas_used: U256::from(gas_used),
+                output: Bytes::default(),
+            })),
+            ..call_trace(trace_address, subtraces, U256::zero())
+        })
+        .collect();
+        let opportunity = opportunity(block_trace(trace, None));
+
+        let (_, mut tx_queue) = simulate
+            .tx_queues(&opportunity)
+            .unwrap()
+            .into_iter()
+            .find(|(strategy, _)| *strategy == QueueStrategy::Internal)
+            .unwrap();
+        // The gas used of both calls plus the intrinsic gas of each tx.
+        assert_eq!(
+            simulate
+                .estimate_queue_gas(&mut tx_queue, 0)
+                .await
+                .unwrap()
+                .total_gas,
+            U256::from(30000 + 50000 + 2 * 21000)
+        );
+        // Nothing was estimated.
+        assert!(mock.assert_request("eth_estimateGas", ()).is_err());
+    }
+
+    #[test]
+    fn origin_call_status_of_successful_trace() {
+        let trace: SimulateTrace = serde_json::from_str(include_str!(
+            "../../../tests/fixtures/trace_call/openethereum.json"
+        ))
+        .unwrap();
+        assert_eq!(origin_call_status(&trace), (Some(U256::from(0x1000)), true));
+    }
+
+    #[test]
+    fn origin_call_status_of_reverted_trace() {
+        let trace: SimulateTrace = serde_json::from_str(include_str!(
+            "../../../tests/fixtures/trace_call/reverted.json"
+        ))
+        .unwrap();
+        assert_eq!(origin_call_status(&trace), (None, false));
+    }
+}
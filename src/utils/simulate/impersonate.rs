@@ -0,0 +1,90 @@
+use super::{Simulate, SimulateError, TxQueue};
+use ethers::prelude::*;
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    // Who the reconstructed calls are sent from, the impersonated sender over our signer.
+    pub(crate) fn queue_sender(&self) -> Address {
+        self.impersonate.unwrap_or(self.signer_address)
+    }
+
+    // Have the node sign for the impersonated sender, anvil first then hardhat. Nothing to do
+    // without `impersonate`.
+    pub async fn start_impersonating(&self) -> Result<(), SimulateError> {
+        let sender = match self.impersonate {
+            Some(sender) => sender,
+            None => return Ok(()),
+        };
+        for method in ["anvil_impersonateAccount", "hardhat_impersonateAccount"] {
+            if self.request::<_, ()>(method, [sender]).await.is_ok() {
+                return Ok(());
+            }
+        }
+        Err(SimulateError::ImpersonationUnsupported(sender))
+    }
+
+    // Broadcast the entries unsigned, the node signs them for the impersonated sender.
+    pub(crate) async fn send_impersonated(
+        &self,
+        tx_queue: &TxQueue,
+    ) -> Result<Vec<TxHash>, SimulateError> {
+        self.start_impersonating().await?;
+        let mut tx_hashes = Vec::with_capacity(tx_queue.entries.len());
+        for tx in tx_queue.tx_list() {
+            let pending = self
+                .inner
+                .inner()
+                .send_transaction(tx, None)
+                .await
+                .map_err(SimulateError::middleware)?;
+            tx_hashes.push(*pending);
+        }
+        Ok(tx_hashes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        mock::{block_trace, call_trace, mock_client},
+        Simulate, SimulateError,
+    };
+    use ethers::prelude::*;
+
+    #[tokio::test]
+    async fn impersonate_skip_mock_tx_data() {
+        let (client, mock) = mock_client();
+        let victim = Address::random();
+        let simulate = Simulate::init(&client, None)
+            .await
+            .unwrap()
+            .impersonate(victim);
+
+        // The victim's own address in the calldata, `mock_tx_data` would swap it for ours.
+        let input = Bytes::from([&[0xa9, 0x05, 0x9c, 0xbb][..], victim.as_bytes()].concat());
+        let mut origin_call = call_trace(vec![], 0, U256::zero());
+        if let Action::Call(call) = &mut origin_call.action {
+            call.from = victim;
+            call.input = input.clone();
+        }
+        let tx_queue = simulate.to_strategy_queue(&block_trace(vec![origin_call], None));
+        let tx = &tx_queue[0].1[0];
+        assert_eq!(tx.from, Some(victim));
+        assert_eq!(tx.data, Some(input));
+
+        // Not anvil, but hardhat.
+        mock.push(()).unwrap();
+        mock.push("no such method").unwrap();
+        simulate.start_impersonating().await.unwrap();
+        mock.assert_request("anvil_impersonateAccount", [victim])
+            .unwrap();
+        mock.assert_request("hardhat_impersonateAccount", [victim])
+            .unwrap();
+
+        mock.push("no such method").unwrap();
+        mock.push("no such method").unwrap();
+        assert!(matches!(
+            simulate.start_impersonating().await,
+            Err(SimulateError::ImpersonationUnsupported(address)) if address == victim
+        ));
+    }
+}
@@ -0,0 +1,168 @@
+use super::{FeeBudget, Simulate, SimulateError, TxQueue};
+use ethers::{
+    abi::{self, ParamType, Token},
+    prelude::*,
+    utils::id,
+};
+
+// The `GasPriceOracle` predeploy of every OP-stack chain.
+pub const GAS_PRICE_ORACLE: Address = H160([
+    0x42, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x0f,
+]);
+
+// Optimism and Base with their testnets, where the L1 calldata fee is charged on top of the gas.
+pub fn is_op_stack(chain_id: u64) -> bool {
+    matches!(chain_id, 10 | 420 | 11155420 | 8453 | 84531 | 84532)
+}
+
+// The L1 data fee of signed txs, from the oracle's `getL1Fee(bytes)`.
+pub struct L1FeeEstimator<'c, M> {
+    client: &'c M,
+}
+
+impl<'c, M: Middleware> L1FeeEstimator<'c, M> {
+    pub fn new(client: &'c M) -> Self {
+        Self { client }
+    }
+
+    // Only OP-stack chains charge an L1 fee, `None` on the others.
+    pub fn for_chain(client: &'c M, chain_id: u64) -> Option<Self> {
+        is_op_stack(chain_id).then(|| Self::new(client))
+    }
+
+    pub async fn l1_fee(&self, raw_tx: &Bytes) -> Result<U256, SimulateError> {
+        let data = [
+            id("getL1Fee(bytes)").to_vec(),
+            abi::encode(&[Token::Bytes(raw_tx.to_vec())]),
+        ]
+        .concat();
+        let tx = TransactionRequest::new().to(GAS_PRICE_ORACLE).data(data);
+        let output = self
+            .client
+            .call(&tx.into(), None)
+            .await
+            .map_err(SimulateError::middleware)?;
+        abi::decode(&[ParamType::Uint(256)], &output)
+            .ok()
+            .and_then(|mut tokens| tokens.pop()?.into_uint())
+            .ok_or(SimulateError::L1FeeDecode(output))
+    }
+
+    // The L1 fee of the whole queue, one oracle call per signed entry.
+    pub async fn queue_l1_fee(&self, raw_tx_list: &[Bytes]) -> Result<U256, SimulateError> {
+        let mut l1_fee = U256::zero();
+        for raw_tx in raw_tx_list {
+            l1_fee = l1_fee
+                .checked_add(self.l1_fee(raw_tx).await?)
+                .ok_or(SimulateError::Overflow("l1 fee"))?;
+        }
+        Ok(l1_fee)
+    }
+}
+
+impl TxQueue {
+    // `break_even_fees` once `l1_fee` is paid from the profit, the gas gets what is left.
+    pub fn break_even_fees_with_l1(
+        &self,
+        profit: U256,
+        margin_bps: u32,
+        l1_fee: U256,
+    ) -> Result<FeeBudget, SimulateError> {
+        let mut budget = self.break_even_fees(profit, margin_bps)?;
+        budget.l1_fee = l1_fee;
+        budget.budget = budget.budget.saturating_sub(l1_fee);
+        budget.max_fee_per_gas = if budget.total_gas.is_zero() {
+            U256::zero()
+        } else {
+            budget.budget / budget.total_gas
+        };
+        Ok(budget)
+    }
+}
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    // The L1 data fee the queue pays on an OP-stack chain, 0 on the others. The entries are signed
+    // as they'd be sent, so run it once the nonces, gas and fees are set.
+    pub async fn queue_l1_fee(&self, tx_queue: &TxQueue) -> Result<U256, SimulateError> {
+        let chain_id = self.chain_id().await?.as_u64();
+        let estimator = match L1FeeEstimator::for_chain(self.inner, chain_id) {
+            Some(estimator) => estimator,
+            None => return Ok(U256::zero()),
+        };
+        let raw_tx_list = tx_queue.sign_all(self.inner).await?;
+        estimator.queue_l1_fee(&raw_tx_list).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{mock::mock_client, Simulate, TxQueue};
+    use super::L1FeeEstimator;
+    use ethers::{
+        abi::{self, Token},
+        prelude::*,
+        utils::parse_ether,
+    };
+
+    fn l1_fee_output(l1_fee: U256) -> Bytes {
+        abi::encode(&[Token::Uint(l1_fee)]).into()
+    }
+
+    #[tokio::test]
+    async fn queue_l1_fee_only_on_op_stack() {
+        let (provider, _) = Provider::mocked();
+        assert!(L1FeeEstimator::for_chain(&provider, 1).is_none());
+        assert!(L1FeeEstimator::for_chain(&provider, 8453).is_some());
+
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let tx_queue = TxQueue::from(vec![
+            TransactionRequest::new()
+                .to(Address::random())
+                .nonce(0)
+                .gas(100000)
+                .gas_price(1),
+            TransactionRequest::new()
+                .to(Address::random())
+                .nonce(1)
+                .gas(100000)
+                .gas_price(1),
+        ]);
+
+        mock.push(l1_fee_output(U256::from(300))).unwrap();
+        mock.push(l1_fee_output(U256::from(200))).unwrap();
+        mock.push(U256::from(10)).unwrap();
+        assert_eq!(
+            simulate.queue_l1_fee(&tx_queue).await.unwrap(),
+            U256::from(500)
+        );
+        mock.assert_request("eth_chainId", ()).unwrap();
+    }
+
+    #[test]
+    fn break_even_fees_pay_l1_fee_first() {
+        let tx_queue = TxQueue::from(vec![TransactionRequest::new().gas(100000)]);
+        let budget = tx_queue
+            .break_even_fees_with_l1(
+                parse_ether("0.01").unwrap(),
+                0,
+                parse_ether("0.006").unwrap(),
+            )
+            .unwrap();
+        assert_eq!(budget.l1_fee, parse_ether("0.006").unwrap());
+        assert_eq!(budget.budget, parse_ether("0.004").unwrap());
+        // 0.004 ETH over 100k gas.
+        assert_eq!(budget.max_fee_per_gas, U256::from(40_000_000_000_u64));
+        assert_eq!(
+            budget.required_cost(budget.max_fee_per_gas),
+            parse_ether("0.01").unwrap()
+        );
+
+        // An L1 fee above the profit leaves nothing for the gas.
+        let budget = tx_queue
+            .break_even_fees_with_l1(U256::from(100), 0, U256::from(200))
+            .unwrap();
+        assert_eq!(budget.max_fee_per_gas, U256::zero());
+    }
+}
@@ -0,0 +1,180 @@
+use super::{Simulate, SimulateError};
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use serde_json::{json, Value};
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    // Also collect the logs the tx emits and hand them to `AnalyzeState::run_with_logs`, for
+    // analyzers that work on events. Costs a `debug_traceCall` per analyzed tx, which the node
+    // has to support next to the `trace_` namespace (erigon, reth, geth for the logs).
+    pub fn include_logs(mut self, include_logs: bool) -> Self {
+        self.include_logs = include_logs;
+        self
+    }
+
+    // The logs of `tx` on the state of `block`, from the `callTracer` with `withLog`.
+    pub(crate) async fn trace_logs(
+        &self,
+        tx: &Transaction,
+        block: Option<BlockNumber>,
+    ) -> Result<Vec<Log>, SimulateError> {
+        let frame = self
+            .tracer()
+            .request(
+                "debug_traceCall",
+                json!([
+                    TypedTransaction::from(tx),
+                    block.unwrap_or(BlockNumber::Latest),
+                    { "tracer": "callTracer", "tracerConfig": { "withLog": true } }
+                ]),
+            )
+            .await?;
+
+        let mut logs = Vec::new();
+        collect_logs(&frame, &mut logs)?;
+        Ok(logs)
+    }
+}
+
+// The logs in the order they were emitted, logs of reverted frames are not emitted. A log's
+// `position` is the number of sub calls its frame made before it, a log without one (older
+// geth) is taken as emitted before them.
+fn collect_logs(frame: &Value, logs: &mut Vec<Log>) -> Result<(), SimulateError> {
+    if frame.get("error").is_some() {
+        return Ok(());
+    }
+    let calls = frame["calls"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let mut next_call = 0;
+    for log in frame["logs"].as_array().into_iter().flatten() {
+        let position = log_position(log).min(calls.len());
+        while next_call < position {
+            collect_logs(&calls[next_call], logs)?;
+            next_call += 1;
+        }
+        logs.push(serde_json::from_value(log.clone()).map_err(SimulateError::middleware)?);
+    }
+    for call in &calls[next_call..] {
+        collect_logs(call, logs)?;
+    }
+    Ok(())
+}
+
+// geth encodes the `position` as a hex quantity.
+fn log_position(log: &Value) -> usize {
+    match &log["position"] {
+        Value::String(position) => {
+            usize::from_str_radix(position.trim_start_matches("0x"), 16).unwrap_or_default()
+        }
+        position => position.as_u64().unwrap_or_default() as usize,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        mock::{block_trace, call_trace, mock_client},
+        state::base::AnalyzeState,
+        ProfitReport, Simulate, SimulateTimings, SimulateTrace,
+    };
+    use super::collect_logs;
+    use async_trait::async_trait;
+    use ethers::prelude::*;
+    use serde_json::json;
+    use std::error::Error;
+
+    // Report every emitter of a log as gaining 1 wei, stand in for an event analyzer.
+    struct AnalyzeLogs;
+
+    #[async_trait]
+    impl<'a, M, S> AnalyzeState<'a, M, S> for AnalyzeLogs {
+        async fn init(_client: &'a SignerMiddleware<M, S>) -> Result<Self, Box<dyn Error + 'a>> {
+            Ok(Self)
+        }
+
+        async fn run(
+            &self,
+            _tx: &Transaction,
+            _trace: &SimulateTrace,
+            _beneficiaries: &[Address],
+        ) -> Result<Vec<ProfitReport>, Box<dyn Error + 'a>> {
+            Ok(vec![])
+        }
+
+        async fn run_with_logs(
+            &self,
+            _tx: &Transaction,
+            _trace: &SimulateTrace,
+            logs: &[Log],
+            _beneficiaries: &[Address],
+        ) -> Result<Vec<ProfitReport>, Box<dyn Error + 'a>> {
+            Ok(logs
+                .iter()
+                .map(|log| ProfitReport::native(log.address, U256::one()))
+                .collect())
+        }
+    }
+
+    #[test]
+    fn collect_logs_by_position() {
+        let (before, inner, after) = (Address::random(), Address::random(), Address::random());
+        let frame = json!({
+            "logs": [
+                { "address": before, "topics": [], "data": "0x", "position": "0x0" },
+                { "address": after, "topics": [], "data": "0x", "position": "0x1" }
+            ],
+            "calls": [{ "logs": [{ "address": inner, "topics": [], "data": "0x", "position": "0x0" }] }]
+        });
+
+        let mut logs = Vec::new();
+        collect_logs(&frame, &mut logs).unwrap();
+        assert_eq!(
+            logs.iter().map(|log| log.address).collect::<Vec<_>>(),
+            vec![before, inner, after]
+        );
+    }
+
+    #[tokio::test]
+    async fn is_valuable_pass_logs_to_analyzers() {
+        let (client, mock) = mock_client();
+        let mut simulate = Simulate::init(&client, None)
+            .await
+            .unwrap()
+            .include_logs(true);
+        simulate.state_analysis.clear();
+        simulate.state_analysis.push(Box::new(AnalyzeLogs));
+        let token = Address::random();
+        let pool = Address::random();
+
+        mock.push(json!({
+            "logs": [{ "address": token, "topics": [H256::random()], "data": "0x" }],
+            "calls": [
+                { "logs": [{ "address": pool, "topics": [], "data": "0x01" }] },
+                // Reverted, its log is discarded.
+                { "error": "execution reverted", "logs": [{ "address": Address::random(), "topics": [], "data": "0x" }] }
+            ]
+        }))
+        .unwrap();
+        mock.push(block_trace(vec![call_trace(vec![], 0, U256::zero())], None))
+            .unwrap();
+        let tx = Transaction {
+            input: "0x00000001".parse().unwrap(),
+            ..Default::default()
+        };
+        let (_, reports) = simulate
+            .is_valuable(tx, None, None, &mut SimulateTimings::default())
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            reports,
+            vec![
+                ProfitReport::native(token, U256::one()),
+                ProfitReport::native(pool, U256::one())
+            ]
+        );
+    }
+}
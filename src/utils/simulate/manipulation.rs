@@ -0,0 +1,201 @@
+use super::{selector_of, AnalyzerFlags, ProfitReport, Simulate, SimulateTrace};
+use ethers::{prelude::*, utils::id};
+
+// The packed reserves of a uniswap v2 style pair, two 112 bit reserves under the timestamp.
+const RESERVES_SLOT: u64 = 8;
+
+// The reserves packed in `slot`.
+fn reserves(slot: &H256) -> (U256, U256) {
+    let slot = U256::from_big_endian(slot.as_bytes());
+    let mask = (U256::one() << 112) - 1;
+    (slot & mask, (slot >> 112) & mask)
+}
+
+// How far the reserves moved, in bps of the reserve that moved the most. A reserve out of
+// nothing moved all the way.
+fn reserves_move_bps(from: &H256, to: &H256) -> U256 {
+    let ((from0, from1), (to0, to1)) = (reserves(from), reserves(to));
+    let move_bps = |from: U256, to: U256| {
+        let moved = if to > from { to - from } else { from - to };
+        match from.is_zero() {
+            true if moved.is_zero() => U256::zero(),
+            true => U256::MAX,
+            // Under 2^112 each, times 10000 doesn't overflow.
+            false => moved * 10_000 / from,
+        }
+    };
+    move_bps(from0, to0).max(move_bps(from1, to1))
+}
+
+// A pool's reserves were moved by a swap and then read by another contract within the tx, as
+// an oracle reading the spot price would be.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ManipulationDetected {
+    pub pool: Address,
+    // What called `getReserves` on the moved pool.
+    pub reader: Address,
+    // The native profit of the tx, see `ProfitReport::total_native`.
+    pub profit: U256,
+}
+
+// The first pool whose reserves the tx moved by at least `min_move_bps` and are read after a
+// swap on it returned. A read from within the swap (e.g. its flash callback) doesn't count, the
+// reserves aren't updated until the swap ends.
+pub fn detect_manipulation(
+    trace: &SimulateTrace,
+    reports: &[ProfitReport],
+    min_move_bps: u32,
+) -> Option<ManipulationDetected> {
+    let (state_diff, calls) = match (&trace.state_diff, &trace.trace) {
+        (Some(state_diff), Some(calls)) => (state_diff, calls),
+        _ => return None,
+    };
+    let reserves_slot = H256::from_low_u64_be(RESERVES_SLOT);
+    let moved = |pool: &Address| {
+        state_diff.0.get(pool).map_or(false, |diff| {
+            matches!(
+                diff.storage.get(&reserves_slot),
+                Some(Diff::Changed(ChangedType { from, to }))
+                    if from != to && reserves_move_bps(from, to) >= U256::from(min_move_bps)
+            )
+        })
+    };
+    let (swap, get_reserves) = (
+        id("swap(uint256,uint256,address,bytes)"),
+        id("getReserves()"),
+    );
+
+    // The calls are in execution order, the trace address of every swap seen so far.
+    let mut swaps: Vec<(Address, &[usize])> = Vec::new();
+    for call in calls {
+        let call_action = match &call.action {
+            Action::Call(call_action) if moved(&call_action.to) => call_action,
+            _ => continue,
+        };
+        let selector = selector_of(&call_action.input);
+        if selector == swap {
+            swaps.push((call_action.to, &call.trace_address));
+        } else if selector == get_reserves {
+            let after_swap = swaps.iter().any(|(pool, trace_address)| {
+                *pool == call_action.to && !call.trace_address.starts_with(trace_address)
+            });
+            if after_swap && call_action.from != call_action.to {
+                return Some(ManipulationDetected {
+                    pool: call_action.to,
+                    reader: call_action.from,
+                    profit: ProfitReport::total_native(reports),
+                });
+            }
+        }
+    }
+
+    None
+}
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    // Flag a manipulation only on a pool whose reserves moved by `min_move_bps`, any move
+    // counts by default. A small swap doesn't move a spot price anyone could profit from.
+    pub fn manipulation_threshold(mut self, min_move_bps: u32) -> Self {
+        self.manipulation_min_move_bps = min_move_bps;
+        self
+    }
+
+    // `detect_manipulation` unless turned off with `AnalyzerFlags::MANIPULATION`.
+    pub(crate) fn manipulation(
+        &self,
+        trace: &SimulateTrace,
+        reports: &[ProfitReport],
+    ) -> Option<ManipulationDetected> {
+        if !self.analyzers.contains(AnalyzerFlags::MANIPULATION) {
+            return None;
+        }
+        detect_manipulation(trace, reports, self.manipulation_min_move_bps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        mock::{block_trace, call_trace},
+        ProfitReport,
+    };
+    use super::detect_manipulation;
+    use ethers::{prelude::*, utils::id};
+    use std::collections::BTreeMap;
+
+    fn call_to(
+        trace_address: Vec<usize>,
+        subtraces: usize,
+        from: Address,
+        to: Address,
+        signature: &str,
+    ) -> TransactionTrace {
+        let mut trace = call_trace(trace_address, subtraces, U256::zero());
+        if let Action::Call(call) = &mut trace.action {
+            call.from = from;
+            call.to = to;
+            call.input = id(signature).to_vec().into();
+        }
+        trace
+    }
+
+    #[test]
+    fn detect_manipulation_read_after_swap() {
+        let (attacker, pool, lending) = (Address::random(), Address::random(), Address::random());
+        let reserves = AccountDiff {
+            balance: Diff::Same,
+            nonce: Diff::Same,
+            code: Diff::Same,
+            storage: BTreeMap::from([(
+                H256::from_low_u64_be(8),
+                Diff::Changed(ChangedType {
+                    from: H256::from_low_u64_be(1000),
+                    to: H256::from_low_u64_be(1010),
+                }),
+            )]),
+        };
+        let state_diff = Some(StateDiff(BTreeMap::from([(pool, reserves)])));
+        let reports = vec![ProfitReport::native(attacker, U256::from(500))];
+        let swap = "swap(uint256,uint256,address,bytes)";
+
+        // Swap, borrow against the moved spot price, swap back.
+        let trace = block_trace(
+            vec![
+                call_trace(vec![], 3, U256::zero()),
+                call_to(vec![0], 0, attacker, pool, swap),
+                call_to(vec![1], 1, attacker, lending, "borrow(uint256)"),
+                call_to(vec![1, 0], 0, lending, pool, "getReserves()"),
+                call_to(vec![2], 0, attacker, pool, swap),
+            ],
+            state_diff.clone(),
+        );
+        let detected = detect_manipulation(&trace, &reports, 0).unwrap();
+        assert_eq!(detected.pool, pool);
+        assert_eq!(detected.reader, lending);
+        assert_eq!(detected.profit, U256::from(500));
+        // Reserves of 1000 moved by 10, 100 bps.
+        assert!(detect_manipulation(&trace, &reports, 100).is_some());
+        assert_eq!(detect_manipulation(&trace, &reports, 101), None);
+
+        // Read within the swap's callback, before the reserves are updated.
+        let trace = block_trace(
+            vec![
+                call_to(vec![], 1, attacker, pool, swap),
+                call_to(vec![0], 1, pool, attacker, "uniswapV2Call()"),
+                call_to(vec![0, 0], 0, attacker, pool, "getReserves()"),
+            ],
+            state_diff,
+        );
+        assert_eq!(detect_manipulation(&trace, &reports, 0), None);
+
+        // The same sequence on a pool whose reserves the tx didn't change.
+        let trace = block_trace(
+            vec![
+                call_to(vec![0], 0, attacker, pool, swap),
+                call_to(vec![1], 0, lending, pool, "getReserves()"),
+            ],
+            None,
+        );
+        assert_eq!(detect_manipulation(&trace, &reports, 0), None);
+    }
+}
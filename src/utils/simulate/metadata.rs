@@ -0,0 +1,157 @@
+use super::{ProfitCurrency, ProfitReport, Simulate, SimulateError, TokenMeta};
+use ethers::{
+    abi::{self, ParamType, Token},
+    prelude::*,
+    utils::id,
+};
+
+// `symbol()` as a string, or as the bytes32 of older tokens (e.g. MKR) with the padding trimmed.
+fn decode_symbol(output: &Bytes) -> Option<String> {
+    if let Ok(Token::String(symbol)) =
+        abi::decode(&[ParamType::String], output).map(|mut tokens| tokens.remove(0))
+    {
+        return Some(symbol);
+    }
+    let bytes = output.get(..32)?;
+    let len = bytes.iter().position(|byte| *byte == 0).unwrap_or(32);
+    String::from_utf8(bytes[..len].to_vec()).ok()
+}
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    // The token's `symbol()` and `decimals()`, asked once per token and kept for the next reports.
+    pub async fn token_metadata(&self, token: Address) -> Result<TokenMeta, SimulateError> {
+        if let Some(meta) = self.token_metadata.lock().unwrap().get(&token) {
+            return Ok(meta.clone());
+        }
+
+        let symbol = decode_symbol(&self.call_token(token, "symbol()").await?).ok_or(
+            SimulateError::TokenMetadataDecode {
+                token,
+                field: "symbol",
+            },
+        )?;
+        let decimals = abi::decode(
+            &[ParamType::Uint(8)],
+            &self.call_token(token, "decimals()").await?,
+        )
+        .ok()
+        .and_then(|mut tokens| tokens.pop()?.into_uint())
+        .filter(|decimals| *decimals <= U256::from(u8::MAX))
+        .ok_or(SimulateError::TokenMetadataDecode {
+            token,
+            field: "decimals",
+        })?;
+
+        let meta = TokenMeta {
+            symbol,
+            decimals: decimals.as_u32() as u8,
+        };
+        self.token_metadata
+            .lock()
+            .unwrap()
+            .insert(token, meta.clone());
+        Ok(meta)
+    }
+
+    // The native token reports are in, see `native_symbol`.
+    pub fn native_token(&self) -> &TokenMeta {
+        &self.native_token
+    }
+
+    // The profit with its unit, `1.5 ETH` or `12.5 USDC`, rounded to `decimals` digits.
+    pub async fn format_report(
+        &self,
+        report: &ProfitReport,
+        decimals: u8,
+    ) -> Result<String, SimulateError> {
+        match report.currency {
+            ProfitCurrency::Native => Ok(report.format_with(&self.native_token, decimals)),
+            ProfitCurrency::Token(token) => {
+                Ok(report.format_with(&self.token_metadata(token).await?, decimals))
+            }
+        }
+    }
+
+    async fn call_token(&self, token: Address, signature: &str) -> Result<Bytes, SimulateError> {
+        let tx = TransactionRequest::new()
+            .to(token)
+            .data(id(signature).to_vec());
+        self.call(&tx.into(), None)
+            .await
+            .map_err(SimulateError::middleware)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{mock::mock_client, ProfitReport, QueueEconomics, Simulate, TokenMeta};
+    use super::decode_symbol;
+    use ethers::{
+        abi::{self, Token},
+        prelude::*,
+    };
+
+    #[tokio::test]
+    async fn token_metadata_fetched_once() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let usdc = Address::random();
+
+        mock.push(Bytes::from(abi::encode(&[Token::Uint(6.into())])))
+            .unwrap();
+        mock.push(Bytes::from(abi::encode(&[Token::String("USDC".into())])))
+            .unwrap();
+
+        let meta = simulate.token_metadata(usdc).await.unwrap();
+        assert_eq!(
+            meta,
+            TokenMeta {
+                symbol: "USDC".into(),
+                decimals: 6,
+            }
+        );
+
+        // No response is left, the token's metadata comes from the cache.
+        let report = ProfitReport::token(Address::random(), usdc, U256::from(12_500_000));
+        assert_eq!(
+            simulate.format_report(&report, 2).await.unwrap(),
+            "12.5 USDC"
+        );
+        let report = ProfitReport::native(Address::random(), U256::exp10(18));
+        assert_eq!(simulate.format_report(&report, 4).await.unwrap(), "1 ETH");
+
+        let mut mkr = [0; 32];
+        mkr[..3].copy_from_slice(b"MKR");
+        assert_eq!(
+            decode_symbol(&Bytes::from(mkr.to_vec())),
+            Some("MKR".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn format_report_in_native_symbol() {
+        let (client, _) = mock_client();
+        let simulate = Simulate::init(&client, None)
+            .await
+            .unwrap()
+            .native_symbol("MATIC");
+
+        let report = ProfitReport::native(Address::random(), U256::exp10(18) * 3 / 2);
+        assert_eq!(
+            simulate.format_report(&report, 4).await.unwrap(),
+            "1.5 MATIC"
+        );
+        let economics = QueueEconomics {
+            expected_profit: U256::exp10(18),
+            ..Default::default()
+        };
+        assert!(economics
+            .format_with(simulate.native_token())
+            .ends_with("net 1.000000000000000000 MATIC, capital 0.000000000000000000 MATIC"));
+
+        // A gas token of other decimals.
+        let simulate = simulate.native_symbol("XYZ").native_decimals(8);
+        let report = ProfitReport::native(Address::random(), U256::from(250_000_000));
+        assert_eq!(simulate.format_report(&report, 2).await.unwrap(), "2.5 XYZ");
+    }
+}
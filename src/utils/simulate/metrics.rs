@@ -0,0 +1,175 @@
+use super::{Opportunity, Simulate, SimulateError};
+use ethers::prelude::*;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+// Upper bounds in seconds, a run slower than a block is useless anyway.
+const DURATION_BUCKETS: [f64; 8] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 12.0];
+
+// Process wide, every `Simulate` reports into it so one endpoint covers them all.
+pub(crate) static METRICS: SimulateMetrics = SimulateMetrics::new();
+
+pub(crate) struct SimulateMetrics {
+    runs: AtomicU64,
+    opportunities: AtomicU64,
+    errors: AtomicU64,
+    round_trips: AtomicU64,
+    // Not cumulative, `render` sums them up.
+    duration_buckets: [AtomicU64; DURATION_BUCKETS.len() + 1],
+    duration_micros: AtomicU64,
+}
+
+impl SimulateMetrics {
+    const fn new() -> Self {
+        const ZERO: AtomicU64 = AtomicU64::new(0);
+        Self {
+            runs: ZERO,
+            opportunities: ZERO,
+            errors: ZERO,
+            round_trips: ZERO,
+            duration_buckets: [ZERO; DURATION_BUCKETS.len() + 1],
+            duration_micros: ZERO,
+        }
+    }
+
+    pub(crate) fn record(&self, result: &Result<Option<Opportunity>, SimulateError>) {
+        self.runs.fetch_add(1, Ordering::Relaxed);
+        match result {
+            Ok(Some(opportunity)) => {
+                self.opportunities.fetch_add(1, Ordering::Relaxed);
+                self.round_trips
+                    .fetch_add(opportunity.timings.round_trips.into(), Ordering::Relaxed);
+
+                let duration = opportunity.timings.total();
+                let bucket = DURATION_BUCKETS
+                    .iter()
+                    .position(|le| duration.as_secs_f64() <= *le)
+                    .unwrap_or(DURATION_BUCKETS.len());
+                self.duration_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+                self.duration_micros
+                    .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+            }
+            Ok(None) => {}
+            Err(_) => {
+                self.errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    // Prometheus text exposition format.
+    pub(crate) fn render(&self) -> String {
+        let mut out = String::new();
+        for (name, help, counter) in [
+            ("simulate_runs_total", "Txs simulated.", &self.runs),
+            (
+                "simulate_opportunities_total",
+                "Runs that found an opportunity.",
+                &self.opportunities,
+            ),
+            ("simulate_errors_total", "Runs that failed.", &self.errors),
+            (
+                "simulate_round_trips_total",
+                "Rpc requests sent by runs that found an opportunity.",
+                &self.round_trips,
+            ),
+        ] {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} counter");
+            let _ = writeln!(out, "{name} {}", counter.load(Ordering::Relaxed));
+        }
+
+        let name = "simulate_opportunity_duration_seconds";
+        let _ = writeln!(out, "# HELP {name} Time to find an opportunity.");
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        let mut count = 0;
+        for (i, bucket) in self.duration_buckets.iter().enumerate() {
+            count += bucket.load(Ordering::Relaxed);
+            let le = DURATION_BUCKETS
+                .get(i)
+                .map_or_else(|| "+Inf".to_string(), ToString::to_string);
+            let _ = writeln!(out, "{name}_bucket{{le=\"{le}\"}} {count}");
+        }
+        let sum = self.duration_micros.load(Ordering::Relaxed) as f64 / 1e6;
+        let _ = writeln!(out, "{name}_sum {sum}");
+        let _ = writeln!(out, "{name}_count {count}");
+        out
+    }
+}
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    // Serve the metrics for scraping on every path, returns the bound address (`addr` may use port 0).
+    // The server is a background task living as long as the runtime.
+    pub async fn serve_metrics(&self, addr: SocketAddr) -> Result<SocketAddr, SimulateError> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .map_err(SimulateError::middleware)?;
+        let local_addr = listener.local_addr().map_err(SimulateError::middleware)?;
+
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    // The request itself doesn't matter, only wait for it before answering.
+                    let mut request = [0; 1024];
+                    let _ = stream.read(&mut request).await;
+
+                    let body = METRICS.render();
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\n\
+                         content-type: text/plain; version=0.0.4\r\n\
+                         content-length: {}\r\n\
+                         connection: close\r\n\r\n{body}",
+                        body.len()
+                    );
+                    let _ = stream.write_all(response.as_bytes()).await;
+                    let _ = stream.shutdown().await;
+                });
+            }
+        });
+
+        Ok(local_addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{mock::mock_client, Simulate, SimulateError};
+    use super::METRICS;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    #[tokio::test]
+    async fn serve_metrics_expose_metric_names() {
+        let (client, _mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        METRICS.record(&Ok(None));
+        METRICS.record(&Err(SimulateError::Middleware("timeout".into())));
+
+        let addr = simulate
+            .serve_metrics("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        for name in [
+            "# TYPE simulate_runs_total counter",
+            "simulate_opportunities_total",
+            "simulate_errors_total",
+            "simulate_round_trips_total",
+            "# TYPE simulate_opportunity_duration_seconds histogram",
+            "simulate_opportunity_duration_seconds_bucket{le=\"+Inf\"}",
+            "simulate_opportunity_duration_seconds_count",
+        ] {
+            assert!(response.contains(name), "missing {name}");
+        }
+    }
+}
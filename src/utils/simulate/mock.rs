@@ -0,0 +1,73 @@
+// Shared fixtures for the unit tests of `Simulate`.
+use super::{Opportunity, SimulateTimings};
+use crate::utils::OpportunityId;
+use ethers::{core::rand::thread_rng, prelude::*};
+use std::collections::BTreeMap;
+
+// The mock answers with the responses pushed to it in reverse order, while the requests it got
+// are asserted in the order they were made.
+pub fn mock_client() -> (
+    SignerMiddleware<Provider<MockProvider>, LocalWallet>,
+    MockProvider,
+) {
+    let (provider, mock) = Provider::mocked();
+    let client = SignerMiddleware::new(provider, LocalWallet::new(&mut thread_rng()));
+    (client, mock)
+}
+
+pub fn block_trace(trace: Vec<TransactionTrace>, state_diff: Option<StateDiff>) -> BlockTrace {
+    BlockTrace {
+        output: Bytes::default(),
+        trace: Some(trace),
+        vm_trace: None,
+        state_diff,
+        transaction_hash: None,
+    }
+}
+
+pub fn call_trace(trace_address: Vec<usize>, subtraces: usize, value: U256) -> TransactionTrace {
+    TransactionTrace {
+        trace_address,
+        subtraces,
+        action: Action::Call(Call {
+            from: Address::random(),
+            to: Address::random(),
+            value,
+            gas: U256::from(100000),
+            input: Bytes::default(),
+            call_type: CallType::Call,
+        }),
+        action_type: ActionType::Call,
+        result: None,
+        error: None,
+    }
+}
+
+pub fn balance_diff(from: U256, to: U256) -> AccountDiff {
+    AccountDiff {
+        balance: Diff::Changed(ChangedType { from, to }),
+        nonce: Diff::Same,
+        code: Diff::Same,
+        storage: BTreeMap::new(),
+    }
+}
+
+// An opportunity found in `trace` with nothing else known, the fields a test needs set over it.
+pub fn opportunity(trace: BlockTrace) -> Opportunity {
+    Opportunity {
+        id: OpportunityId::new(),
+        victim: None,
+        tx_queue: vec![],
+        reports: vec![],
+        timings: SimulateTimings::default(),
+        block: None,
+        block_hash: None,
+        trace,
+        original_gas_used: None,
+        original_success: true,
+        victim_raw_tx: None,
+        selector: [0; 4],
+        decoded: None,
+        manipulation: None,
+    }
+}
@@ -0,0 +1,179 @@
+use super::{QueueEntry, SimulateError, TxQueue};
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use serde_json::{json, Value};
+
+// Bumped on any change of the exported layout, remote signers reject versions they don't know.
+pub const UNSIGNED_JSON_VERSION: u64 = 1;
+
+impl TxQueue {
+    // The queue as `{ "version", "transactions" }` for a remote signer, every tx in ethers' typed
+    // tx json (`type`, `chainId`, `nonce`, `gas`, the fees, ...) in send order. Every entry must
+    // be complete, a signer filling the blanks itself could sign something else than simulated.
+    pub fn export_unsigned_json(&self) -> Result<String, SimulateError> {
+        let missing = self
+            .entries
+            .iter()
+            .enumerate()
+            .flat_map(|(index, entry)| {
+                let tx = &entry.tx;
+                let has_fees = entry.max_fee_per_gas.is_some() || tx.gas_price.is_some();
+                [
+                    ("from", tx.from.is_some()),
+                    ("nonce", tx.nonce.is_some()),
+                    ("gas", tx.gas.is_some()),
+                    ("chain_id", tx.chain_id.is_some()),
+                    ("fees", has_fees),
+                ]
+                .into_iter()
+                .filter(|(_, present)| !present)
+                .map(move |(field, _)| format!("{index}.{field}"))
+            })
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(SimulateError::IncompleteTx(missing));
+        }
+
+        let transactions = self
+            .entries
+            .iter()
+            .map(QueueEntry::typed_tx)
+            .collect::<Vec<_>>();
+        Ok(json!({
+            "version": UNSIGNED_JSON_VERSION,
+            "transactions": transactions,
+        })
+        .to_string())
+    }
+
+    // The txs of an `export_unsigned_json` export, for the signer side and round trips.
+    pub fn parse_unsigned_json(json: &str) -> Result<Vec<TypedTransaction>, SimulateError> {
+        let value =
+            serde_json::from_str::<Value>(json).map_err(SimulateError::UnsignedJsonDecode)?;
+        match value["version"].as_u64() {
+            Some(UNSIGNED_JSON_VERSION) => {}
+            version => return Err(SimulateError::UnsupportedUnsignedJson(version)),
+        }
+        serde_json::from_value(value["transactions"].clone())
+            .map_err(SimulateError::UnsignedJsonDecode)
+    }
+
+    // Attach the signatures a remote signer made for `export_unsigned_json`, one per entry in
+    // order, as raw txs ready to send. Each must recover to the entry's sender.
+    pub fn import_signatures(
+        &self,
+        signatures: Vec<Signature>,
+    ) -> Result<Vec<Bytes>, SimulateError> {
+        if signatures.len() != self.entries.len() {
+            return Err(SimulateError::SignatureCountMismatch {
+                signatures: signatures.len(),
+                entries: self.entries.len(),
+            });
+        }
+
+        self.entries
+            .iter()
+            .zip(signatures)
+            .enumerate()
+            .map(|(index, (entry, signature))| {
+                let tx = entry.typed_tx();
+                let signer =
+                    signature
+                        .recover(tx.sighash())
+                        .map_err(|e| SimulateError::Signing {
+                            index,
+                            reason: e.to_string(),
+                        })?;
+                if Some(signer) != entry.tx.from {
+                    return Err(SimulateError::Signing {
+                        index,
+                        reason: format!("signed by {signer:?}"),
+                    });
+                }
+                Ok(tx.rlp_signed(&signature))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{decode_raw_tx, QueueEntry, SimulateError, TxQueue};
+    use ethers::{
+        core::rand::thread_rng, prelude::*, types::transaction::eip2718::TypedTransaction,
+    };
+
+    fn complete_queue(from: Address) -> TxQueue {
+        TxQueue::from(vec![
+            TransactionRequest::new()
+                .from(from)
+                .to(Address::random())
+                .nonce(3)
+                .gas(100000)
+                .gas_price(10)
+                .chain_id(1)
+                .data(vec![0, 0, 0, 1]),
+            TransactionRequest::new()
+                .from(from)
+                .to(Address::random())
+                .nonce(4)
+                .gas(100000)
+                .chain_id(1)
+                .value(7),
+        ])
+    }
+
+    #[tokio::test]
+    async fn export_unsigned_json_round_trip() {
+        let wallet = LocalWallet::new(&mut thread_rng());
+        let mut tx_queue = complete_queue(wallet.address());
+        tx_queue.entries[1].max_fee_per_gas = Some(U256::from(100));
+        tx_queue.entries[1].max_priority_fee_per_gas = Some(U256::from(2));
+
+        let json = tx_queue.export_unsigned_json().unwrap();
+        let parsed = TxQueue::parse_unsigned_json(&json).unwrap();
+        let typed = tx_queue
+            .entries
+            .iter()
+            .map(QueueEntry::typed_tx)
+            .collect::<Vec<_>>();
+        assert_eq!(parsed, typed);
+        assert!(matches!(parsed[1], TypedTransaction::Eip1559(_)));
+
+        // What the remote signer does with the export.
+        let mut signatures = Vec::new();
+        for tx in &parsed {
+            signatures.push(wallet.sign_transaction(tx).await.unwrap());
+        }
+        let raw_tx_list = tx_queue.import_signatures(signatures.clone()).unwrap();
+        for (raw_tx, nonce) in raw_tx_list.iter().zip([3, 4]) {
+            let tx = decode_raw_tx(raw_tx).unwrap();
+            assert_eq!(tx.from, wallet.address());
+            assert_eq!(tx.nonce, U256::from(nonce));
+        }
+
+        signatures.pop();
+        assert!(matches!(
+            tx_queue.import_signatures(signatures),
+            Err(SimulateError::SignatureCountMismatch {
+                signatures: 1,
+                entries: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn export_unsigned_json_list_missing_fields() {
+        let mut tx_queue = complete_queue(Address::random());
+        tx_queue.entries[0].tx.nonce = None;
+        tx_queue.entries[1].tx.gas = None;
+        tx_queue.entries[1].tx.chain_id = None;
+
+        match tx_queue.export_unsigned_json() {
+            Err(SimulateError::IncompleteTx(missing)) => {
+                assert_eq!(missing, vec!["0.nonce", "1.gas", "1.chain_id", "1.fees"])
+            }
+            result => panic!("expected missing fields, got {result:?}"),
+        }
+    }
+}
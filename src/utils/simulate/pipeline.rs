@@ -0,0 +1,451 @@
+use super::{
+    BribeMethod, FeeEstimate, FeeEstimator, Opportunity, ProfitReport, QueueStrategy, Simulate,
+    SimulateError, SimulateTarget, SimulateTimings, TxQueue, Urgency, Verification,
+};
+use crate::utils::{
+    BundleOptions, BundleSubmitter, SubmissionLoop, SubmissionPath, SubmissionReport,
+};
+use ethers::prelude::*;
+use std::future::pending;
+
+// What `run_and_execute` starts from.
+#[derive(Debug, Clone)]
+pub enum PipelineInput {
+    Hash(TxHash),
+    // A tx seen in the mempool, not mined yet.
+    Pending(Transaction),
+    // Its signed form, kept to backrun it.
+    Raw(Bytes),
+}
+
+impl From<TxHash> for PipelineInput {
+    fn from(tx_hash: TxHash) -> Self {
+        Self::Hash(tx_hash)
+    }
+}
+
+impl From<Transaction> for PipelineInput {
+    fn from(tx: Transaction) -> Self {
+        Self::Pending(tx)
+    }
+}
+
+impl From<Bytes> for PipelineInput {
+    fn from(raw: Bytes) -> Self {
+        Self::Raw(raw)
+    }
+}
+
+// Where `run_and_execute` stopped, carried by `SimulateError::PipelineStopped`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipelineStage {
+    Simulate,
+    // The analyzed profit is below `ExecutionPolicy::min_profit`.
+    Threshold,
+    // No queue could be reconstructed from the trace.
+    Build,
+    // Every tx list reverts on the traced block.
+    Verify,
+    Gas,
+    Fees,
+    Nonces,
+    // Gas, fees and bribe net no profit.
+    Economics,
+    Submit,
+}
+
+// Everything `run_and_execute` goes by, from the profit it needs to the relays it sends to.
+pub struct ExecutionPolicy<'p, A> {
+    submitter: &'p BundleSubmitter<A>,
+    fee_estimator: &'p dyn FeeEstimator,
+    urgency: Urgency,
+    min_profit: U256,
+    // Of the profit the fees may not eat into, see `TxQueue::break_even_fees`.
+    fee_margin_bps: u32,
+    gas_buffer_pct: u64,
+    // The method and percent of the profit paid to the builder.
+    bribe: Option<(BribeMethod, u32)>,
+    dry_run: bool,
+}
+
+impl<'p, A: Signer> ExecutionPolicy<'p, A> {
+    pub fn new(submitter: &'p BundleSubmitter<A>, fee_estimator: &'p dyn FeeEstimator) -> Self {
+        Self {
+            submitter,
+            fee_estimator,
+            urgency: Urgency::NextBlock,
+            min_profit: U256::zero(),
+            fee_margin_bps: 0,
+            gas_buffer_pct: 10,
+            bribe: None,
+            dry_run: false,
+        }
+    }
+
+    pub fn urgency(mut self, urgency: Urgency) -> Self {
+        self.urgency = urgency;
+        self
+    }
+
+    // The native profit the analysis must find, nothing is built below it.
+    pub fn min_profit(mut self, min_profit: U256) -> Self {
+        self.min_profit = min_profit;
+        self
+    }
+
+    pub fn fee_margin_bps(mut self, fee_margin_bps: u32) -> Self {
+        self.fee_margin_bps = fee_margin_bps;
+        self
+    }
+
+    // Added on top of the gas of each entry, see `Simulate::estimate_queue_gas`.
+    pub fn gas_buffer_pct(mut self, gas_buffer_pct: u64) -> Self {
+        self.gas_buffer_pct = gas_buffer_pct;
+        self
+    }
+
+    // Pay the builder `percent_of_profit`, see `TxQueue::append_bribe`.
+    pub fn bribe(mut self, method: BribeMethod, percent_of_profit: u32) -> Self {
+        self.bribe = Some((method, percent_of_profit));
+        self
+    }
+
+    // Stop before anything is sent, the report holds what would have been.
+    pub fn dry_run(mut self) -> Self {
+        self.dry_run = true;
+        self
+    }
+}
+
+// What `run_and_execute` did, or on a dry run would have done.
+#[derive(Debug, Clone)]
+pub struct ExecutionReport {
+    pub opportunity: Opportunity,
+    // The tx list that verified with the most profit.
+    pub strategy: QueueStrategy,
+    pub verification: Verification,
+    // With its gas, fees, nonces, bribe and economics.
+    pub tx_queue: TxQueue,
+    pub fees: FeeEstimate,
+    pub bribe: Option<U256>,
+    pub options: BundleOptions,
+    // Signed by the client's signer, in send order.
+    pub raw_tx_list: Vec<Bytes>,
+    // Of the `SubmissionLoop` the queue went through, `None` on a dry run.
+    pub submission: Option<SubmissionReport>,
+}
+
+fn stopped(stage: PipelineStage) -> impl FnOnce(SimulateError) -> SimulateError {
+    move |error| SimulateError::PipelineStopped {
+        stage,
+        error: Box::new(error),
+    }
+}
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    // Simulate the tx, then build, verify, fill gas, fees and nonces, bribe and submit its queue
+    // for the next block under `policy`, in one call. `None` if the tx holds no opportunity, an
+    // error of a stage comes as `PipelineStopped` with the stage.
+    pub async fn run_and_execute<A: Signer>(
+        &self,
+        input: impl Into<PipelineInput>,
+        policy: &ExecutionPolicy<'_, A>,
+    ) -> Result<Option<ExecutionReport>, SimulateError> {
+        let opportunity = match input.into() {
+            PipelineInput::Hash(tx_hash) => self.run(tx_hash, SimulateTarget::Rewind).await,
+            PipelineInput::Pending(tx) => {
+                self.run_tx(tx, SimulateTarget::Rewind, None, SimulateTimings::default())
+                    .await
+            }
+            PipelineInput::Raw(raw) => self.run_raw(raw).await,
+        }
+        .map_err(stopped(PipelineStage::Simulate))?;
+
+        match opportunity {
+            Some(opportunity) => self.execute(opportunity, policy).await.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    // The stages of `run_and_execute` after the simulation.
+    pub async fn execute<A: Signer>(
+        &self,
+        opportunity: Opportunity,
+        policy: &ExecutionPolicy<'_, A>,
+    ) -> Result<ExecutionReport, SimulateError> {
+        let profit = ProfitReport::total_native(&opportunity.reports);
+        if profit < policy.min_profit {
+            return Err(stopped(PipelineStage::Threshold)(
+                SimulateError::BelowMinProfit {
+                    profit,
+                    min_profit: policy.min_profit,
+                },
+            ));
+        }
+
+        let tx_queues = self
+            .tx_queues(&opportunity)
+            .map_err(stopped(PipelineStage::Build))?;
+        if tx_queues.is_empty() {
+            return Err(stopped(PipelineStage::Build)(SimulateError::NoQueue));
+        }
+
+        let mut verified: Option<(QueueStrategy, TxQueue, Verification)> = None;
+        let mut first_revert = None;
+        for (strategy, tx_queue) in tx_queues {
+            let verification = self
+                .verify_revertible(
+                    &tx_queue.tx_list(),
+                    opportunity.block,
+                    &tx_queue.revertible(),
+                )
+                .await
+                .map_err(stopped(PipelineStage::Verify))?;
+            match (&verification.reverted, &verified) {
+                (Some((index, reason)), _) => {
+                    first_revert.get_or_insert(format!("{strategy:?} entry {index}: {reason}"));
+                }
+                (None, Some((_, _, best))) if best.profit >= verification.profit => {}
+                (None, _) => verified = Some((strategy, tx_queue, verification)),
+            }
+        }
+        let (strategy, mut tx_queue, verification) = verified.ok_or_else(|| {
+            stopped(PipelineStage::Verify)(SimulateError::EveryQueueReverts(
+                first_revert.unwrap_or_default(),
+            ))
+        })?;
+
+        let budget = self
+            .estimate_queue_gas(&mut tx_queue, policy.gas_buffer_pct)
+            .await
+            .map_err(stopped(PipelineStage::Gas))?;
+        if budget.over_budget {
+            return Err(stopped(PipelineStage::Gas)(SimulateError::OverGasBudget {
+                excess: budget.excess,
+            }));
+        }
+
+        let fee_budget = tx_queue
+            .break_even_fees(profit, policy.fee_margin_bps)
+            .map_err(stopped(PipelineStage::Fees))?;
+        let fees = tx_queue
+            .fill_fees(policy.fee_estimator, policy.urgency, &fee_budget)
+            .await
+            .map_err(stopped(PipelineStage::Fees))?;
+
+        tx_queue
+            .assign_nonces(self.inner)
+            .await
+            .map_err(stopped(PipelineStage::Nonces))?;
+
+        let bribe = policy.bribe.and_then(|(method, percent_of_profit)| {
+            let bribe =
+                tx_queue.append_bribe(percent_of_profit, profit, method, Some(&fee_budget))?;
+            Some((method, bribe))
+        });
+        // Signed as they'll be sent, the bribe included.
+        let l1_fee = self
+            .queue_l1_fee(&tx_queue)
+            .await
+            .map_err(stopped(PipelineStage::Economics))?;
+        tx_queue.fill_economics(profit, bribe, l1_fee);
+        tx_queue
+            .ensure_profitable()
+            .map_err(stopped(PipelineStage::Economics))?;
+
+        let current_block = self
+            .get_block_number()
+            .await
+            .map_err(|e| stopped(PipelineStage::Submit)(SimulateError::middleware(e)))?;
+        let mut options = BundleOptions::new(current_block + 1).reverting_entries(&tx_queue);
+        if let Some(victim_raw_tx) = &opportunity.victim_raw_tx {
+            options = options.backrun(victim_raw_tx.clone());
+        }
+        let raw_tx_list = tx_queue
+            .sign_all(self.inner)
+            .await
+            .map_err(stopped(PipelineStage::Submit))?;
+        let submission = match policy.dry_run {
+            true => None,
+            false => {
+                // Resubmitted until it lands, expires or turns unprofitable, gated on every block.
+                let mut submission =
+                    SubmissionLoop::new(self.inner, policy.submitter, self.inner.signer())
+                        .urgency(policy.urgency)
+                        .gate(self);
+                if let Some((method, bribe)) = bribe {
+                    submission = submission.bribe(method, bribe);
+                }
+                if let Some(victim_raw_tx) = &opportunity.victim_raw_tx {
+                    // Only a bundle carries the victim.
+                    submission = submission
+                        .path(SubmissionPath::Bundle)
+                        .backrun(victim_raw_tx.clone());
+                }
+                Some(
+                    submission
+                        .run(tx_queue.clone(), pending())
+                        .await
+                        .map_err(stopped(PipelineStage::Submit))?,
+                )
+            }
+        };
+
+        Ok(ExecutionReport {
+            opportunity,
+            strategy,
+            verification,
+            tx_queue,
+            fees,
+            bribe: bribe.map(|(_, bribe)| bribe),
+            options,
+            raw_tx_list,
+            submission,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        mock::{self, balance_diff, block_trace, call_trace, mock_client},
+        BribeMethod, FeeEstimate, FeeEstimator, GasSource, Opportunity, ProfitReport,
+        QueueStrategy, Simulate, SimulateError, Urgency,
+    };
+    use super::{ExecutionPolicy, PipelineStage};
+    use crate::utils::BundleSubmitter;
+    use async_trait::async_trait;
+    use ethers::{
+        abi::{self, Token},
+        core::rand::thread_rng,
+        prelude::*,
+    };
+    use std::collections::BTreeMap;
+    use url::Url;
+
+    struct FixedFees;
+
+    #[async_trait]
+    impl FeeEstimator for FixedFees {
+        async fn estimate(&self, _urgency: Urgency) -> Result<FeeEstimate, SimulateError> {
+            Ok(FeeEstimate::Eip1559 {
+                max_fee_per_gas: U256::from(20),
+                max_priority_fee_per_gas: U256::from(2),
+            })
+        }
+    }
+
+    fn opportunity(profit: u64) -> Opportunity {
+        let origin_call = TransactionTrace {
+            result: Some(Res::Call(CallResult {
+                gas_used: U256::from(50000),
+                output: Bytes::default(),
+            })),
+            ..call_trace(vec![], 0, U256::zero())
+        };
+        Opportunity {
+            reports: vec![ProfitReport::native(Address::random(), U256::from(profit))],
+            block: Some(BlockNumber::Number(U64::from(99))),
+            ..mock::opportunity(block_trace(vec![origin_call], None))
+        }
+    }
+
+    #[tokio::test]
+    async fn execute_dry_run_stop_before_submission() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None)
+            .await
+            .unwrap()
+            .gas_estimation(GasSource::TraceGasUsed);
+        // Nothing listens, a submission would fail.
+        let submitter = BundleSubmitter::flashbots(
+            Url::parse("http://127.0.0.1:1").unwrap(),
+            LocalWallet::new(&mut thread_rng()),
+        );
+        let policy = ExecutionPolicy::new(&submitter, &FixedFees)
+            .min_profit(U256::from(1_000_000))
+            .bribe(
+                BribeMethod::Coinbase {
+                    contract: Address::random(),
+                },
+                50,
+            )
+            .dry_run();
+
+        // Verified, the nonce, the chain and the L1 fee of both entries, the latest block.
+        mock.push(U64::from(100)).unwrap();
+        for _ in 0..2 {
+            mock.push(Bytes::from(abi::encode(&[Token::Uint(U256::from(1000))])))
+                .unwrap();
+        }
+        mock.push(U256::from(10)).unwrap();
+        mock.push(U256::from(7)).unwrap();
+        let verified = block_trace(
+            vec![call_trace(vec![], 0, U256::zero())],
+            Some(StateDiff(BTreeMap::from([(
+                client.address(),
+                balance_diff(U256::zero(), U256::from(10_000_000)),
+            )]))),
+        );
+        mock.push::<Vec<BlockTrace>, _>(vec![verified]).unwrap();
+
+        let report = simulate
+            .execute(opportunity(10_000_000), &policy)
+            .await
+            .unwrap();
+        assert_eq!(report.strategy, QueueStrategy::Origin);
+        assert_eq!(report.verification.profit, I256::from(10_000_000));
+        assert_eq!(report.options.target_block, U64::from(101));
+        assert!(report.submission.is_none());
+        // The queue's entry and the bribe after it.
+        assert!(report.bribe.is_some());
+        assert_eq!(report.raw_tx_list.len(), 2);
+        assert_eq!(
+            report
+                .tx_queue
+                .tx_list()
+                .iter()
+                .map(|tx| tx.nonce)
+                .collect::<Vec<_>>(),
+            vec![Some(U256::from(7)), Some(U256::from(8))]
+        );
+        assert!(report.tx_queue.ensure_profitable().is_ok());
+        assert_eq!(report.tx_queue.economics.unwrap().l1_fee, U256::from(2000));
+    }
+
+    #[tokio::test]
+    async fn execute_report_stage_stopped_at() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None)
+            .await
+            .unwrap()
+            .gas_estimation(GasSource::TraceGasUsed);
+        let submitter = BundleSubmitter::flashbots(
+            Url::parse("http://127.0.0.1:1").unwrap(),
+            LocalWallet::new(&mut thread_rng()),
+        );
+        let policy = ExecutionPolicy::new(&submitter, &FixedFees).min_profit(U256::from(1000));
+        let stage = |result: Result<_, SimulateError>| match result {
+            Err(SimulateError::PipelineStopped { stage, .. }) => Some(stage),
+            _ => None,
+        };
+
+        // Nothing is asked below the threshold.
+        let result = simulate.execute(opportunity(999), &policy).await;
+        assert!(matches!(
+            &result,
+            Err(SimulateError::PipelineStopped { error, .. })
+                if matches!(**error, SimulateError::BelowMinProfit { .. })
+        ));
+        assert_eq!(stage(result), Some(PipelineStage::Threshold));
+
+        let mut reverted_call = call_trace(vec![], 0, U256::zero());
+        reverted_call.error = Some("Reverted".into());
+        mock.push::<Vec<BlockTrace>, _>(vec![block_trace(vec![reverted_call], None)])
+            .unwrap();
+        assert_eq!(
+            stage(simulate.execute(opportunity(10_000_000), &policy).await),
+            Some(PipelineStage::Verify)
+        );
+    }
+}
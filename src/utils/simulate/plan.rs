@@ -0,0 +1,153 @@
+use super::{
+    Opportunity, ProfitReport, QueueStrategy, Simulate, SimulateError, SimulateTarget, TxQueue,
+};
+use ethers::prelude::*;
+
+// One tx list of the plan, each an alternative way to reproduce the opportunity.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlanStep {
+    pub strategy: QueueStrategy,
+    pub tx_list: Vec<TransactionRequest>,
+    // What the txs attach, nothing is attached under `ValueSource::ContractFunds`.
+    pub value: U256,
+    // The gas limits set or else the gas the calls used in the trace.
+    pub gas: U256,
+}
+
+impl PlanStep {
+    fn new(strategy: QueueStrategy, tx_queue: &TxQueue) -> Self {
+        let entries = &tx_queue.entries;
+        Self {
+            strategy,
+            tx_list: tx_queue.tx_list(),
+            value: entries
+                .iter()
+                .map(|entry| entry.tx.value.unwrap_or_default())
+                .fold(U256::zero(), U256::saturating_add),
+            gas: entries
+                .iter()
+                .map(|entry| entry.tx.gas.or(entry.trace_gas).unwrap_or_default())
+                .fold(U256::zero(), U256::saturating_add),
+        }
+    }
+}
+
+// The reconstructed queue along with what it's for: how each tx list reproduces the opportunity,
+// what it takes and what the analysis found it earns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecutionPlan {
+    // Alternatives, only one of them runs, so what they take doesn't add up.
+    pub steps: Vec<PlanStep>,
+    // The native balance gain, see `ProfitReport::total_native`.
+    pub profit: U256,
+    // Where the profit comes from, every report of the analysis.
+    pub sources: Vec<ProfitReport>,
+}
+
+impl From<ExecutionPlan> for (Vec<Vec<TransactionRequest>>, Vec<ProfitReport>) {
+    fn from(plan: ExecutionPlan) -> Self {
+        (
+            plan.steps.into_iter().map(|step| step.tx_list).collect(),
+            plan.sources,
+        )
+    }
+}
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    // The plan of the opportunity's queue, see `tx_queues`.
+    pub fn execution_plan(
+        &self,
+        opportunity: &Opportunity,
+    ) -> Result<ExecutionPlan, SimulateError> {
+        Ok(ExecutionPlan {
+            steps: self
+                .tx_queues(opportunity)?
+                .iter()
+                .map(|(strategy, tx_queue)| PlanStep::new(*strategy, tx_queue))
+                .collect(),
+            profit: ProfitReport::total_native(&opportunity.reports),
+            sources: opportunity.reports.clone(),
+        })
+    }
+
+    // `run`, with the opportunity as its plan.
+    pub async fn run_plan(
+        &self,
+        tx_hash: TxHash,
+        target: impl Into<SimulateTarget>,
+    ) -> Result<Option<ExecutionPlan>, SimulateError> {
+        self.run(tx_hash, target)
+            .await?
+            .map(|opportunity| self.execution_plan(&opportunity))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        mock::{block_trace, call_trace, mock_client, opportunity},
+        Opportunity, ProfitReport, QueueStrategy, Simulate,
+    };
+    use ethers::prelude::*;
+
+    #[tokio::test]
+    async fn execution_plan_aggregate_queue() {
+        let (client, _) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let call =
+            |trace_address: Vec<usize>, subtraces, value: u64, gas_used: u64| TransactionTrace {
+                result: Some(Res::Call(CallResult {
+                    gas_used: U256::from(gas_used),
+                    output: Bytes::default(),
+                })),
+                ..call_trace(trace_address, subtraces, U256::from(value))
+            };
+        let trace = block_trace(
+            vec![
+                call(vec![], 2, 300, 90000),
+                call(vec![0], 0, 100, 30000),
+                call(vec![1], 0, 200, 40000),
+            ],
+            None,
+        );
+        let reports = vec![ProfitReport::native(Address::random(), U256::from(5000))];
+        let opportunity = Opportunity {
+            reports: reports.clone(),
+            ..opportunity(trace)
+        };
+
+        let plan = simulate.execution_plan(&opportunity).unwrap();
+        let tx_queues = simulate.tx_queues(&opportunity).unwrap();
+        assert_eq!(
+            plan.steps
+                .iter()
+                .map(|step| step.strategy)
+                .collect::<Vec<_>>(),
+            vec![QueueStrategy::Origin, QueueStrategy::Internal]
+        );
+        assert_eq!(plan.steps[0].value, U256::from(300));
+        assert_eq!(plan.steps[1].value, U256::from(300));
+        for (step, (_, tx_queue)) in plan.steps.iter().zip(&tx_queues) {
+            let trace_gas = tx_queue
+                .entries
+                .iter()
+                .map(|entry| entry.trace_gas.unwrap_or_default())
+                .fold(U256::zero(), |total, gas| total + gas);
+            assert_eq!(step.gas, trace_gas);
+        }
+        // Over the 90000 the origin call used, the tx pays its intrinsic gas.
+        assert!(plan.steps[0].gas > U256::from(90000));
+        assert_eq!(plan.profit, U256::from(5000));
+
+        let (tx_list, sources): (Vec<Vec<TransactionRequest>>, _) = plan.into();
+        assert_eq!(
+            tx_list,
+            tx_queues
+                .into_iter()
+                .map(|(_, tx_queue)| tx_queue.tx_list())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(sources, reports);
+    }
+}
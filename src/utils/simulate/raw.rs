@@ -0,0 +1,199 @@
+use super::{Opportunity, Simulate, SimulateError, SimulateTarget, SimulateTimings};
+use ethers::prelude::*;
+use ethers::utils::rlp::{Decodable, Rlp};
+use std::time::Instant;
+
+// Decode a signed legacy / 2930 / 1559 tx and recover its sender, like the rpc would return it.
+pub fn decode_raw_tx(raw: &Bytes) -> Result<Transaction, SimulateError> {
+    match raw.first() {
+        None => return Err(SimulateError::RawTxDecode("empty raw tx".into())),
+        // A legacy tx is a rlp list, which starts above 0x7f.
+        Some(tx_type @ (0x00 | 0x03..=0x7f)) => {
+            return Err(SimulateError::UnsupportedTxType(*tx_type))
+        }
+        _ => {}
+    }
+
+    let mut tx = Transaction::decode(&Rlp::new(raw.as_ref()))
+        .map_err(|e| SimulateError::RawTxDecode(e.to_string()))?;
+    tx.recover_from_mut()
+        .map_err(|e| SimulateError::SignatureRecovery(e.to_string()))?;
+    Ok(tx)
+}
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    // Like `run` for a tx seen in the mempool feed before the rpc indexed it, saves the fetch.
+    // The tx is not mined, so it is simulated on the latest state.
+    pub async fn run_raw(&self, raw: Bytes) -> Result<Option<Opportunity>, SimulateError> {
+        let mut timings = SimulateTimings::default();
+
+        let start = Instant::now();
+        let tx = decode_raw_tx(&raw)?;
+        timings.fetch = start.elapsed();
+
+        let result = self
+            .run_tx(tx, SimulateTarget::Rewind, None, timings)
+            .await
+            .map(|opportunity| {
+                opportunity.map(|opportunity| Opportunity {
+                    victim_raw_tx: Some(raw),
+                    ..opportunity
+                })
+            });
+        #[cfg(feature = "metrics")]
+        super::metrics::METRICS.record(&result);
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        mock::{balance_diff, block_trace, call_trace, mock_client},
+        Simulate, SimulateError,
+    };
+    use super::decode_raw_tx;
+    use ethers::{
+        core::rand::thread_rng,
+        prelude::*,
+        types::transaction::{eip2718::TypedTransaction, eip2930::AccessList},
+    };
+    use std::collections::BTreeMap;
+
+    async fn sign(mut tx: TypedTransaction) -> (LocalWallet, TypedTransaction, Bytes) {
+        let wallet = LocalWallet::new(&mut thread_rng()).with_chain_id(1_u64);
+        tx.set_from(wallet.address()).set_chain_id(1);
+        let signature = wallet.sign_transaction(&tx).await.unwrap();
+        let raw = tx.rlp_signed(&signature);
+        (wallet, tx, raw)
+    }
+
+    async fn assert_round_trip(tx: TypedTransaction) {
+        let (wallet, tx, raw) = sign(tx).await;
+        let decoded = decode_raw_tx(&raw).unwrap();
+
+        assert_eq!(decoded.from, wallet.address());
+        assert_eq!(decoded.hash, H256(ethers::utils::keccak256(&raw)));
+        assert_eq!(
+            decoded.to.as_ref(),
+            tx.to().and_then(NameOrAddress::as_address)
+        );
+        assert_eq!(decoded.nonce, *tx.nonce().unwrap());
+        assert_eq!(decoded.value, *tx.value().unwrap());
+        assert_eq!(decoded.gas, *tx.gas().unwrap());
+        assert_eq!(&decoded.input, tx.data().unwrap());
+        assert_eq!(decoded.chain_id, Some(U256::one()));
+    }
+
+    #[tokio::test]
+    async fn decode_legacy_tx() {
+        assert_round_trip(
+            TransactionRequest::new()
+                .to(Address::random())
+                .value(1)
+                .nonce(7)
+                .gas(21000)
+                .gas_price(10)
+                .data(vec![0, 0, 0, 1])
+                .into(),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn decode_eip2930_tx() {
+        assert_round_trip(
+            TransactionRequest::new()
+                .to(Address::random())
+                .value(1)
+                .nonce(7)
+                .gas(21000)
+                .gas_price(10)
+                .data(vec![0, 0, 0, 1])
+                .with_access_list(AccessList::default())
+                .into(),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn decode_eip1559_tx() {
+        assert_round_trip(
+            Eip1559TransactionRequest::new()
+                .to(Address::random())
+                .value(1)
+                .nonce(7)
+                .gas(21000)
+                .max_fee_per_gas(100)
+                .max_priority_fee_per_gas(2)
+                .data(vec![0, 0, 0, 1])
+                .into(),
+        )
+        .await;
+    }
+
+    #[test]
+    fn decode_reject_unsupported_tx_type() {
+        let raw = Bytes::from(vec![0x03, 0xc0]);
+        assert!(matches!(
+            decode_raw_tx(&raw),
+            Err(SimulateError::UnsupportedTxType(0x03))
+        ));
+    }
+
+    #[test]
+    fn decode_reject_invalid_signature() {
+        let tx: TypedTransaction = TransactionRequest::new()
+            .to(Address::random())
+            .nonce(0)
+            .gas(21000)
+            .gas_price(10)
+            .value(0)
+            .into();
+        let raw = tx.rlp_signed(&Signature {
+            r: U256::zero(),
+            s: U256::zero(),
+            v: 27,
+        });
+        assert!(matches!(
+            decode_raw_tx(&raw),
+            Err(SimulateError::SignatureRecovery(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn run_raw_trace_without_fetch() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let (wallet, _, raw) = sign(
+            TransactionRequest::new()
+                .to(Address::random())
+                .nonce(0)
+                .gas(100000)
+                .gas_price(10)
+                .value(0)
+                .data(vec![0, 0, 0, 1])
+                .into(),
+        )
+        .await;
+
+        let state_diff = StateDiff(BTreeMap::from([(
+            wallet.address(),
+            balance_diff(U256::zero(), U256::from(1)),
+        )]));
+        // Only the trace is queued, a fetch would have consumed it.
+        mock.push(U256::one()).unwrap();
+        mock.push(block_trace(
+            vec![call_trace(vec![], 0, U256::zero())],
+            Some(state_diff),
+        ))
+        .unwrap();
+
+        let opportunity = simulate.run_raw(raw.clone()).await.unwrap().unwrap();
+        assert_eq!(opportunity.block, None);
+        assert_eq!(opportunity.reports[0].beneficiary, wallet.address());
+        // Held for a backrun bundle.
+        assert_eq!(opportunity.victim_raw_tx, Some(raw));
+    }
+}
@@ -0,0 +1,215 @@
+use super::{FeeBudget, QueueEntry, Simulate, SimulateError, TxQueue};
+use ethers::prelude::*;
+
+// Nodes drop a replacement that doesn't raise every fee by at least 10%.
+const MIN_BUMP_PCT: u64 = 10;
+
+impl TxQueue {
+    // A replacement of the entry at `entry_index` for when it is stuck: same nonce and calldata,
+    // every fee `bump_pct` percent higher but at least the 10% nodes require. Within `budget` the
+    // bump is lowered to its max fee, `None` if not even the minimum bump fits.
+    pub fn bump(
+        &self,
+        entry_index: usize,
+        bump_pct: u64,
+        budget: Option<&FeeBudget>,
+    ) -> Option<QueueEntry> {
+        let mut entry = self.entries.get(entry_index)?.clone();
+        let cap = budget.map(|budget| budget.max_fee_per_gas);
+
+        match entry.max_fee_per_gas {
+            Some(max_fee_per_gas) => {
+                let max_fee_per_gas = bumped(max_fee_per_gas, bump_pct, cap)?;
+                let max_priority_fee_per_gas = bumped(
+                    entry.max_priority_fee_per_gas.unwrap_or_default(),
+                    bump_pct,
+                    Some(max_fee_per_gas),
+                )?;
+                entry.max_fee_per_gas = Some(max_fee_per_gas);
+                entry.max_priority_fee_per_gas = Some(max_priority_fee_per_gas);
+            }
+            None => entry.tx.gas_price = Some(bumped(entry.tx.gas_price?, bump_pct, cap)?),
+        }
+        Some(entry)
+    }
+
+    // A 0 value transfer to the sender itself at the entry's nonce, with the minimum bump, so the
+    // stuck entry never lands.
+    pub fn cancel(&self, entry_index: usize) -> Option<QueueEntry> {
+        let bumped = self.bump(entry_index, MIN_BUMP_PCT, None)?;
+        let from = bumped.tx.from?;
+        let mut tx = TransactionRequest::new()
+            .from(from)
+            .to(from)
+            .value(0)
+            .gas(21000);
+        tx.nonce = bumped.tx.nonce;
+        tx.chain_id = bumped.tx.chain_id;
+        tx.gas_price = bumped.tx.gas_price;
+        Some(QueueEntry {
+            max_fee_per_gas: bumped.max_fee_per_gas,
+            max_priority_fee_per_gas: bumped.max_priority_fee_per_gas,
+            ..QueueEntry::from(tx)
+        })
+    }
+}
+
+// `fee` raised by `bump_pct` percent (at least the minimum), rounded up so the minimum is met.
+// Lowered to `cap` if the minimum bump still fits below it, `None` past `U256`.
+fn bumped(fee: U256, bump_pct: u64, cap: Option<U256>) -> Option<U256> {
+    let raise = |pct: u64| {
+        let bump = fee
+            .checked_mul(U256::from(pct))?
+            .checked_add(U256::from(99))?
+            / 100;
+        fee.checked_add(bump)
+    };
+    let min_fee = raise(MIN_BUMP_PCT)?;
+    let fee = raise(bump_pct)?.max(min_fee);
+    match cap {
+        Some(cap) if min_fee > cap => None,
+        Some(cap) => Some(fee.min(cap)),
+        None => Some(fee),
+    }
+}
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    // Link a sent replacement (or cancellation) to the tx it replaces, `landed` then follows both.
+    pub fn track_replacement(&self, original: TxHash, replacement: TxHash) {
+        let mut replacements = self.replacements.lock().unwrap();
+        // Replacing a replacement still links to the first tx.
+        let original = replacements.get(&original).copied().unwrap_or(original);
+        replacements.insert(replacement, original);
+    }
+
+    // The original hash and every replacement tracked for it.
+    pub fn replacement_chain(&self, original: TxHash) -> Vec<TxHash> {
+        let replacements = self.replacements.lock().unwrap();
+        let mut chain = vec![original];
+        chain.extend(
+            replacements
+                .iter()
+                .filter(|(_, tx_hash)| **tx_hash == original)
+                .map(|(replacement, _)| *replacement),
+        );
+        chain
+    }
+
+    // The receipt of whichever tx of the replacement chain was mined, `None` while none was.
+    pub async fn landed(
+        &self,
+        original: TxHash,
+    ) -> Result<Option<TransactionReceipt>, SimulateError> {
+        for tx_hash in self.replacement_chain(original) {
+            let receipt = self
+                .get_transaction_receipt(tx_hash)
+                .await
+                .map_err(SimulateError::middleware)?;
+            if receipt.is_some() {
+                return Ok(receipt);
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{mock::mock_client, QueueEntry, Simulate, TxQueue};
+    use ethers::prelude::*;
+
+    fn stuck_queue(from: Address) -> TxQueue {
+        TxQueue {
+            entries: vec![QueueEntry {
+                max_fee_per_gas: Some(U256::from(1001)),
+                max_priority_fee_per_gas: Some(U256::from(100)),
+                ..TransactionRequest::new()
+                    .from(from)
+                    .to(Address::random())
+                    .nonce(7)
+                    .data(vec![0, 0, 0, 1])
+                    .into()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn bump_at_least_the_minimum() {
+        let tx_queue = stuck_queue(Address::random());
+
+        // 5% is raised to the 10% nodes require, rounded up.
+        let replacement = tx_queue.bump(0, 5, None).unwrap();
+        assert_eq!(replacement.max_fee_per_gas, Some(U256::from(1102)));
+        assert_eq!(replacement.max_priority_fee_per_gas, Some(U256::from(110)));
+        assert_eq!(replacement.tx.nonce, Some(U256::from(7)));
+        assert_eq!(replacement.tx.data, tx_queue.entries[0].tx.data);
+
+        let replacement = tx_queue.bump(0, 50, None).unwrap();
+        assert_eq!(replacement.max_fee_per_gas, Some(U256::from(1502)));
+        assert!(tx_queue.bump(1, 50, None).is_none());
+    }
+
+    #[test]
+    fn bump_within_budget() {
+        let tx_queue = TxQueue {
+            entries: vec![QueueEntry {
+                tx: TransactionRequest::new().gas(100000),
+                ..stuck_queue(Address::random()).entries[0].clone()
+            }],
+            ..Default::default()
+        };
+
+        // At most 1200 per gas, the 50% bump is lowered to it.
+        let budget = tx_queue
+            .break_even_fees(U256::from(120_000_000), 0)
+            .unwrap();
+        let replacement = tx_queue.bump(0, 50, Some(&budget)).unwrap();
+        assert_eq!(replacement.max_fee_per_gas, Some(U256::from(1200)));
+        assert_eq!(replacement.max_priority_fee_per_gas, Some(U256::from(150)));
+
+        // At most 1100 per gas, below the minimum bump.
+        let budget = tx_queue
+            .break_even_fees(U256::from(110_000_000), 0)
+            .unwrap();
+        assert!(tx_queue.bump(0, 50, Some(&budget)).is_none());
+    }
+
+    #[test]
+    fn cancel_with_self_transfer() {
+        let from = Address::random();
+        let cancellation = stuck_queue(from).cancel(0).unwrap();
+        assert_eq!(cancellation.tx.to, Some(from.into()));
+        assert_eq!(cancellation.tx.value, Some(U256::zero()));
+        assert_eq!(cancellation.tx.data, None);
+        assert_eq!(cancellation.tx.nonce, Some(U256::from(7)));
+        assert_eq!(cancellation.max_fee_per_gas, Some(U256::from(1102)));
+    }
+
+    #[tokio::test]
+    async fn landed_follow_replacement() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let original = TxHash::random();
+        let replacement = TxHash::random();
+        let cancellation = TxHash::random();
+        simulate.track_replacement(original, replacement);
+        simulate.track_replacement(replacement, cancellation);
+        assert_eq!(simulate.replacement_chain(original).len(), 3);
+
+        let receipt = TransactionReceipt {
+            transaction_hash: cancellation,
+            ..Default::default()
+        };
+        // None of the hashes but the mined one has a receipt.
+        let mut chain = simulate.replacement_chain(original);
+        let mined = chain.iter().position(|h| *h == cancellation).unwrap();
+        chain.truncate(mined + 1);
+        mock.push(receipt.clone()).unwrap();
+        for _ in 1..chain.len() {
+            mock.push(serde_json::Value::Null).unwrap();
+        }
+
+        assert_eq!(simulate.landed(original).await.unwrap(), Some(receipt));
+    }
+}
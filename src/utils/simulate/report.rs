@@ -0,0 +1,213 @@
+use ethers::prelude::*;
+use std::collections::HashMap;
+use std::iter::Sum;
+
+struct SumU256(U256);
+impl Sum for SumU256 {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self(U256::zero()), |a, b| Self(a.0 + b.0))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProfitCurrency {
+    Native,
+    Token(Address),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ProfitKind {
+    // A balance gain over the whole tx.
+    #[default]
+    Balance,
+    // A fee rebate paid out by `collector`, already part of the holder's balance gain: it tells
+    // the share of the profit that isn't from the swap, see `ProfitReport::total_rebates`.
+    Rebate {
+        collector: Address,
+    },
+}
+
+// One detected profit source, the caller decides how to aggregate them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfitReport {
+    pub beneficiary: Address,
+    pub currency: ProfitCurrency,
+    pub amount: U256,
+    // The sender's nonce in the diff doesn't match the tx, only set under `NoncePolicy::Warn`.
+    pub nonce_mismatch: bool,
+    pub kind: ProfitKind,
+}
+
+impl ProfitReport {
+    pub fn native(beneficiary: Address, amount: U256) -> Self {
+        Self {
+            beneficiary,
+            currency: ProfitCurrency::Native,
+            amount,
+            nonce_mismatch: false,
+            kind: ProfitKind::Balance,
+        }
+    }
+
+    pub fn token(beneficiary: Address, token: Address, amount: U256) -> Self {
+        Self {
+            beneficiary,
+            currency: ProfitCurrency::Token(token),
+            amount,
+            nonce_mismatch: false,
+            kind: ProfitKind::Balance,
+        }
+    }
+
+    pub fn nonce_mismatch(mut self, nonce_mismatch: bool) -> Self {
+        self.nonce_mismatch = nonce_mismatch;
+        self
+    }
+
+    pub fn rebate(mut self, collector: Address) -> Self {
+        self.kind = ProfitKind::Rebate { collector };
+        self
+    }
+
+    // The amount in ETH (18 decimals) rounded to `decimals` digits, meant for native profit.
+    pub fn format_eth(&self, decimals: u8) -> String {
+        format_units_rounded(self.amount, 18, decimals)
+    }
+
+    // Token profit in whole tokens, `None` if the token isn't registered.
+    pub fn format_token(&self, registry: &TokenRegistry, decimals: u8) -> Option<String> {
+        match self.currency {
+            ProfitCurrency::Native => Some(self.format_eth(decimals)),
+            ProfitCurrency::Token(token) => registry
+                .decimals(&token)
+                .map(|unit| format_units_rounded(self.amount, unit.into(), decimals)),
+        }
+    }
+
+    // The amount in whole tokens with the token's symbol, e.g. `12.5 USDC`.
+    pub fn format_with(&self, meta: &TokenMeta, decimals: u8) -> String {
+        let amount = format_units_rounded(self.amount, meta.decimals.into(), decimals);
+        format!("{amount} {}", meta.symbol)
+    }
+
+    // Token profit is skipped, it can't be summed with native token without a price. So are
+    // rebates, the balance gains already count them.
+    pub fn total_native(reports: &[ProfitReport]) -> U256 {
+        reports
+            .iter()
+            .filter(|r| r.currency == ProfitCurrency::Native && r.kind == ProfitKind::Balance)
+            .map(|r| SumU256(r.amount))
+            .sum::<SumU256>()
+            .0
+    }
+
+    // The native rebates of `reports`, the part of `total_native` not earned by the swap.
+    pub fn total_rebates(reports: &[ProfitReport]) -> U256 {
+        reports
+            .iter()
+            .filter(|r| {
+                r.currency == ProfitCurrency::Native && matches!(r.kind, ProfitKind::Rebate { .. })
+            })
+            .map(|r| SumU256(r.amount))
+            .sum::<SumU256>()
+            .0
+    }
+}
+
+// What a token calls itself, see `Simulate::token_metadata`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenMeta {
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+impl TokenMeta {
+    // The native token of mainnet and the L2s settling on it.
+    pub fn eth() -> Self {
+        Self {
+            symbol: "ETH".into(),
+            decimals: 18,
+        }
+    }
+}
+
+// Decimals of the tokens profit is reported in, for display only.
+#[derive(Debug, Clone, Default)]
+pub struct TokenRegistry(HashMap<Address, u8>);
+
+impl TokenRegistry {
+    pub fn register(&mut self, token: Address, decimals: u8) -> &mut Self {
+        self.0.insert(token, decimals);
+        self
+    }
+
+    pub fn decimals(&self, token: &Address) -> Option<u8> {
+        self.0.get(token).copied()
+    }
+}
+
+// `amount / 10^unit` rounded half up to `decimals` digits, trailing zeros trimmed.
+fn format_units_rounded(amount: U256, unit: u32, decimals: u8) -> String {
+    let unit_scale = U256::exp10(unit as usize);
+    let (mut integer, fraction) = amount.div_mod(unit_scale);
+    let decimals = u32::from(decimals).min(unit);
+    let scale = U256::exp10(decimals as usize);
+
+    // `fraction < 10^unit`, it can't overflow for any sane unit.
+    let mut fraction = (fraction * scale + unit_scale / 2) / unit_scale;
+    if fraction >= scale {
+        integer += U256::one();
+        fraction -= scale;
+    }
+
+    let fraction = format!(
+        "{:0>width$}",
+        fraction.to_string(),
+        width = decimals as usize
+    );
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.is_empty() {
+        integer.to_string()
+    } else {
+        format!("{integer}.{fraction}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ProfitReport, TokenRegistry};
+    use ethers::prelude::*;
+
+    #[test]
+    fn total_native_skip_token_profit() {
+        let reports = vec![
+            ProfitReport::native(Address::random(), U256::from(1)),
+            ProfitReport::token(Address::random(), Address::random(), U256::from(10)),
+            ProfitReport::native(Address::random(), U256::from(2)),
+        ];
+        assert_eq!(ProfitReport::total_native(&reports), U256::from(3));
+    }
+
+    #[test]
+    fn format_eth_round_to_decimals() {
+        let report = ProfitReport::native(Address::random(), U256::from(15) * U256::exp10(17));
+        assert_eq!(report.format_eth(4), "1.5");
+        assert_eq!(report.format_eth(0), "2");
+
+        let report = ProfitReport::native(Address::random(), U256::from(123_456_789_u64));
+        assert_eq!(report.format_eth(6), "0");
+        assert_eq!(report.format_eth(12), "0.000000000123");
+    }
+
+    #[test]
+    fn format_token_with_registered_decimals() {
+        let usdc = Address::random();
+        let mut registry = TokenRegistry::default();
+        registry.register(usdc, 6);
+
+        let report = ProfitReport::token(Address::random(), usdc, U256::from(12_500_000));
+        assert_eq!(report.format_token(&registry, 2).unwrap(), "12.5");
+        let report = ProfitReport::token(Address::random(), Address::random(), U256::one());
+        assert!(report.format_token(&registry, 2).is_none());
+    }
+}
@@ -0,0 +1,107 @@
+use super::{BlockMevReport, Simulate, SimulateError};
+use ethers::prelude::*;
+
+// The last block a `scan_range` fully processed, handed to `persist` after every block so a
+// restarted scan picks up behind it.
+pub struct Checkpoint<'c> {
+    last_block: Option<U64>,
+    persist: Box<dyn FnMut(U64) + 'c>,
+}
+
+impl<'c> Checkpoint<'c> {
+    // `last_block` as loaded from wherever `persist` writes it, `None` for a fresh scan.
+    pub fn new(last_block: Option<U64>, persist: impl FnMut(U64) + 'c) -> Self {
+        Self {
+            last_block,
+            persist: Box::new(persist),
+        }
+    }
+
+    pub fn last_block(&self) -> Option<U64> {
+        self.last_block
+    }
+
+    fn advance(&mut self, block: U64) {
+        self.last_block = Some(block);
+        (self.persist)(block);
+    }
+}
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    // `analyze_block` for every block from `from` to `to`, both included, paced like
+    // `run_many`. With a checkpoint the blocks up to its last one are skipped and it moves
+    // past every analyzed block, a failed block stops the scan before it.
+    pub async fn scan_range(
+        &self,
+        from: U64,
+        to: U64,
+        mut checkpoint: Option<&mut Checkpoint<'_>>,
+    ) -> Result<Vec<BlockMevReport>, SimulateError> {
+        let start = match checkpoint.as_ref().and_then(|c| c.last_block()) {
+            Some(last_block) => from.max(last_block + U64::one()),
+            None => from,
+        };
+
+        let mut reports = Vec::new();
+        let mut block = start;
+        while block <= to {
+            self.pace(reports.len()).await;
+            reports.push(self.analyze_block(BlockNumber::Number(block)).await?);
+            if let Some(checkpoint) = checkpoint.as_deref_mut() {
+                checkpoint.advance(block);
+            }
+            block += U64::one();
+        }
+
+        Ok(reports)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{mock::mock_client, Simulate};
+    use super::Checkpoint;
+    use ethers::prelude::*;
+
+    #[tokio::test]
+    async fn scan_range_resume_after_checkpoint() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+
+        // Nothing is there for 100 and 101.
+        for number in [103_u64, 102] {
+            mock.push::<Vec<BlockTrace>, _>(vec![]).unwrap();
+            mock.push(Block::<Transaction> {
+                number: Some(U64::from(number)),
+                ..Default::default()
+            })
+            .unwrap();
+        }
+
+        // 101 was the last block done before the crash.
+        let mut persisted = vec![];
+        let mut checkpoint = Checkpoint::new(Some(U64::from(101)), |block| persisted.push(block));
+        let reports = simulate
+            .scan_range(U64::from(100), U64::from(103), Some(&mut checkpoint))
+            .await
+            .unwrap();
+        let blocks = reports
+            .iter()
+            .map(|report| report.block)
+            .collect::<Vec<_>>();
+        assert_eq!(blocks, vec![U64::from(102), U64::from(103)]);
+        assert_eq!(checkpoint.last_block(), Some(U64::from(103)));
+
+        // Past the end of the range, nothing is left to scan.
+        let reports = simulate
+            .scan_range(U64::from(100), U64::from(103), Some(&mut checkpoint))
+            .await
+            .unwrap();
+        assert!(reports.is_empty());
+
+        drop(checkpoint);
+        assert_eq!(persisted, vec![U64::from(102), U64::from(103)]);
+        mock.assert_request("eth_getBlockByNumber", ("0x66", true))
+            .unwrap();
+    }
+}
@@ -0,0 +1,107 @@
+use super::Simulate;
+use ethers::{
+    abi::{Abi, Token},
+    prelude::*,
+};
+
+// The first four bytes of `input`, zero padded if the calldata is shorter.
+pub fn selector_of(input: &Bytes) -> [u8; 4] {
+    let mut selector = [0; 4];
+    let len = input.len().min(4);
+    selector[..len].copy_from_slice(&input[..len]);
+    selector
+}
+
+// Addresses and bytes in 0x hex, numbers in decimal.
+fn format_token(token: &Token) -> String {
+    let join = |tokens: &[Token]| {
+        tokens
+            .iter()
+            .map(format_token)
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    match token {
+        Token::Address(address) => format!("{address:?}"),
+        Token::FixedBytes(bytes) | Token::Bytes(bytes) => Bytes::from(bytes.clone()).to_string(),
+        Token::Int(int) => I256::from_raw(*int).to_string(),
+        Token::Uint(uint) => uint.to_string(),
+        Token::Bool(value) => value.to_string(),
+        Token::String(value) => format!("{value:?}"),
+        Token::FixedArray(tokens) | Token::Array(tokens) => format!("[{}]", join(tokens)),
+        Token::Tuple(tokens) => format!("({})", join(tokens)),
+    }
+}
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    // Decode the victim's call with the functions of `abi` into `Opportunity::decoded`, e.g. the
+    // router it swaps through. Several abis may be registered.
+    pub fn abi(mut self, abi: &Abi) -> Self {
+        for function in abi.functions() {
+            self.functions
+                .insert(function.short_signature(), function.clone());
+        }
+        self
+    }
+
+    // `name(args)` of the call, `None` without a registered function or for bad calldata.
+    pub(crate) fn decode_call(&self, input: &Bytes) -> Option<String> {
+        let function = self.functions.get(&selector_of(input))?;
+        let tokens = function.decode_input(input.get(4..)?).ok()?;
+        let args = tokens.iter().map(format_token).collect::<Vec<_>>();
+        Some(format!("{}({})", function.name, args.join(", ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        mock::{balance_diff, block_trace, call_trace, mock_client},
+        Simulate,
+    };
+    use super::selector_of;
+    use ethers::{
+        abi::{self, parse_abi, Token},
+        prelude::*,
+        utils::id,
+    };
+    use std::collections::BTreeMap;
+
+    #[tokio::test]
+    async fn run_report_selector_and_decoded_call() {
+        let (client, mock) = mock_client();
+        let abi =
+            parse_abi(&["function transfer(address to, uint256 amount) returns (bool)"]).unwrap();
+        let simulate = Simulate::init(&client, None).await.unwrap().abi(&abi);
+        let to = Address::random();
+        let tx = Transaction {
+            hash: TxHash::random(),
+            input: [
+                id("transfer(address,uint256)").to_vec(),
+                abi::encode(&[Token::Address(to), Token::Uint(1000.into())]),
+            ]
+            .concat()
+            .into(),
+            ..Default::default()
+        };
+        let trace = block_trace(
+            vec![call_trace(vec![], 0, U256::zero())],
+            Some(StateDiff(BTreeMap::from([(
+                tx.from,
+                balance_diff(U256::zero(), U256::from(1000)),
+            )]))),
+        );
+
+        mock.push(U256::one()).unwrap();
+        mock.push(trace).unwrap();
+        mock.push(tx.clone()).unwrap();
+
+        let opportunity = simulate.run(tx.hash, true).await.unwrap().unwrap();
+        assert_eq!(opportunity.selector, tx.input[..4]);
+        assert_eq!(opportunity.decoded, Some(format!("transfer({to:?}, 1000)")));
+
+        // Too short for a selector, and nothing registered for it.
+        assert_eq!(selector_of(&Bytes::from(vec![0xab])), [0xab, 0, 0, 0]);
+        assert_eq!(simulate.decode_call(&Bytes::from(vec![0xab])), None);
+    }
+}
@@ -0,0 +1,339 @@
+use super::{tx_queue::fill_entry_gas, verify::revert_reason, SimulateError, TxQueue};
+use ethers::prelude::*;
+use std::time::Duration;
+
+// Buffer of the gas `execute_sequential` estimates itself, a public tx that runs out of gas still
+// pays for it.
+const GAS_BUFFER_PCT: u64 = 20;
+
+impl TxQueue {
+    // Send the entries publicly, in order, each once the one before it has a receipt with
+    // `confirmations` blocks (1 is the block it was mined in), for chains without a private relay.
+    // Before the first send the queue is checked for expiry and the missing nonces are filled, a
+    // missing gas limit is estimated right before its entry is sent, on the state the ones before
+    // it left. Stops at the first entry mined with status 0, with its index and revert reason.
+    pub async fn execute_sequential<M: Middleware, S: Signer>(
+        &mut self,
+        client: &SignerMiddleware<M, S>,
+        confirmations: usize,
+        timeout_per_tx: Duration,
+    ) -> Result<Vec<TransactionReceipt>, SimulateError> {
+        self.execute_sequential_from(client, 0, confirmations, timeout_per_tx)
+            .await
+    }
+
+    // `execute_sequential` resumed at the entry `start`, e.g. once the failure of the one before
+    // it was dealt with. Returns the receipts of the entries from `start` on.
+    pub async fn execute_sequential_from<M: Middleware, S: Signer>(
+        &mut self,
+        client: &SignerMiddleware<M, S>,
+        start: usize,
+        confirmations: usize,
+        timeout_per_tx: Duration,
+    ) -> Result<Vec<TransactionReceipt>, SimulateError> {
+        let current_block = client
+            .get_block_number()
+            .await
+            .map_err(SimulateError::middleware)?;
+        self.ensure_valid(current_block)?;
+
+        let start = start.min(self.entries.len());
+        let from = client.address();
+        let pending = &mut self.entries[start..];
+        if pending.iter().any(|entry| entry.tx.nonce.is_none()) {
+            // The entries before `start` are sent, the pending nonce is already past theirs. A set
+            // nonce is kept, the missing ones follow it.
+            let mut nonce = client
+                .get_transaction_count(from, Some(BlockNumber::Pending.into()))
+                .await
+                .map_err(SimulateError::middleware)?;
+            for entry in pending.iter_mut() {
+                match entry.tx.nonce {
+                    Some(set) => nonce = set,
+                    None => {
+                        entry.tx.from = Some(from);
+                        entry.tx.nonce = Some(nonce);
+                    }
+                }
+                nonce += U256::one();
+            }
+        }
+        let gas_limit = if pending.iter().any(|entry| entry.tx.gas.is_none()) {
+            client
+                .get_block(BlockNumber::Latest)
+                .await
+                .map_err(SimulateError::middleware)?
+                .map(|block| block.gas_limit)
+        } else {
+            None
+        };
+
+        let mut receipts = Vec::with_capacity(pending.len());
+        for (index, entry) in (start..).zip(pending.iter_mut()) {
+            if entry.tx.gas.is_none() {
+                fill_entry_gas(client, entry, GAS_BUFFER_PCT, gas_limit, None).await;
+            }
+            if entry.tx.gas.is_none() {
+                return Err(SimulateError::Signing {
+                    index,
+                    reason: match &entry.estimate_error {
+                        Some(e) => format!("no gas limit, the estimate failed: {e}"),
+                        None => "no gas limit".into(),
+                    },
+                });
+            }
+            let raw_tx = TxQueue {
+                entries: vec![entry.clone()],
+                ..Default::default()
+            }
+            .sign_all(client)
+            .await
+            .map_err(|e| match e {
+                SimulateError::Signing { reason, .. } => SimulateError::Signing { index, reason },
+                e => e,
+            })?
+            .remove(0);
+            let tx_hash = *client
+                .send_raw_transaction(raw_tx)
+                .await
+                .map_err(SimulateError::middleware)?;
+            let receipt =
+                tokio::time::timeout(timeout_per_tx, confirmed(client, tx_hash, confirmations))
+                    .await
+                    .map_err(|_| SimulateError::ReceiptTimeout {
+                        index,
+                        tx_hash,
+                        timeout: timeout_per_tx,
+                    })??;
+            if receipt.status == Some(U64::zero()) {
+                return Err(SimulateError::TxReverted {
+                    index,
+                    tx_hash,
+                    reason: replayed_revert(client, tx_hash).await,
+                });
+            }
+            receipts.push(receipt);
+        }
+
+        Ok(receipts)
+    }
+}
+
+// Poll at the provider's interval until the receipt of `tx_hash` is `confirmations` blocks deep.
+async fn confirmed<M: Middleware>(
+    client: &M,
+    tx_hash: TxHash,
+    confirmations: usize,
+) -> Result<TransactionReceipt, SimulateError> {
+    let interval = client.provider().get_interval();
+    loop {
+        let receipt = client
+            .get_transaction_receipt(tx_hash)
+            .await
+            .map_err(SimulateError::middleware)?;
+        if let Some(receipt) = receipt {
+            if confirmations <= 1 {
+                return Ok(receipt);
+            }
+            let mined = receipt.block_number.unwrap_or_default();
+            let current_block = client
+                .get_block_number()
+                .await
+                .map_err(SimulateError::middleware)?;
+            if current_block >= mined + (confirmations as u64 - 1) {
+                return Ok(receipt);
+            }
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+// The receipt carries no reason, replay the mined tx for it.
+async fn replayed_revert<M: Middleware>(client: &M, tx_hash: TxHash) -> String {
+    match client
+        .trace_replay_transaction(tx_hash, vec![TraceType::Trace])
+        .await
+    {
+        Ok(trace) => revert_reason(&trace.output)
+            .or_else(|| trace.trace?.first()?.error.clone())
+            .unwrap_or_else(|| "status 0".into()),
+        Err(e) => format!("status 0, the replay failed: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        mock::{block_trace, mock_client},
+        SimulateError, TxQueue,
+    };
+    use ethers::{
+        abi::{self, Token},
+        prelude::*,
+        utils::id,
+    };
+    use std::time::Duration;
+
+    fn receipt(transaction_hash: TxHash, block: u64, status: u64) -> TransactionReceipt {
+        TransactionReceipt {
+            transaction_hash,
+            block_number: Some(U64::from(block)),
+            status: Some(U64::from(status)),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn execute_sequential_stop_on_revert_and_resume() {
+        let (client, mock) = mock_client();
+        let mut tx_queue = TxQueue::from(vec![
+            TransactionRequest::new()
+                .to(Address::random())
+                .gas(100000)
+                .gas_price(1),
+            TransactionRequest::new()
+                .to(Address::random())
+                .gas(100000)
+                .gas_price(1),
+        ]);
+        tx_queue.valid_until_block = Some(U64::from(105));
+        let (first, second, retried) = (TxHash::random(), TxHash::random(), TxHash::random());
+        let mut reverted = block_trace(vec![], None);
+        reverted.output = [
+            id("Error(string)").to_vec(),
+            abi::encode(&[Token::String("too little received".into())]),
+        ]
+        .concat()
+        .into();
+
+        mock.push(reverted).unwrap();
+        mock.push(U64::from(104)).unwrap();
+        mock.push(receipt(second, 103, 0)).unwrap();
+        mock.push(second).unwrap();
+        // Mined at 101, two confirmations once 102 is.
+        mock.push(U64::from(102)).unwrap();
+        mock.push(receipt(first, 101, 1)).unwrap();
+        mock.push(U64::from(101)).unwrap();
+        mock.push(receipt(first, 101, 1)).unwrap();
+        mock.push(Option::<TransactionReceipt>::None).unwrap();
+        mock.push(first).unwrap();
+        mock.push(U256::from(5)).unwrap();
+        mock.push(U64::from(100)).unwrap();
+
+        let result = tx_queue
+            .execute_sequential(&client, 2, Duration::from_secs(60))
+            .await;
+        match result {
+            Err(SimulateError::TxReverted {
+                index,
+                tx_hash,
+                reason,
+            }) => {
+                assert_eq!((index, tx_hash), (1, second));
+                assert_eq!(reason, "too little received");
+            }
+            result => panic!("expected the second entry to revert, got {result:?}"),
+        }
+        let nonces = tx_queue
+            .entries
+            .iter()
+            .map(|entry| entry.tx.nonce)
+            .collect::<Vec<_>>();
+        assert_eq!(nonces, vec![Some(U256::from(5)), Some(U256::from(6))]);
+
+        // Resumed at the reverted entry, its nonce was used up so it gets the next one.
+        tx_queue.entries[1].tx.nonce = None;
+        mock.push(receipt(retried, 105, 1)).unwrap();
+        mock.push(retried).unwrap();
+        mock.push(U256::from(7)).unwrap();
+        mock.push(U64::from(104)).unwrap();
+        let receipts = tx_queue
+            .execute_sequential_from(&client, 1, 1, Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert_eq!(receipts, vec![receipt(retried, 105, 1)]);
+        assert_eq!(tx_queue.entries[1].tx.nonce, Some(U256::from(7)));
+
+        // The chain passed the queue's last block, nothing is sent.
+        mock.push(U64::from(105)).unwrap();
+        assert!(matches!(
+            tx_queue
+                .execute_sequential_from(&client, 1, 1, Duration::from_secs(60))
+                .await,
+            Err(SimulateError::QueueExpired { .. })
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn execute_sequential_estimate_after_previous_receipt() {
+        let (client, mock) = mock_client();
+        let mut tx_queue = TxQueue::from(vec![
+            TransactionRequest::new()
+                .to(Address::random())
+                .gas(100000)
+                .gas_price(1),
+            TransactionRequest::new()
+                .to(Address::random())
+                .nonce(9)
+                .gas_price(1),
+        ]);
+        let (first, second) = (TxHash::random(), TxHash::random());
+
+        // Estimated after the first entry's receipt, any earlier the answers wouldn't fit.
+        mock.push(receipt(second, 102, 1)).unwrap();
+        mock.push(second).unwrap();
+        mock.push(U256::from(50000)).unwrap();
+        mock.push(receipt(first, 101, 1)).unwrap();
+        mock.push(first).unwrap();
+        mock.push(Block::<TxHash> {
+            gas_limit: U256::from(30_000_000),
+            ..Default::default()
+        })
+        .unwrap();
+        mock.push(U256::from(5)).unwrap();
+        mock.push(U64::from(100)).unwrap();
+
+        let receipts = tx_queue
+            .execute_sequential(&client, 1, Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert_eq!(receipts.len(), 2);
+        let sent = tx_queue
+            .entries
+            .iter()
+            .map(|entry| (entry.tx.nonce, entry.tx.gas))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sent,
+            vec![
+                (Some(U256::from(5)), Some(U256::from(100000))),
+                (Some(U256::from(9)), Some(U256::from(60000))),
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn execute_sequential_time_out_unconfirmed() {
+        let (client, mock) = mock_client();
+        let mut tx_queue = TxQueue::from(vec![TransactionRequest::new()
+            .to(Address::random())
+            .nonce(0)
+            .gas(21000)
+            .gas_price(1)]);
+        let tx_hash = TxHash::random();
+
+        // The tx is polled for every 7s of the timeout.
+        for _ in 0..5 {
+            mock.push(Option::<TransactionReceipt>::None).unwrap();
+        }
+        mock.push(tx_hash).unwrap();
+        mock.push(U64::from(100)).unwrap();
+
+        assert!(matches!(
+            tx_queue
+                .execute_sequential(&client, 1, Duration::from_secs(30))
+                .await,
+            Err(SimulateError::ReceiptTimeout { index: 0, .. })
+        ));
+    }
+}
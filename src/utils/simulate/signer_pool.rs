@@ -0,0 +1,406 @@
+use super::{Opportunity, QueueStrategy, Simulate, SimulateError, TxQueue};
+use ethers::prelude::*;
+use std::sync::Mutex;
+
+// Several EOAs to send from, so queues of the same block don't wait on each other's nonces.
+pub struct SignerPool<S> {
+    signers: Vec<S>,
+    // Per signer, in the order of `signers`.
+    slots: Mutex<Vec<PoolSlot>>,
+}
+
+// What a signer of the pool has in flight, checked against its nonce and balance on the chain
+// every time it's picked.
+#[derive(Debug, Clone, Default)]
+struct PoolSlot {
+    address: Address,
+    // The nonce after the last one reserved, `None` before the first reservation.
+    next_nonce: Option<U256>,
+    // Not released and not mined yet.
+    reservations: Vec<Reservation>,
+    // Count of assignments when the signer was last picked, 0 if never.
+    last_used: u64,
+}
+
+// The nonces of a queue and what it may spend on value and gas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Reservation {
+    first_nonce: U256,
+    count: usize,
+    cost: U256,
+}
+
+impl Reservation {
+    fn end(&self) -> U256 {
+        self.first_nonce.saturating_add(U256::from(self.count))
+    }
+}
+
+impl<S: Signer> SignerPool<S> {
+    pub fn new(signers: Vec<S>) -> Self {
+        let slots = signers
+            .iter()
+            .map(|signer| PoolSlot {
+                address: signer.address(),
+                ..Default::default()
+            })
+            .collect();
+        Self {
+            signers,
+            slots: Mutex::new(slots),
+        }
+    }
+
+    pub fn addresses(&self) -> Vec<Address> {
+        let slots = self.slots.lock().unwrap();
+        slots.iter().map(|slot| slot.address).collect()
+    }
+
+    pub fn signer(&self, address: Address) -> Option<&S> {
+        let index = self.addresses().iter().position(|a| *a == address)?;
+        self.signers.get(index)
+    }
+
+    // Hand back the reservation of a queue that won't be sent (refused by the gate, unprofitable,
+    // rejected by the relay). Its nonces go back unless a later queue holds the ones after them,
+    // that queue can't land behind the gap either and is released in turn.
+    pub fn release(&self, address: Address, first_nonce: U256, count: usize) {
+        let mut slots = self.slots.lock().unwrap();
+        let slot = match slots.iter_mut().find(|slot| slot.address == address) {
+            Some(slot) => slot,
+            None => return,
+        };
+        slot.reservations.retain(|reservation| {
+            (reservation.first_nonce, reservation.count) != (first_nonce, count)
+        });
+        let later = slot
+            .reservations
+            .iter()
+            .any(|reservation| reservation.first_nonce > first_nonce);
+        if !later {
+            slot.next_nonce = slot
+                .next_nonce
+                .map(|next_nonce| next_nonce.min(first_nonce));
+        }
+    }
+
+    // Mark the least recently used signer not in `tried` as used now, in one step so concurrent
+    // callers never pick the same one.
+    fn pick(&self, tried: &[Address]) -> Option<Address> {
+        let mut slots = self.slots.lock().unwrap();
+        let uses = slots
+            .iter()
+            .map(|slot| slot.last_used)
+            .max()
+            .unwrap_or_default()
+            .saturating_add(1);
+        let slot = slots
+            .iter_mut()
+            .filter(|slot| !tried.contains(&slot.address))
+            .min_by_key(|slot| slot.last_used)?;
+        slot.last_used = uses;
+        Some(slot.address)
+    }
+
+    // Take `count` nonces and `cost` of the balance of the signer, `None` if it can't pay it on
+    // top of what it has in flight. `pending_nonce` / `balance` are the chain's: reservations
+    // below the nonce were mined and are in the balance already, and a signer that sent outside
+    // the pool continues after its txs.
+    fn reserve(
+        &self,
+        address: Address,
+        count: usize,
+        cost: U256,
+        pending_nonce: U256,
+        balance: U256,
+    ) -> Result<Option<U256>, SimulateError> {
+        let mut slots = self.slots.lock().unwrap();
+        let slot = match slots.iter_mut().find(|slot| slot.address == address) {
+            Some(slot) => slot,
+            None => return Ok(None),
+        };
+        slot.reservations
+            .retain(|reservation| reservation.end() > pending_nonce);
+        let required = slot
+            .reservations
+            .iter()
+            .try_fold(cost, |total, reservation| {
+                total.checked_add(reservation.cost)
+            })
+            .ok_or(SimulateError::Overflow("pooled queue cost"))?;
+        if balance < required {
+            return Ok(None);
+        }
+        let first_nonce = slot
+            .next_nonce
+            .map_or(pending_nonce, |next_nonce| next_nonce.max(pending_nonce));
+        let next_nonce = first_nonce
+            .checked_add(U256::from(count))
+            .ok_or(SimulateError::Overflow("pooled nonce"))?;
+        slot.next_nonce = Some(next_nonce);
+        slot.reservations.push(Reservation {
+            first_nonce,
+            count,
+            cost,
+        });
+        Ok(Some(first_nonce))
+    }
+}
+
+// The value of the queue and its gas at the entries' max fee (or gas price), `fee_per_gas` for an
+// entry whose fees aren't filled yet.
+fn queue_cost(tx_queue: &TxQueue, fee_per_gas: U256) -> Option<U256> {
+    tx_queue
+        .entries
+        .iter()
+        .try_fold(U256::zero(), |total, entry| {
+            let gas = entry.tx.gas.or(entry.trace_gas).unwrap_or_default();
+            let fee_per_gas = entry
+                .max_fee_per_gas
+                .or(entry.tx.gas_price)
+                .unwrap_or(fee_per_gas);
+            total
+                .checked_add(entry.tx.value.unwrap_or_default())?
+                .checked_add(gas.checked_mul(fee_per_gas)?)
+        })
+}
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    // Send the queues of `pooled_tx_queue` from these signers instead of the client's one.
+    pub fn signer_pool(mut self, signers: Vec<S>) -> Self {
+        self.signer_pool = Some(SignerPool::new(signers));
+        self
+    }
+
+    // The `strategy` queue of the opportunity for the least recently used signer of the pool that
+    // can pay its value and gas: sent from it, its calldata rewritten for it and its nonces
+    // following the ones the signer already has in flight. `None` if the opportunity has no such
+    // queue. A queue that isn't sent in the end goes back with `release_pooled`.
+    pub async fn pooled_tx_queue(
+        &self,
+        opportunity: &Opportunity,
+        strategy: QueueStrategy,
+    ) -> Result<Option<(Address, TxQueue)>, SimulateError> {
+        let pool = self
+            .signer_pool
+            .as_ref()
+            .ok_or(SimulateError::NoSignerPool)?;
+
+        let mut tried = Vec::new();
+        let mut gas_price = None;
+        while let Some(address) = pool.pick(&tried) {
+            tried.push(address);
+            let mut tx_queue = match self
+                .tx_queues_from(opportunity, address)?
+                .into_iter()
+                .find(|(queue_strategy, _)| *queue_strategy == strategy)
+            {
+                Some((_, tx_queue)) => tx_queue,
+                None => return Ok(None),
+            };
+
+            // The fees are filled later, until then the gas costs what the node asks now.
+            let fee_per_gas = match gas_price {
+                Some(gas_price) => gas_price,
+                None => {
+                    let price = self
+                        .get_gas_price()
+                        .await
+                        .map_err(SimulateError::middleware)?;
+                    *gas_price.insert(price)
+                }
+            };
+            let cost = queue_cost(&tx_queue, fee_per_gas)
+                .ok_or(SimulateError::Overflow("pooled queue cost"))?;
+            let nonce = self
+                .get_transaction_count(address, Some(BlockNumber::Pending.into()))
+                .await
+                .map_err(SimulateError::middleware)?;
+            let balance = self
+                .get_balance(address, None)
+                .await
+                .map_err(SimulateError::middleware)?;
+
+            if let Some(first_nonce) =
+                pool.reserve(address, tx_queue.entries.len(), cost, nonce, balance)?
+            {
+                for (entry, nonce) in tx_queue.entries.iter_mut().zip(0_u64..) {
+                    entry.tx.nonce = Some(
+                        first_nonce
+                            .checked_add(U256::from(nonce))
+                            .ok_or(SimulateError::Overflow("pooled nonce"))?,
+                    );
+                }
+                return Ok(Some((address, tx_queue)));
+            }
+        }
+
+        Err(SimulateError::NoSignerCanPay)
+    }
+
+    // Give back the nonces and balance a queue of `pooled_tx_queue` reserved, when it won't be sent.
+    pub fn release_pooled(
+        &self,
+        address: Address,
+        tx_queue: &TxQueue,
+    ) -> Result<(), SimulateError> {
+        let pool = self
+            .signer_pool
+            .as_ref()
+            .ok_or(SimulateError::NoSignerPool)?;
+        if let Some(first_nonce) = tx_queue.entries.first().and_then(|entry| entry.tx.nonce) {
+            pool.release(address, first_nonce, tx_queue.entries.len());
+        }
+        Ok(())
+    }
+
+    // Sign a queue of `pooled_tx_queue` with the signer it was assigned to.
+    pub async fn sign_pooled(
+        &self,
+        address: Address,
+        tx_queue: &TxQueue,
+    ) -> Result<Vec<Bytes>, SimulateError> {
+        let pool = self
+            .signer_pool
+            .as_ref()
+            .ok_or(SimulateError::NoSignerPool)?;
+        let signer = pool
+            .signer(address)
+            .ok_or(SimulateError::SignerNotInPool { address })?;
+        tx_queue.sign_with(signer).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        decode_raw_tx,
+        mock::{block_trace, call_trace, mock_client, opportunity},
+        QueueStrategy, Simulate, SimulateError, TxQueue,
+    };
+    use ethers::{core::rand::thread_rng, prelude::*, utils::parse_ether};
+
+    #[tokio::test]
+    async fn pooled_tx_queue_use_least_recently_used_signer() {
+        let (client, mock) = mock_client();
+        let signers = (0..2)
+            .map(|_| LocalWallet::new(&mut thread_rng()).with_chain_id(1_u64))
+            .collect::<Vec<_>>();
+        let addresses = signers.iter().map(Signer::address).collect::<Vec<_>>();
+        let simulate = Simulate::init(&client, None)
+            .await
+            .unwrap()
+            .signer_pool(signers);
+        let opportunity = opportunity(block_trace(
+            vec![
+                call_trace(vec![], 2, U256::zero()),
+                call_trace(vec![0], 0, U256::zero()),
+                call_trace(vec![1], 0, U256::zero()),
+            ],
+            None,
+        ));
+
+        // The gas price, then nonce and balance of each signer.
+        for nonce in [9, 4] {
+            mock.push(parse_ether(1).unwrap()).unwrap();
+            mock.push(U256::from(nonce)).unwrap();
+            mock.push(U256::from(10)).unwrap();
+        }
+        let (first, second) = tokio::join!(
+            simulate.pooled_tx_queue(&opportunity, QueueStrategy::Internal),
+            simulate.pooled_tx_queue(&opportunity, QueueStrategy::Internal),
+        );
+        let (first, first_queue) = first.unwrap().unwrap();
+        let (second, second_queue) = second.unwrap().unwrap();
+        assert_eq!((first, second), (addresses[0], addresses[1]));
+        let nonces = |queue: &TxQueue| {
+            queue
+                .entries
+                .iter()
+                .map(|entry| (entry.tx.from, entry.tx.nonce))
+                .collect::<Vec<_>>()
+        };
+        let nonce = |nonce: u64| Some(U256::from(nonce));
+        assert_eq!(
+            nonces(&first_queue),
+            vec![(Some(first), nonce(4)), (Some(first), nonce(5))]
+        );
+        assert_eq!(
+            nonces(&second_queue),
+            vec![(Some(second), nonce(9)), (Some(second), nonce(10))]
+        );
+
+        // Back to the first signer, after the nonces it has in flight the chain doesn't know yet.
+        mock.push(parse_ether(1).unwrap()).unwrap();
+        mock.push(U256::from(4)).unwrap();
+        mock.push(U256::from(10)).unwrap();
+        let (third, third_queue) = simulate
+            .pooled_tx_queue(&opportunity, QueueStrategy::Origin)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(third, first);
+        assert_eq!(nonces(&third_queue), vec![(Some(first), nonce(6))]);
+
+        let mut signed = simulate
+            .sign_pooled(third, &third_queue)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|raw_tx| decode_raw_tx(&raw_tx).unwrap());
+        assert_eq!(signed.next().unwrap().from, first);
+    }
+
+    #[tokio::test]
+    async fn pooled_tx_queue_reserve_gas_and_release() {
+        let (client, mock) = mock_client();
+        let signer = LocalWallet::new(&mut thread_rng()).with_chain_id(1_u64);
+        let address = signer.address();
+        let simulate = Simulate::init(&client, None)
+            .await
+            .unwrap()
+            .signer_pool(vec![signer]);
+        // Sends 1 ether, and 30000 gas on top of the intrinsic 21000.
+        let mut origin = call_trace(vec![], 0, parse_ether(1).unwrap());
+        origin.result = Some(Res::Call(CallResult {
+            gas_used: U256::from(30000),
+            output: Bytes::default(),
+        }));
+        let opportunity = opportunity(block_trace(vec![origin], None));
+        let pooled = |nonce: u64, balance: U256| {
+            mock.push(balance).unwrap();
+            mock.push(U256::from(nonce)).unwrap();
+            mock.push(U256::one()).unwrap();
+            simulate.pooled_tx_queue(&opportunity, QueueStrategy::Origin)
+        };
+        let first_nonce = |queue: &TxQueue| queue.entries[0].tx.nonce.unwrap();
+
+        // Holding just the value, the gas can't be paid.
+        assert!(matches!(
+            pooled(4, parse_ether(1).unwrap()).await,
+            Err(SimulateError::NoSignerCanPay)
+        ));
+
+        // Two queues in flight, the balance covers no third.
+        let balance = parse_ether(2).unwrap() + U256::from(51000 * 2);
+        let (_, first) = pooled(4, balance).await.unwrap().unwrap();
+        let (_, second) = pooled(4, balance).await.unwrap().unwrap();
+        assert_eq!(
+            (first_nonce(&first), first_nonce(&second)),
+            (U256::from(4), U256::from(5))
+        );
+        assert!(pooled(4, balance).await.is_err());
+
+        // The second isn't sent, its nonce and balance come back to the next one.
+        simulate.release_pooled(address, &second).unwrap();
+        let (_, third) = pooled(4, balance).await.unwrap().unwrap();
+        assert_eq!(first_nonce(&third), U256::from(5));
+
+        // The first two were mined and the signer sent another tx outside the pool.
+        let (_, fourth) = pooled(7, parse_ether(1).unwrap() + U256::from(51000))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(first_nonce(&fourth), U256::from(7));
+    }
+}
@@ -1,7 +1,94 @@
-use crate::utils::SimulateTrace;
+use crate::utils::{ProfitReport, SimulateTrace};
 use async_trait::async_trait;
-use ethers::prelude::*;
+use ethers::{prelude::*, utils::get_contract_address};
 use std::error::Error;
+use std::ops::{BitOr, BitOrAssign};
+
+// The built-in analyzers `Simulate::analyzers` runs, combined with `|`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AnalyzerFlags(u8);
+
+impl AnalyzerFlags {
+    pub const NATIVE: Self = Self(1);
+    pub const ERC20: Self = Self(1 << 1);
+    // Only runs with `Simulate::lp_tokens` configured.
+    pub const LP: Self = Self(1 << 2);
+    // Not a profit source, it sets `Opportunity::manipulation`.
+    pub const MANIPULATION: Self = Self(1 << 3);
+    // Only runs with `Simulate::fee_collectors` configured.
+    pub const REBATE: Self = Self(1 << 4);
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub const fn all() -> Self {
+        Self(Self::NATIVE.0 | Self::ERC20.0 | Self::LP.0 | Self::MANIPULATION.0 | Self::REBATE.0)
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+// Every analyzer, as before the flags existed.
+impl Default for AnalyzerFlags {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+impl BitOr for AnalyzerFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for AnalyzerFlags {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+// How the native analyzer combines the gains of several accounts of the tx (the beneficiaries,
+// or `from` and `to` without them).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Aggregation {
+    // Report every account that gains, their total is the profit. For accounts of one operator,
+    // e.g. a bot's EOA and its contract, where each gain is part of the same profit.
+    #[default]
+    Sum,
+    // Report only the account that gains the most. For accounts that may be unrelated, e.g. the
+    // sender and a router it calls, whose gains can't be added up as one profit.
+    Max,
+}
+
+// What the native analyzer does with a diff whose sender nonce doesn't match the tx's, see
+// `DiffAnalysis::invalid_nonce`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NoncePolicy {
+    // Report nothing for the tx.
+    #[default]
+    Reject,
+    // Report the profit with `ProfitReport::nonce_mismatch` set.
+    Warn,
+    // Report the profit as if the nonce matched.
+    Ignore,
+}
+
+impl NoncePolicy {
+    // `None` if the profit is dropped, otherwise whether its report is tagged.
+    pub fn admit(&self, invalid_nonce: bool) -> Option<bool> {
+        match (self, invalid_nonce) {
+            (_, false) => Some(false),
+            (Self::Reject, true) => None,
+            (Self::Warn, true) => Some(true),
+            (Self::Ignore, true) => Some(false),
+        }
+    }
+}
 
 #[async_trait]
 pub trait AnalyzeState<'a, M, S> {
@@ -9,11 +96,59 @@ pub trait AnalyzeState<'a, M, S> {
     where
         Self: Sized;
 
+    // `beneficiaries` empty means the default accounts of the analyzer (e.g. `from` / `to`).
     async fn run(
         &self,
         tx: &Transaction,
         trace: &SimulateTrace,
-    ) -> Result<Option<U256>, Box<dyn Error + 'a>>;
+        beneficiaries: &[Address],
+    ) -> Result<Vec<ProfitReport>, Box<dyn Error + 'a>>;
+
+    // Like `run` with the logs the tx emitted, only called with `Simulate::include_logs`.
+    // Event based analyzers override it, the others ignore the logs.
+    async fn run_with_logs(
+        &self,
+        tx: &Transaction,
+        trace: &SimulateTrace,
+        _logs: &[Log],
+        beneficiaries: &[Address],
+    ) -> Result<Vec<ProfitReport>, Box<dyn Error + 'a>> {
+        self.run(tx, trace, beneficiaries).await
+    }
+
+    // Like `run_with_logs` (`run` without logs) knowing the `block` whose state the tx was traced
+    // on, for analyzers that read more of it from the node. The others ignore it.
+    async fn run_at(
+        &self,
+        tx: &Transaction,
+        trace: &SimulateTrace,
+        _block: Option<BlockNumber>,
+        logs: Option<&[Log]>,
+        beneficiaries: &[Address],
+    ) -> Result<Vec<ProfitReport>, Box<dyn Error + 'a>> {
+        match logs {
+            Some(logs) => self.run_with_logs(tx, trace, logs, beneficiaries).await,
+            None => self.run(tx, trace, beneficiaries).await,
+        }
+    }
+
+    // The flag that turns the analyzer off, `None` for one that always runs (e.g. a custom one).
+    fn flag(&self) -> Option<AnalyzerFlags> {
+        None
+    }
+
+    // Whether `run` asks the node for more than the trace holds, such an analyzer is left out
+    // offline, see `Simulate::analyze_trace_file`.
+    fn reads_node(&self) -> bool {
+        false
+    }
+}
+
+// `to` of the tx, for a contract creation the address the contract is deployed at, since the
+// profit may land in it.
+pub fn to_or_created(tx: &Transaction) -> Address {
+    tx.to
+        .unwrap_or_else(|| get_contract_address(tx.from, tx.nonce))
 }
 
 #[derive(Default, Debug)]
@@ -28,9 +163,17 @@ impl DiffAnalysis {
         let mut increase_balance = false;
         let mut balance_diff = U256::zero();
 
-        if let Diff::Changed(ChangedType { from, to }) = diff.balance {
-            increase_balance = to > from;
-            balance_diff = from.abs_diff(to);
+        match diff.balance {
+            Diff::Changed(ChangedType { from, to }) => {
+                increase_balance = to > from;
+                balance_diff = from.abs_diff(to);
+            }
+            // A contract deployed by the tx starts from nothing.
+            Diff::Born(to) => {
+                increase_balance = !to.is_zero();
+                balance_diff = to;
+            }
+            _ => {}
         }
 
         Self {
@@ -45,3 +188,225 @@ impl DiffAnalysis {
         }
     }
 }
+
+// Chain the diffs of two consecutive txs into one, as if they were executed as a single tx.
+// Balance deltas are summed since other txs may move the balance in between, and the nonce
+// of the latter is kept so the nonce check of the second tx still applies, the former's where
+// the latter left it the same.
+pub fn merge_state_diff(first: &StateDiff, second: &StateDiff) -> StateDiff {
+    let mut merged = first.0.clone();
+    for (address, diff) in &second.0 {
+        let merged_diff = match first.0.get(address) {
+            Some(first_diff) => {
+                let mut storage = first_diff.storage.clone();
+                for (slot, slot_diff) in &diff.storage {
+                    let slot_diff = match first_diff.storage.get(slot) {
+                        Some(first_slot_diff) => merge_diff(first_slot_diff, slot_diff),
+                        None => slot_diff.clone(),
+                    };
+                    storage.insert(*slot, slot_diff);
+                }
+                AccountDiff {
+                    balance: merge_balance(&first_diff.balance, &diff.balance),
+                    nonce: match diff.nonce {
+                        Diff::Same => first_diff.nonce.clone(),
+                        _ => diff.nonce.clone(),
+                    },
+                    code: merge_diff(&first_diff.code, &diff.code),
+                    storage,
+                }
+            }
+            None => diff.clone(),
+        };
+        merged.insert(*address, merged_diff);
+    }
+
+    StateDiff(merged)
+}
+
+fn merge_balance(first: &Diff<U256>, second: &Diff<U256>) -> Diff<U256> {
+    let endpoints = |diff: &Diff<U256>| match diff {
+        Diff::Same => None,
+        Diff::Born(to) => Some((U256::zero(), *to)),
+        Diff::Died(from) => Some((*from, U256::zero())),
+        Diff::Changed(ChangedType { from, to }) => Some((*from, *to)),
+    };
+
+    match (endpoints(first), endpoints(second)) {
+        (None, None) => Diff::Same,
+        (Some(_), None) => first.clone(),
+        (None, Some(_)) => second.clone(),
+        (Some((from, first_to)), Some((second_from, to))) => {
+            // The end of the first diff moved by the second's delta, never past the bounds.
+            let to = match to >= second_from {
+                true => first_to.saturating_add(to - second_from),
+                false => first_to.saturating_sub(second_from - to),
+            };
+            if from == to {
+                Diff::Same
+            } else {
+                Diff::Changed(ChangedType { from, to })
+            }
+        }
+    }
+}
+
+fn merge_diff<T: Clone + PartialEq>(first: &Diff<T>, second: &Diff<T>) -> Diff<T> {
+    match (first, second) {
+        (Diff::Same, diff) | (diff, Diff::Same) => diff.clone(),
+        (Diff::Born(_), Diff::Died(_)) => Diff::Same,
+        (Diff::Born(_), Diff::Born(to))
+        | (Diff::Born(_), Diff::Changed(ChangedType { to, .. })) => Diff::Born(to.clone()),
+        (Diff::Changed(ChangedType { from, .. }) | Diff::Died(from), Diff::Died(_)) => {
+            Diff::Died(from.clone())
+        }
+        (
+            Diff::Changed(ChangedType { from, .. }) | Diff::Died(from),
+            Diff::Changed(ChangedType { to, .. }) | Diff::Born(to),
+        ) => {
+            if from == to {
+                Diff::Same
+            } else {
+                Diff::Changed(ChangedType {
+                    from: from.clone(),
+                    to: to.clone(),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::merge_state_diff;
+    use ethers::prelude::*;
+    use std::collections::BTreeMap;
+
+    fn account_diff(balance: Diff<U256>) -> AccountDiff {
+        AccountDiff {
+            balance,
+            nonce: Diff::Same,
+            code: Diff::Same,
+            storage: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn merge_state_diff_sum_balance_delta() {
+        let address = Address::random();
+        let first = StateDiff(BTreeMap::from([(
+            address,
+            account_diff(Diff::Changed(ChangedType {
+                from: U256::from(10),
+                to: U256::from(9),
+            })),
+        )]));
+        // Other txs moved the balance between the two diffs.
+        let second = StateDiff(BTreeMap::from([(
+            address,
+            account_diff(Diff::Changed(ChangedType {
+                from: U256::from(20),
+                to: U256::from(23),
+            })),
+        )]));
+
+        let merged = merge_state_diff(&first, &second);
+        assert_eq!(
+            merged.0.get(&address).unwrap().balance,
+            Diff::Changed(ChangedType {
+                from: U256::from(10),
+                to: U256::from(12),
+            })
+        );
+    }
+
+    #[test]
+    fn merge_state_diff_saturate_near_max() {
+        let address = Address::random();
+        let diff = |from, to| {
+            StateDiff(BTreeMap::from([(
+                address,
+                account_diff(Diff::Changed(ChangedType { from, to })),
+            )]))
+        };
+
+        let merged = merge_state_diff(
+            &diff(U256::zero(), U256::MAX - 1),
+            &diff(U256::from(10), U256::MAX),
+        );
+        assert_eq!(
+            merged.0.get(&address).unwrap().balance,
+            Diff::Changed(ChangedType {
+                from: U256::zero(),
+                to: U256::MAX,
+            })
+        );
+
+        let merged = merge_state_diff(
+            &diff(U256::from(10), U256::from(5)),
+            &diff(U256::MAX, U256::zero()),
+        );
+        assert_eq!(
+            merged.0.get(&address).unwrap().balance,
+            Diff::Changed(ChangedType {
+                from: U256::from(10),
+                to: U256::zero(),
+            })
+        );
+    }
+
+    #[test]
+    fn merge_state_diff_keep_nonce_of_first_when_second_same() {
+        let address = Address::random();
+        let nonce = |from: u64, to: u64| {
+            Diff::Changed(ChangedType {
+                from: U256::from(from),
+                to: U256::from(to),
+            })
+        };
+        let first = StateDiff(BTreeMap::from([(
+            address,
+            AccountDiff {
+                nonce: nonce(3, 4),
+                ..account_diff(Diff::Same)
+            },
+        )]));
+        // E.g. a call the sender was paid by, its nonce didn't move.
+        let second = StateDiff(BTreeMap::from([(address, account_diff(Diff::Same))]));
+
+        let merged = merge_state_diff(&first, &second);
+        assert_eq!(merged.0.get(&address).unwrap().nonce, nonce(3, 4));
+
+        let second = StateDiff(BTreeMap::from([(
+            address,
+            AccountDiff {
+                nonce: nonce(4, 5),
+                ..account_diff(Diff::Same)
+            },
+        )]));
+        let merged = merge_state_diff(&first, &second);
+        assert_eq!(merged.0.get(&address).unwrap().nonce, nonce(4, 5));
+    }
+
+    #[test]
+    fn merge_state_diff_keep_untouched_accounts() {
+        let (first_only, second_only) = (Address::random(), Address::random());
+        let changed = Diff::Changed(ChangedType {
+            from: U256::from(1),
+            to: U256::from(2),
+        });
+        let first = StateDiff(BTreeMap::from([(
+            first_only,
+            account_diff(changed.clone()),
+        )]));
+        let second = StateDiff(BTreeMap::from([(
+            second_only,
+            account_diff(changed.clone()),
+        )]));
+
+        let merged = merge_state_diff(&first, &second);
+        assert_eq!(merged.0.len(), 2);
+        assert_eq!(merged.0.get(&first_only).unwrap().balance, changed);
+        assert_eq!(merged.0.get(&second_only).unwrap().balance, changed);
+    }
+}
@@ -1,50 +1,333 @@
-use super::base::{AnalyzeState, DiffAnalysis};
-use crate::utils::SimulateTrace;
+use super::base::{
+    to_or_created, Aggregation, AnalyzeState, AnalyzerFlags, DiffAnalysis, NoncePolicy,
+};
+use crate::utils::{ProfitReport, SimulateTrace};
 use async_trait::async_trait;
 use ethers::prelude::*;
 use std::error::Error;
 
 // Analyze whether the native token is profitable.
-pub struct AnalyzeEth;
+#[derive(Default)]
+pub struct AnalyzeEth {
+    nonce_policy: NoncePolicy,
+    aggregation: Aggregation,
+}
+
+impl AnalyzeEth {
+    pub fn new(nonce_policy: NoncePolicy) -> Self {
+        Self {
+            nonce_policy,
+            aggregation: Aggregation::default(),
+        }
+    }
+
+    pub fn aggregation(mut self, aggregation: Aggregation) -> Self {
+        self.aggregation = aggregation;
+        self
+    }
+
+    // The reports as `aggregation` combines them, the first of the biggest under `Max`.
+    fn aggregate(&self, reports: Vec<ProfitReport>) -> Vec<ProfitReport> {
+        match self.aggregation {
+            Aggregation::Sum => reports,
+            Aggregation::Max => reports
+                .into_iter()
+                .reduce(|max, report| match report.amount > max.amount {
+                    true => report,
+                    false => max,
+                })
+                .into_iter()
+                .collect(),
+        }
+    }
+}
 
 #[async_trait]
 impl<'a, M, S> AnalyzeState<'a, M, S> for AnalyzeEth {
     async fn init(_client: &'a SignerMiddleware<M, S>) -> Result<Self, Box<dyn Error + 'a>> {
-        Ok(Self)
+        Ok(Self::default())
+    }
+
+    fn flag(&self) -> Option<AnalyzerFlags> {
+        Some(AnalyzerFlags::NATIVE)
     }
 
     async fn run(
         &self,
         tx: &Transaction,
         trace: &SimulateTrace,
-    ) -> Result<Option<U256>, Box<dyn Error + 'a>> {
-        let mut profit = U256::zero();
+        beneficiaries: &[Address],
+    ) -> Result<Vec<ProfitReport>, Box<dyn Error + 'a>> {
+        let mut reports = Vec::new();
 
         if let Some(state_diff) = &trace.state_diff {
-            if let Some(account_diff) = state_diff.0.get(&tx.from) {
-                let from_account_diff = DiffAnalysis::init(account_diff, Some(tx.nonce));
-                if from_account_diff.increase_balance && !from_account_diff.invalid_nonce {
-                    profit += from_account_diff.balance_diff;
-                };
-
-                if let Some(to) = tx.to {
-                    if let Some(account_diff) = state_diff.0.get(&to) {
-                        let to_account_diff = DiffAnalysis::init(account_diff, None);
-                        if to_account_diff.increase_balance
-                            && !to_account_diff.invalid_nonce
-                            && to_account_diff.balance_diff > from_account_diff.balance_diff
+            if !beneficiaries.is_empty() {
+                for beneficiary in beneficiaries {
+                    if let Some(account_diff) = state_diff.0.get(beneficiary) {
+                        let nonce = (*beneficiary == tx.from).then_some(tx.nonce);
+                        let account_diff = DiffAnalysis::init(account_diff, nonce);
+                        let admitted = self.nonce_policy.admit(account_diff.invalid_nonce);
+                        if let (true, Some(nonce_mismatch)) =
+                            (account_diff.increase_balance, admitted)
                         {
-                            profit += to_account_diff.balance_diff;
-                        };
+                            reports.push(
+                                ProfitReport::native(*beneficiary, account_diff.balance_diff)
+                                    .nonce_mismatch(nonce_mismatch),
+                            );
+                        }
                     }
                 }
+            } else {
+                let from_account_diff = state_diff
+                    .0
+                    .get(&tx.from)
+                    .map(|diff| DiffAnalysis::init(diff, Some(tx.nonce)))
+                    .unwrap_or_default();
+                // The tx itself is invalid, neither account gains anything unless the policy
+                // lets it through.
+                let nonce_mismatch = match self.nonce_policy.admit(from_account_diff.invalid_nonce)
+                {
+                    Some(nonce_mismatch) => nonce_mismatch,
+                    None => return Ok(reports),
+                };
+                if from_account_diff.increase_balance {
+                    reports.push(
+                        ProfitReport::native(tx.from, from_account_diff.balance_diff)
+                            .nonce_mismatch(nonce_mismatch),
+                    );
+                };
+
+                // `to` is evaluated on its own, the sender may stay flat (gas-neutral via contract)
+                // or even lose the gas while the profit lands on `to`.
+                let to = to_or_created(tx);
+                if let Some(account_diff) = state_diff.0.get(&to) {
+                    let to_account_diff = DiffAnalysis::init(account_diff, None);
+                    // The value the tx pays `to` (e.g. a WETH `deposit()` or a paid mint) came from
+                    // the sender, it isn't a gain.
+                    let gain = to_account_diff.balance_diff.saturating_sub(tx.value);
+                    if to_account_diff.increase_balance
+                        && !to_account_diff.invalid_nonce
+                        && !gain.is_zero()
+                    {
+                        reports.push(ProfitReport::native(to, gain).nonce_mismatch(nonce_mismatch));
+                    };
+                }
             }
         }
 
-        if profit.is_zero() {
-            Ok(None)
-        } else {
-            Ok(Some(profit))
-        }
+        Ok(self.aggregate(reports))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AnalyzeEth;
+    use crate::utils::simulate::{
+        mock::{balance_diff, block_trace},
+        state::base::{Aggregation, AnalyzeState, NoncePolicy},
+    };
+    use crate::utils::{ProfitReport, SimulateTrace};
+    use ethers::{prelude::*, utils::get_contract_address};
+    use std::collections::BTreeMap;
+
+    async fn analyze(tx: &Transaction, trace: &SimulateTrace) -> Vec<ProfitReport> {
+        analyze_with(NoncePolicy::default(), tx, trace).await
+    }
+
+    async fn analyze_with(
+        nonce_policy: NoncePolicy,
+        tx: &Transaction,
+        trace: &SimulateTrace,
+    ) -> Vec<ProfitReport> {
+        run(&AnalyzeEth::new(nonce_policy), tx, trace, &[]).await
+    }
+
+    async fn run(
+        analyzer: &AnalyzeEth,
+        tx: &Transaction,
+        trace: &SimulateTrace,
+        beneficiaries: &[Address],
+    ) -> Vec<ProfitReport> {
+        <AnalyzeEth as AnalyzeState<'_, Provider<MockProvider>, LocalWallet>>::run(
+            analyzer,
+            tx,
+            trace,
+            beneficiaries,
+        )
+        .await
+        .unwrap()
+    }
+
+    // The sender gains 5 but its nonce moved from another value than the tx's.
+    fn nonce_mismatch() -> (Transaction, SimulateTrace) {
+        let tx = Transaction {
+            nonce: U256::from(3),
+            to: Some(Address::random()),
+            ..Default::default()
+        };
+        let mut from_diff = balance_diff(U256::from(10), U256::from(15));
+        from_diff.nonce = Diff::Changed(ChangedType {
+            from: U256::from(5),
+            to: U256::from(6),
+        });
+        let trace = block_trace(
+            vec![],
+            Some(StateDiff(BTreeMap::from([(tx.from, from_diff)]))),
+        );
+        (tx, trace)
+    }
+
+    #[tokio::test]
+    async fn nonce_mismatch_rejected() {
+        let (tx, trace) = nonce_mismatch();
+        assert!(analyze_with(NoncePolicy::Reject, &tx, &trace)
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn nonce_mismatch_tagged_on_warn() {
+        let (tx, trace) = nonce_mismatch();
+        assert_eq!(
+            analyze_with(NoncePolicy::Warn, &tx, &trace).await,
+            vec![ProfitReport::native(tx.from, U256::from(5)).nonce_mismatch(true)]
+        );
+    }
+
+    #[tokio::test]
+    async fn nonce_mismatch_ignored() {
+        let (tx, trace) = nonce_mismatch();
+        assert_eq!(
+            analyze_with(NoncePolicy::Ignore, &tx, &trace).await,
+            vec![ProfitReport::native(tx.from, U256::from(5))]
+        );
+    }
+
+    #[tokio::test]
+    async fn detect_to_profit_with_flat_from() {
+        let to = Address::random();
+        let tx = Transaction {
+            to: Some(to),
+            ..Default::default()
+        };
+        let trace = block_trace(
+            vec![],
+            Some(StateDiff(BTreeMap::from([(
+                to,
+                balance_diff(U256::from(10), U256::from(15)),
+            )]))),
+        );
+
+        assert_eq!(
+            analyze(&tx, &trace).await,
+            vec![ProfitReport::native(to, U256::from(5))]
+        );
+    }
+
+    #[tokio::test]
+    async fn detect_to_profit_with_from_paying_gas() {
+        let to = Address::random();
+        let tx = Transaction {
+            to: Some(to),
+            ..Default::default()
+        };
+        let trace = block_trace(
+            vec![],
+            Some(StateDiff(BTreeMap::from([
+                (tx.from, balance_diff(U256::from(100), U256::from(99))),
+                (to, balance_diff(U256::from(10), U256::from(15))),
+            ]))),
+        );
+
+        assert_eq!(
+            analyze(&tx, &trace).await,
+            vec![ProfitReport::native(to, U256::from(5))]
+        );
+    }
+
+    #[tokio::test]
+    async fn not_count_value_paid_to_to() {
+        let to = Address::random();
+        // A WETH `deposit()` of 10.
+        let tx = Transaction {
+            to: Some(to),
+            value: U256::from(10),
+            ..Default::default()
+        };
+        let trace = |to_after| {
+            block_trace(
+                vec![],
+                Some(StateDiff(BTreeMap::from([
+                    (tx.from, balance_diff(U256::from(100), U256::from(89))),
+                    (to, balance_diff(U256::from(10), U256::from(to_after))),
+                ]))),
+            )
+        };
+        assert!(analyze(&tx, &trace(20)).await.is_empty());
+
+        // Past the value it's a gain.
+        assert_eq!(
+            analyze(&tx, &trace(25)).await,
+            vec![ProfitReport::native(to, U256::from(5))]
+        );
+    }
+
+    #[tokio::test]
+    async fn detect_profit_of_created_contract() {
+        let tx = Transaction {
+            nonce: U256::from(7),
+            ..Default::default()
+        };
+        let created = get_contract_address(tx.from, 7);
+        let trace = block_trace(
+            vec![],
+            Some(StateDiff(BTreeMap::from([
+                (tx.from, balance_diff(U256::from(100), U256::from(99))),
+                (
+                    created,
+                    AccountDiff {
+                        balance: Diff::Born(U256::from(15)),
+                        nonce: Diff::Born(U256::one()),
+                        code: Diff::Born(Bytes::default()),
+                        storage: BTreeMap::new(),
+                    },
+                ),
+            ]))),
+        );
+
+        assert_eq!(
+            analyze(&tx, &trace).await,
+            vec![ProfitReport::native(created, U256::from(15))]
+        );
+    }
+
+    #[tokio::test]
+    async fn aggregation_sum_or_max_of_gaining_accounts() {
+        let to = Address::random();
+        let tx = Transaction {
+            to: Some(to),
+            ..Default::default()
+        };
+        let trace = block_trace(
+            vec![],
+            Some(StateDiff(BTreeMap::from([
+                (tx.from, balance_diff(U256::from(100), U256::from(105))),
+                (to, balance_diff(U256::from(10), U256::from(20))),
+            ]))),
+        );
+        let sum = AnalyzeEth::default();
+        let max = AnalyzeEth::default().aggregation(Aggregation::Max);
+
+        let reports = run(&sum, &tx, &trace, &[]).await;
+        assert_eq!(ProfitReport::total_native(&reports), U256::from(15));
+        let reports = run(&max, &tx, &trace, &[]).await;
+        assert_eq!(reports, vec![ProfitReport::native(to, U256::from(10))]);
+
+        // The same with the accounts as beneficiaries.
+        let beneficiaries = [tx.from, to];
+        let reports = run(&sum, &tx, &trace, &beneficiaries).await;
+        assert_eq!(ProfitReport::total_native(&reports), U256::from(15));
+        let reports = run(&max, &tx, &trace, &beneficiaries).await;
+        assert_eq!(ProfitReport::total_native(&reports), U256::from(10));
     }
 }
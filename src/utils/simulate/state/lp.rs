@@ -0,0 +1,332 @@
+use super::{
+    base::{to_or_created, AnalyzeState, AnalyzerFlags},
+    token::is_balance_slot,
+};
+use crate::utils::{ProfitReport, SimulateError, SimulateTrace};
+use async_trait::async_trait;
+use ethers::{
+    abi::{self, ParamType},
+    prelude::*,
+    utils::id,
+};
+use serde_json::{json, Map, Value};
+use std::error::Error;
+
+// A uniswap v2 style pair whose LP token is valued, one side of it is the wrapped native token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LpToken {
+    pub pair: Address,
+    pub native_is_token0: bool,
+}
+
+// @dev Analyze whether the LP tokens of the configured pairs are profitable
+// @return The share of the pool's reserves the LP token gain is worth, in native token
+pub struct AnalyzeLp<'a, M, S> {
+    client: &'a SignerMiddleware<M, S>,
+    lp_tokens: Vec<LpToken>,
+}
+
+impl<'a, M, S> AnalyzeLp<'a, M, S> {
+    pub fn new(client: &'a SignerMiddleware<M, S>, lp_tokens: Vec<LpToken>) -> Self {
+        Self { client, lp_tokens }
+    }
+}
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> AnalyzeLp<'a, M, S> {
+    // `selector` of the pair on the state after the tx: `block`, the state it was traced on,
+    // with its state diff applied as override.
+    async fn call_after(
+        &self,
+        pair: Address,
+        selector: &str,
+        block: Option<BlockNumber>,
+        overrides: &Value,
+    ) -> Result<Bytes, Box<dyn Error + 'a>> {
+        let tx = TransactionRequest::new()
+            .to(pair)
+            .data(id(selector).to_vec());
+        let output = self
+            .client
+            .provider()
+            .request(
+                "eth_call",
+                json!([tx, block.unwrap_or(BlockNumber::Latest), overrides]),
+            )
+            .await?;
+        Ok(output)
+    }
+
+    // Both halves of a pair are worth the same, so an LP token is worth twice its share of the
+    // native reserve.
+    async fn native_value(
+        &self,
+        lp_token: &LpToken,
+        amount: U256,
+        block: Option<BlockNumber>,
+        overrides: &Value,
+    ) -> Result<U256, Box<dyn Error + 'a>> {
+        let reserves = self
+            .call_after(lp_token.pair, "getReserves()", block, overrides)
+            .await?;
+        let reserves = abi::decode(
+            &[
+                ParamType::Uint(112),
+                ParamType::Uint(112),
+                ParamType::Uint(32),
+            ],
+            &reserves,
+        )?;
+        let native_reserve = reserves[if lp_token.native_is_token0 { 0 } else { 1 }]
+            .clone()
+            .into_uint()
+            .unwrap_or_default();
+
+        let total_supply = self
+            .call_after(lp_token.pair, "totalSupply()", block, overrides)
+            .await?;
+        let total_supply = abi::decode(&[ParamType::Uint(256)], &total_supply)?
+            .pop()
+            .and_then(|token| token.into_uint())
+            .unwrap_or_default();
+        if total_supply.is_zero() {
+            return Ok(U256::zero());
+        }
+
+        let value = amount
+            .checked_mul(native_reserve)
+            .and_then(|value| value.checked_mul(2.into()))
+            .ok_or_else(|| SimulateError::analyze("LP token value overflows"))?;
+        Ok(value / total_supply)
+    }
+}
+
+// The state `state_diff` leaves behind, as `eth_call` state overrides.
+fn state_overrides(state_diff: &StateDiff) -> Value {
+    let mut overrides = Map::new();
+    for (address, account_diff) in &state_diff.0 {
+        let mut account = Map::new();
+        if let Some(balance) = after(&account_diff.balance) {
+            account.insert("balance".into(), json!(balance));
+        }
+        if let Some(nonce) = after(&account_diff.nonce) {
+            account.insert("nonce".into(), json!(nonce));
+        }
+        if let Some(code) = after(&account_diff.code) {
+            account.insert("code".into(), json!(code));
+        }
+        let storage = account_diff
+            .storage
+            .iter()
+            .filter_map(|(slot, diff)| Some((format!("{slot:?}"), json!(after(diff)?))))
+            .collect::<Map<_, _>>();
+        if !storage.is_empty() {
+            account.insert("stateDiff".into(), Value::Object(storage));
+        }
+        if !account.is_empty() {
+            overrides.insert(format!("{address:?}"), Value::Object(account));
+        }
+    }
+    Value::Object(overrides)
+}
+
+// What a diff leaves, `None` if it left it alone. A died value is cleared.
+fn after<T: Clone + Default>(diff: &Diff<T>) -> Option<T> {
+    match diff {
+        Diff::Same => None,
+        Diff::Born(to) | Diff::Changed(ChangedType { to, .. }) => Some(to.clone()),
+        Diff::Died(_) => Some(T::default()),
+    }
+}
+
+#[async_trait]
+impl<'a, M: Middleware + 'a, S: Signer + 'a> AnalyzeState<'a, M, S> for AnalyzeLp<'a, M, S> {
+    async fn init(client: &'a SignerMiddleware<M, S>) -> Result<Self, Box<dyn Error + 'a>> {
+        Ok(Self::new(client, vec![]))
+    }
+
+    fn flag(&self) -> Option<AnalyzerFlags> {
+        Some(AnalyzerFlags::LP)
+    }
+
+    // The reserves and the supply are read with `eth_call`.
+    fn reads_node(&self) -> bool {
+        true
+    }
+
+    async fn run(
+        &self,
+        tx: &Transaction,
+        trace: &SimulateTrace,
+        beneficiaries: &[Address],
+    ) -> Result<Vec<ProfitReport>, Box<dyn Error + 'a>> {
+        self.run_at(tx, trace, None, None, beneficiaries).await
+    }
+
+    async fn run_at(
+        &self,
+        tx: &Transaction,
+        trace: &SimulateTrace,
+        block: Option<BlockNumber>,
+        _logs: Option<&[Log]>,
+        beneficiaries: &[Address],
+    ) -> Result<Vec<ProfitReport>, Box<dyn Error + 'a>> {
+        let mut reports = Vec::new();
+        let holders: Vec<Address> = match beneficiaries {
+            [] => vec![tx.from, to_or_created(tx)],
+            beneficiaries => beneficiaries.to_vec(),
+        };
+        let state_diff = match &trace.state_diff {
+            Some(state_diff) => state_diff,
+            None => return Ok(reports),
+        };
+
+        // The reserves and supply are read on the state the tx leaves behind.
+        let overrides = state_overrides(state_diff);
+        for lp_token in &self.lp_tokens {
+            let account_diff = match state_diff.0.get(&lp_token.pair) {
+                Some(account_diff) => account_diff,
+                None => continue,
+            };
+
+            let mut gains = Vec::new();
+            for (slot, diff) in &account_diff.storage {
+                let (from, to) = match diff {
+                    Diff::Born(to) => (H256::zero(), *to),
+                    Diff::Changed(ChangedType { from, to }) => (*from, *to),
+                    _ => continue,
+                };
+                let (from, to) = (
+                    U256::from_big_endian(from.as_bytes()),
+                    U256::from_big_endian(to.as_bytes()),
+                );
+                if to <= from {
+                    continue;
+                }
+                for holder in &holders {
+                    if is_balance_slot(*slot, *holder) {
+                        gains.push((*holder, to - from));
+                    }
+                }
+            }
+            if gains.is_empty() {
+                continue;
+            }
+
+            for (holder, amount) in gains {
+                let value = self
+                    .native_value(lp_token, amount, block, &overrides)
+                    .await?;
+                reports.push(ProfitReport::native(holder, value));
+            }
+        }
+
+        Ok(reports)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AnalyzeLp, LpToken};
+    use crate::utils::simulate::{
+        mock::{balance_diff, block_trace, mock_client},
+        state::base::AnalyzeState,
+    };
+    use crate::utils::ProfitReport;
+    use ethers::{
+        abi::{self, Token},
+        prelude::*,
+        utils::keccak256,
+    };
+    use serde_json::json;
+    use std::collections::BTreeMap;
+
+    #[tokio::test]
+    async fn value_lp_gain_from_reserves() {
+        let (client, mock) = mock_client();
+        let pair = Address::random();
+        let analyzer = AnalyzeLp::new(
+            &client,
+            vec![LpToken {
+                pair,
+                native_is_token0: false,
+            }],
+        );
+        let tx = Transaction {
+            from: Address::random(),
+            ..Default::default()
+        };
+        // `UniswapV2ERC20.balanceOf` is the mapping at slot 1.
+        let balance_slot = H256(keccak256(
+            [
+                H256::from(tx.from).as_bytes(),
+                H256::from_low_u64_be(1).as_bytes(),
+            ]
+            .concat(),
+        ));
+        // The pair's router moved funds in the same tx, its state is read too.
+        let router = Address::random();
+        let trace = block_trace(
+            vec![],
+            Some(StateDiff(BTreeMap::from([
+                (
+                    pair,
+                    AccountDiff {
+                        balance: Diff::Same,
+                        nonce: Diff::Same,
+                        code: Diff::Same,
+                        storage: BTreeMap::from([(
+                            balance_slot,
+                            Diff::Born(H256::from_low_u64_be(50)),
+                        )]),
+                    },
+                ),
+                (router, balance_diff(U256::from(10), U256::from(3))),
+            ]))),
+        );
+
+        // Reserves then total supply.
+        mock.push(Bytes::from(abi::encode(&[Token::Uint(1000.into())])))
+            .unwrap();
+        mock.push(Bytes::from(abi::encode(&[
+            Token::Uint(3_000_000.into()),
+            Token::Uint(400.into()),
+            Token::Uint(0.into()),
+        ])))
+        .unwrap();
+
+        // 50 of 1000 LP tokens is 5% of the pool, twice 5% of the 400 native reserve.
+        let block = Some(BlockNumber::Number(U64::from(99)));
+        assert_eq!(
+            analyzer
+                .run_at(&tx, &trace, block, None, &[])
+                .await
+                .unwrap(),
+            vec![ProfitReport::native(tx.from, U256::from(40))]
+        );
+        // Read on the traced block with the whole state the tx left behind.
+        let overrides = json!({
+            format!("{pair:?}"): { "stateDiff": {
+                format!("{balance_slot:?}"): H256::from_low_u64_be(50)
+            } },
+            format!("{router:?}"): { "balance": U256::from(3) }
+        });
+        for selector in ["getReserves()", "totalSupply()"] {
+            let call = TransactionRequest::new()
+                .to(pair)
+                .data(ethers::utils::id(selector).to_vec());
+            mock.assert_request("eth_call", json!([call, "0x63", overrides]))
+                .unwrap();
+        }
+
+        // A reserve no pair could hold overflows instead of reporting a capped value.
+        mock.push(Bytes::from(abi::encode(&[Token::Uint(1000.into())])))
+            .unwrap();
+        mock.push(Bytes::from(abi::encode(&[
+            Token::Uint(0.into()),
+            Token::Uint(U256::MAX),
+            Token::Uint(0.into()),
+        ])))
+        .unwrap();
+        assert!(analyzer.run(&tx, &trace, &[]).await.is_err());
+    }
+}
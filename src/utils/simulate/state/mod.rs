@@ -1,3 +1,5 @@
 pub mod base;
 pub mod eth;
+pub mod lp;
+pub mod rebate;
 pub mod token;
@@ -0,0 +1,157 @@
+use super::base::{to_or_created, AnalyzeState, AnalyzerFlags};
+use crate::utils::{ProfitReport, SimulateTrace};
+use async_trait::async_trait;
+use ethers::{
+    abi::{self, ParamType, Token},
+    prelude::*,
+    utils::id,
+};
+use std::error::Error;
+
+// @dev Analyze the fee rebates the configured fee collectors pay to the holders
+// @return The rebates in native token or the token paid, tagged with `ProfitKind::Rebate`
+pub struct AnalyzeRebate {
+    fee_collectors: Vec<Address>,
+}
+
+impl AnalyzeRebate {
+    pub fn new(fee_collectors: Vec<Address>) -> Self {
+        Self { fee_collectors }
+    }
+}
+
+// Recipient and amount of a `transfer(address,uint256)` call.
+fn decode_transfer(input: &Bytes) -> Option<(Address, U256)> {
+    if input.get(..4)? != id("transfer(address,uint256)") {
+        return None;
+    }
+    match abi::decode(&[ParamType::Address, ParamType::Uint(256)], &input[4..])
+        .ok()?
+        .as_slice()
+    {
+        [Token::Address(to), Token::Uint(amount)] => Some((*to, *amount)),
+        _ => None,
+    }
+}
+
+#[async_trait]
+impl<'a, M, S> AnalyzeState<'a, M, S> for AnalyzeRebate {
+    async fn init(_client: &'a SignerMiddleware<M, S>) -> Result<Self, Box<dyn Error + 'a>> {
+        Ok(Self::new(vec![]))
+    }
+
+    fn flag(&self) -> Option<AnalyzerFlags> {
+        Some(AnalyzerFlags::REBATE)
+    }
+
+    async fn run(
+        &self,
+        tx: &Transaction,
+        trace: &SimulateTrace,
+        beneficiaries: &[Address],
+    ) -> Result<Vec<ProfitReport>, Box<dyn Error + 'a>> {
+        let holders: Vec<Address> = match beneficiaries {
+            [] => vec![tx.from, to_or_created(tx)],
+            beneficiaries => beneficiaries.to_vec(),
+        };
+
+        let mut reports = Vec::new();
+        for call in trace.trace.iter().flatten() {
+            let call_action = match &call.action {
+                Action::Call(call_action)
+                    if call.error.is_none()
+                        && call_action.call_type == CallType::Call
+                        && self.fee_collectors.contains(&call_action.from) =>
+                {
+                    call_action
+                }
+                _ => continue,
+            };
+            let collector = call_action.from;
+            if !call_action.value.is_zero() && holders.contains(&call_action.to) {
+                reports.push(
+                    ProfitReport::native(call_action.to, call_action.value).rebate(collector),
+                );
+            }
+            if let Some((to, amount)) = decode_transfer(&call_action.input) {
+                if holders.contains(&to) && !amount.is_zero() {
+                    reports.push(ProfitReport::token(to, call_action.to, amount).rebate(collector));
+                }
+            }
+        }
+
+        Ok(reports)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AnalyzeRebate;
+    use crate::utils::simulate::{
+        mock::{block_trace, call_trace},
+        state::base::AnalyzeState,
+    };
+    use crate::utils::ProfitReport;
+    use ethers::{
+        abi::{self, Token},
+        prelude::*,
+        utils::id,
+    };
+
+    fn call(from: Address, to: Address, value: u64, input: Vec<u8>) -> TransactionTrace {
+        let mut trace = call_trace(vec![0], 0, U256::from(value));
+        if let Action::Call(call) = &mut trace.action {
+            call.from = from;
+            call.to = to;
+            call.input = input.into();
+        }
+        trace
+    }
+
+    #[tokio::test]
+    async fn rebate_paid_by_fee_collector() {
+        let (collector, token) = (Address::random(), Address::random());
+        let tx = Transaction {
+            from: Address::random(),
+            to: Some(Address::random()),
+            ..Default::default()
+        };
+        let transfer = |to: Address, amount: u64| {
+            [
+                id("transfer(address,uint256)").to_vec(),
+                abi::encode(&[Token::Address(to), Token::Uint(amount.into())]),
+            ]
+            .concat()
+        };
+        let trace = block_trace(
+            vec![
+                call_trace(vec![], 3, U256::zero()),
+                call(collector, tx.from, 100, vec![]),
+                call(collector, token, 0, transfer(tx.from, 500)),
+                // The same transfer from a pool is the swap's output, not a rebate.
+                call(Address::random(), token, 0, transfer(tx.from, 9000)),
+            ],
+            None,
+        );
+
+        let reports =
+            <AnalyzeRebate as AnalyzeState<'_, Provider<MockProvider>, LocalWallet>>::run(
+                &AnalyzeRebate::new(vec![collector]),
+                &tx,
+                &trace,
+                &[],
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            reports,
+            vec![
+                ProfitReport::native(tx.from, U256::from(100)).rebate(collector),
+                ProfitReport::token(tx.from, token, U256::from(500)).rebate(collector),
+            ]
+        );
+        // Already in the sender's balance gain, kept apart from it.
+        assert_eq!(ProfitReport::total_native(&reports), U256::zero());
+        assert_eq!(ProfitReport::total_rebates(&reports), U256::from(100));
+    }
+}
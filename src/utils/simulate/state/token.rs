@@ -1,9 +1,13 @@
-use super::base::AnalyzeState;
-use crate::utils::SimulateTrace;
+use super::base::{to_or_created, AnalyzeState, AnalyzerFlags};
+use crate::utils::{ProfitReport, SimulateTrace};
 use async_trait::async_trait;
-use ethers::prelude::*;
+use ethers::{prelude::*, utils::keccak256};
 use std::error::Error;
 
+// Storage slot of the `balanceOf` / `allowance` mapping, it depends on the token's layout:
+// 0 for OpenZeppelin, 3 for WETH, 9 for USDC, etc. Probe the first few.
+const MAPPING_SLOTS: u64 = 10;
+
 // @dev Analyze whether the contract token (erc20, erc223, erc777, etc.) is profitable
 // @return The profit convert to native token
 pub struct AnalyzeToken;
@@ -14,11 +18,159 @@ impl<'a, M, S> AnalyzeState<'a, M, S> for AnalyzeToken {
         Ok(Self)
     }
 
+    fn flag(&self) -> Option<AnalyzerFlags> {
+        Some(AnalyzerFlags::ERC20)
+    }
+
     async fn run(
         &self,
         tx: &Transaction,
         trace: &SimulateTrace,
-    ) -> Result<Option<U256>, Box<dyn Error + 'a>> {
-        Ok(None)
+        beneficiaries: &[Address],
+    ) -> Result<Vec<ProfitReport>, Box<dyn Error + 'a>> {
+        let mut reports = Vec::new();
+        let holders: Vec<Address> = match beneficiaries {
+            [] => vec![tx.from, to_or_created(tx)],
+            beneficiaries => beneficiaries.to_vec(),
+        };
+
+        if let Some(state_diff) = &trace.state_diff {
+            // Any touched account may be the spender of an allowance.
+            let spenders = state_diff.0.keys().copied().collect::<Vec<_>>();
+            for (token, account_diff) in &state_diff.0 {
+                for (slot, diff) in &account_diff.storage {
+                    let (from, to) = match diff {
+                        Diff::Born(to) => (U256::zero(), U256::from_big_endian(to.as_bytes())),
+                        Diff::Changed(ChangedType { from, to }) => (
+                            U256::from_big_endian(from.as_bytes()),
+                            U256::from_big_endian(to.as_bytes()),
+                        ),
+                        _ => continue,
+                    };
+                    if to <= from {
+                        continue;
+                    }
+
+                    for holder in &holders {
+                        // An approval is no realized profit, even if the allowance is the holder's.
+                        if spenders
+                            .iter()
+                            .any(|spender| is_allowance_slot(*slot, *holder, *spender))
+                        {
+                            continue;
+                        }
+                        if is_balance_slot(*slot, *holder) {
+                            reports.push(ProfitReport::token(*holder, *token, to - from));
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(reports)
+    }
+}
+
+// `keccak(key . slot)`, the storage slot of `mapping[key]`.
+fn mapping_slot(key: H256, slot: H256) -> H256 {
+    H256(keccak256([key.as_bytes(), slot.as_bytes()].concat()))
+}
+
+pub(crate) fn is_balance_slot(slot: H256, holder: Address) -> bool {
+    (0..MAPPING_SLOTS)
+        .any(|mapping| slot == mapping_slot(holder.into(), H256::from_low_u64_be(mapping)))
+}
+
+// `allowance[owner][spender]` is at `keccak(spender . keccak(owner . slot))`.
+fn is_allowance_slot(slot: H256, owner: Address, spender: Address) -> bool {
+    (0..MAPPING_SLOTS).any(|mapping| {
+        let owner_slot = mapping_slot(owner.into(), H256::from_low_u64_be(mapping));
+        slot == mapping_slot(spender.into(), owner_slot)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mapping_slot, AnalyzeToken};
+    use crate::utils::simulate::{mock::block_trace, state::base::AnalyzeState};
+    use crate::utils::{ProfitReport, SimulateTrace};
+    use ethers::prelude::*;
+    use std::collections::BTreeMap;
+
+    async fn analyze(tx: &Transaction, trace: &SimulateTrace) -> Vec<ProfitReport> {
+        <AnalyzeToken as AnalyzeState<'_, Provider<MockProvider>, LocalWallet>>::run(
+            &AnalyzeToken,
+            tx,
+            trace,
+            &[],
+        )
+        .await
+        .unwrap()
+    }
+
+    fn storage_diff(storage: Vec<(H256, Diff<H256>)>) -> AccountDiff {
+        AccountDiff {
+            balance: Diff::Same,
+            nonce: Diff::Same,
+            code: Diff::Same,
+            storage: storage.into_iter().collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn count_balance_slot_increase() {
+        let token = Address::random();
+        let tx = Transaction {
+            from: Address::random(),
+            ..Default::default()
+        };
+        let balance_slot = mapping_slot(tx.from.into(), H256::from_low_u64_be(0));
+        let trace = block_trace(
+            vec![],
+            Some(StateDiff(BTreeMap::from([(
+                token,
+                storage_diff(vec![(
+                    balance_slot,
+                    Diff::Changed(ChangedType {
+                        from: H256::from_low_u64_be(10),
+                        to: H256::from_low_u64_be(25),
+                    }),
+                )]),
+            )]))),
+        );
+
+        assert_eq!(
+            analyze(&tx, &trace).await,
+            vec![ProfitReport::token(tx.from, token, U256::from(15))]
+        );
+    }
+
+    #[tokio::test]
+    async fn ignore_allowance_slot_increase() {
+        let token = Address::random();
+        let spender = Address::random();
+        let tx = Transaction {
+            from: Address::random(),
+            to: Some(spender),
+            ..Default::default()
+        };
+        // OpenZeppelin layout, `_allowances` right after `_balances`.
+        let owner_slot = mapping_slot(tx.from.into(), H256::from_low_u64_be(1));
+        let allowance_slot = mapping_slot(spender.into(), owner_slot);
+        let trace = block_trace(
+            vec![],
+            Some(StateDiff(BTreeMap::from([
+                (
+                    token,
+                    storage_diff(vec![(
+                        allowance_slot,
+                        Diff::Born(H256::from_low_u64_be(1000)),
+                    )]),
+                ),
+                (spender, storage_diff(vec![])),
+            ]))),
+        );
+
+        assert!(analyze(&tx, &trace).await.is_empty());
     }
 }
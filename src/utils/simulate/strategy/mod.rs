@@ -1,2 +1,4 @@
 pub mod flashloan;
+pub mod queue;
+pub mod sandwich;
 pub mod transfer;
@@ -0,0 +1,81 @@
+use ethers::prelude::*;
+
+// What to do with a reconstructed queue longer than the cap.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QueueOverflow {
+    // A huge queue is almost certainly not a clean arbitrage, drop it.
+    #[default]
+    Reject,
+    // Keep the first txs up to the cap.
+    Truncate,
+}
+
+// Cap the total number of reconstructed txs, return false if the queue should be dropped.
+pub fn run(
+    tx_queue: &mut Vec<Vec<TransactionRequest>>,
+    max_len: Option<usize>,
+    overflow: QueueOverflow,
+) -> bool {
+    let max_len = match max_len {
+        Some(max_len) => max_len,
+        None => return true,
+    };
+    if tx_queue.iter().map(Vec::len).sum::<usize>() <= max_len {
+        return true;
+    }
+
+    match overflow {
+        QueueOverflow::Reject => false,
+        QueueOverflow::Truncate => {
+            let mut remain = max_len;
+            for tx_list in tx_queue.iter_mut() {
+                tx_list.truncate(remain);
+                remain -= tx_list.len();
+            }
+            tx_queue.retain(|tx_list| !tx_list.is_empty());
+            !tx_queue.is_empty()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run as pass_queue_check, QueueOverflow};
+    use ethers::prelude::*;
+
+    fn tx_queue() -> Vec<Vec<TransactionRequest>> {
+        vec![
+            vec![TransactionRequest::new()],
+            vec![TransactionRequest::new(); 300],
+        ]
+    }
+
+    #[test]
+    fn not_filter_without_cap() {
+        let mut tx_queue = tx_queue();
+        assert!(pass_queue_check(&mut tx_queue, None, QueueOverflow::Reject));
+        assert_eq!(tx_queue[1].len(), 300);
+    }
+
+    #[test]
+    fn filter_queue_over_cap() {
+        let mut tx_queue = tx_queue();
+        assert!(!pass_queue_check(
+            &mut tx_queue,
+            Some(50),
+            QueueOverflow::Reject
+        ));
+    }
+
+    #[test]
+    fn truncate_queue_over_cap() {
+        let mut tx_queue = tx_queue();
+        assert!(pass_queue_check(
+            &mut tx_queue,
+            Some(50),
+            QueueOverflow::Truncate
+        ));
+        assert_eq!(tx_queue[0].len(), 1);
+        assert_eq!(tx_queue[1].len(), 49);
+    }
+}
@@ -0,0 +1,205 @@
+use ethers::{
+    abi::{self, ParamType, Token},
+    prelude::*,
+    utils::id,
+};
+
+// A Uniswap V2 style exact-input swap of the victim, decoded from its calldata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VictimSwap {
+    pub amount_in: U256,
+    pub amount_out_min: U256,
+    pub path: Vec<Address>,
+}
+
+impl VictimSwap {
+    // `swapExactTokensForTokens` / `swapExactETHForTokens` of the V2 router, the latter sends
+    // the amount in as the tx value.
+    pub fn decode(tx: &Transaction) -> Option<Self> {
+        let (selector, data) = (tx.input.get(..4)?, &tx.input[4..]);
+        let path_type = ParamType::Array(Box::new(ParamType::Address));
+        let (amount_in, tokens) = if selector
+            == id("swapExactTokensForTokens(uint256,uint256,address[],address,uint256)")
+        {
+            let mut tokens = abi::decode(
+                &[
+                    ParamType::Uint(256),
+                    ParamType::Uint(256),
+                    path_type,
+                    ParamType::Address,
+                    ParamType::Uint(256),
+                ],
+                data,
+            )
+            .ok()?;
+            (tokens.remove(0).into_uint()?, tokens)
+        } else if selector == id("swapExactETHForTokens(uint256,address[],address,uint256)") {
+            let tokens = abi::decode(
+                &[
+                    ParamType::Uint(256),
+                    path_type,
+                    ParamType::Address,
+                    ParamType::Uint(256),
+                ],
+                data,
+            )
+            .ok()?;
+            (tx.value, tokens)
+        } else {
+            return None;
+        };
+
+        let mut tokens = tokens.into_iter();
+        let amount_out_min = tokens.next()?.into_uint()?;
+        let path = match tokens.next()? {
+            Token::Array(path) => path
+                .into_iter()
+                .map(Token::into_address)
+                .collect::<Option<Vec<_>>>()?,
+            _ => return None,
+        };
+        Some(Self {
+            amount_in,
+            amount_out_min,
+            path,
+        })
+    }
+}
+
+// Uniswap V2 `getAmountOut`, 0.3% fee. `None` where the pair's math overflows.
+pub fn amount_out(amount_in: U256, reserve_in: U256, reserve_out: U256) -> Option<U256> {
+    let amount_in_with_fee = amount_in.checked_mul(U256::from(997))?;
+    let denominator = reserve_in
+        .checked_mul(U256::from(1000))?
+        .checked_add(amount_in_with_fee)?;
+    if denominator.is_zero() {
+        return Some(U256::zero());
+    }
+    Some(amount_in_with_fee.checked_mul(reserve_out)? / denominator)
+}
+
+// The biggest frontrun on the victim's pool (`pool_reserves` is `(reserve_in, reserve_out)` in the
+// victim's direction) after which the victim still gets its `amount_out_min`, 0 if it doesn't
+// even without one.
+pub fn max_frontrun(victim: &VictimSwap, pool_reserves: (U256, U256)) -> U256 {
+    // A frontrun the pair's math overflows on fails.
+    let victim_succeeds = |frontrun: U256| {
+        let (reserve_in, reserve_out) = pool_reserves;
+        let victim_out = amount_out(frontrun, reserve_in, reserve_out).and_then(|frontrun_out| {
+            amount_out(
+                victim.amount_in,
+                reserve_in.checked_add(frontrun)?,
+                reserve_out.checked_sub(frontrun_out)?,
+            )
+        });
+        matches!(victim_out, Some(victim_out) if victim_out >= victim.amount_out_min)
+    };
+    if !victim_succeeds(U256::zero()) {
+        return U256::zero();
+    }
+
+    // The victim's output only shrinks with the frontrun, grow the bound until it fails (or
+    // reaches where `amount_out` can't take it any more).
+    let cap = U256::MAX / 1000;
+    let mut high = pool_reserves.0.max(U256::one()).min(cap);
+    while victim_succeeds(high) {
+        if high == cap {
+            return high;
+        }
+        high = high.saturating_mul(U256::from(2)).min(cap);
+    }
+
+    let mut low = U256::zero();
+    while high - low > U256::one() {
+        let middle = low + (high - low) / 2;
+        if victim_succeeds(middle) {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    low
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{amount_out, max_frontrun, VictimSwap};
+    use ethers::{
+        abi::{self, Token},
+        prelude::*,
+        utils::{id, parse_ether},
+    };
+
+    #[test]
+    fn max_frontrun_keep_victim_above_min_out() {
+        let weth = Address::random();
+        let token = Address::random();
+        let data = [
+            id("swapExactTokensForTokens(uint256,uint256,address[],address,uint256)").to_vec(),
+            abi::encode(&[
+                Token::Uint(parse_ether(1).unwrap()),
+                Token::Uint(parse_ether(1940).unwrap()),
+                Token::Array(vec![Token::Address(weth), Token::Address(token)]),
+                Token::Address(Address::random()),
+                Token::Uint(U256::MAX),
+            ]),
+        ]
+        .concat();
+        let tx = Transaction {
+            input: data.into(),
+            ..Default::default()
+        };
+        let victim = VictimSwap::decode(&tx).unwrap();
+        assert_eq!(victim.amount_out_min, parse_ether(1940).unwrap());
+        assert_eq!(victim.path, vec![weth, token]);
+
+        let (reserve_in, reserve_out) = (parse_ether(100).unwrap(), parse_ether(200000).unwrap());
+        let frontrun = max_frontrun(&victim, (reserve_in, reserve_out));
+        assert!(frontrun > U256::zero());
+
+        let victim_out = |frontrun: U256| {
+            let frontrun_out = amount_out(frontrun, reserve_in, reserve_out).unwrap();
+            amount_out(
+                victim.amount_in,
+                reserve_in + frontrun,
+                reserve_out - frontrun_out,
+            )
+            .unwrap()
+        };
+        assert!(victim_out(frontrun) >= victim.amount_out_min);
+        assert!(victim_out(frontrun + 1) < victim.amount_out_min);
+    }
+
+    #[test]
+    fn max_frontrun_zero_if_victim_already_fails() {
+        let victim = VictimSwap {
+            amount_in: parse_ether(1).unwrap(),
+            amount_out_min: parse_ether(2000).unwrap(),
+            path: vec![],
+        };
+        let reserves = (parse_ether(100).unwrap(), parse_ether(200000).unwrap());
+        assert_eq!(max_frontrun(&victim, reserves), U256::zero());
+    }
+
+    #[test]
+    fn max_frontrun_bounded_without_min_out() {
+        // Any frontrun leaves the victim its 0, the bound stops where the pair's math would
+        // overflow.
+        let victim = VictimSwap {
+            amount_in: parse_ether(1).unwrap(),
+            amount_out_min: U256::zero(),
+            path: vec![],
+        };
+        let (reserve_in, reserve_out) = (parse_ether(100).unwrap(), parse_ether(200000).unwrap());
+        let frontrun = max_frontrun(&victim, (reserve_in, reserve_out));
+        assert!(frontrun > reserve_in);
+        let frontrun_out = amount_out(frontrun, reserve_in, reserve_out).unwrap();
+        assert!(amount_out(
+            victim.amount_in,
+            reserve_in + frontrun,
+            reserve_out - frontrun_out
+        )
+        .is_some());
+        assert!(amount_out(frontrun * 2, reserve_in, reserve_out).is_none());
+    }
+}
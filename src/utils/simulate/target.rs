@@ -0,0 +1,285 @@
+use super::{Simulate, SimulateError, SimulateTrace};
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use std::iter;
+
+// Which block state a tx is simulated on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimulateTarget {
+    // The state before the tx's block, as the victim saw it.
+    Rewind,
+    // The state after the tx's block, the whole block included.
+    Inclusion,
+    // A fixed block, `Safe` / `Finalized` are resolved to a number since trace api needs numerics.
+    Block(BlockNumber),
+    // Inside the block of the given tx, right before it: the txs ahead of it in the block are
+    // replayed first and the later ones are left out, what a backrun actually lands on.
+    BeforeTx(TxHash),
+}
+
+impl From<bool> for SimulateTarget {
+    fn from(rewind: bool) -> Self {
+        if rewind {
+            Self::Rewind
+        } else {
+            Self::Inclusion
+        }
+    }
+}
+
+impl From<BlockNumber> for SimulateTarget {
+    fn from(block: BlockNumber) -> Self {
+        Self::Block(block)
+    }
+}
+
+// The block a trace is taken at, `hash` is only known when resolved from a tag.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResolvedBlock {
+    pub number: Option<BlockNumber>,
+    pub hash: Option<H256>,
+}
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    pub(crate) async fn resolve_block(
+        &self,
+        tx: &Transaction,
+        target: SimulateTarget,
+    ) -> Result<ResolvedBlock, SimulateError> {
+        let number = match (target, tx.block_number) {
+            (SimulateTarget::Rewind, Some(block_number)) => Some((block_number - 1).into()),
+            (SimulateTarget::Inclusion, Some(block_number)) => Some(block_number.into()),
+            // Pending tx, simulate on the latest state.
+            (SimulateTarget::Rewind | SimulateTarget::Inclusion, None) => None,
+            (SimulateTarget::Block(tag @ (BlockNumber::Safe | BlockNumber::Finalized)), _) => {
+                let block = self
+                    .get_block(tag)
+                    .await
+                    .map_err(SimulateError::middleware)?
+                    .ok_or(SimulateError::BlockNotFound(tag.into()))?;
+                return Ok(ResolvedBlock {
+                    number: block.number.map(BlockNumber::Number),
+                    hash: block.hash,
+                });
+            }
+            (SimulateTarget::Block(block), _) => Some(block),
+            // The base state, the prior txs of the block are applied on top of it when tracing.
+            (SimulateTarget::BeforeTx(before_tx), _) => {
+                Some((self.tx_block_number(tx, before_tx).await? - 1).into())
+            }
+        };
+
+        Ok(ResolvedBlock { number, hash: None })
+    }
+
+    async fn tx_block_number(
+        &self,
+        tx: &Transaction,
+        tx_hash: TxHash,
+    ) -> Result<U64, SimulateError> {
+        let block_number = if tx.hash == tx_hash {
+            tx.block_number
+        } else {
+            self.get_transaction(tx_hash)
+                .await
+                .map_err(SimulateError::middleware)?
+                .and_then(|tx| tx.block_number)
+        };
+        block_number.ok_or(SimulateError::TargetTxNotMined(tx_hash))
+    }
+
+    // `to_trace` on the state of `target`, `block` is the one `resolve_block` returned for it.
+    pub(crate) async fn to_target_trace(
+        &self,
+        tx: &Transaction,
+        target: SimulateTarget,
+        block: Option<BlockNumber>,
+    ) -> Result<SimulateTrace, SimulateError> {
+        match (target, block) {
+            (SimulateTarget::BeforeTx(before_tx), Some(BlockNumber::Number(parent))) => {
+                self.to_trace_before(tx, before_tx, parent).await
+            }
+            _ => self.to_trace(tx, block).await,
+        }
+    }
+
+    async fn to_trace_before(
+        &self,
+        tx: &Transaction,
+        before_tx: TxHash,
+        parent: U64,
+    ) -> Result<SimulateTrace, SimulateError> {
+        let block = self
+            .get_block_with_txs(parent + 1)
+            .await
+            .map_err(SimulateError::middleware)?
+            .ok_or(SimulateError::BlockNotFound((parent + 1).into()))?;
+        let position = block
+            .transactions
+            .iter()
+            .position(|tx| tx.hash == before_tx)
+            .ok_or(SimulateError::TargetTxNotInBlock {
+                tx_hash: before_tx,
+                block: parent + 1,
+            })?;
+
+        // Only the trace of `tx` is needed, the prior txs just move the state.
+        let tx_list: Vec<(TypedTransaction, Vec<TraceType>)> = block.transactions[..position]
+            .iter()
+            .map(|prior_tx| (TypedTransaction::from(prior_tx), vec![]))
+            .chain(iter::once((
+                TypedTransaction::from(tx),
+                vec![TraceType::Trace, TraceType::StateDiff],
+            )))
+            .collect();
+        let trace = self
+            .tracer()
+            .trace_call_many(tx_list, Some(parent.into()))
+            .await?
+            .pop()
+            .ok_or(SimulateError::TraceCountMismatch {
+                expected: position + 1,
+                traces: 0,
+            })?;
+        let _ = self.trace_supported.set(true);
+
+        Ok(trace)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        mock::{balance_diff, block_trace, call_trace, mock_client},
+        Simulate,
+    };
+    use super::SimulateTarget;
+    use ethers::{prelude::*, types::transaction::eip2718::TypedTransaction};
+    use std::collections::BTreeMap;
+
+    #[tokio::test]
+    async fn run_resolve_finalized_tag() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let tx = Transaction {
+            hash: TxHash::random(),
+            input: "0x00000001".parse().unwrap(),
+            ..Default::default()
+        };
+        let block = Block::<TxHash> {
+            number: Some(U64::from(50)),
+            hash: Some(H256::random()),
+            ..Default::default()
+        };
+        let state_diff = StateDiff(BTreeMap::from([(
+            tx.from,
+            balance_diff(U256::zero(), U256::from(1)),
+        )]));
+
+        mock.push(U256::one()).unwrap();
+        mock.push(block_trace(
+            vec![call_trace(vec![], 0, U256::zero())],
+            Some(state_diff),
+        ))
+        .unwrap();
+        mock.push(block.clone()).unwrap();
+        mock.push(tx.clone()).unwrap();
+
+        let opportunity = simulate
+            .run(tx.hash, BlockNumber::Finalized)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(opportunity.block_hash, block.hash);
+
+        mock.assert_request("eth_getTransactionByHash", [tx.hash])
+            .unwrap();
+        mock.assert_request("eth_getBlockByNumber", ("finalized", false))
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn run_before_tx_replay_only_prior_txs() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let tx = Transaction {
+            hash: TxHash::random(),
+            block_number: Some(U64::from(10)),
+            input: "0x00000001".parse().unwrap(),
+            ..Default::default()
+        };
+        let prior_tx_list = vec![
+            Transaction {
+                hash: TxHash::random(),
+                ..Default::default()
+            },
+            Transaction {
+                hash: TxHash::random(),
+                ..Default::default()
+            },
+        ];
+        let later_tx = Transaction {
+            hash: TxHash::random(),
+            ..Default::default()
+        };
+        let block = Block::<Transaction> {
+            number: Some(U64::from(10)),
+            transactions: [prior_tx_list.clone(), vec![tx.clone(), later_tx]].concat(),
+            ..Default::default()
+        };
+        let state_diff = StateDiff(BTreeMap::from([(
+            tx.from,
+            balance_diff(U256::zero(), U256::from(1)),
+        )]));
+
+        mock.push(U256::one()).unwrap();
+        mock.push::<Vec<BlockTrace>, _>(vec![
+            block_trace(vec![], None),
+            block_trace(vec![], None),
+            block_trace(vec![call_trace(vec![], 0, U256::zero())], Some(state_diff)),
+        ])
+        .unwrap();
+        mock.push(block).unwrap();
+        mock.push(tx.clone()).unwrap();
+
+        let opportunity = simulate
+            .run(tx.hash, SimulateTarget::BeforeTx(tx.hash))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(opportunity.block, Some(BlockNumber::Number(U64::from(9))));
+
+        mock.assert_request("eth_getTransactionByHash", [tx.hash])
+            .unwrap();
+        mock.assert_request("eth_getBlockByNumber", ("0xa", true))
+            .unwrap();
+        let mut tx_list = prior_tx_list
+            .iter()
+            .map(|prior_tx| (TypedTransaction::from(prior_tx), Vec::<TraceType>::new()))
+            .collect::<Vec<_>>();
+        tx_list.push(((&tx).into(), vec![TraceType::Trace, TraceType::StateDiff]));
+        // The later tx of the block is left out.
+        mock.assert_request(
+            "trace_callMany",
+            (tx_list, BlockNumber::Number(U64::from(9))),
+        )
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn resolve_block_keep_numeric_block() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let tx = Transaction {
+            block_number: Some(U64::from(10)),
+            ..Default::default()
+        };
+
+        let block = simulate.resolve_block(&tx, true.into()).await.unwrap();
+        assert_eq!(block.number, Some(BlockNumber::Number(U64::from(9))));
+        let block = simulate.resolve_block(&tx, false.into()).await.unwrap();
+        assert_eq!(block.number, Some(BlockNumber::Number(U64::from(10))));
+        assert_eq!(block.hash, None);
+        assert!(mock.assert_request("eth_getBlockByNumber", ()).is_err());
+    }
+}
@@ -0,0 +1,59 @@
+use std::fmt;
+use std::time::Duration;
+
+// Time spent in each phase of `Simulate::run`, the phases share names with the tracing spans.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SimulateTimings {
+    pub fetch: Duration,
+    pub trace: Duration,
+    pub analyze: Duration,
+    pub build_queue: Duration,
+    pub verify: Duration,
+    // Rpc requests sent, a json-rpc batch counts once.
+    pub round_trips: u32,
+}
+
+impl SimulateTimings {
+    pub fn total(&self) -> Duration {
+        self.fetch + self.trace + self.analyze + self.build_queue + self.verify
+    }
+}
+
+impl fmt::Display for SimulateTimings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "fetch={:?} trace={:?} analyze={:?} build_queue={:?} verify={:?} total={:?} round_trips={}",
+            self.fetch,
+            self.trace,
+            self.analyze,
+            self.build_queue,
+            self.verify,
+            self.total(),
+            self.round_trips
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SimulateTimings;
+    use std::time::Duration;
+
+    #[test]
+    fn total_sum_all_phases() {
+        let timings = SimulateTimings {
+            fetch: Duration::from_millis(10),
+            trace: Duration::from_millis(200),
+            analyze: Duration::from_millis(3),
+            build_queue: Duration::from_millis(1),
+            verify: Duration::ZERO,
+            round_trips: 2,
+        };
+        assert_eq!(timings.total(), Duration::from_millis(214));
+        assert_eq!(
+            timings.to_string(),
+            "fetch=10ms trace=200ms analyze=3ms build_queue=1ms verify=0ns total=214ms round_trips=2"
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use super::{Simulate, SimulateError, SimulateTarget, SimulateTrace};
+use ethers::prelude::*;
+use std::collections::HashSet;
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    // Every address the tx interacts with on the state of `target`, for access lists and filters.
+    // Empty if the tx is unknown.
+    pub async fn touched_addresses(
+        &self,
+        tx_hash: TxHash,
+        target: impl Into<SimulateTarget>,
+    ) -> Result<HashSet<Address>, SimulateError> {
+        let tx = match self
+            .get_transaction(tx_hash)
+            .await
+            .map_err(SimulateError::middleware)?
+        {
+            Some(tx) => tx,
+            None => return Ok(HashSet::new()),
+        };
+
+        let target = target.into();
+        let block = self.resolve_block(&tx, target).await?;
+        let trace = self.to_target_trace(&tx, target, block.number).await?;
+        Ok(touched_addresses(&trace))
+    }
+}
+
+// Senders, recipients and created contracts of every call, plus every account of the state diff.
+pub fn touched_addresses(trace: &SimulateTrace) -> HashSet<Address> {
+    let mut addresses = HashSet::new();
+    for trace in trace.trace.iter().flatten() {
+        match &trace.action {
+            Action::Call(call) => addresses.extend([call.from, call.to]),
+            Action::Create(create) => addresses.extend([create.from]),
+            Action::Suicide(suicide) => addresses.extend([suicide.address, suicide.refund_address]),
+            Action::Reward(reward) => addresses.extend([reward.author]),
+        }
+        if let Some(Res::Create(result)) = &trace.result {
+            addresses.insert(result.address);
+        }
+    }
+    if let Some(state_diff) = &trace.state_diff {
+        addresses.extend(state_diff.0.keys());
+    }
+    addresses
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        mock::{balance_diff, block_trace, call_trace, mock_client},
+        Simulate,
+    };
+    use ethers::prelude::*;
+    use std::collections::{BTreeMap, HashSet};
+
+    #[tokio::test]
+    async fn touched_addresses_cover_trace_and_state_diff() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let tx = Transaction {
+            hash: TxHash::random(),
+            ..Default::default()
+        };
+        let call = call_trace(vec![], 1, U256::zero());
+        let created = Address::random();
+        let create = TransactionTrace {
+            action: Action::Create(Create {
+                from: Address::random(),
+                value: U256::zero(),
+                gas: U256::from(100000),
+                init: Bytes::default(),
+            }),
+            action_type: ActionType::Create,
+            result: Some(Res::Create(CreateResult {
+                gas_used: U256::zero(),
+                code: Bytes::default(),
+                address: created,
+            })),
+            ..call_trace(vec![0], 0, U256::zero())
+        };
+        let coinbase = Address::random();
+        let state_diff = StateDiff(BTreeMap::from([(
+            coinbase,
+            balance_diff(U256::zero(), U256::from(1)),
+        )]));
+
+        mock.push(block_trace(
+            vec![call.clone(), create.clone()],
+            Some(state_diff),
+        ))
+        .unwrap();
+        mock.push(tx.clone()).unwrap();
+
+        let (call, create) = match (call.action, create.action) {
+            (Action::Call(call), Action::Create(create)) => (call, create),
+            other => panic!("expected a call and a create: {other:?}"),
+        };
+        assert_eq!(
+            simulate.touched_addresses(tx.hash, false).await.unwrap(),
+            HashSet::from([call.from, call.to, create.from, created, coinbase])
+        );
+    }
+}
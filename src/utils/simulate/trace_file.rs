@@ -0,0 +1,215 @@
+use super::{
+    origin_call_status, ProfitReport, Simulate, SimulateError, SimulateTarget, SimulateTrace,
+};
+use ethers::prelude::*;
+use serde_json::{json, Value};
+use std::fs;
+use std::path::Path;
+
+// Bumped on any change of the saved layout, older files are refused rather than misread.
+pub const TRACE_FILE_VERSION: u64 = 1;
+
+// A tx and its trace as saved by `Simulate::trace_to_file`.
+pub fn read_trace_file(
+    path: impl AsRef<Path>,
+) -> Result<(Transaction, SimulateTrace), SimulateError> {
+    let path = path.as_ref();
+    let json = fs::read_to_string(path).map_err(|source| SimulateError::TraceFileIo {
+        path: path.into(),
+        source,
+    })?;
+    let decode = |source| SimulateError::TraceFileDecode {
+        path: path.into(),
+        source,
+    };
+    let value = serde_json::from_str::<Value>(&json).map_err(decode)?;
+    match value["version"].as_u64() {
+        Some(TRACE_FILE_VERSION) => {}
+        version => {
+            return Err(SimulateError::UnsupportedTraceFile {
+                path: path.into(),
+                version,
+            })
+        }
+    }
+    let tx = serde_json::from_value(value["tx"].clone()).map_err(decode)?;
+    let trace = serde_json::from_value(value["trace"].clone()).map_err(decode)?;
+    Ok((tx, trace))
+}
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    // Fetch and trace the tx like `run` does (on the state before it), then save both to `path`
+    // as json for `analyze_trace_file`.
+    pub async fn trace_to_file(
+        &self,
+        tx_hash: TxHash,
+        path: impl AsRef<Path>,
+    ) -> Result<(), SimulateError> {
+        let tx = self
+            .get_transaction(tx_hash)
+            .await
+            .map_err(SimulateError::middleware)?
+            .ok_or(SimulateError::TxNotFound(tx_hash))?;
+        let block = self.resolve_block(&tx, SimulateTarget::Rewind).await?;
+        let trace = self.to_trace(&tx, block.number).await?;
+
+        let path = path.as_ref();
+        let json = json!({
+            "version": TRACE_FILE_VERSION,
+            "tx": tx,
+            "trace": trace,
+        });
+        fs::write(path, json.to_string()).map_err(|source| SimulateError::TraceFileIo {
+            path: path.into(),
+            source,
+        })
+    }
+
+    // Run the enabled analyzers on a trace saved by `trace_to_file`, the same checks as `run` but
+    // nothing is asked from the node: no logs, no inferred beneficiary and no analyzer that reads
+    // the node (e.g. `lp_tokens`, see `AnalyzeState::reads_node`). `None` if the tx holds no
+    // profit.
+    pub async fn analyze_trace_file(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> Result<Option<Vec<ProfitReport>>, SimulateError> {
+        let (tx, trace) = read_trace_file(path)?;
+        self.check_trace_size(&trace)?;
+        if !self.allow_reverted && !origin_call_status(&trace).1 {
+            return Ok(None);
+        }
+
+        let reports = self
+            .run_analyzers(&tx, &trace, None, &self.beneficiaries, &None, true)
+            .await;
+        Ok(Some(reports).filter(|reports| !reports.is_empty()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        mock::{balance_diff, block_trace, mock_client},
+        ProfitReport, Simulate, SimulateError,
+    };
+    use super::{read_trace_file, TRACE_FILE_VERSION};
+    use crate::utils::LpToken;
+    use ethers::{
+        abi::{self, Token},
+        prelude::*,
+        utils::{keccak256, parse_ether},
+    };
+    use serde_json::json;
+    use std::collections::BTreeMap;
+    use std::{env, fs};
+
+    #[tokio::test]
+    async fn trace_to_file_round_trip() {
+        let (client, mock) = mock_client();
+        let beneficiary = Address::random();
+        let simulate = Simulate::init(&client, None)
+            .await
+            .unwrap()
+            .beneficiaries(vec![beneficiary]);
+        let tx = Transaction {
+            hash: TxHash::random(),
+            block_number: Some(U64::from(10)),
+            input: "0x00000001".parse().unwrap(),
+            ..Default::default()
+        };
+        let trace = block_trace(
+            vec![],
+            Some(StateDiff(BTreeMap::from([(
+                beneficiary,
+                balance_diff(U256::zero(), parse_ether(1).unwrap()),
+            )]))),
+        );
+
+        // The tx, then its trace.
+        mock.push(trace.clone()).unwrap();
+        mock.push(tx.clone()).unwrap();
+        let path = env::temp_dir().join(format!("trace-{:?}.json", tx.hash));
+        simulate.trace_to_file(tx.hash, &path).await.unwrap();
+
+        let (saved_tx, saved_trace) = read_trace_file(&path).unwrap();
+        assert_eq!(saved_tx, tx);
+        assert_eq!(saved_trace, trace);
+        // Replayed with no response left on the mock.
+        assert_eq!(
+            simulate.analyze_trace_file(&path).await.unwrap(),
+            Some(vec![ProfitReport::native(
+                beneficiary,
+                parse_ether(1).unwrap()
+            )])
+        );
+
+        fs::write(&path, r#"{"version":0}"#).unwrap();
+        assert!(matches!(
+            simulate.analyze_trace_file(&path).await,
+            Err(SimulateError::UnsupportedTraceFile {
+                version: Some(0),
+                ..
+            })
+        ));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn analyze_trace_file_leave_out_node_analyzers() {
+        let (client, mock) = mock_client();
+        let (beneficiary, pair) = (Address::random(), Address::random());
+        let simulate = Simulate::init(&client, None)
+            .await
+            .unwrap()
+            .beneficiaries(vec![beneficiary])
+            .lp_tokens(vec![LpToken {
+                pair,
+                native_is_token0: false,
+            }]);
+        // The beneficiary gained native token and LP tokens of the pair.
+        let balance_slot = H256(keccak256(
+            [
+                H256::from(beneficiary).as_bytes(),
+                H256::from_low_u64_be(1).as_bytes(),
+            ]
+            .concat(),
+        ));
+        let lp_diff = AccountDiff {
+            balance: Diff::Same,
+            nonce: Diff::Same,
+            code: Diff::Same,
+            storage: BTreeMap::from([(balance_slot, Diff::Born(H256::from_low_u64_be(50)))]),
+        };
+        let trace = block_trace(
+            vec![],
+            Some(StateDiff(BTreeMap::from([
+                (beneficiary, balance_diff(U256::zero(), U256::from(7))),
+                (pair, lp_diff),
+            ]))),
+        );
+        let path = env::temp_dir().join(format!("trace-{:?}.json", TxHash::random()));
+        let tx = Transaction {
+            input: "0x00000001".parse().unwrap(),
+            ..Default::default()
+        };
+        fs::write(
+            &path,
+            json!({ "version": TRACE_FILE_VERSION, "tx": tx, "trace": trace }).to_string(),
+        )
+        .unwrap();
+
+        // The reserves and the supply the LP analyzer would value the gain with, at 40.
+        mock.push(Bytes::from(abi::encode(&[Token::Uint(1000.into())])))
+            .unwrap();
+        mock.push(Bytes::from(abi::encode(&[
+            Token::Uint(0.into()),
+            Token::Uint(400.into()),
+            Token::Uint(0.into()),
+        ])))
+        .unwrap();
+        let reports = simulate.analyze_trace_file(&path).await.unwrap().unwrap();
+        assert!(reports.contains(&ProfitReport::native(beneficiary, U256::from(7))));
+        assert!(!reports.contains(&ProfitReport::native(beneficiary, U256::from(40))));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,107 @@
+use super::{Simulate, SimulateError, SimulateTrace};
+use async_trait::async_trait;
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use serde_json::Value;
+
+// The rpc calls used for tracing, object safe so a dedicated trace endpoint
+// (e.g. a paid provider with auth headers) can be held without another type parameter.
+#[async_trait]
+pub trait TraceClient: Send + Sync {
+    async fn trace_call(
+        &self,
+        tx: TypedTransaction,
+        trace_type: Vec<TraceType>,
+        block: Option<BlockNumber>,
+    ) -> Result<SimulateTrace, SimulateError>;
+
+    async fn trace_call_many(
+        &self,
+        tx_list: Vec<(TypedTransaction, Vec<TraceType>)>,
+        block: Option<BlockNumber>,
+    ) -> Result<Vec<SimulateTrace>, SimulateError>;
+
+    async fn client_version(&self) -> Result<String, SimulateError>;
+
+    async fn request(&self, method: &str, params: Value) -> Result<Value, SimulateError>;
+}
+
+#[async_trait]
+impl<T: Middleware> TraceClient for T {
+    async fn trace_call(
+        &self,
+        tx: TypedTransaction,
+        trace_type: Vec<TraceType>,
+        block: Option<BlockNumber>,
+    ) -> Result<SimulateTrace, SimulateError> {
+        Middleware::trace_call(self, tx, trace_type, block)
+            .await
+            .map_err(SimulateError::middleware)
+    }
+
+    async fn trace_call_many(
+        &self,
+        tx_list: Vec<(TypedTransaction, Vec<TraceType>)>,
+        block: Option<BlockNumber>,
+    ) -> Result<Vec<SimulateTrace>, SimulateError> {
+        Middleware::trace_call_many(self, tx_list, block)
+            .await
+            .map_err(SimulateError::middleware)
+    }
+
+    async fn client_version(&self) -> Result<String, SimulateError> {
+        Middleware::client_version(self)
+            .await
+            .map_err(SimulateError::middleware)
+    }
+
+    async fn request(&self, method: &str, params: Value) -> Result<Value, SimulateError> {
+        self.provider()
+            .request(method, params)
+            .await
+            .map_err(SimulateError::middleware)
+    }
+}
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    // Route the heavy trace traffic to `provider`, cheap reads stay on the main client.
+    pub fn trace_provider<T: Middleware + 'a>(mut self, provider: T) -> Self {
+        self.trace_provider = Some(Box::new(provider));
+        self
+    }
+
+    pub(crate) fn tracer(&self) -> &(dyn TraceClient + 'a) {
+        match &self.trace_provider {
+            Some(provider) => provider.as_ref(),
+            None => self.inner,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        mock::{block_trace, mock_client},
+        Simulate,
+    };
+    use ethers::prelude::*;
+
+    #[tokio::test]
+    async fn trace_call_use_trace_provider() {
+        let (client, mock) = mock_client();
+        let (trace_provider, trace_mock) = Provider::mocked();
+        let simulate = Simulate::init(&client, None)
+            .await
+            .unwrap()
+            .trace_provider(trace_provider);
+
+        trace_mock.push(block_trace(vec![], None)).unwrap();
+        simulate
+            .to_trace(&Transaction::default(), None)
+            .await
+            .unwrap();
+
+        // Only the trace provider had a response queued.
+        assert!(mock.assert_request("trace_call", ()).is_err());
+    }
+}
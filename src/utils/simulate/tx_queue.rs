@@ -0,0 +1,1109 @@
+use super::{
+    gas::{gas_estimate_from_trace, gas_limit_for, intrinsic_gas, trace_gas_used},
+    native_flows_within, Opportunity, OpportunityId, QueueEconomics, QueueStrategy, Simulate,
+    SimulateError, SimulateTrace, TraceClient, ValueSource,
+};
+use ethers::prelude::*;
+use ethers::types::transaction::{eip2718::TypedTransaction, eip2930::AccessList};
+use serde_json::json;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueEntry {
+    pub tx: TransactionRequest,
+    // Gas the call used in the victim's trace plus the intrinsic cost, the fallback of estimation.
+    pub trace_gas: Option<U256>,
+    // Only the execution part of `trace_gas`, see `gas_estimate_from_trace`.
+    pub execution_gas: Option<U256>,
+    // Why `estimate_gas` failed, the gas is then the trace-derived one (if any).
+    pub estimate_error: Option<String>,
+    // Set by `fill_fees` on EIP-1559 chains, `TransactionRequest` can't carry them.
+    pub max_fee_per_gas: Option<U256>,
+    pub max_priority_fee_per_gas: Option<U256>,
+    // Set by `create_access_lists`, with the gas the call used with the list warm.
+    pub access_list: Option<AccessList>,
+    pub access_list_gas: Option<U256>,
+    // Why `eth_createAccessList` failed, e.g. the call reverted, the entry then has no list.
+    pub access_list_error: Option<String>,
+    // What the searcher contract pays from its own funds for the call, with
+    // `ValueSource::ContractFunds` the tx itself carries no value.
+    pub contract_value: Option<U256>,
+    // Never trimmed from the queue, e.g. an approval the later swaps depend on.
+    pub required: bool,
+    // Native value the call's sender got back less what it put in over the call and its sub calls
+    // in the victim's trace (see `native_flows_within`), `None` for an entry not rebuilt from one.
+    // What `enforce_gas_budget` trims by.
+    pub net_flow: Option<I256>,
+    // A nice-to-have the bundle still lands without, e.g. sweeping dust: sent in
+    // `revertingTxHashes` / with `canRevert`, and its revert is only a warning in verification.
+    pub revertible: bool,
+}
+
+impl From<TransactionRequest> for QueueEntry {
+    fn from(tx: TransactionRequest) -> Self {
+        Self {
+            tx,
+            trace_gas: None,
+            execution_gas: None,
+            estimate_error: None,
+            max_fee_per_gas: None,
+            max_priority_fee_per_gas: None,
+            access_list: None,
+            access_list_gas: None,
+            access_list_error: None,
+            contract_value: None,
+            required: false,
+            net_flow: None,
+            revertible: false,
+        }
+    }
+}
+
+impl QueueEntry {
+    // The entry as the tx type `chain` accepts: 1559 once `fill_fees` set its fees, 2930 if it
+    // only has an access list, legacy otherwise. Chains without a base fee only get legacy txs,
+    // priced at the max fee if the entry has no gas price, and their access list is dropped.
+    pub fn to_typed(&self, chain: Chain) -> TypedTransaction {
+        if chain.is_legacy() {
+            let mut tx = self.tx.clone();
+            tx.gas_price = tx.gas_price.or(self.max_fee_per_gas);
+            return tx.into();
+        }
+        match (self.max_fee_per_gas, &self.access_list) {
+            (Some(max_fee_per_gas), access_list) => Eip1559TransactionRequest {
+                from: self.tx.from,
+                to: self.tx.to.clone(),
+                gas: self.tx.gas,
+                value: self.tx.value,
+                data: self.tx.data.clone(),
+                nonce: self.tx.nonce,
+                access_list: access_list.clone().unwrap_or_default(),
+                max_priority_fee_per_gas: self.max_priority_fee_per_gas,
+                max_fee_per_gas: Some(max_fee_per_gas),
+                chain_id: self.tx.chain_id,
+            }
+            .into(),
+            (None, Some(access_list)) => {
+                self.tx.clone().with_access_list(access_list.clone()).into()
+            }
+            (None, None) => self.tx.clone().into(),
+        }
+    }
+
+    // `to_typed` on the chain of the tx, mainnet if it has none or an unknown one.
+    pub fn typed_tx(&self) -> TypedTransaction {
+        let chain = self
+            .tx
+            .chain_id
+            .and_then(|chain_id| Chain::try_from(chain_id.as_u64()).ok())
+            .unwrap_or(Chain::Mainnet);
+        self.to_typed(chain)
+    }
+}
+
+impl From<&QueueEntry> for TypedTransaction {
+    fn from(entry: &QueueEntry) -> Self {
+        entry.typed_tx()
+    }
+}
+
+// One tx list of the queue, ready to be prepared for sending.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TxQueue {
+    pub entries: Vec<QueueEntry>,
+    // The last block the queue may be included in, the opportunity is likely gone after it.
+    // `None` when the simulation block wasn't a number, the queue then never expires.
+    pub valid_until_block: Option<U64>,
+    // Set by `fill_economics` once the gas, fees and bribe are known.
+    pub economics: Option<QueueEconomics>,
+    // Of the opportunity the queue was built for, what its submission is recorded under.
+    pub opportunity_id: Option<OpportunityId>,
+    // The tx the queue goes after, checked for inclusion before each submission.
+    pub victim: Option<TxHash>,
+}
+
+// The queue's gas against `max_total_gas`, see `TxQueue::enforce_gas_budget`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GasBudget {
+    pub total_gas: U256,
+    // Optional entries trimmed to get under the budget, in queue order.
+    pub removed: Vec<QueueEntry>,
+    pub over_budget: bool,
+    // How much gas the queue is still over the budget even without the optional entries.
+    pub excess: U256,
+}
+
+impl From<Vec<TransactionRequest>> for TxQueue {
+    fn from(tx_list: Vec<TransactionRequest>) -> Self {
+        Self {
+            entries: tx_list.into_iter().map(QueueEntry::from).collect(),
+            valid_until_block: None,
+            economics: None,
+            opportunity_id: None,
+            victim: None,
+        }
+    }
+}
+
+impl TxQueue {
+    // Nothing can include the queue anymore once `current_block` reached its last valid block.
+    pub fn is_expired(&self, current_block: U64) -> bool {
+        self.valid_until_block.map_or(false, |valid_until_block| {
+            current_block >= valid_until_block
+        })
+    }
+
+    // `is_expired` as an error, for the paths that send the queue.
+    pub fn ensure_valid(&self, current_block: U64) -> Result<(), SimulateError> {
+        match self.valid_until_block {
+            Some(valid_until_block) if self.is_expired(current_block) => {
+                Err(SimulateError::QueueExpired {
+                    valid_until_block,
+                    current_block,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Let the entry at `index` revert without the bundle, not a required one: the later entries
+    // depend on it. Whether it was marked.
+    pub fn mark_revertible(&mut self, index: usize) -> bool {
+        match self.entries.get_mut(index) {
+            Some(entry) if !entry.required => {
+                entry.revertible = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Indices of the entries marked with `mark_revertible`.
+    pub fn revertible(&self) -> Vec<usize> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.revertible)
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    pub fn tx_list(&self) -> Vec<TransactionRequest> {
+        self.entries.iter().map(|entry| entry.tx.clone()).collect()
+    }
+
+    // Entries whose estimation failed, they are filled from the trace or not at all.
+    pub fn estimate_failed(&self) -> impl Iterator<Item = &QueueEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.estimate_error.is_some())
+    }
+
+    // Set `gas` of every entry, in order, to its estimate plus `buffer_pct` percent, capped at the
+    // block gas limit. Left to the provider, the estimate would run on a state where the
+    // opportunity may not exist yet, so a failed estimate falls back to the trace-derived gas.
+    // `eth_estimateGas` only sees the latest state, the entries after the first get the gas of
+    // their call in a `trace_callMany` of the queue instead, on the state the entries before them
+    // leave. Without that trace, or where the call reverts in it, they're estimated alone.
+    pub async fn fill_gas<M: Middleware>(
+        &mut self,
+        client: &M,
+        buffer_pct: u64,
+    ) -> Result<(), SimulateError> {
+        self.fill_gas_within(client, buffer_pct, None).await
+    }
+
+    // `fill_gas`, an estimate taking longer than `timeout` falls back like a failed one.
+    pub async fn fill_gas_within<M: Middleware>(
+        &mut self,
+        client: &M,
+        buffer_pct: u64,
+        timeout: Option<Duration>,
+    ) -> Result<(), SimulateError> {
+        let gas_limit = client
+            .get_block(BlockNumber::Latest)
+            .await
+            .map_err(SimulateError::middleware)?
+            .map(|block| block.gas_limit);
+
+        let sequential = match self.entries.len() {
+            0 | 1 => None,
+            _ => self.trace_in_order(client, timeout).await,
+        };
+        for (index, entry) in self.entries.iter_mut().enumerate() {
+            let traced = sequential
+                .as_ref()
+                .filter(|_| index > 0)
+                .and_then(|traces| traces.get(index))
+                .and_then(|trace| traced_gas(&entry.tx, trace));
+            match traced {
+                Some(gas) => {
+                    entry.estimate_error = None;
+                    entry.tx.gas = Some(buffered_gas(gas, buffer_pct, gas_limit));
+                }
+                None => fill_entry_gas(client, entry, buffer_pct, gas_limit, timeout).await,
+            }
+        }
+
+        Ok(())
+    }
+
+    // The traces of the entries run one after the other on the latest state, `None` if the node
+    // can't trace them (in `timeout`).
+    async fn trace_in_order<M: Middleware>(
+        &self,
+        client: &M,
+        timeout: Option<Duration>,
+    ) -> Option<Vec<BlockTrace>> {
+        let calls = self
+            .entries
+            .iter()
+            .map(|entry| {
+                (
+                    TypedTransaction::from(entry.tx.clone()),
+                    vec![TraceType::Trace],
+                )
+            })
+            .collect();
+        let traces = client.trace_call_many(calls, Some(BlockNumber::Latest));
+        let traces = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, traces).await.ok()?,
+            None => traces.await,
+        };
+        traces.ok()
+    }
+
+    // Every entry as the tx type `chain` accepts, in send order, see `QueueEntry::to_typed`.
+    pub fn to_typed(&self, chain: Chain) -> Vec<TypedTransaction> {
+        self.entries
+            .iter()
+            .map(|entry| entry.to_typed(chain))
+            .collect()
+    }
+
+    // Sign every entry with the client's signer, in send order, as raw txs for a relay. The chain
+    // id and sender are the signer's where the entry has none, the nonce must already be set.
+    pub async fn sign_all<M: Middleware, S: Signer>(
+        &self,
+        client: &SignerMiddleware<M, S>,
+    ) -> Result<Vec<Bytes>, SimulateError> {
+        self.sign_with(client.signer()).await
+    }
+
+    // `sign_all` with any signer, e.g. one of the pool the queue was assigned to.
+    pub async fn sign_with<S: Signer>(&self, signer: &S) -> Result<Vec<Bytes>, SimulateError> {
+        // A chain ethers doesn't know gets the mainnet tx types.
+        let chain = Chain::try_from(signer.chain_id()).unwrap_or(Chain::Mainnet);
+        let mut raw_tx_list = Vec::with_capacity(self.entries.len());
+        for (index, mut tx) in self.to_typed(chain).into_iter().enumerate() {
+            if tx.chain_id().is_none() {
+                tx.set_chain_id(signer.chain_id());
+            }
+            if tx.from().is_none() {
+                tx.set_from(signer.address());
+            }
+            if tx.nonce().is_none() {
+                return Err(SimulateError::Signing {
+                    index,
+                    reason: "no nonce assigned".into(),
+                });
+            }
+
+            let signature =
+                signer
+                    .sign_transaction(&tx)
+                    .await
+                    .map_err(|e| SimulateError::Signing {
+                        index,
+                        reason: e.to_string(),
+                    })?;
+            raw_tx_list.push(tx.rlp_signed(&signature));
+        }
+
+        Ok(raw_tx_list)
+    }
+
+    // Ask the rpc for the access list of every entry on `block` (the rewound block of the
+    // opportunity), the slots an arbitrage touches are mostly cold so it saves gas, and a call
+    // that reverts here won't land either.
+    pub async fn create_access_lists<M: Middleware>(
+        &mut self,
+        client: &M,
+        block: Option<BlockNumber>,
+    ) -> Result<(), SimulateError> {
+        let block = block.unwrap_or(BlockNumber::Latest);
+        for entry in &mut self.entries {
+            let result = TraceClient::request(
+                client,
+                "eth_createAccessList",
+                json!([entry.typed_tx(), block]),
+            )
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|result| {
+                // Geth reports a revert next to a partial list instead of as an rpc error.
+                if let Some(error) = result["error"].as_str() {
+                    return Err(error.to_string());
+                }
+                let access_list =
+                    serde_json::from_value::<AccessList>(result["accessList"].clone())
+                        .map_err(|e| e.to_string())?;
+                let gas_used = serde_json::from_value::<U256>(result["gasUsed"].clone())
+                    .map_err(|e| e.to_string())?;
+                Ok((access_list, gas_used))
+            });
+
+            match result {
+                Ok((access_list, gas_used)) => {
+                    entry.access_list = Some(access_list);
+                    entry.access_list_gas = Some(gas_used);
+                    entry.access_list_error = None;
+                }
+                Err(e) => {
+                    entry.access_list = None;
+                    entry.access_list_gas = None;
+                    entry.access_list_error = Some(e);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Trim optional entries until the queue's gas is within `max_total_gas`: the lowest `net_flow`
+    // first (an unknown one counts as none), of equal ones the most gas first so the fewest go.
+    // Required entries and those netting their sender value (the profit takers) always stay, the
+    // queue is over budget if they alone are. The nonces left are made consecutive again.
+    pub fn enforce_gas_budget(&mut self, max_total_gas: U256) -> Result<GasBudget, SimulateError> {
+        let gas = |entry: &QueueEntry| entry.tx.gas.or(entry.trace_gas).unwrap_or_default();
+        let flow = |entry: &QueueEntry| entry.net_flow.unwrap_or_default();
+        let mut total_gas = self.total_gas()?;
+        let mut trimmed = vec![false; self.entries.len()];
+        while total_gas > max_total_gas {
+            let next = self
+                .entries
+                .iter()
+                .enumerate()
+                .filter(|(i, entry)| !entry.required && flow(entry) <= I256::zero() && !trimmed[*i])
+                .min_by(|(_, a), (_, b)| flow(a).cmp(&flow(b)).then_with(|| gas(b).cmp(&gas(a))));
+            match next {
+                Some((i, entry)) => {
+                    total_gas = total_gas
+                        .checked_sub(gas(entry))
+                        .ok_or(SimulateError::Overflow("total gas"))?;
+                    trimmed[i] = true;
+                }
+                None => break,
+            }
+        }
+
+        let first_nonce = self.entries.iter().filter_map(|entry| entry.tx.nonce).min();
+        let (removed, kept) = std::mem::take(&mut self.entries)
+            .into_iter()
+            .zip(trimmed)
+            .partition::<Vec<_>, _>(|(_, trimmed)| *trimmed);
+        self.entries = kept.into_iter().map(|(entry, _)| entry).collect();
+        if let (Some(mut nonce), false) = (first_nonce, removed.is_empty()) {
+            for entry in &mut self.entries {
+                if entry.tx.nonce.is_some() {
+                    entry.tx.nonce = Some(nonce);
+                    nonce += U256::one();
+                }
+            }
+        }
+        Ok(GasBudget {
+            total_gas,
+            removed: removed.into_iter().map(|(entry, _)| entry).collect(),
+            over_budget: total_gas > max_total_gas,
+            excess: total_gas.saturating_sub(max_total_gas),
+        })
+    }
+
+    // Give the entries consecutive nonces from the sender's pending nonce, returns the next free one.
+    pub async fn assign_nonces<M: Middleware>(
+        &mut self,
+        client: &M,
+    ) -> Result<U256, SimulateError> {
+        Self::assign_nonces_all(std::slice::from_mut(self), client).await
+    }
+
+    // Like `assign_nonces` for queues sent together (e.g. both strategies), in order, the nonce
+    // is fetched once and continues from one queue to the next so no two entries share one.
+    pub async fn assign_nonces_all<M: Middleware>(
+        queues: &mut [TxQueue],
+        client: &M,
+    ) -> Result<U256, SimulateError> {
+        let from = queues
+            .iter()
+            .flat_map(|queue| &queue.entries)
+            .find_map(|entry| entry.tx.from)
+            .ok_or(SimulateError::NoSender)?;
+        let mut nonce = client
+            .get_transaction_count(from, Some(BlockNumber::Pending.into()))
+            .await
+            .map_err(SimulateError::middleware)?;
+
+        for entry in queues
+            .iter_mut()
+            .flat_map(|queue| queue.entries.iter_mut())
+            .filter(|entry| entry.tx.from == Some(from))
+        {
+            entry.tx.nonce = Some(nonce);
+            nonce += U256::one();
+        }
+
+        Ok(nonce)
+    }
+
+    // Drop the entry at `failed` and shift the nonces of the later entries of the same sender down,
+    // otherwise they wait forever on the nonce that will never be used.
+    pub fn repair_nonce_gap(&mut self, failed: usize) -> Option<QueueEntry> {
+        Self::repair_nonce_gap_all(std::slice::from_mut(self), 0, failed)
+    }
+
+    // Like `repair_nonce_gap` for queues sent together, whose nonces `assign_nonces_all` continued
+    // from one to the next: the entry at `failed` of `queues[queue]` is dropped, and every entry of
+    // its sender past its nonce shifts down, in whichever queue.
+    pub fn repair_nonce_gap_all(
+        queues: &mut [TxQueue],
+        queue: usize,
+        failed: usize,
+    ) -> Option<QueueEntry> {
+        let entries = &mut queues.get_mut(queue)?.entries;
+        if failed >= entries.len() {
+            return None;
+        }
+
+        let removed = entries.remove(failed);
+        let gap = match removed.tx.nonce {
+            Some(gap) => gap,
+            None => return Some(removed),
+        };
+        for entry in queues
+            .iter_mut()
+            .flat_map(|tx_queue| tx_queue.entries.iter_mut())
+            .filter(|entry| entry.tx.from == removed.tx.from)
+        {
+            if let Some(nonce) = entry.tx.nonce.filter(|nonce| *nonce > gap) {
+                entry.tx.nonce = nonce.checked_sub(U256::one());
+            }
+        }
+        Some(removed)
+    }
+}
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    // The queue of the opportunity, each entry with the gas its call used in the trace.
+    pub fn tx_queues(
+        &self,
+        opportunity: &Opportunity,
+    ) -> Result<Vec<(QueueStrategy, TxQueue)>, SimulateError> {
+        self.tx_queues_from(opportunity, self.queue_sender())
+    }
+
+    // `tx_queues` sent by `sender`, the profit of its calldata goes to it too without a contract.
+    pub(crate) fn tx_queues_from(
+        &self,
+        opportunity: &Opportunity,
+        sender: Address,
+    ) -> Result<Vec<(QueueStrategy, TxQueue)>, SimulateError> {
+        let valid_until_block = match opportunity.block {
+            Some(BlockNumber::Number(block)) => Some(
+                block
+                    .checked_add(self.validity_horizon.into())
+                    .ok_or(SimulateError::Overflow("valid until block"))?,
+            ),
+            _ => None,
+        };
+        Ok(self
+            .to_strategy_traces(&opportunity.trace, sender)
+            .into_iter()
+            .map(|(strategy, tx_list)| {
+                let entries = tx_list
+                    .into_iter()
+                    .map(|(tx, trace)| QueueEntry {
+                        trace_gas: trace
+                            .result
+                            .as_ref()
+                            .map(|_| trace_gas_used(trace) + intrinsic_gas(&tx)),
+                        execution_gas: trace.result.as_ref().map(|_| trace_gas_used(trace)),
+                        required: is_approval(&tx),
+                        net_flow: net_flow(&opportunity.trace, trace),
+                        contract_value: match (&trace.action, self.value_source) {
+                            (Action::Call(call), ValueSource::ContractFunds) => Some(call.value),
+                            (Action::Create(create), ValueSource::ContractFunds) => {
+                                Some(create.value)
+                            }
+                            _ => None,
+                        },
+                        ..QueueEntry::from(tx)
+                    })
+                    .collect();
+                (
+                    strategy,
+                    TxQueue {
+                        entries,
+                        valid_until_block,
+                        economics: None,
+                        opportunity_id: Some(opportunity.id),
+                        victim: opportunity.victim,
+                    },
+                )
+            })
+            .collect())
+    }
+}
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    // Sign the queue and broadcast its entries in order, refused once the queue expired or, with
+    // `refuse_unprofitable`, when its economics don't net a profit.
+    pub async fn send_queue(&self, tx_queue: &TxQueue) -> Result<Vec<TxHash>, SimulateError> {
+        if self.refuse_unprofitable {
+            tx_queue.ensure_profitable()?;
+        }
+        let current_block = self
+            .get_block_number()
+            .await
+            .map_err(SimulateError::middleware)?;
+        tx_queue.ensure_valid(current_block)?;
+        if self.impersonate.is_some() {
+            return self.send_impersonated(tx_queue).await;
+        }
+
+        let mut tx_hashes = Vec::with_capacity(tx_queue.entries.len());
+        for raw_tx in tx_queue.sign_all(self.inner).await? {
+            let pending = self
+                .send_raw_transaction(raw_tx)
+                .await
+                .map_err(SimulateError::middleware)?;
+            tx_hashes.push(*pending);
+        }
+        Ok(tx_hashes)
+    }
+}
+
+// See `QueueEntry::net_flow`, a create has no sender to net for.
+fn net_flow(trace: &SimulateTrace, call: &TransactionTrace) -> Option<I256> {
+    let from = match &call.action {
+        Action::Call(action) => action.from,
+        _ => return None,
+    };
+    let (inbound, outbound) = native_flows_within(trace, from, &call.trace_address);
+    let signed = |value: U256| I256::try_from(value).unwrap_or(I256::MAX);
+    Some(signed(inbound).saturating_sub(signed(outbound)))
+}
+
+// `approve(address,uint256)`, the swaps after it fail without it.
+fn is_approval(tx: &TransactionRequest) -> bool {
+    tx.data
+        .as_ref()
+        .map_or(false, |data| data.starts_with(&[0x09, 0x5e, 0xa7, 0xb3]))
+}
+
+// Set `gas` of `entry` on the latest state the way `fill_gas_within` does, `gas_limit` being the
+// block's.
+pub(crate) async fn fill_entry_gas<M: Middleware>(
+    client: &M,
+    entry: &mut QueueEntry,
+    buffer_pct: u64,
+    gas_limit: Option<U256>,
+    timeout: Option<Duration>,
+) {
+    let tx = TypedTransaction::from(entry.tx.clone());
+    let estimate = client.estimate_gas(&tx, None);
+    let estimate = match timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, estimate).await {
+            Ok(estimate) => estimate.map_err(|e| e.to_string()),
+            Err(_) => Err(format!("estimate took over {timeout:?}")),
+        },
+        None => estimate.await.map_err(|e| e.to_string()),
+    };
+    let gas = match estimate {
+        Ok(gas) => {
+            entry.estimate_error = None;
+            Some(gas)
+        }
+        Err(e) => {
+            entry.estimate_error = Some(e);
+            gas_estimate_from_trace(entry)
+        }
+    };
+    entry.tx.gas = gas.map(|gas| buffered_gas(gas, buffer_pct, gas_limit));
+}
+
+// `gas` plus `buffer_pct` percent, at most `gas_limit`.
+pub(crate) fn buffered_gas(gas: U256, buffer_pct: u64, gas_limit: Option<U256>) -> U256 {
+    let gas = gas.saturating_add(gas.saturating_mul(U256::from(buffer_pct)) / 100);
+    gas_limit.map_or(gas, |gas_limit| gas.min(gas_limit))
+}
+
+// The gas limit `tx` needs from the top level call of its trace, `None` if it reverted there.
+fn traced_gas(tx: &TransactionRequest, trace: &BlockTrace) -> Option<U256> {
+    let origin_call = trace
+        .trace
+        .iter()
+        .flatten()
+        .find(|call| call.trace_address.is_empty())?;
+    if origin_call.error.is_some() {
+        return None;
+    }
+    Some(gas_limit_for(tx, trace_gas_used(origin_call)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        decode_raw_tx,
+        mock::{block_trace, call_trace, mock_client, opportunity},
+        Opportunity, Simulate, SimulateError,
+    };
+    use super::{QueueEntry, TxQueue};
+    use ethers::{
+        prelude::*,
+        types::transaction::{
+            eip2718::TypedTransaction,
+            eip2930::{AccessList, AccessListItem},
+        },
+    };
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn fill_gas_add_buffer_fall_back_and_cap() {
+        let (provider, mock) = Provider::mocked();
+        let mut tx_queue = TxQueue {
+            entries: vec![
+                TransactionRequest::new().into(),
+                QueueEntry {
+                    trace_gas: Some(U256::from(50000)),
+                    ..TransactionRequest::new().into()
+                },
+                TransactionRequest::new().into(),
+                TransactionRequest::new().into(),
+            ],
+            ..Default::default()
+        };
+
+        mock.push(U256::from(40_000_000)).unwrap();
+        // Not a quantity, the estimation of the two middle entries fails.
+        mock.push("reverted").unwrap();
+        mock.push("reverted").unwrap();
+        mock.push(U256::from(100000)).unwrap();
+        // The node can't trace the queue, every entry is estimated alone.
+        mock.push("trace_callMany unsupported").unwrap();
+        mock.push(Block::<TxHash> {
+            gas_limit: U256::from(30_000_000),
+            ..Default::default()
+        })
+        .unwrap();
+
+        tx_queue.fill_gas(&provider, 10).await.unwrap();
+
+        let gas = tx_queue
+            .entries
+            .iter()
+            .map(|entry| entry.tx.gas)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            gas,
+            vec![
+                Some(U256::from(110000)),
+                Some(U256::from(55000)),
+                None,
+                Some(U256::from(30_000_000)),
+            ]
+        );
+        assert_eq!(tx_queue.estimate_failed().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn fill_gas_sequential_from_queue_trace() {
+        let (provider, mock) = Provider::mocked();
+        let mut tx_queue = TxQueue::from(vec![
+            TransactionRequest::new().to(Address::random()),
+            TransactionRequest::new().to(Address::random()),
+            TransactionRequest::new().to(Address::random()),
+        ]);
+        let traced = |gas_used: u64, error: Option<&str>| {
+            block_trace(
+                vec![TransactionTrace {
+                    result: Some(Res::Call(CallResult {
+                        gas_used: U256::from(gas_used),
+                        output: Bytes::default(),
+                    })),
+                    error: error.map(String::from),
+                    ..call_trace(vec![], 0, U256::zero())
+                }],
+                None,
+            )
+        };
+
+        // Estimated alone: the first entry on the latest state, the third as it reverts after
+        // the second.
+        mock.push(U256::from(200000)).unwrap();
+        mock.push(U256::from(100000)).unwrap();
+        mock.push(vec![
+            traced(70000, None),
+            traced(30000, None),
+            traced(0, Some("Reverted")),
+        ])
+        .unwrap();
+        mock.push(Block::<TxHash> {
+            gas_limit: U256::from(30_000_000),
+            ..Default::default()
+        })
+        .unwrap();
+
+        tx_queue.fill_gas(&provider, 10).await.unwrap();
+
+        let gas = tx_queue
+            .entries
+            .iter()
+            .map(|entry| entry.tx.gas)
+            .collect::<Vec<_>>();
+        // The 30000 the second call traced, its 21000 intrinsic gas and a quarter for the refund.
+        assert_eq!(
+            gas,
+            vec![
+                Some(U256::from(110000)),
+                Some(U256::from(70125)),
+                Some(U256::from(220000)),
+            ]
+        );
+        assert_eq!(tx_queue.estimate_failed().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn create_access_lists_keep_revert_error() {
+        let (provider, mock) = Provider::mocked();
+        let token = Address::random();
+        let mut tx_queue = TxQueue::from(vec![
+            TransactionRequest::new().to(token),
+            TransactionRequest::new().to(token),
+        ]);
+        let access_list = AccessList(vec![AccessListItem {
+            address: token,
+            storage_keys: vec![H256::zero()],
+        }]);
+
+        mock.push(json!({
+            "accessList": [],
+            "gasUsed": "0x5208",
+            "error": "execution reverted",
+        }))
+        .unwrap();
+        mock.push(json!({ "accessList": access_list, "gasUsed": "0x186a0" }))
+            .unwrap();
+
+        tx_queue
+            .create_access_lists(&provider, Some(BlockNumber::Number(U64::from(99))))
+            .await
+            .unwrap();
+
+        let entry = &tx_queue.entries[0];
+        assert_eq!(entry.access_list, Some(access_list.clone()));
+        assert_eq!(entry.access_list_gas, Some(U256::from(100000)));
+        assert_eq!(
+            entry.typed_tx(),
+            TypedTransaction::Eip2930(entry.tx.clone().with_access_list(access_list))
+        );
+
+        let entry = &tx_queue.entries[1];
+        assert_eq!(entry.access_list, None);
+        assert_eq!(
+            entry.access_list_error.as_deref(),
+            Some("execution reverted")
+        );
+    }
+
+    #[tokio::test]
+    async fn sign_all_recover_signer() {
+        let (client, _) = mock_client();
+        let mut tx_queue = TxQueue::from(vec![
+            TransactionRequest::new()
+                .to(Address::random())
+                .nonce(3)
+                .gas(100000)
+                .gas_price(10)
+                .value(0)
+                .data(vec![0, 0, 0, 1]),
+            TransactionRequest::new()
+                .to(Address::random())
+                .nonce(4)
+                .gas(100000)
+                .value(0)
+                .data(vec![0, 0, 0, 2]),
+        ]);
+        tx_queue.entries[1].max_fee_per_gas = Some(U256::from(100));
+        tx_queue.entries[1].max_priority_fee_per_gas = Some(U256::from(2));
+
+        let raw_tx_list = tx_queue.sign_all(&client).await.unwrap();
+        assert_eq!(raw_tx_list.len(), 2);
+        for (raw, nonce) in raw_tx_list.iter().zip([3, 4]) {
+            let tx = decode_raw_tx(raw).unwrap();
+            assert_eq!(tx.from, client.signer().address());
+            assert_eq!(tx.nonce, U256::from(nonce));
+            assert_eq!(tx.chain_id, Some(U256::one()));
+        }
+
+        tx_queue.entries[1].tx.nonce = None;
+        assert!(matches!(
+            tx_queue.sign_all(&client).await,
+            Err(SimulateError::Signing { index: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn to_typed_pick_tx_type_of_chain() {
+        let access_list = AccessList(vec![AccessListItem {
+            address: Address::random(),
+            storage_keys: vec![H256::random()],
+        }]);
+        let tx_queue = TxQueue {
+            entries: vec![
+                QueueEntry {
+                    max_fee_per_gas: Some(U256::from(100)),
+                    max_priority_fee_per_gas: Some(U256::from(2)),
+                    access_list: Some(access_list.clone()),
+                    ..TransactionRequest::new().nonce(1).into()
+                },
+                QueueEntry {
+                    access_list: Some(access_list.clone()),
+                    ..TransactionRequest::new().nonce(2).gas_price(10).into()
+                },
+            ],
+            ..Default::default()
+        };
+
+        let typed = tx_queue.to_typed(Chain::Mainnet);
+        match &typed[0] {
+            TypedTransaction::Eip1559(tx) => {
+                assert_eq!(tx.max_fee_per_gas, Some(U256::from(100)));
+                assert_eq!(tx.access_list, access_list);
+            }
+            tx => panic!("expected a 1559 tx, got {tx:?}"),
+        }
+        assert_eq!(typed[1].access_list(), Some(&access_list));
+
+        // No base fee, both go out as legacy at their max fee or gas price.
+        let typed = tx_queue.to_typed(Chain::BinanceSmartChain);
+        assert!(
+            matches!(&typed[0], TypedTransaction::Legacy(tx) if tx.gas_price == Some(U256::from(100)))
+        );
+        assert!(
+            matches!(&typed[1], TypedTransaction::Legacy(tx) if tx.gas_price == Some(U256::from(10)))
+        );
+    }
+
+    #[tokio::test]
+    async fn send_queue_refuse_after_deadline() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None)
+            .await
+            .unwrap()
+            .validity_horizon(2);
+        let opportunity = Opportunity {
+            block: Some(BlockNumber::Number(U64::from(100))),
+            ..opportunity(block_trace(vec![call_trace(vec![], 0, U256::zero())], None))
+        };
+        let (_, mut tx_queue) = simulate.tx_queues(&opportunity).unwrap().remove(0);
+        assert_eq!(tx_queue.valid_until_block, Some(U64::from(102)));
+        assert_eq!(tx_queue.opportunity_id, Some(opportunity.id));
+        tx_queue.entries[0].tx = tx_queue.entries[0]
+            .tx
+            .clone()
+            .nonce(0)
+            .gas(100000)
+            .gas_price(1);
+
+        let tx_hash = TxHash::random();
+        mock.push(tx_hash).unwrap();
+        mock.push(U64::from(101)).unwrap();
+        assert!(!tx_queue.is_expired(U64::from(101)));
+        assert_eq!(simulate.send_queue(&tx_queue).await.unwrap(), vec![tx_hash]);
+
+        // Block 102 is mined, no block is left to include the queue in.
+        mock.push(U64::from(102)).unwrap();
+        assert!(matches!(
+            simulate.send_queue(&tx_queue).await,
+            Err(SimulateError::QueueExpired { .. })
+        ));
+    }
+
+    #[test]
+    fn enforce_gas_budget_keep_required_entries() {
+        let entry = |gas: u64, required| QueueEntry {
+            required,
+            ..TransactionRequest::new().gas(gas).into()
+        };
+        let mut tx_queue = TxQueue {
+            entries: vec![
+                entry(50000, true),
+                entry(300000, false),
+                entry(100000, false),
+                entry(200000, false),
+            ],
+            ..Default::default()
+        };
+
+        // The heaviest optional entry goes first and is enough.
+        let budget = tx_queue
+            .clone()
+            .enforce_gas_budget(U256::from(400000))
+            .unwrap();
+        assert!(!budget.over_budget);
+        assert_eq!(budget.total_gas, U256::from(350000));
+        assert_eq!(budget.removed, vec![tx_queue.entries[1].clone()]);
+
+        // Only the required approval is left, still over.
+        let budget = tx_queue.enforce_gas_budget(U256::from(40000)).unwrap();
+        assert!(budget.over_budget);
+        assert_eq!(budget.excess, U256::from(10000));
+        assert_eq!(budget.removed.len(), 3);
+        assert_eq!(tx_queue.entries, vec![entry(50000, true)]);
+    }
+
+    #[test]
+    fn enforce_gas_budget_trim_by_net_flow() {
+        let entry = |nonce: u64, gas: u64, net_flow: i64| QueueEntry {
+            net_flow: Some(I256::from(net_flow)),
+            ..TransactionRequest::new().nonce(nonce).gas(gas).into()
+        };
+        let mut tx_queue = TxQueue {
+            entries: vec![
+                entry(3, 100000, -50),
+                entry(4, 300000, 1000),
+                entry(5, 50000, 0),
+                entry(6, 200000, -10),
+            ],
+            ..Default::default()
+        };
+
+        // The one losing the most goes first though it's light, then the next loser. The heaviest
+        // takes the profit and stays.
+        let budget = tx_queue.enforce_gas_budget(U256::from(400000)).unwrap();
+        assert!(!budget.over_budget);
+        assert_eq!(budget.total_gas, U256::from(350000));
+        assert_eq!(budget.removed.len(), 2);
+        assert_eq!(
+            tx_queue
+                .entries
+                .iter()
+                .map(|entry| (entry.tx.nonce, entry.net_flow))
+                .collect::<Vec<_>>(),
+            vec![
+                (Some(U256::from(3)), Some(I256::from(1000))),
+                (Some(U256::from(4)), Some(I256::zero())),
+            ]
+        );
+
+        // Not even the zero-flow entry gets it under, the profit taker isn't dropped for it.
+        let budget = tx_queue.enforce_gas_budget(U256::from(100000)).unwrap();
+        assert!(budget.over_budget);
+        assert_eq!(budget.excess, U256::from(200000));
+        assert_eq!(tx_queue.entries.len(), 1);
+        assert_eq!(tx_queue.entries[0].tx.nonce, Some(U256::from(3)));
+    }
+
+    fn nonces(tx_queue: &TxQueue) -> Vec<Option<U256>> {
+        tx_queue
+            .entries
+            .iter()
+            .map(|entry| entry.tx.nonce)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn assign_nonces_across_queues() {
+        let (provider, mock) = Provider::mocked();
+        let from = Address::random();
+        let mut tx_queues = vec![
+            TxQueue::from(vec![TransactionRequest::new().from(from)]),
+            TxQueue::from(vec![
+                TransactionRequest::new().from(from),
+                TransactionRequest::new().from(from),
+            ]),
+        ];
+
+        mock.push(U256::from(5)).unwrap();
+        let next_nonce = TxQueue::assign_nonces_all(&mut tx_queues, &provider)
+            .await
+            .unwrap();
+
+        assert_eq!(next_nonce, U256::from(8));
+        assert_eq!(nonces(&tx_queues[0]), vec![Some(U256::from(5))]);
+        assert_eq!(
+            nonces(&tx_queues[1]),
+            vec![Some(U256::from(6)), Some(U256::from(7))]
+        );
+        mock.assert_request("eth_getTransactionCount", (from, "pending"))
+            .unwrap();
+    }
+
+    #[test]
+    fn mark_revertible_skip_required_entries() {
+        let mut tx_queue =
+            TxQueue::from(vec![TransactionRequest::new(), TransactionRequest::new()]);
+        tx_queue.entries[0].required = true;
+
+        assert!(!tx_queue.mark_revertible(0));
+        assert!(tx_queue.mark_revertible(1));
+        assert!(!tx_queue.mark_revertible(2));
+        assert_eq!(tx_queue.revertible(), vec![1]);
+    }
+
+    #[test]
+    fn repair_nonce_gap_rebase_later_entries() {
+        let from = Address::random();
+        let mut tx_queue = TxQueue::from(
+            (5..9)
+                .map(|nonce| TransactionRequest::new().from(from).nonce(nonce))
+                .collect::<Vec<_>>(),
+        );
+
+        let removed = tx_queue.repair_nonce_gap(1).unwrap();
+        assert_eq!(removed.tx.nonce, Some(U256::from(6)));
+        assert_eq!(
+            nonces(&tx_queue),
+            vec![
+                Some(U256::from(5)),
+                Some(U256::from(6)),
+                Some(U256::from(7))
+            ]
+        );
+        assert!(tx_queue.repair_nonce_gap(3).is_none());
+    }
+
+    #[test]
+    fn repair_nonce_gap_all_across_queues() {
+        let (from, other) = (Address::random(), Address::random());
+        let queue = |nonces: std::ops::Range<u64>| {
+            TxQueue::from(
+                nonces
+                    .map(|nonce| TransactionRequest::new().from(from).nonce(nonce))
+                    .collect::<Vec<_>>(),
+            )
+        };
+        // Nonces continued from the first queue to the second, another sender in between.
+        let mut queues = vec![queue(5..7), queue(7..9)];
+        queues[0]
+            .entries
+            .push(TransactionRequest::new().from(other).nonce(9).into());
+
+        let removed = TxQueue::repair_nonce_gap_all(&mut queues, 0, 0).unwrap();
+        assert_eq!(removed.tx.nonce, Some(U256::from(5)));
+        assert_eq!(
+            nonces(&queues[0]),
+            vec![Some(U256::from(5)), Some(U256::from(9))]
+        );
+        assert_eq!(
+            nonces(&queues[1]),
+            vec![Some(U256::from(6)), Some(U256::from(7))]
+        );
+
+        // The last of the sender's entries, nothing after it shifts.
+        let removed = TxQueue::repair_nonce_gap_all(&mut queues, 1, 1).unwrap();
+        assert_eq!(removed.tx.nonce, Some(U256::from(7)));
+        assert_eq!(nonces(&queues[1]), vec![Some(U256::from(6))]);
+        assert!(TxQueue::repair_nonce_gap_all(&mut queues, 2, 0).is_none());
+    }
+}
@@ -0,0 +1,704 @@
+use super::{
+    decode_raw_tx, gas::trace_gas_used, Opportunity, ProfitReport, Simulate, SimulateError,
+    SimulateTarget, TxQueue,
+};
+use crate::utils::BundleOptions;
+use ethers::{
+    abi::{self, ParamType, Token},
+    prelude::*,
+    types::transaction::eip2718::TypedTransaction,
+    utils::keccak256,
+};
+use std::time::Instant;
+use tracing::warn;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verification {
+    // Native balance change of the signer and the contract over the whole tx list.
+    pub profit: I256,
+    pub gas_used: U256,
+    // Index and error of the first reverted tx, the decoded revert reason if it returned one.
+    pub reverted: Option<(usize, String)>,
+    // The same for every revertible tx that reverted, they don't fail the tx list.
+    pub warnings: Vec<(usize, String)>,
+}
+
+impl Verification {
+    pub fn is_success(&self) -> bool {
+        self.reverted.is_none()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueueOutcome {
+    Success { profit: I256, gas_used: U256 },
+    // The first tx of the queue that reverted, the later ones ran on its state.
+    Reverted { index: usize, reason: String },
+}
+
+impl From<Verification> for QueueOutcome {
+    fn from(verification: Verification) -> Self {
+        match verification.reverted {
+            Some((index, reason)) => Self::Reverted { index, reason },
+            None => Self::Success {
+                profit: verification.profit,
+                gas_used: verification.gas_used,
+            },
+        }
+    }
+}
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    // Replay the tx list in order on the same state, each tx sees the changes of the previous ones.
+    pub async fn verify(
+        &self,
+        tx_list: &[TransactionRequest],
+        block: Option<BlockNumber>,
+    ) -> Result<Verification, SimulateError> {
+        self.verify_revertible(tx_list, block, &[]).await
+    }
+
+    // `verify` where the txs at `revertible` may revert, see `QueueEntry::revertible`.
+    pub(crate) async fn verify_revertible(
+        &self,
+        tx_list: &[TransactionRequest],
+        block: Option<BlockNumber>,
+        revertible: &[usize],
+    ) -> Result<Verification, SimulateError> {
+        let traces = self
+            .tracer()
+            .trace_call_many(
+                tx_list
+                    .iter()
+                    .map(|tx| {
+                        (
+                            tx.clone().into(),
+                            vec![TraceType::Trace, TraceType::StateDiff],
+                        )
+                    })
+                    .collect(),
+                block,
+            )
+            .await?;
+
+        let mut receivers = vec![self.signer_address];
+        if let Some(contract) = self.contract.filter(|c| !receivers.contains(c)) {
+            receivers.push(contract);
+        }
+
+        let mut verification = Verification {
+            profit: I256::zero(),
+            gas_used: U256::zero(),
+            reverted: None,
+            warnings: vec![],
+        };
+        for (i, trace) in traces.iter().enumerate() {
+            let origin_call = trace
+                .trace
+                .iter()
+                .flatten()
+                .find(|t| t.trace_address.is_empty());
+            if let Some(origin_call) = origin_call {
+                verification.gas_used += trace_gas_used(origin_call);
+                if let Some(error) = &origin_call.error {
+                    let reason = revert_reason(&trace.output).unwrap_or_else(|| error.clone());
+                    if revertible.contains(&i) {
+                        verification.warnings.push((i, reason));
+                    } else if verification.reverted.is_none() {
+                        verification.reverted = Some((i, reason));
+                    }
+                }
+            }
+            if let Some(state_diff) = &trace.state_diff {
+                for receiver in &receivers {
+                    if let Some(account_diff) = state_diff.0.get(receiver) {
+                        verification.profit += balance_delta(&account_diff.balance);
+                    }
+                }
+            }
+        }
+
+        Ok(verification)
+    }
+
+    // Replay the queue as it would be sent now, on the latest state, and whether it lands as a
+    // whole. Its revertible entries may revert.
+    pub async fn simulate_queue(&self, queue: &TxQueue) -> Result<QueueOutcome, SimulateError> {
+        Ok(self
+            .verify_revertible(&queue.tx_list(), None, &queue.revertible())
+            .await?
+            .into())
+    }
+
+    // The check before the queue is sent for `options.target_block`: replayed on the parent state
+    // behind the victim (when backrunning), no entry may revert that `options` doesn't allow to
+    // and that isn't revertible, and the coinbase must gain at least `bribe` from the queue.
+    // `GateRefused` with what failed. A victim (the backrun tx or the queue's) mined already is
+    // left out, the queue then has to pass on the state after it or is `VictimAlreadyIncluded`.
+    pub async fn gate_bundle(
+        &self,
+        queue: &TxQueue,
+        options: &BundleOptions,
+        bribe: U256,
+    ) -> Result<(), SimulateError> {
+        let parent = options.target_block.saturating_sub(U64::one());
+        let victim_hash = options
+            .backrun
+            .as_ref()
+            .map(|raw_tx| H256(keccak256(raw_tx)))
+            .or(queue.victim);
+        let included = match victim_hash {
+            Some(victim_hash) => self
+                .get_transaction_receipt(victim_hash)
+                .await
+                .map_err(SimulateError::middleware)?
+                .and_then(|receipt| receipt.block_number),
+            None => None,
+        };
+        let refuse = |reason: String| match included {
+            Some(block) => SimulateError::VictimAlreadyIncluded { block, reason },
+            None => SimulateError::GateRefused {
+                block: parent,
+                reason,
+            },
+        };
+        // Relays reject a bundle with a mined tx.
+        let backrun = options.backrun.as_ref().filter(|_| included.is_none());
+        let victim = backrun.map(decode_raw_tx).transpose()?;
+        let trace_type = vec![TraceType::Trace, TraceType::StateDiff];
+        let tx_list = victim
+            .iter()
+            .map(TypedTransaction::from)
+            .chain(queue.tx_list().into_iter().map(TypedTransaction::from))
+            .map(|tx| (tx, trace_type.clone()))
+            .collect();
+        let traces = self
+            .tracer()
+            .trace_call_many(tx_list, Some(parent.into()))
+            .await?;
+        let coinbase = self
+            .get_block(parent)
+            .await
+            .map_err(SimulateError::middleware)?
+            .ok_or_else(|| refuse("block not found".into()))?
+            .author
+            .unwrap_or_default();
+
+        // A short or long answer would put the traces on the wrong entries.
+        let expected = queue.entries.len() + usize::from(victim.is_some());
+        if traces.len() != expected {
+            return Err(SimulateError::TraceCountMismatch {
+                expected,
+                traces: traces.len(),
+            });
+        }
+
+        let mut coinbase_diff = I256::zero();
+        for (i, trace) in traces.iter().enumerate() {
+            // `None` for the victim.
+            let index = i.checked_sub(usize::from(victim.is_some()));
+            let origin_error = trace
+                .trace
+                .iter()
+                .flatten()
+                .find(|t| t.trace_address.is_empty())
+                .and_then(|origin_call| origin_call.error.clone());
+            if let Some(error) = origin_error {
+                let reason = revert_reason(&trace.output).unwrap_or(error);
+                match index {
+                    None => return Err(refuse(format!("victim tx reverts: {reason}"))),
+                    Some(index)
+                        if queue
+                            .entries
+                            .get(index)
+                            .map_or(false, |entry| entry.revertible) =>
+                    {
+                        warn!(index, %reason, "revertible entry reverts on the parent state");
+                    }
+                    Some(index) if backrun.is_none() && !options.reverting.contains(&index) => {
+                        return Err(refuse(format!("entry {index} reverts: {reason}")));
+                    }
+                    Some(_) => {}
+                }
+            }
+            let coinbase_balance = trace
+                .state_diff
+                .as_ref()
+                .and_then(|state_diff| state_diff.0.get(&coinbase));
+            if let (Some(_), Some(account_diff)) = (index, coinbase_balance) {
+                coinbase_diff += balance_delta(&account_diff.balance);
+            }
+        }
+        if coinbase_diff < I256::from_raw(bribe) {
+            return Err(refuse(format!(
+                "coinbase {coinbase:?} gains {coinbase_diff}, less than the bribe {bribe}"
+            )));
+        }
+
+        Ok(())
+    }
+
+    // The profit of the queue replayed on the state of each of `blocks`, in order, `None` where it
+    // reverts or nets nothing. Shows how long an opportunity outlives base fee and pool drift.
+    pub async fn forecast(
+        &self,
+        queue: &TxQueue,
+        blocks: &[BlockNumber],
+    ) -> Result<Vec<Option<U256>>, SimulateError> {
+        let tx_list = queue.tx_list();
+        let mut profits = Vec::with_capacity(blocks.len());
+        for block in blocks {
+            let verification = self.verify(&tx_list, Some(*block)).await?;
+            profits.push(
+                (verification.is_success() && verification.profit > I256::zero())
+                    .then(|| verification.profit.into_raw()),
+            );
+        }
+        Ok(profits)
+    }
+
+    // How far, in basis points of the analyzed profit, the verified profit may be off in `run_verified`.
+    pub fn verify_tolerance_bps(mut self, verify_tolerance_bps: u64) -> Self {
+        self.verify_tolerance_bps = verify_tolerance_bps;
+        self
+    }
+
+    // `run` then `verify` every tx list of the queue on the traced block. The opportunity is only
+    // returned if the best successful tx list earns the analyzed profit, within the tolerance,
+    // refused with the first revert if none succeeds.
+    pub async fn run_verified(
+        &self,
+        tx_hash: TxHash,
+        target: impl Into<SimulateTarget>,
+    ) -> Result<Option<Opportunity>, SimulateError> {
+        let mut opportunity = match self.run(tx_hash, target).await? {
+            Some(opportunity) => opportunity,
+            None => return Ok(None),
+        };
+
+        let start = Instant::now();
+        let mut verified: Option<I256> = None;
+        let mut first_revert = None;
+        for (list, tx_list) in opportunity.tx_queue.iter().enumerate() {
+            let verification = self.verify(tx_list, opportunity.block).await?;
+            match verification.reverted {
+                Some((index, reason)) => {
+                    first_revert.get_or_insert(format!("tx list {list} entry {index}: {reason}"));
+                }
+                None => {
+                    verified =
+                        Some(verified.map_or(verification.profit, |v| v.max(verification.profit)))
+                }
+            }
+        }
+        opportunity.timings.verify = start.elapsed();
+        opportunity.timings.round_trips += opportunity.tx_queue.len() as u32;
+
+        let verified = verified.ok_or_else(|| match first_revert {
+            Some(first_revert) => SimulateError::EveryQueueReverts(first_revert),
+            None => SimulateError::NoQueue,
+        })?;
+        let analyzed = ProfitReport::total_native(&opportunity.reports);
+        let tolerance = analyzed
+            .checked_mul(U256::from(self.verify_tolerance_bps))
+            .ok_or(SimulateError::Overflow("verify tolerance"))?
+            / 10000;
+        let deviation = verified
+            .checked_sub(I256::from_raw(analyzed))
+            .ok_or(SimulateError::Overflow("verified profit deviation"))?;
+        if deviation.unsigned_abs() > tolerance {
+            return Err(SimulateError::VerificationMismatch { analyzed, verified });
+        }
+
+        Ok(Some(opportunity))
+    }
+}
+
+// `Error(string)` and `Panic(uint256)` of a reverted call's return data.
+pub(crate) fn revert_reason(output: &Bytes) -> Option<String> {
+    let (selector, data) = (output.get(..4)?, &output[4..]);
+    match selector {
+        [0x08, 0xc3, 0x79, 0xa0] => match abi::decode(&[ParamType::String], data).ok()?.pop()? {
+            Token::String(reason) => Some(reason),
+            _ => None,
+        },
+        [0x4e, 0x48, 0x7b, 0x71] => match abi::decode(&[ParamType::Uint(256)], data).ok()?.pop()? {
+            Token::Uint(code) => Some(format!("panic {code:#x}")),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+pub(crate) fn balance_delta(diff: &Diff<U256>) -> I256 {
+    match diff {
+        Diff::Same => I256::zero(),
+        Diff::Born(to) => I256::from_raw(*to),
+        Diff::Died(from) => -I256::from_raw(*from),
+        Diff::Changed(ChangedType { from, to }) => I256::from_raw(*to) - I256::from_raw(*from),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        mock::{balance_diff, block_trace, call_trace, mock_client},
+        Simulate, SimulateError, TxQueue,
+    };
+    use super::QueueOutcome;
+    use crate::utils::BundleOptions;
+    use ethers::{
+        abi::{self, Token},
+        prelude::*,
+        types::transaction::eip2718::TypedTransaction,
+        utils::keccak256,
+    };
+    use std::collections::BTreeMap;
+
+    #[tokio::test]
+    async fn verify_sum_profit_and_report_first_revert() {
+        let (client, mock) = mock_client();
+        let contract = Address::random();
+        let simulate = Simulate::init(&client, Some(contract)).await.unwrap();
+
+        let mut first_call = call_trace(vec![], 0, U256::zero());
+        first_call.result = Some(Res::Call(CallResult {
+            gas_used: U256::from(30000),
+            output: Bytes::default(),
+        }));
+        let mut second_call = call_trace(vec![], 0, U256::zero());
+        second_call.error = Some("Reverted".into());
+
+        let first = block_trace(
+            vec![first_call],
+            Some(StateDiff(BTreeMap::from([(
+                contract,
+                balance_diff(U256::from(10), U256::from(15)),
+            )]))),
+        );
+        let second = block_trace(
+            vec![second_call],
+            Some(StateDiff(BTreeMap::from([(
+                client.address(),
+                balance_diff(U256::from(10), U256::from(9)),
+            )]))),
+        );
+        mock.push::<Vec<BlockTrace>, _>(vec![first, second])
+            .unwrap();
+
+        let verification = simulate
+            .verify(
+                &[TransactionRequest::new(), TransactionRequest::new()],
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(verification.profit, I256::from(4));
+        assert_eq!(verification.gas_used, U256::from(30000));
+        assert_eq!(verification.reverted, Some((1, "Reverted".into())));
+        assert!(!verification.is_success());
+    }
+
+    #[tokio::test]
+    async fn simulate_queue_report_first_revert_reason() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+
+        let mut reverted_call = call_trace(vec![], 0, U256::zero());
+        reverted_call.error = Some("Reverted".into());
+        let mut reverted = block_trace(vec![reverted_call], None);
+        reverted.output = [
+            vec![0x08, 0xc3, 0x79, 0xa0],
+            abi::encode(&[Token::String("INSUFFICIENT_OUTPUT_AMOUNT".into())]),
+        ]
+        .concat()
+        .into();
+        let traces = vec![
+            block_trace(vec![call_trace(vec![], 0, U256::zero())], None),
+            reverted,
+            block_trace(vec![call_trace(vec![], 0, U256::zero())], None),
+        ];
+        mock.push::<Vec<BlockTrace>, _>(traces.clone()).unwrap();
+
+        let mut queue = TxQueue::from(vec![TransactionRequest::new(); 3]);
+        assert_eq!(
+            simulate.simulate_queue(&queue).await.unwrap(),
+            QueueOutcome::Reverted {
+                index: 1,
+                reason: "INSUFFICIENT_OUTPUT_AMOUNT".into(),
+            }
+        );
+
+        // A revertible entry's revert is only a warning.
+        queue.mark_revertible(1);
+        mock.push::<Vec<BlockTrace>, _>(traces.clone()).unwrap();
+        assert!(matches!(
+            simulate.simulate_queue(&queue).await.unwrap(),
+            QueueOutcome::Success { .. }
+        ));
+        mock.push::<Vec<BlockTrace>, _>(traces).unwrap();
+        let verification = simulate
+            .verify_revertible(&queue.tx_list(), None, &queue.revertible())
+            .await
+            .unwrap();
+        assert!(verification.is_success());
+        assert_eq!(
+            verification.warnings,
+            vec![(1, "INSUFFICIENT_OUTPUT_AMOUNT".into())]
+        );
+    }
+
+    #[tokio::test]
+    async fn forecast_profit_per_block_state() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let profit = |amount: u64| {
+            vec![block_trace(
+                vec![call_trace(vec![], 0, U256::zero())],
+                Some(StateDiff(BTreeMap::from([(
+                    client.address(),
+                    balance_diff(U256::from(1000), U256::from(1000 + amount)),
+                )]))),
+            )]
+        };
+        let mut reverted_call = call_trace(vec![], 0, U256::zero());
+        reverted_call.error = Some("Reverted".into());
+
+        mock.push::<Vec<BlockTrace>, _>(vec![block_trace(vec![reverted_call], None)])
+            .unwrap();
+        mock.push::<Vec<BlockTrace>, _>(profit(40)).unwrap();
+        mock.push::<Vec<BlockTrace>, _>(profit(100)).unwrap();
+
+        let queue = TxQueue::from(vec![TransactionRequest::new()]);
+        let blocks = [100, 101, 102].map(|block| BlockNumber::Number(U64::from(block)));
+        assert_eq!(
+            simulate.forecast(&queue, &blocks).await.unwrap(),
+            vec![Some(U256::from(100)), Some(U256::from(40)), None]
+        );
+        for block in blocks {
+            let tx_list = vec![(
+                TypedTransaction::from(TransactionRequest::new()),
+                vec![TraceType::Trace, TraceType::StateDiff],
+            )];
+            mock.assert_request("trace_callMany", (tx_list, block))
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn gate_bundle_refuse_revert_and_short_bribe() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let coinbase = Address::random();
+        let parent = Block::<TxHash> {
+            number: Some(U64::from(99)),
+            author: Some(coinbase),
+            ..Default::default()
+        };
+        let paid = |amount: u64| {
+            block_trace(
+                vec![call_trace(vec![], 0, U256::zero())],
+                Some(StateDiff(BTreeMap::from([(
+                    coinbase,
+                    balance_diff(U256::from(1000), U256::from(1000 + amount)),
+                )]))),
+            )
+        };
+        let mut reverted_call = call_trace(vec![], 0, U256::zero());
+        reverted_call.error = Some("Reverted".into());
+        let reverted = block_trace(vec![reverted_call], None);
+        let queue = TxQueue::from(vec![TransactionRequest::new(); 2]);
+        let options = BundleOptions::new(U64::from(100));
+
+        mock.push(parent.clone()).unwrap();
+        mock.push::<Vec<BlockTrace>, _>(vec![paid(30), paid(20)])
+            .unwrap();
+        simulate
+            .gate_bundle(&queue, &options, U256::from(50))
+            .await
+            .unwrap();
+        let tx_list = vec![
+            (
+                TypedTransaction::from(TransactionRequest::new()),
+                vec![TraceType::Trace, TraceType::StateDiff],
+            );
+            2
+        ];
+        mock.assert_request(
+            "trace_callMany",
+            (tx_list, BlockNumber::Number(U64::from(99))),
+        )
+        .unwrap();
+
+        mock.push(parent.clone()).unwrap();
+        mock.push::<Vec<BlockTrace>, _>(vec![paid(30), paid(10)])
+            .unwrap();
+        let err = simulate
+            .gate_bundle(&queue, &options, U256::from(50))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            SimulateError::GateRefused { block, reason }
+                if block == U64::from(99) && reason.contains("less than the bribe 50")
+        ));
+
+        // A short answer from the node.
+        mock.push(parent.clone()).unwrap();
+        mock.push::<Vec<BlockTrace>, _>(vec![paid(30)]).unwrap();
+        assert!(matches!(
+            simulate.gate_bundle(&queue, &options, U256::zero()).await,
+            Err(SimulateError::TraceCountMismatch {
+                expected: 2,
+                traces: 1
+            })
+        ));
+
+        // Only the entries `options` allow and the revertible ones may revert.
+        let mut revertible = queue.clone();
+        revertible.mark_revertible(1);
+        for (queue, options, refused) in [
+            (&queue, options.clone(), true),
+            (&queue, options.clone().reverting(1), false),
+            (&revertible, options, false),
+        ] {
+            mock.push(parent.clone()).unwrap();
+            mock.push::<Vec<BlockTrace>, _>(vec![paid(30), reverted.clone()])
+                .unwrap();
+            let gate = simulate.gate_bundle(queue, &options, U256::zero()).await;
+            assert_eq!(
+                matches!(gate, Err(SimulateError::GateRefused { reason, .. }) if reason == "entry 1 reverts: Reverted"),
+                refused
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn gate_bundle_replay_without_mined_victim() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let parent = Block::<TxHash> {
+            number: Some(U64::from(99)),
+            author: Some(Address::random()),
+            ..Default::default()
+        };
+        let mined = TransactionReceipt {
+            block_number: Some(U64::from(98)),
+            ..Default::default()
+        };
+        let victim_raw_tx = Bytes::from(vec![0xf8, 0x01]);
+        let queue = TxQueue::from(vec![TransactionRequest::new()]);
+        let options = BundleOptions::new(U64::from(100)).backrun(victim_raw_tx.clone());
+
+        // Still fine on top of the victim's block.
+        mock.push(parent.clone()).unwrap();
+        mock.push::<Vec<BlockTrace>, _>(vec![block_trace(
+            vec![call_trace(vec![], 0, U256::zero())],
+            None,
+        )])
+        .unwrap();
+        mock.push(mined.clone()).unwrap();
+        simulate
+            .gate_bundle(&queue, &options, U256::zero())
+            .await
+            .unwrap();
+        mock.assert_request(
+            "eth_getTransactionReceipt",
+            [H256(keccak256(&victim_raw_tx))],
+        )
+        .unwrap();
+        let tx_list = vec![(
+            TypedTransaction::from(TransactionRequest::new()),
+            vec![TraceType::Trace, TraceType::StateDiff],
+        )];
+        mock.assert_request(
+            "trace_callMany",
+            (tx_list, BlockNumber::Number(U64::from(99))),
+        )
+        .unwrap();
+
+        // Without the victim ahead the entry may no longer revert.
+        let mut reverted_call = call_trace(vec![], 0, U256::zero());
+        reverted_call.error = Some("Reverted".into());
+        mock.push(parent).unwrap();
+        mock.push::<Vec<BlockTrace>, _>(vec![block_trace(vec![reverted_call], None)])
+            .unwrap();
+        mock.push(mined).unwrap();
+        assert!(matches!(
+            simulate.gate_bundle(&queue, &options, U256::zero()).await,
+            Err(SimulateError::VictimAlreadyIncluded { block, reason })
+                if block == U64::from(98) && reason == "entry 0 reverts: Reverted"
+        ));
+    }
+
+    #[tokio::test]
+    async fn run_verified_reject_diverging_profit() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let tx = Transaction {
+            hash: TxHash::random(),
+            input: "0x00000001".parse().unwrap(),
+            ..Default::default()
+        };
+        let analyzed = block_trace(
+            vec![call_trace(vec![], 0, U256::zero())],
+            Some(StateDiff(BTreeMap::from([(
+                tx.from,
+                balance_diff(U256::zero(), U256::from(1000)),
+            )]))),
+        );
+        // The replay only earns a tenth of what the victim's trace showed.
+        let verified = block_trace(
+            vec![call_trace(vec![], 0, U256::zero())],
+            Some(StateDiff(BTreeMap::from([(
+                client.address(),
+                balance_diff(U256::zero(), U256::from(100)),
+            )]))),
+        );
+
+        mock.push::<Vec<BlockTrace>, _>(vec![verified]).unwrap();
+        mock.push(U256::one()).unwrap();
+        mock.push(analyzed).unwrap();
+        mock.push(tx.clone()).unwrap();
+
+        let err = simulate.run_verified(tx.hash, true).await.unwrap_err();
+        assert!(matches!(
+            err,
+            SimulateError::VerificationMismatch { analyzed, verified }
+                if analyzed == U256::from(1000) && verified == I256::from(100)
+        ));
+    }
+
+    #[tokio::test]
+    async fn run_verified_refuse_when_every_list_reverts() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let tx = Transaction {
+            hash: TxHash::random(),
+            input: "0x00000001".parse().unwrap(),
+            ..Default::default()
+        };
+        let analyzed = block_trace(
+            vec![call_trace(vec![], 0, U256::zero())],
+            Some(StateDiff(BTreeMap::from([(
+                tx.from,
+                balance_diff(U256::zero(), U256::from(1000)),
+            )]))),
+        );
+        let mut reverted_call = call_trace(vec![], 0, U256::zero());
+        reverted_call.error = Some("Reverted".into());
+
+        mock.push::<Vec<BlockTrace>, _>(vec![block_trace(vec![reverted_call], None)])
+            .unwrap();
+        mock.push(U256::one()).unwrap();
+        mock.push(analyzed).unwrap();
+        mock.push(tx.clone()).unwrap();
+
+        // Not a mismatch against a profit of 0, the replay never got that far.
+        let err = simulate.run_verified(tx.hash, true).await.unwrap_err();
+        assert!(matches!(
+            err,
+            SimulateError::EveryQueueReverts(first) if first.starts_with("tx list 0 entry 0")
+        ));
+    }
+}
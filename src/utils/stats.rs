@@ -0,0 +1,222 @@
+use crate::utils::{BundleSubmitter, Relay, SimulateError};
+use ethers::prelude::*;
+use serde_json::{json, Value};
+use url::Url;
+
+// When a builder considered or sealed a bundle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuilderTimestamp {
+    pub pubkey: String,
+    pub timestamp: String,
+}
+
+// The relay's view of a bundle, from `flashbots_getBundleStatsV2`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BundleStats {
+    pub is_high_priority: bool,
+    pub is_simulated: bool,
+    // RFC 3339 timestamps, as the relay sends them.
+    pub received_at: Option<String>,
+    pub simulated_at: Option<String>,
+    pub considered_by_builders_at: Vec<BuilderTimestamp>,
+    pub sealed_by_builders_at: Vec<BuilderTimestamp>,
+}
+
+// The reputation of the signing address, from `flashbots_getUserStatsV2`. Payments are in wei.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UserStats {
+    pub is_high_priority: bool,
+    pub all_time_validator_payments: U256,
+    pub all_time_gas_simulated: U256,
+    pub last_7d_validator_payments: U256,
+    pub last_7d_gas_simulated: U256,
+    pub last_1d_validator_payments: U256,
+    pub last_1d_gas_simulated: U256,
+}
+
+fn flag(result: &Value, name: &str) -> bool {
+    result[name].as_bool().unwrap_or_default()
+}
+
+fn text(result: &Value, name: &str) -> Option<String> {
+    result[name].as_str().map(String::from)
+}
+
+// The relay sends amounts as decimal strings, some as hex ones.
+fn amount(result: &Value, name: &str) -> Result<U256, SimulateError> {
+    let invalid = || SimulateError::StatsDecode(format!("{name} is {}", result[name]));
+    match &result[name] {
+        Value::String(amount) => match amount.strip_prefix("0x") {
+            Some(hex) => U256::from_str_radix(hex, 16).map_err(|_| invalid()),
+            None => U256::from_dec_str(amount).map_err(|_| invalid()),
+        },
+        Value::Number(amount) => amount.as_u64().map(U256::from).ok_or_else(invalid),
+        _ => Ok(U256::zero()),
+    }
+}
+
+fn builder_timestamps(result: &Value, name: &str) -> Vec<BuilderTimestamp> {
+    result[name]
+        .as_array()
+        .map(|builders| {
+            builders
+                .iter()
+                .filter_map(|builder| {
+                    Some(BuilderTimestamp {
+                        pubkey: text(builder, "pubkey")?,
+                        timestamp: text(builder, "timestamp")?,
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+impl BundleStats {
+    pub fn from_json(result: &Value) -> Result<Self, SimulateError> {
+        if !result.is_object() {
+            return Err(SimulateError::StatsDecode(format!(
+                "bundle stats aren't an object: {result}"
+            )));
+        }
+        Ok(Self {
+            is_high_priority: flag(result, "isHighPriority"),
+            is_simulated: flag(result, "isSimulated"),
+            received_at: text(result, "receivedAt"),
+            simulated_at: text(result, "simulatedAt"),
+            considered_by_builders_at: builder_timestamps(result, "consideredByBuildersAt"),
+            sealed_by_builders_at: builder_timestamps(result, "sealedByBuildersAt"),
+        })
+    }
+}
+
+impl UserStats {
+    pub fn from_json(result: &Value) -> Result<Self, SimulateError> {
+        if !result.is_object() {
+            return Err(SimulateError::StatsDecode(format!(
+                "user stats aren't an object: {result}"
+            )));
+        }
+        Ok(Self {
+            is_high_priority: flag(result, "isHighPriority"),
+            all_time_validator_payments: amount(result, "allTimeValidatorPayments")?,
+            all_time_gas_simulated: amount(result, "allTimeGasSimulated")?,
+            last_7d_validator_payments: amount(result, "last7dValidatorPayments")?,
+            last_7d_gas_simulated: amount(result, "last7dGasSimulated")?,
+            last_1d_validator_payments: amount(result, "last1dValidatorPayments")?,
+            last_1d_gas_simulated: amount(result, "last1dGasSimulated")?,
+        })
+    }
+}
+
+impl<A: Signer> BundleSubmitter<A> {
+    // The stats of a bundle sent for `block`, asked at `relay`, the relay that accepted it: an
+    // other relay doesn't know the bundle. Relays fill them in some time after the bundle came
+    // in, an error until then.
+    pub async fn bundle_stats(
+        &self,
+        relay: &Url,
+        bundle_hash: H256,
+        block: U64,
+    ) -> Result<BundleStats, SimulateError> {
+        let relay = self
+            .flashbots_relays()
+            .find(|flashbots| flashbots.url == *relay)
+            .ok_or_else(|| SimulateError::NoStatsRelay {
+                relay: Some(relay.clone()),
+            })?;
+        let params = json!([{ "bundleHash": bundle_hash, "blockNumber": block }]);
+        let result = self
+            .stats_request(relay, "flashbots_getBundleStatsV2", params)
+            .await?;
+        BundleStats::from_json(&result)
+    }
+
+    // The stats of the reputation key the requests are signed with, as of `block`, asked at the
+    // first relay of the flashbots dialect.
+    pub async fn user_stats(&self, block: U64) -> Result<UserStats, SimulateError> {
+        let relay = self
+            .flashbots_relays()
+            .next()
+            .ok_or(SimulateError::NoStatsRelay { relay: None })?;
+        let params = json!([{ "blockNumber": block }]);
+        let result = self
+            .stats_request(relay, "flashbots_getUserStatsV2", params)
+            .await?;
+        UserStats::from_json(&result)
+    }
+
+    async fn stats_request(
+        &self,
+        relay: &Relay,
+        method: &str,
+        params: Value,
+    ) -> Result<Value, SimulateError> {
+        self.request(relay, method, params)
+            .await
+            .map_err(|outcome| SimulateError::StatsRequest {
+                method: method.into(),
+                outcome,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BuilderTimestamp, BundleStats, UserStats};
+    use ethers::prelude::*;
+    use serde_json::json;
+
+    #[test]
+    fn stats_from_relay_json() {
+        let bundle_stats = BundleStats::from_json(&json!({
+            "isHighPriority": true,
+            "isSimulated": true,
+            "simulatedAt": "2022-10-06T21:36:06.317Z",
+            "receivedAt": "2022-10-06T21:36:06.250Z",
+            "consideredByBuildersAt": [
+                { "pubkey": "0x81babeec", "timestamp": "2022-10-06T21:36:06.343Z" }
+            ],
+            "sealedByBuildersAt": []
+        }))
+        .unwrap();
+        assert!(bundle_stats.is_simulated);
+        assert_eq!(
+            bundle_stats.considered_by_builders_at,
+            vec![BuilderTimestamp {
+                pubkey: "0x81babeec".into(),
+                timestamp: "2022-10-06T21:36:06.343Z".into(),
+            }]
+        );
+        // Never sealed, no builder put it in a block.
+        assert!(bundle_stats.sealed_by_builders_at.is_empty());
+
+        let user_stats = UserStats::from_json(&json!({
+            "isHighPriority": false,
+            "allTimeValidatorPayments": "1280749594841588639",
+            "allTimeGasSimulated": "30049470846",
+            "last7dValidatorPayments": "1280749594841588639",
+            "last7dGasSimulated": "30049470846",
+            "last1dValidatorPayments": "0x1f9921b9d3203f5",
+            "last1dGasSimulated": "2731770076"
+        }))
+        .unwrap();
+        assert!(!user_stats.is_high_priority);
+        assert_eq!(
+            user_stats.all_time_validator_payments,
+            U256::from(1_280_749_594_841_588_639_u64)
+        );
+        // Hex or decimal alike.
+        assert_eq!(
+            user_stats.last_1d_validator_payments,
+            U256::from(142_305_510_537_954_293_u64)
+        );
+        assert_eq!(
+            user_stats.last_1d_gas_simulated,
+            U256::from(2_731_770_076_u64)
+        );
+
+        assert!(BundleStats::from_json(&json!(null)).is_err());
+        assert!(UserStats::from_json(&json!({ "allTimeGasSimulated": "0xzz" })).is_err());
+    }
+}
@@ -0,0 +1,244 @@
+use crate::utils::{
+    Opportunity, ProfitReport, QueueEconomics, Reconciliation, SimulateError, SubmissionAttempt,
+    SubmissionReport,
+};
+use async_trait::async_trait;
+use ethers::{core::rand::random, prelude::*};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStore;
+
+// Crockford's base32, without I, L, O and U.
+const ULID_ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+// A ULID given to every simulated opportunity, carried by its queues and submission so they can
+// be joined later. Sorts by the millisecond it was made in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OpportunityId(u128);
+
+impl OpportunityId {
+    pub fn new() -> Self {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        Self::from_parts(timestamp_ms as u64, random())
+    }
+
+    // The 48 bits of the timestamp and the 80 random bits.
+    fn from_parts(timestamp_ms: u64, random: u128) -> Self {
+        let timestamp = u128::from(timestamp_ms) & ((1 << 48) - 1);
+        Self((timestamp << 80) | (random & ((1 << 80) - 1)))
+    }
+
+    pub fn timestamp_ms(&self) -> u64 {
+        (self.0 >> 80) as u64
+    }
+}
+
+impl Default for OpportunityId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for OpportunityId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let encoded = (0..26)
+            .rev()
+            .map(|i| ULID_ALPHABET[(self.0 >> (i * 5)) as usize & 31] as char)
+            .collect::<String>();
+        f.write_str(&encoded)
+    }
+}
+
+impl FromStr for OpportunityId {
+    type Err = SimulateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || SimulateError::Store(format!("invalid opportunity id {s:?}"));
+        // The first character only holds 3 bits.
+        if s.len() != 26 || !matches!(s.as_bytes()[0], b'0'..=b'7') {
+            return Err(invalid());
+        }
+        s.to_ascii_uppercase()
+            .bytes()
+            .try_fold(0_u128, |id, byte| {
+                let digit = ULID_ALPHABET.iter().position(|c| *c == byte)?;
+                Some((id << 5) | digit as u128)
+            })
+            .map(Self)
+            .ok_or_else(invalid)
+    }
+}
+
+// What the simulation of an opportunity found, recorded once it's built.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimulationSummary {
+    pub victim: Option<TxHash>,
+    // The block the trace was taken at, `None` for a pending tx traced on the latest state.
+    pub block: Option<U64>,
+    pub selector: [u8; 4],
+    // The native profit of the analysis.
+    pub expected_profit: U256,
+    pub queue_len: usize,
+}
+
+impl SimulationSummary {
+    pub fn new(opportunity: &Opportunity) -> Self {
+        Self {
+            victim: opportunity.victim,
+            block: match opportunity.block {
+                Some(BlockNumber::Number(block)) => Some(block),
+                _ => None,
+            },
+            selector: opportunity.selector,
+            expected_profit: ProfitReport::total_native(&opportunity.reports),
+            queue_len: opportunity.tx_queue.len(),
+        }
+    }
+}
+
+// One attempt of a submission, what the relays made of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttemptRecord {
+    pub target_block: U64,
+    pub accepted: usize,
+    // Why nothing was sent.
+    pub error: Option<String>,
+}
+
+impl From<&SubmissionAttempt> for AttemptRecord {
+    fn from(attempt: &SubmissionAttempt) -> Self {
+        let (accepted, error) = match &attempt.submission {
+            Ok(submission) => (submission.accepted(), None),
+            Err(e) => (0, Some(e.clone())),
+        };
+        Self {
+            target_block: attempt.target_block,
+            accepted,
+            error,
+        }
+    }
+}
+
+// Everything recorded under an id, the parts not recorded (yet) are `None` or empty.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredOpportunity {
+    pub id: OpportunityId,
+    pub summary: SimulationSummary,
+    pub economics: Option<QueueEconomics>,
+    pub attempts: Vec<AttemptRecord>,
+    // The `SubmissionEnd` of the submission, e.g. `Included(17000000)` or `Expired`.
+    pub outcome: Option<String>,
+    pub landed_block: Option<U64>,
+    pub reconciliation: Option<Reconciliation>,
+}
+
+// The opportunities `OpportunityStore::query` returns, every set condition must hold.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OpportunityQuery {
+    // Of the simulation, both bounds included.
+    pub blocks: Option<(U64, U64)>,
+    pub min_profit: Option<U256>,
+    pub landed_only: bool,
+}
+
+// Where the simulations, their queue economics, submission attempts and reconciliation are kept
+// under the opportunity's id. The summary comes first, the other records update its entry. Async,
+// so a store doing blocking IO moves it off the runtime, see `SqliteStore`.
+#[async_trait]
+pub trait OpportunityStore: Send + Sync {
+    async fn record_simulation(
+        &self,
+        id: OpportunityId,
+        summary: &SimulationSummary,
+    ) -> Result<(), SimulateError>;
+
+    async fn record_economics(
+        &self,
+        id: OpportunityId,
+        economics: &QueueEconomics,
+    ) -> Result<(), SimulateError>;
+
+    async fn record_submission(
+        &self,
+        id: OpportunityId,
+        report: &SubmissionReport,
+    ) -> Result<(), SimulateError>;
+
+    async fn record_reconciliation(
+        &self,
+        id: OpportunityId,
+        reconciliation: &Reconciliation,
+    ) -> Result<(), SimulateError>;
+
+    // Ordered by id, so by the time they were simulated.
+    async fn query(
+        &self,
+        query: &OpportunityQuery,
+    ) -> Result<Vec<StoredOpportunity>, SimulateError>;
+
+    async fn by_block_range(
+        &self,
+        from: U64,
+        to: U64,
+    ) -> Result<Vec<StoredOpportunity>, SimulateError> {
+        self.query(&OpportunityQuery {
+            blocks: Some((from, to)),
+            ..Default::default()
+        })
+        .await
+    }
+
+    // The opportunities expected to earn at least `min_profit`.
+    async fn by_profit(&self, min_profit: U256) -> Result<Vec<StoredOpportunity>, SimulateError> {
+        self.query(&OpportunityQuery {
+            min_profit: Some(min_profit),
+            ..Default::default()
+        })
+        .await
+    }
+
+    async fn landed(&self) -> Result<Vec<StoredOpportunity>, SimulateError> {
+        self.query(&OpportunityQuery {
+            landed_only: true,
+            ..Default::default()
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OpportunityId;
+
+    #[test]
+    fn opportunity_id_encode_as_ulid() {
+        let id = OpportunityId::from_parts(1_469_918_176_385, 0x2a);
+        assert_eq!(id.to_string(), "01ARYZ6S41000000000000001A");
+        assert_eq!(id.timestamp_ms(), 1_469_918_176_385);
+        assert_eq!(
+            "01aryz6s41000000000000001a"
+                .parse::<OpportunityId>()
+                .unwrap(),
+            id
+        );
+
+        // Sorted by time, whatever the random part.
+        let later = OpportunityId::from_parts(1_469_918_176_386, 0);
+        assert!(id < later && id.to_string() < later.to_string());
+        assert!("81ARYZ6S41000000000000001A"
+            .parse::<OpportunityId>()
+            .is_err());
+        assert!("01ARYZ6S41000000000000001U"
+            .parse::<OpportunityId>()
+            .is_err());
+    }
+}
@@ -0,0 +1,493 @@
+use super::{
+    AttemptRecord, OpportunityId, OpportunityQuery, OpportunityStore, SimulationSummary,
+    StoredOpportunity,
+};
+use crate::utils::{QueueEconomics, Reconciliation, SimulateError, SubmissionReport};
+use async_trait::async_trait;
+use ethers::{prelude::*, utils::hex};
+use rusqlite::{params, params_from_iter, types::Value as SqlValue, Connection};
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+// The amounts are 64 hex digits so they compare as text, economics and reconciliation are their
+// serde json.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS opportunities (
+    id TEXT PRIMARY KEY,
+    victim TEXT,
+    block INTEGER,
+    selector TEXT NOT NULL,
+    expected_profit TEXT NOT NULL,
+    queue_len INTEGER NOT NULL,
+    economics TEXT,
+    outcome TEXT,
+    landed_block INTEGER,
+    reconciliation TEXT
+);
+CREATE INDEX IF NOT EXISTS opportunities_block ON opportunities (block);
+CREATE TABLE IF NOT EXISTS attempts (
+    opportunity_id TEXT NOT NULL REFERENCES opportunities (id),
+    attempt INTEGER NOT NULL,
+    target_block INTEGER NOT NULL,
+    accepted INTEGER NOT NULL,
+    error TEXT,
+    PRIMARY KEY (opportunity_id, attempt)
+);
+";
+
+fn store_error(e: rusqlite::Error) -> SimulateError {
+    SimulateError::Store(e.to_string())
+}
+
+fn to_hex(amount: U256) -> String {
+    let mut bytes = [0; 32];
+    amount.to_big_endian(&mut bytes);
+    hex::encode(bytes)
+}
+
+fn from_hex(amount: &str) -> Result<U256, SimulateError> {
+    match hex::decode(amount) {
+        Ok(bytes) if bytes.len() == 32 => Ok(U256::from_big_endian(&bytes)),
+        _ => Err(SimulateError::Store(format!("invalid amount {amount:?}"))),
+    }
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<String, SimulateError> {
+    serde_json::to_string(value).map_err(|e| SimulateError::Store(e.to_string()))
+}
+
+fn from_json<T: DeserializeOwned>(json: &str) -> Result<T, SimulateError> {
+    serde_json::from_str(json).map_err(|e| SimulateError::Store(format!("invalid {json}: {e}")))
+}
+
+// The columns of an `opportunities` row, in the order of the table.
+type OpportunityRow = (
+    String,
+    Option<String>,
+    Option<i64>,
+    String,
+    String,
+    i64,
+    Option<String>,
+    Option<String>,
+    Option<i64>,
+    Option<String>,
+);
+
+// An `OpportunityStore` in a SQLite database, with the `sqlite` feature. Every statement runs
+// on tokio's blocking pool, the connection's lock is never held on the runtime.
+pub struct SqliteStore {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    // The database at `path`, created with its tables if missing.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, SimulateError> {
+        Self::init(Connection::open(path).map_err(store_error)?)
+    }
+
+    // A database gone with the store, e.g. for tests.
+    pub fn open_in_memory() -> Result<Self, SimulateError> {
+        Self::init(Connection::open_in_memory().map_err(store_error)?)
+    }
+
+    fn init(connection: Connection) -> Result<Self, SimulateError> {
+        connection.execute_batch(SCHEMA).map_err(store_error)?;
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    // Run `f` with the connection on the blocking pool.
+    async fn blocking<T, F>(&self, f: F) -> Result<T, SimulateError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, SimulateError> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut connection = connection
+                .lock()
+                .map_err(|_| SimulateError::Store("connection poisoned".into()))?;
+            f(&mut connection)
+        })
+        .await
+        .map_err(|e| SimulateError::Store(e.to_string()))?
+    }
+
+    // Set a column of a recorded opportunity, an error if its summary never was.
+    async fn update(
+        &self,
+        id: OpportunityId,
+        column: &'static str,
+        value: String,
+    ) -> Result<(), SimulateError> {
+        self.blocking(move |connection| {
+            let updated = connection
+                .execute(
+                    &format!("UPDATE opportunities SET {column} = ?2 WHERE id = ?1"),
+                    params![id.to_string(), value],
+                )
+                .map_err(store_error)?;
+            match updated {
+                0 => Err(SimulateError::Store(format!(
+                    "opportunity {id} was never recorded"
+                ))),
+                _ => Ok(()),
+            }
+        })
+        .await
+    }
+
+    fn attempts(connection: &Connection, id: &str) -> Result<Vec<AttemptRecord>, SimulateError> {
+        let mut statement = connection
+            .prepare(
+                "SELECT target_block, accepted, error FROM attempts
+                 WHERE opportunity_id = ?1 ORDER BY attempt",
+            )
+            .map_err(store_error)?;
+        statement
+            .query_map(params![id], |row| {
+                Ok(AttemptRecord {
+                    target_block: U64::from(row.get::<_, i64>(0)? as u64),
+                    accepted: row.get::<_, i64>(1)? as usize,
+                    error: row.get(2)?,
+                })
+            })
+            .map_err(store_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(store_error)
+    }
+
+    fn query_blocking(
+        connection: &Connection,
+        query: &OpportunityQuery,
+    ) -> Result<Vec<StoredOpportunity>, SimulateError> {
+        let mut conditions = vec!["1 = 1"];
+        let mut values = Vec::new();
+        if let Some((from, to)) = query.blocks {
+            conditions.push("block BETWEEN ? AND ?");
+            values.push(SqlValue::Integer(from.as_u64() as i64));
+            values.push(SqlValue::Integer(to.as_u64() as i64));
+        }
+        if let Some(min_profit) = query.min_profit {
+            conditions.push("expected_profit >= ?");
+            values.push(SqlValue::Text(to_hex(min_profit)));
+        }
+        if query.landed_only {
+            conditions.push("landed_block IS NOT NULL");
+        }
+
+        let mut statement = connection
+            .prepare(&format!(
+                "SELECT id, victim, block, selector, expected_profit, queue_len, economics,
+                 outcome, landed_block, reconciliation FROM opportunities
+                 WHERE {} ORDER BY id",
+                conditions.join(" AND ")
+            ))
+            .map_err(store_error)?;
+        let rows = statement
+            .query_map(params_from_iter(values), |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                    row.get(5)?,
+                    row.get(6)?,
+                    row.get(7)?,
+                    row.get(8)?,
+                    row.get(9)?,
+                ))
+            })
+            .map_err(store_error)?
+            .collect::<Result<Vec<OpportunityRow>, _>>()
+            .map_err(store_error)?;
+
+        rows.into_iter()
+            .map(
+                |(
+                    id,
+                    victim,
+                    block,
+                    selector,
+                    expected_profit,
+                    queue_len,
+                    economics,
+                    outcome,
+                    landed_block,
+                    reconciliation,
+                )| {
+                    let mut selector_bytes = [0; 4];
+                    match hex::decode(&selector) {
+                        Ok(bytes) if bytes.len() == 4 => selector_bytes.copy_from_slice(&bytes),
+                        _ => {
+                            return Err(SimulateError::Store(format!(
+                                "invalid selector {selector:?}"
+                            )))
+                        }
+                    }
+                    Ok(StoredOpportunity {
+                        id: id.parse()?,
+                        summary: SimulationSummary {
+                            victim: victim
+                                .map(|victim| {
+                                    victim.parse().map_err(|_| {
+                                        SimulateError::Store(format!("invalid victim {victim:?}"))
+                                    })
+                                })
+                                .transpose()?,
+                            block: block.map(|block| U64::from(block as u64)),
+                            selector: selector_bytes,
+                            expected_profit: from_hex(&expected_profit)?,
+                            queue_len: queue_len as usize,
+                        },
+                        economics: economics.as_deref().map(from_json).transpose()?,
+                        attempts: Self::attempts(connection, &id)?,
+                        outcome,
+                        landed_block: landed_block.map(|block| U64::from(block as u64)),
+                        reconciliation: reconciliation.as_deref().map(from_json).transpose()?,
+                    })
+                },
+            )
+            .collect()
+    }
+}
+
+#[async_trait]
+impl OpportunityStore for SqliteStore {
+    async fn record_simulation(
+        &self,
+        id: OpportunityId,
+        summary: &SimulationSummary,
+    ) -> Result<(), SimulateError> {
+        let summary = summary.clone();
+        self.blocking(move |connection| {
+            connection
+                .execute(
+                    "INSERT INTO opportunities
+                     (id, victim, block, selector, expected_profit, queue_len)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![
+                        id.to_string(),
+                        summary.victim.map(|victim| format!("{victim:?}")),
+                        summary.block.map(|block| block.as_u64() as i64),
+                        hex::encode(summary.selector),
+                        to_hex(summary.expected_profit),
+                        summary.queue_len as i64,
+                    ],
+                )
+                .map_err(store_error)?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn record_economics(
+        &self,
+        id: OpportunityId,
+        economics: &QueueEconomics,
+    ) -> Result<(), SimulateError> {
+        self.update(id, "economics", to_json(economics)?).await
+    }
+
+    async fn record_submission(
+        &self,
+        id: OpportunityId,
+        report: &SubmissionReport,
+    ) -> Result<(), SimulateError> {
+        let outcome = format!("{:?}", report.end);
+        let landed_block = report.landed_block().map(|block| block.as_u64() as i64);
+        let records = report
+            .attempts
+            .iter()
+            .map(AttemptRecord::from)
+            .collect::<Vec<_>>();
+        self.blocking(move |connection| {
+            let transaction = connection.transaction().map_err(store_error)?;
+            let updated = transaction
+                .execute(
+                    "UPDATE opportunities SET outcome = ?2, landed_block = ?3 WHERE id = ?1",
+                    params![id.to_string(), outcome, landed_block],
+                )
+                .map_err(store_error)?;
+            if updated == 0 {
+                return Err(SimulateError::Store(format!(
+                    "opportunity {id} was never recorded"
+                )));
+            }
+            // A later report of the same opportunity replaces the attempts.
+            transaction
+                .execute(
+                    "DELETE FROM attempts WHERE opportunity_id = ?1",
+                    params![id.to_string()],
+                )
+                .map_err(store_error)?;
+            for (attempt, record) in records.into_iter().enumerate() {
+                transaction
+                    .execute(
+                        "INSERT INTO attempts
+                         (opportunity_id, attempt, target_block, accepted, error)
+                         VALUES (?1, ?2, ?3, ?4, ?5)",
+                        params![
+                            id.to_string(),
+                            attempt as i64,
+                            record.target_block.as_u64() as i64,
+                            record.accepted as i64,
+                            record.error,
+                        ],
+                    )
+                    .map_err(store_error)?;
+            }
+            transaction.commit().map_err(store_error)
+        })
+        .await
+    }
+
+    async fn record_reconciliation(
+        &self,
+        id: OpportunityId,
+        reconciliation: &Reconciliation,
+    ) -> Result<(), SimulateError> {
+        self.update(id, "reconciliation", to_json(reconciliation)?)
+            .await
+    }
+
+    async fn query(
+        &self,
+        query: &OpportunityQuery,
+    ) -> Result<Vec<StoredOpportunity>, SimulateError> {
+        let query = query.clone();
+        self.blocking(move |connection| Self::query_blocking(connection, &query))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SqliteStore;
+    use crate::utils::{
+        AttemptRecord, BundleSubmission, OpportunityId, OpportunityStore, QueueEconomics,
+        Reconciliation, SimulationSummary, StoredOpportunity, SubmissionAttempt, SubmissionEnd,
+        SubmissionReport,
+    };
+    use ethers::prelude::*;
+    use std::collections::HashMap;
+
+    fn summary(block: u64, expected_profit: u64) -> SimulationSummary {
+        SimulationSummary {
+            victim: Some(TxHash::random()),
+            block: Some(U64::from(block)),
+            selector: [0x12, 0x34, 0x56, 0x78],
+            expected_profit: U256::from(expected_profit),
+            queue_len: 2,
+        }
+    }
+
+    #[tokio::test]
+    async fn sqlite_store_join_records_by_id() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let (landed, missed) = (OpportunityId::new(), OpportunityId::new());
+        let economics = QueueEconomics {
+            expected_profit: U256::from(80),
+            gas_cost: U256::from(30),
+            ..Default::default()
+        };
+        store
+            .record_simulation(landed, &summary(100, 80))
+            .await
+            .unwrap();
+        store
+            .record_simulation(missed, &summary(105, 5))
+            .await
+            .unwrap();
+        store.record_economics(landed, &economics).await.unwrap();
+
+        let attempt = |target_block: u64, submission| SubmissionAttempt {
+            target_block: U64::from(target_block),
+            base_fee: None,
+            economics,
+            submission,
+            tx_hashes: vec![],
+            bundle: None,
+        };
+        let report = SubmissionReport {
+            attempts: vec![
+                attempt(101, Err("gate refused".into())),
+                attempt(
+                    102,
+                    Ok(BundleSubmission {
+                        outcomes: HashMap::new(),
+                        succeeded: false,
+                        bundle: None,
+                    }),
+                ),
+            ],
+            end: SubmissionEnd::Included(U64::from(102)),
+            replacement_uuid: None,
+            cancellation: None,
+            bundle_stats: None,
+            user_stats: None,
+            fee_trajectory: vec![],
+            reputation_key: Address::zero(),
+            opportunity_id: Some(landed),
+        };
+        store.record_submission(landed, &report).await.unwrap();
+        let reconciliation = Reconciliation {
+            block: U64::from(102),
+            simulated_profit: I256::from(50),
+            realized_profit: U256::from(90),
+            gas_paid: U256::from(30),
+            slippage: I256::from(-40),
+        };
+        store
+            .record_reconciliation(landed, &reconciliation)
+            .await
+            .unwrap();
+
+        let stored = store.landed().await.unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].id, landed);
+        assert_eq!(stored[0].summary.selector, [0x12, 0x34, 0x56, 0x78]);
+        assert_eq!(stored[0].economics, Some(economics));
+        assert_eq!(
+            stored[0].attempts,
+            vec![
+                AttemptRecord {
+                    target_block: U64::from(101),
+                    accepted: 0,
+                    error: Some("gate refused".into()),
+                },
+                AttemptRecord {
+                    target_block: U64::from(102),
+                    accepted: 0,
+                    error: None,
+                },
+            ]
+        );
+        assert_eq!(stored[0].outcome.as_deref(), Some("Included(102)"));
+        assert_eq!(stored[0].reconciliation, Some(reconciliation));
+
+        let ids = |stored: Vec<StoredOpportunity>| {
+            stored.iter().map(|stored| stored.id).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ids(store
+                .by_block_range(U64::from(101), U64::from(110))
+                .await
+                .unwrap()),
+            vec![missed]
+        );
+        // The amounts are compared as numbers, not as their decimal text.
+        assert_eq!(
+            ids(store.by_profit(U256::from(10)).await.unwrap()),
+            vec![landed]
+        );
+        assert!(store
+            .record_economics(OpportunityId::new(), &economics)
+            .await
+            .is_err());
+    }
+}
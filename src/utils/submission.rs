@@ -0,0 +1,1475 @@
+use super::simulate::decimal;
+use crate::utils::{
+    BribeMethod, BundleOptions, BundlePayload, BundleSubmission, BundleSubmitter, OpportunityId,
+    OpportunityStore, PrivateTxOptions, ProfitReport, QueueEconomics, Simulate, SimulateError,
+    SubmissionPath, TxQueue, Urgency,
+};
+use async_trait::async_trait;
+use ethers::{prelude::*, utils::keccak256};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::time::Duration;
+use tracing::{info, warn};
+
+// One `submit_all` of the loop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubmissionAttempt {
+    pub target_block: U64,
+    // The base fee the fees were adjusted to, `None` before London.
+    pub base_fee: Option<U256>,
+    pub economics: QueueEconomics,
+    // The relays' answer, or why nothing was sent.
+    pub submission: Result<BundleSubmission, String>,
+    // Of the signed txs of the attempt, in queue order.
+    pub tx_hashes: Vec<TxHash>,
+    // What was sent to the relays, `None` on the private tx path or when nothing was sent.
+    pub bundle: Option<BundlePayload>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubmissionEnd {
+    // The queue landed in the block.
+    Included(U64),
+    Expired,
+    // The adjusted fees (or the escalated tip) ate up the profit.
+    Unprofitable,
+    // The escalated tip would pass its cap, see `SubmissionLoop::escalate`.
+    TipCapped,
+    // No inclusion within `SubmissionLoop::max_attempts`.
+    GaveUp,
+    Cancelled,
+    // `SubmissionLoop::stale_if` held while waiting for the target block.
+    Stale,
+    // The gate refused the queue, the reason is in the last attempt.
+    Refused,
+    // The next base fee alone passed the break-even fee per gas, see
+    // `SubmissionLoop::abort_above_break_even`.
+    BaseFeeAboveBreakEven,
+    // The victim was mined by the block, and a gate didn't let the queue through on top of it.
+    VictimAlreadyIncluded(U64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubmissionReport {
+    pub attempts: Vec<SubmissionAttempt>,
+    pub end: SubmissionEnd,
+    // The `replacementUuid` every bundle of the loop was sent with, `None` on the private tx path.
+    pub replacement_uuid: Option<String>,
+    // What the relays answered when the last attempt was withdrawn on `Cancelled` or `Stale`, or
+    // the one `Cancelled` while the relays were asked, which isn't in `attempts`.
+    pub cancellation: Option<BundleCancellation>,
+    // The relay's stats of the last accepted bundle and of the reputation key, with
+    // `SubmissionLoop::stats`. `None` when the relay had none.
+    pub bundle_stats: Option<BundleStats>,
+    pub user_stats: Option<UserStats>,
+    // The base fee projected for the next block from every head the loop saw, as (head, fee).
+    pub fee_trajectory: Vec<(U64, U256)>,
+    // The reputation key the relay requests were signed with, see `BundleSubmitter::auth_address`.
+    pub reputation_key: Address,
+    // Of the queue, see `TxQueue::opportunity_id`.
+    pub opportunity_id: Option<OpportunityId>,
+}
+
+impl SubmissionReport {
+    // The `eth_sendBundle` params of the last attempt sent as a bundle, to replay by hand, see
+    // `BundlePayload::to_relay_json`.
+    pub fn to_relay_json(&self) -> Option<String> {
+        self.attempts
+            .iter()
+            .rev()
+            .find_map(|attempt| attempt.bundle.as_ref())
+            .map(BundlePayload::to_relay_json)
+    }
+
+    pub fn landed_block(&self) -> Option<U64> {
+        match self.end {
+            SubmissionEnd::Included(block) => Some(block),
+            _ => None,
+        }
+    }
+
+    // How the landed queue did against its simulation: every tx of the landing attempt is
+    // replayed on its block and run through the analyzers of `simulate`. `None` if it didn't land.
+    // Recorded in `store` under the opportunity's id, if it has one.
+    pub async fn reconcile<'a, M: Middleware + 'a, S: Signer + 'a>(
+        &self,
+        simulate: &Simulate<'a, M, S>,
+        store: Option<&dyn OpportunityStore>,
+    ) -> Result<Option<Reconciliation>, SimulateError> {
+        let (block, attempt) = match (self.landed_block(), self.attempts.last()) {
+            (Some(block), Some(attempt)) => (block, attempt),
+            _ => return Ok(None),
+        };
+
+        let mut reconciliation = Reconciliation {
+            block,
+            simulated_profit: attempt.economics.net_profit(),
+            realized_profit: U256::zero(),
+            gas_paid: U256::zero(),
+            slippage: I256::zero(),
+        };
+        for tx_hash in &attempt.tx_hashes {
+            let tx = simulate
+                .get_transaction(*tx_hash)
+                .await
+                .map_err(SimulateError::middleware)?
+                .ok_or(SimulateError::TxNotFound(*tx_hash))?;
+            let receipt = simulate
+                .get_transaction_receipt(*tx_hash)
+                .await
+                .map_err(SimulateError::middleware)?
+                .ok_or(SimulateError::ReceiptNotFound(*tx_hash))?;
+            reconciliation.gas_paid = reconciliation.gas_paid.saturating_add(
+                receipt
+                    .gas_used
+                    .unwrap_or_default()
+                    .saturating_mul(receipt.effective_gas_price.unwrap_or_default()),
+            );
+
+            let trace = simulate.replay_transaction(*tx_hash).await?;
+            let reports = simulate
+                .analyze(&tx, &trace, Some(BlockNumber::Number(block)))
+                .await;
+            reconciliation.realized_profit = reconciliation
+                .realized_profit
+                .saturating_add(ProfitReport::total_native(&reports));
+        }
+        reconciliation.slippage = reconciliation
+            .simulated_profit
+            .saturating_sub(I256::try_from(reconciliation.realized_profit).unwrap_or(I256::MAX));
+
+        if let (Some(store), Some(id)) = (store, self.opportunity_id) {
+            // The reconciliation is done, a failing store doesn't undo it.
+            if let Err(e) = store.record_reconciliation(id, &reconciliation).await {
+                warn!(%id, "reconciliation not recorded: {e}");
+            }
+        }
+        Ok(Some(reconciliation))
+    }
+}
+
+// A landed queue against its simulation, from `SubmissionReport::reconcile`. Serialized with the
+// amounts in wei as decimal strings, for the analytics pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reconciliation {
+    pub block: U64,
+    // The net profit the attempt's economics expected, see `QueueEconomics::net_profit`.
+    #[serde(with = "decimal")]
+    pub simulated_profit: I256,
+    // What the analyzers see in the state diffs of the landed txs, which the gas, bribe and L1
+    // fee were paid from already.
+    #[serde(with = "decimal")]
+    pub realized_profit: U256,
+    #[serde(with = "decimal")]
+    pub gas_paid: U256,
+    // Simulated minus realized, negative when the queue earned more than expected.
+    #[serde(with = "decimal")]
+    pub slippage: I256,
+}
+
+// Submit a verified queue for the next block and, until it lands, again for every following
+// one with its fees adjusted to the new base fee. It stops once the queue expires or its
+// economics (see `TxQueue::fill_economics`) turn unprofitable.
+pub struct SubmissionLoop<'s, M, A, S> {
+    client: &'s M,
+    submitter: &'s BundleSubmitter<A>,
+    signer: &'s S,
+    urgency: Urgency,
+    bribe: Option<(BribeMethod, U256)>,
+    // Step and cap of the tip, raised every attempt after the first.
+    escalation: Option<(U256, U256)>,
+    max_attempts: usize,
+    poll_interval: Duration,
+    path: SubmissionPath,
+    private_options: PrivateTxOptions,
+    stale: Option<StaleCheck<'s>>,
+    // Blocks the bundle stats are asked again for, no stats without it.
+    stats_retry_blocks: Option<u64>,
+    gate: Option<&'s (dyn SubmissionGate + 's)>,
+    // Margin of the break-even fee the next base fee may not pass, no such check without it.
+    break_even_margin_bps: Option<u32>,
+    store: Option<&'s (dyn OpportunityStore + 's)>,
+    // The victim's signed tx every bundle backruns, see `BundleOptions::backrun`.
+    backrun: Option<Bytes>,
+}
+
+// Decides whether a queue may be sent for a target block, before every attempt.
+#[async_trait(?Send)]
+pub trait SubmissionGate {
+    // An error with the reason if the queue must not be sent under `options`, `bribe` is what it
+    // pays the coinbase directly.
+    async fn check(
+        &self,
+        tx_queue: &TxQueue,
+        options: &BundleOptions,
+        bribe: U256,
+    ) -> Result<(), SimulateError>;
+}
+
+#[async_trait(?Send)]
+impl<'a, M: Middleware + 'a, S: Signer + 'a> SubmissionGate for Simulate<'a, M, S> {
+    async fn check(
+        &self,
+        tx_queue: &TxQueue,
+        options: &BundleOptions,
+        bribe: U256,
+    ) -> Result<(), SimulateError> {
+        self.gate_bundle(tx_queue, options, bribe).await
+    }
+}
+
+type StaleCheck<'s> = Box<dyn Fn(&TxQueue, U64) -> bool + Send + Sync + 's>;
+
+impl<'s, M: Middleware, A: Signer, S: Signer> SubmissionLoop<'s, M, A, S> {
+    pub fn new(client: &'s M, submitter: &'s BundleSubmitter<A>, signer: &'s S) -> Self {
+        Self {
+            client,
+            submitter,
+            signer,
+            urgency: Urgency::NextBlock,
+            bribe: None,
+            escalation: None,
+            max_attempts: 25,
+            poll_interval: Duration::from_secs(1),
+            path: SubmissionPath::Auto,
+            private_options: PrivateTxOptions::new(),
+            stale: None,
+            stats_retry_blocks: None,
+            gate: None,
+            break_even_margin_bps: None,
+            store: None,
+            backrun: None,
+        }
+    }
+
+    // Headroom of the max fee over the next base fee, only the next block is targeted by default.
+    pub fn urgency(mut self, urgency: Urgency) -> Self {
+        self.urgency = urgency;
+        self
+    }
+
+    // The bribe `append_bribe` added to the queue, counted in the economics of every attempt.
+    pub fn bribe(mut self, method: BribeMethod, bribe: U256) -> Self {
+        self.bribe = Some((method, bribe));
+        self
+    }
+
+    // Outbid the competition: raise the tip of every entry by `step` each block, up to
+    // `max_priority` and never past the tip the profit can pay for.
+    pub fn escalate(mut self, step: U256, max_priority: U256) -> Self {
+        self.escalation = Some((step, max_priority));
+        self
+    }
+
+    // The bound for a queue that never expires.
+    pub fn max_attempts(mut self, max_attempts: usize) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    // How often the block number is asked while waiting for the target block.
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    // Force the bundle or the private tx path, by default a queue of one entry goes private.
+    pub fn path(mut self, path: SubmissionPath) -> Self {
+        self.path = path;
+        self
+    }
+
+    // The preferences of private txs, each attempt's max block is its target block.
+    pub fn private_options(mut self, private_options: PrivateTxOptions) -> Self {
+        self.private_options = private_options;
+        self
+    }
+
+    // Polled with the queue and the latest block while waiting for the target block, e.g. on a
+    // flag a mempool watcher raises. Once it holds the sent attempt is withdrawn and the loop ends.
+    pub fn stale_if(mut self, stale: impl Fn(&TxQueue, U64) -> bool + Send + Sync + 's) -> Self {
+        self.stale = Some(Box::new(stale));
+        self
+    }
+
+    // Only send what `gate` lets through, e.g. a `Simulate` replaying the queue on the target
+    // block's parent (`Simulate::gate_bundle`). `Simulate::compete` always sets one.
+    pub fn gate(mut self, gate: &'s (dyn SubmissionGate + 's)) -> Self {
+        self.gate = Some(gate);
+        self
+    }
+
+    // Send without asking the gate, the submitter still runs its own (see
+    // `BundleSubmitter::skip_gate`).
+    pub fn skip_gate(mut self) -> Self {
+        self.gate = None;
+        self
+    }
+
+    // Stop with `BaseFeeAboveBreakEven` once the gas at the base fee projected from a new head,
+    // the tips on top, costs more than the queue breaks even at, keeping `margin_bps` of the
+    // profit (see `TxQueue::break_even_fees`), instead of sending it again.
+    pub fn abort_above_break_even(mut self, margin_bps: u32) -> Self {
+        self.break_even_margin_bps = Some(margin_bps);
+        self
+    }
+
+    // Record the economics and the attempts of the queue under its opportunity once the loop
+    // ended, a queue not built from an opportunity has nothing to join them with.
+    pub fn store(mut self, store: &'s (dyn OpportunityStore + 's)) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    // Put `victim_raw_tx` ahead of the queue in every bundle, the submitter leaves it out once the
+    // victim was mined.
+    pub fn backrun(mut self, victim_raw_tx: Bytes) -> Self {
+        self.backrun = Some(victim_raw_tx);
+        self
+    }
+
+    // Fetch the relay's stats once the loop ended, to tell why a bundle didn't land. Relays fill
+    // them in lazily, so they are asked again every block for up to `retry_blocks`.
+    pub fn stats(mut self, retry_blocks: u64) -> Self {
+        self.stats_retry_blocks = Some(retry_blocks);
+        self
+    }
+
+    // Run the loop on `tx_queue`, whose economics were filled already, until an end or `cancel`
+    // completes. Only rpc errors of the chain are returned, a failing relay is in the attempt.
+    pub async fn run(
+        &self,
+        mut tx_queue: TxQueue,
+        cancel: impl Future<Output = ()>,
+    ) -> Result<SubmissionReport, SimulateError> {
+        // Refused by every attempt, so not even the first is made.
+        self.submitter.ensure_own_key(self.signer.address())?;
+        let reputation_key = self.submitter.auth_address();
+        let economics = tx_queue.economics.unwrap_or_default();
+        let private = self.path.is_private(&tx_queue);
+        let replacement_uuid = (!private).then(new_replacement_uuid);
+        let mut attempts = Vec::new();
+        let mut fee_trajectory = Vec::new();
+        // The tx hashes of the attempt the relays are asked about, see `attempt`.
+        let mut in_flight = None;
+        tokio::pin!(cancel);
+
+        let end = loop {
+            if attempts.len() >= self.max_attempts {
+                break SubmissionEnd::GaveUp;
+            }
+            tokio::select! {
+                biased;
+                _ = &mut cancel => break SubmissionEnd::Cancelled,
+                end = self.attempt(
+                    &mut tx_queue,
+                    economics,
+                    private,
+                    replacement_uuid.as_deref(),
+                    &mut attempts,
+                    &mut fee_trajectory,
+                    &mut in_flight,
+                ) => {
+                    if let Some(end) = end? {
+                        break end;
+                    }
+                }
+            }
+        };
+
+        // The last attempt may still land in its target block (a private tx until its max block),
+        // withdraw it. Cancelled while the relays were asked, the relays may have taken it already.
+        let sent = match (end, &in_flight, attempts.last()) {
+            (SubmissionEnd::Cancelled, Some(tx_hashes), _) => Some((None, tx_hashes)),
+            (SubmissionEnd::Cancelled | SubmissionEnd::Stale, None, Some(attempt)) => attempt
+                .submission
+                .as_ref()
+                .ok()
+                .map(|submission| (Some(submission), &attempt.tx_hashes)),
+            _ => None,
+        };
+        let mut cancellation = None;
+        if let Some((submission, tx_hashes)) = sent {
+            cancellation = match (&replacement_uuid, tx_hashes.first()) {
+                (Some(replacement_uuid), _) => {
+                    Some(self.submitter.cancel(replacement_uuid, submission).await)
+                }
+                (None, Some(tx_hash)) => Some(self.submitter.cancel_private(*tx_hash).await),
+                (None, None) => None,
+            };
+        }
+        if let Some(cancellation) = &cancellation {
+            info!(
+                ?end,
+                acknowledged = cancellation.acknowledged(),
+                "submission withdrawn"
+            );
+        }
+
+        let (bundle_stats, user_stats) = match self.stats_retry_blocks {
+            Some(retry_blocks) => self.fetch_stats(&attempts, private, retry_blocks).await?,
+            None => (None, None),
+        };
+
+        let report = SubmissionReport {
+            attempts,
+            end,
+            replacement_uuid,
+            cancellation,
+            bundle_stats,
+            user_stats,
+            fee_trajectory,
+            reputation_key,
+            opportunity_id: tx_queue.opportunity_id,
+        };
+        if let (Some(store), Some(id)) = (self.store, report.opportunity_id) {
+            // The submission is done, a failing store doesn't undo it.
+            let recorded = match &tx_queue.economics {
+                Some(economics) => store.record_economics(id, economics).await,
+                None => Ok(()),
+            };
+            let recorded = match recorded {
+                Ok(()) => store.record_submission(id, &report).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = recorded {
+                warn!(%id, "submission not recorded: {e}");
+            }
+        }
+        Ok(report)
+    }
+
+    // The stats of the last bundle a relay accepted and of the reputation key, the relay failing
+    // to give them is no error.
+    async fn fetch_stats(
+        &self,
+        attempts: &[SubmissionAttempt],
+        private: bool,
+        retry_blocks: u64,
+    ) -> Result<(Option<BundleStats>, Option<UserStats>), SimulateError> {
+        let mut block = self
+            .client
+            .get_block_number()
+            .await
+            .map_err(SimulateError::middleware)?;
+        // A private tx has no bundle stats.
+        let accepted = match private {
+            true => None,
+            false => attempts.iter().rev().find_map(|attempt| {
+                let submission = attempt.submission.as_ref().ok()?;
+                submission
+                    .outcomes
+                    .iter()
+                    .find_map(|(relay, outcome)| match outcome {
+                        RelayOutcome::Accepted(bundle_hash) => {
+                            Some((relay, *bundle_hash, attempt.target_block))
+                        }
+                        _ => None,
+                    })
+            }),
+        };
+
+        let mut bundle_stats = None;
+        if let Some((relay, bundle_hash, target_block)) = accepted {
+            let last_block = block + retry_blocks;
+            loop {
+                match self
+                    .submitter
+                    .bundle_stats(relay, bundle_hash, target_block)
+                    .await
+                {
+                    Ok(stats) => {
+                        bundle_stats = Some(stats);
+                        break;
+                    }
+                    Err(error) if block < last_block => {
+                        info!(%bundle_hash, %error, "bundle stats not ready");
+                        while self
+                            .client
+                            .get_block_number()
+                            .await
+                            .map_err(SimulateError::middleware)?
+                            <= block
+                        {
+                            tokio::time::sleep(self.poll_interval).await;
+                        }
+                        block += 1;
+                    }
+                    Err(error) => {
+                        info!(%bundle_hash, %error, "no bundle stats");
+                        break;
+                    }
+                }
+            }
+        }
+        let user_stats = self.submitter.user_stats(block).await.ok();
+
+        Ok((bundle_stats, user_stats))
+    }
+
+    // Submit for the block after the latest and wait for it, `None` if the queue didn't land.
+    async fn attempt(
+        &self,
+        tx_queue: &mut TxQueue,
+        economics: QueueEconomics,
+        private: bool,
+        replacement_uuid: Option<&str>,
+        attempts: &mut Vec<SubmissionAttempt>,
+        fee_trajectory: &mut Vec<(U64, U256)>,
+        in_flight: &mut Option<Vec<TxHash>>,
+    ) -> Result<Option<SubmissionEnd>, SimulateError> {
+        let block = self
+            .client
+            .get_block(BlockNumber::Latest)
+            .await
+            .map_err(SimulateError::middleware)?
+            .ok_or(SimulateError::BlockNotFound(BlockNumber::Latest.into()))?;
+        let current_block = block.number.unwrap_or_default();
+        if tx_queue.is_expired(current_block) {
+            return Ok(Some(SubmissionEnd::Expired));
+        }
+
+        let base_fee = next_base_fee(&block);
+        if let Some(base_fee) = base_fee {
+            fee_trajectory.push((current_block, base_fee));
+            if let Some(margin_bps) = self.break_even_margin_bps {
+                // What the gas may cost of the profit, the bribe and l1 fee are paid first.
+                let profit = economics
+                    .expected_profit
+                    .saturating_sub(self.bribe.map(|(_, bribe)| bribe).unwrap_or_default());
+                // The tips are paid on top of the base fee.
+                let budget =
+                    tx_queue.break_even_fees_with_l1(profit, margin_bps, economics.l1_fee)?;
+                let gas_cost = tx_queue.gas_cost_at(base_fee);
+                if gas_cost > budget.budget {
+                    info!(
+                        %current_block,
+                        %base_fee,
+                        %gas_cost,
+                        budget = %budget.budget,
+                        "base fee above break-even"
+                    );
+                    return Ok(Some(SubmissionEnd::BaseFeeAboveBreakEven));
+                }
+            }
+            let max_base_fee = self.urgency.max_base_fee(base_fee)?;
+            if let (Some((step, max_priority)), false) = (self.escalation, attempts.is_empty()) {
+                // What's left of the profit after the l1 fee and bribe, spread over the gas.
+                let profit = economics
+                    .expected_profit
+                    .saturating_sub(economics.l1_fee)
+                    .saturating_sub(self.bribe.map(|(_, bribe)| bribe).unwrap_or_default());
+                let break_even_tip = tx_queue
+                    .break_even_fees(profit, 0)?
+                    .max_priority_fee_per_gas(max_base_fee);
+                for entry in &mut tx_queue.entries {
+                    let tip = match entry.max_priority_fee_per_gas {
+                        Some(tip) => tip.saturating_add(step),
+                        None => continue,
+                    };
+                    if tip > max_priority {
+                        return Ok(Some(SubmissionEnd::TipCapped));
+                    }
+                    if tip >= break_even_tip {
+                        info!(%current_block, %tip, %break_even_tip, "tip at break-even");
+                        return Ok(Some(SubmissionEnd::Unprofitable));
+                    }
+                    entry.max_priority_fee_per_gas = Some(tip);
+                }
+            }
+            for entry in &mut tx_queue.entries {
+                if let Some(max_priority_fee_per_gas) = entry.max_priority_fee_per_gas {
+                    entry.max_fee_per_gas =
+                        Some(max_base_fee.saturating_add(max_priority_fee_per_gas));
+                }
+            }
+        }
+        let economics =
+            tx_queue.fill_economics(economics.expected_profit, self.bribe, economics.l1_fee);
+        if tx_queue.ensure_profitable().is_err() {
+            info!(%current_block, net_profit = %economics.net_profit(), "bundle unprofitable");
+            return Ok(Some(SubmissionEnd::Unprofitable));
+        }
+
+        let target_block = current_block + 1;
+        let mut options = BundleOptions::new(target_block);
+        options.replacement_uuid = replacement_uuid.map(String::from);
+        // The gates and the submitter leave it out once the victim was mined.
+        options.backrun = self.backrun.clone();
+        // A bribe in the tip isn't a coinbase transfer the replay could show.
+        let coinbase_bribe = match self.bribe {
+            Some((BribeMethod::Coinbase { .. }, bribe)) => bribe,
+            _ => U256::zero(),
+        };
+        let refusal = match self.gate {
+            Some(gate) => gate.check(tx_queue, &options, coinbase_bribe).await.err(),
+            None => None,
+        };
+        let gate_refused = refusal.is_some();
+        let raw_tx_list = tx_queue.sign_with(self.signer).await?;
+        let tx_hashes = raw_tx_list
+            .iter()
+            .map(|raw_tx| H256(keccak256(raw_tx)))
+            .collect::<Vec<_>>();
+        // Until the relays answered, a `cancel` dropping the attempt still has to withdraw it.
+        if !gate_refused {
+            *in_flight = Some(tx_hashes.clone());
+        }
+        let submission = match refusal {
+            Some(refusal) => Err(refusal),
+            None if private => {
+                let options = PrivateTxOptions {
+                    max_block_number: Some(target_block),
+                    ..self.private_options.clone()
+                };
+                self.submitter
+                    .submit_private(tx_queue, self.signer, &options)
+                    .await
+            }
+            None => {
+                self.submitter
+                    .submit_all(tx_queue, self.signer, &options)
+                    .await
+            }
+        };
+        // The submitter's own gate refuses like ours.
+        let end = match &submission {
+            Err(SimulateError::VictimAlreadyIncluded { block, .. }) => {
+                Some(SubmissionEnd::VictimAlreadyIncluded(*block))
+            }
+            Err(SimulateError::GateRefused { .. }) => Some(SubmissionEnd::Refused),
+            Err(_) if gate_refused => Some(SubmissionEnd::Refused),
+            _ => None,
+        };
+        let bundle = submission
+            .as_ref()
+            .ok()
+            .and_then(|submission| submission.bundle.clone());
+        let submission = submission.map_err(|e| e.to_string());
+        match &submission {
+            Ok(submission) => {
+                info!(%target_block, accepted = submission.accepted(), "bundle submitted")
+            }
+            Err(error) => info!(%target_block, %error, "bundle not submitted"),
+        }
+        *in_flight = None;
+        attempts.push(SubmissionAttempt {
+            target_block,
+            base_fee,
+            economics,
+            submission,
+            tx_hashes: tx_hashes.clone(),
+            bundle,
+        });
+        if end.is_some() {
+            return Ok(end);
+        }
+
+        // The bundle lands as a whole, so its first tx tells.
+        let first_tx = match tx_hashes.first() {
+            Some(tx_hash) => *tx_hash,
+            None => return Ok(Some(SubmissionEnd::GaveUp)),
+        };
+        loop {
+            let latest = self
+                .client
+                .get_block_number()
+                .await
+                .map_err(SimulateError::middleware)?;
+            if latest >= target_block {
+                break;
+            }
+            if let Some(stale) = &self.stale {
+                if stale(tx_queue, latest) {
+                    info!(%target_block, "submission stale");
+                    return Ok(Some(SubmissionEnd::Stale));
+                }
+            }
+            tokio::time::sleep(self.poll_interval).await;
+        }
+        let receipt = self
+            .client
+            .get_transaction_receipt(first_tx)
+            .await
+            .map_err(SimulateError::middleware)?;
+
+        Ok(receipt
+            .and_then(|receipt| receipt.block_number)
+            .map(SubmissionEnd::Included))
+    }
+}
+
+impl<'a, M: Middleware + 'a, S: Signer + 'a> Simulate<'a, M, S> {
+    // Bid for inclusion block by block with the client's signer: the queue is submitted with its
+    // tip raised by `step` every missed block, see `SubmissionLoop::escalate`. Every attempt is
+    // replayed on the parent of its target block first and refused if it reverts, see
+    // `gate_bundle`.
+    pub async fn compete<A: Signer>(
+        &self,
+        submitter: &BundleSubmitter<A>,
+        queue: TxQueue,
+        max_priority: U256,
+        step: U256,
+        cancel: impl Future<Output = ()>,
+    ) -> Result<SubmissionReport, SimulateError> {
+        let client: &SignerMiddleware<M, S> = self;
+        SubmissionLoop::new(client, submitter, client.signer())
+            .gate(self)
+            .escalate(step, max_priority)
+            .run(queue, cancel)
+            .await
+    }
+}
+
+// The EIP-1559 base fee of the block after `block`.
+fn next_base_fee(block: &Block<TxHash>) -> Option<U256> {
+    let base_fee = block.base_fee_per_gas?;
+    let gas_target = block.gas_limit / 2;
+    if gas_target.is_zero() || block.gas_used == gas_target {
+        return Some(base_fee);
+    }
+    // The node's numbers, a wild block doesn't overflow them.
+    if block.gas_used > gas_target {
+        let delta = base_fee.saturating_mul(block.gas_used - gas_target) / gas_target / 8;
+        Some(base_fee.saturating_add(delta.max(U256::one())))
+    } else {
+        let delta = base_fee.saturating_mul(gas_target - block.gas_used) / gas_target / 8;
+        Some(base_fee.saturating_sub(delta))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        next_base_fee, SubmissionAttempt, SubmissionEnd, SubmissionGate, SubmissionLoop,
+        SubmissionReport,
+    };
+    use crate::utils::{
+        BundleOptions, BundlePayload, BundleSubmission, BundleSubmitter, OpportunityId,
+        OpportunityQuery, OpportunityStore, QueueEconomics, Reconciliation, RelayOutcome, Simulate,
+        SimulateError, SimulationSummary, StoredOpportunity, SubmissionPath, TxQueue,
+    };
+    use async_trait::async_trait;
+    use ethers::{core::rand::thread_rng, prelude::*, utils::keccak256};
+    use serde_json::{json, Value};
+    use std::collections::{BTreeMap, HashMap};
+    use std::future::pending;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+    use url::Url;
+
+    fn latest_block(number: u64, base_fee: u64) -> Block<TxHash> {
+        Block {
+            number: Some(U64::from(number)),
+            base_fee_per_gas: Some(U256::from(base_fee)),
+            // Exactly at the target, the base fee stays.
+            gas_limit: U256::from(30_000_000),
+            gas_used: U256::from(15_000_000),
+            ..Default::default()
+        }
+    }
+
+    // A replay of the queue that doesn't revert.
+    fn replayed() -> BlockTrace {
+        BlockTrace {
+            output: Bytes::default(),
+            trace: None,
+            vm_trace: None,
+            state_diff: None,
+            transaction_hash: None,
+        }
+    }
+
+    fn verified_queue(expected_profit: u64) -> TxQueue {
+        let mut tx_queue = TxQueue::from(vec![TransactionRequest::new()
+            .to(Address::random())
+            .nonce(0)
+            .gas(100000)
+            .chain_id(1)]);
+        tx_queue.entries[0].max_fee_per_gas = Some(U256::from(12));
+        tx_queue.entries[0].max_priority_fee_per_gas = Some(U256::from(2));
+        tx_queue.fill_economics(U256::from(expected_profit), None, U256::zero());
+        tx_queue
+    }
+
+    fn read_body(reader: &mut BufReader<TcpStream>) -> Value {
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap();
+                }
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    // A relay that takes the first request and never answers it, `sent` is set once it has it.
+    // The next one gets `null`, both are handed back.
+    fn hanging_relay(sent: Arc<AtomicBool>) -> (Url, thread::JoinHandle<Vec<Value>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut hanging = BufReader::new(stream);
+            let first = read_body(&mut hanging);
+            sent.store(true, Ordering::SeqCst);
+
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let second = read_body(&mut reader);
+            let body = json!({ "jsonrpc": "2.0", "id": 1, "result": null }).to_string();
+            write!(
+                reader.get_mut(),
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            )
+            .unwrap();
+            vec![first, second]
+        });
+        (url, handle)
+    }
+
+    #[tokio::test]
+    async fn run_resubmit_until_included() {
+        let (provider, mock) = Provider::mocked();
+        // Nothing listens, the relay's failure doesn't stop the loop.
+        let relay = Url::parse("http://127.0.0.1:1").unwrap();
+        let submitter =
+            BundleSubmitter::flashbots(relay.clone(), LocalWallet::new(&mut thread_rng()))
+                .skip_gate();
+        let signer = LocalWallet::new(&mut thread_rng()).with_chain_id(1_u64);
+        let submission = SubmissionLoop::new(&provider, &submitter, &signer)
+            .path(SubmissionPath::Bundle)
+            .poll_interval(Duration::ZERO);
+
+        let receipt = TransactionReceipt {
+            block_number: Some(U64::from(102)),
+            ..Default::default()
+        };
+        // Missed 101, landed in 102.
+        mock.push(receipt).unwrap();
+        mock.push(U64::from(102)).unwrap();
+        mock.push(latest_block(101, 20)).unwrap();
+        mock.push(Option::<TransactionReceipt>::None).unwrap();
+        mock.push(U64::from(101)).unwrap();
+        mock.push(U64::from(100)).unwrap();
+        mock.push(latest_block(100, 10)).unwrap();
+
+        let report = submission
+            .run(verified_queue(10_000_000), pending())
+            .await
+            .unwrap();
+        assert_eq!(report.end, SubmissionEnd::Included(U64::from(102)));
+        assert_eq!(report.landed_block(), Some(U64::from(102)));
+        let targets = report
+            .attempts
+            .iter()
+            .map(|attempt| attempt.target_block)
+            .collect::<Vec<_>>();
+        assert_eq!(targets, vec![U64::from(101), U64::from(102)]);
+        // The max fee follows the base fee, the tip stays.
+        assert_eq!(
+            report.attempts[0].economics.gas_cost,
+            U256::from(100000 * 12)
+        );
+        assert_eq!(
+            report.attempts[1].economics.gas_cost,
+            U256::from(100000 * 22)
+        );
+        assert!(matches!(
+            &report.attempts[0].submission,
+            Ok(submission) if matches!(submission.outcomes[&relay], RelayOutcome::Error(_))
+        ));
+    }
+
+    #[test]
+    fn next_base_fee_saturate() {
+        let full = |base_fee| Block {
+            gas_used: U256::from(30_000_000),
+            base_fee_per_gas: Some(base_fee),
+            ..latest_block(100, 0)
+        };
+        assert_eq!(next_base_fee(&full(U256::from(800))), Some(U256::from(900)));
+        assert_eq!(next_base_fee(&full(U256::MAX)), Some(U256::MAX));
+
+        let empty = Block {
+            gas_used: U256::zero(),
+            base_fee_per_gas: Some(U256::MAX),
+            ..latest_block(100, 0)
+        };
+        assert!(next_base_fee(&empty).unwrap() < U256::MAX);
+    }
+
+    #[tokio::test]
+    async fn run_stop_on_collapsed_profit_and_cancel() {
+        let (provider, mock) = Provider::mocked();
+        let submitter = BundleSubmitter::flashbots(
+            Url::parse("http://127.0.0.1:1").unwrap(),
+            LocalWallet::new(&mut thread_rng()),
+        );
+        let signer = LocalWallet::new(&mut thread_rng()).with_chain_id(1_u64);
+        let submission = SubmissionLoop::new(&provider, &submitter, &signer);
+
+        // At a base fee of 100 the gas costs 10_200_000, more than the queue earns.
+        mock.push(latest_block(100, 100)).unwrap();
+        let report = submission
+            .run(verified_queue(10_000_000), pending())
+            .await
+            .unwrap();
+        assert_eq!(report.end, SubmissionEnd::Unprofitable);
+        assert!(report.attempts.is_empty());
+
+        let report = submission
+            .run(verified_queue(10_000_000), async {})
+            .await
+            .unwrap();
+        assert_eq!(report.end, SubmissionEnd::Cancelled);
+        assert_eq!(report.landed_block(), None);
+    }
+
+    #[tokio::test]
+    async fn run_abort_on_base_fee_above_break_even() {
+        let (provider, mock) = Provider::mocked();
+        let submitter = BundleSubmitter::flashbots(
+            Url::parse("http://127.0.0.1:1").unwrap(),
+            LocalWallet::new(&mut thread_rng()),
+        )
+        .skip_gate();
+        let signer = LocalWallet::new(&mut thread_rng()).with_chain_id(1_u64);
+        // Half of 10_000_000 over 100000 gas, the base fee and the tip of 2 may reach 50.
+        let submission = SubmissionLoop::new(&provider, &submitter, &signer)
+            .path(SubmissionPath::Bundle)
+            .poll_interval(Duration::ZERO)
+            .abort_above_break_even(5000);
+
+        // Sent for 101, missed, then the base fee spikes.
+        mock.push(latest_block(101, 49)).unwrap();
+        mock.push(Option::<TransactionReceipt>::None).unwrap();
+        mock.push(U64::from(101)).unwrap();
+        mock.push(U64::from(100)).unwrap();
+        mock.push(latest_block(100, 10)).unwrap();
+
+        let report = submission
+            .run(verified_queue(10_000_000), pending())
+            .await
+            .unwrap();
+        // Below 50 but not with the tip, still profitable without the margin, no longer sent.
+        assert_eq!(report.end, SubmissionEnd::BaseFeeAboveBreakEven);
+        assert_eq!(report.attempts.len(), 1);
+        assert_eq!(
+            report.fee_trajectory,
+            vec![
+                (U64::from(100), U256::from(10)),
+                (U64::from(101), U256::from(49))
+            ]
+        );
+    }
+
+    // Refuses every queue, keeps the backrun of each check.
+    #[derive(Default)]
+    struct RefusingGate(Mutex<Vec<Option<Bytes>>>);
+
+    #[async_trait(?Send)]
+    impl SubmissionGate for RefusingGate {
+        async fn check(
+            &self,
+            _: &TxQueue,
+            options: &BundleOptions,
+            _: U256,
+        ) -> Result<(), SimulateError> {
+            self.0.lock().unwrap().push(options.backrun.clone());
+            Err(SimulateError::VictimAlreadyIncluded {
+                block: options.target_block - 1,
+                reason: "unprofitable after the victim".into(),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn run_abort_on_victim_already_included() {
+        let (provider, mock) = Provider::mocked();
+        let submitter = BundleSubmitter::flashbots(
+            Url::parse("http://127.0.0.1:1").unwrap(),
+            LocalWallet::new(&mut thread_rng()),
+        );
+        let signer = LocalWallet::new(&mut thread_rng()).with_chain_id(1_u64);
+
+        // The gate gets the victim and finds the queue unprofitable on top of its block.
+        let victim_raw_tx = Bytes::from(vec![0xf8, 0x01]);
+        let gate = RefusingGate::default();
+        mock.push(latest_block(100, 10)).unwrap();
+        let report = SubmissionLoop::new(&provider, &submitter, &signer)
+            .path(SubmissionPath::Bundle)
+            .gate(&gate)
+            .backrun(victim_raw_tx.clone())
+            .run(verified_queue(10_000_000), pending())
+            .await
+            .unwrap();
+        assert_eq!(
+            report.end,
+            SubmissionEnd::VictimAlreadyIncluded(U64::from(100))
+        );
+        assert_eq!(*gate.0.lock().unwrap(), vec![Some(victim_raw_tx)]);
+        assert!(report.attempts[0].submission.is_err());
+        mock.assert_request("eth_getBlockByNumber", ("latest", false))
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn run_refuse_shared_reputation_key() {
+        let (provider, mock) = Provider::mocked();
+        let signer = LocalWallet::new(&mut thread_rng()).with_chain_id(1_u64);
+        let submitter =
+            BundleSubmitter::flashbots(Url::parse("http://127.0.0.1:1").unwrap(), signer.clone());
+        let submission = SubmissionLoop::new(&provider, &submitter, &signer);
+
+        assert!(matches!(
+            submission.run(verified_queue(10_000_000), pending()).await,
+            Err(SimulateError::SharedReputationKey { address }) if address == signer.address()
+        ));
+
+        // At a base fee of 100 the queue is unprofitable right away.
+        mock.push(latest_block(100, 100)).unwrap();
+        let submitter = submitter.allow_shared_key();
+        let report = SubmissionLoop::new(&provider, &submitter, &signer)
+            .run(verified_queue(10_000_000), pending())
+            .await
+            .unwrap();
+        assert_eq!(report.end, SubmissionEnd::Unprofitable);
+        assert_eq!(report.reputation_key, signer.address());
+    }
+
+    // Keeps what was recorded, in order.
+    #[derive(Default)]
+    struct RecordingStore(Mutex<Vec<(OpportunityId, String)>>);
+
+    impl RecordingStore {
+        fn push(&self, id: OpportunityId, record: String) -> Result<(), SimulateError> {
+            self.0.lock().unwrap().push((id, record));
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl OpportunityStore for RecordingStore {
+        async fn record_simulation(
+            &self,
+            id: OpportunityId,
+            _: &SimulationSummary,
+        ) -> Result<(), SimulateError> {
+            self.push(id, "simulation".into())
+        }
+
+        async fn record_economics(
+            &self,
+            id: OpportunityId,
+            economics: &QueueEconomics,
+        ) -> Result<(), SimulateError> {
+            self.push(id, format!("economics {}", economics.expected_profit))
+        }
+
+        async fn record_submission(
+            &self,
+            id: OpportunityId,
+            report: &SubmissionReport,
+        ) -> Result<(), SimulateError> {
+            self.push(id, format!("submission {:?}", report.end))
+        }
+
+        async fn record_reconciliation(
+            &self,
+            id: OpportunityId,
+            _: &Reconciliation,
+        ) -> Result<(), SimulateError> {
+            self.push(id, "reconciliation".into())
+        }
+
+        async fn query(
+            &self,
+            _: &OpportunityQuery,
+        ) -> Result<Vec<StoredOpportunity>, SimulateError> {
+            Ok(vec![])
+        }
+    }
+
+    #[tokio::test]
+    async fn run_record_submission_under_opportunity_id() {
+        let (provider, mock) = Provider::mocked();
+        let submitter = BundleSubmitter::flashbots(
+            Url::parse("http://127.0.0.1:1").unwrap(),
+            LocalWallet::new(&mut thread_rng()),
+        );
+        let signer = LocalWallet::new(&mut thread_rng()).with_chain_id(1_u64);
+        let store = RecordingStore::default();
+        let submission = SubmissionLoop::new(&provider, &submitter, &signer).store(&store);
+        let id = OpportunityId::new();
+
+        // Unprofitable right away.
+        mock.push(latest_block(100, 100)).unwrap();
+        let report = submission
+            .run(
+                TxQueue {
+                    opportunity_id: Some(id),
+                    ..verified_queue(10_000_000)
+                },
+                pending(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(report.opportunity_id, Some(id));
+        assert_eq!(
+            *store.0.lock().unwrap(),
+            vec![
+                (id, "economics 10000000".to_string()),
+                (id, "submission Unprofitable".to_string()),
+            ]
+        );
+
+        // Nothing to record it under.
+        mock.push(latest_block(100, 100)).unwrap();
+        submission
+            .run(verified_queue(10_000_000), pending())
+            .await
+            .unwrap();
+        assert_eq!(store.0.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn run_withdraw_stale_bundle() {
+        let (provider, mock) = Provider::mocked();
+        // Nothing listens, the cancellation is still tried.
+        let relay = Url::parse("http://127.0.0.1:1").unwrap();
+        let submitter =
+            BundleSubmitter::flashbots(relay.clone(), LocalWallet::new(&mut thread_rng()))
+                .skip_gate();
+        let signer = LocalWallet::new(&mut thread_rng()).with_chain_id(1_u64);
+        let submission = SubmissionLoop::new(&provider, &submitter, &signer)
+            .path(SubmissionPath::Bundle)
+            .stale_if(|_, latest| latest >= U64::from(100));
+
+        mock.push(U64::from(100)).unwrap();
+        mock.push(latest_block(100, 10)).unwrap();
+
+        let report = submission
+            .run(verified_queue(10_000_000), pending())
+            .await
+            .unwrap();
+        assert_eq!(report.end, SubmissionEnd::Stale);
+        assert_eq!(report.attempts.len(), 1);
+        assert_eq!(report.replacement_uuid.as_ref().unwrap().len(), 36);
+        let bundle = BundlePayload::from_relay_json(&report.to_relay_json().unwrap()).unwrap();
+        assert_eq!(bundle.target_block, U64::from(101));
+        assert_eq!(bundle.replacement_uuid, report.replacement_uuid);
+        assert_eq!(
+            bundle
+                .txs
+                .iter()
+                .map(|raw_tx| H256(keccak256(raw_tx)))
+                .collect::<Vec<_>>(),
+            report.attempts[0].tx_hashes
+        );
+        let cancellation = report.cancellation.unwrap();
+        assert!(cancellation.outcomes[&relay].is_err());
+        assert_eq!(cancellation.acknowledged(), 0);
+
+        // A single entry goes private by default, no uuid then.
+        mock.push(latest_block(100, 100)).unwrap();
+        let report = SubmissionLoop::new(&provider, &submitter, &signer)
+            .run(verified_queue(10_000_000), pending())
+            .await
+            .unwrap();
+        assert_eq!(report.replacement_uuid, None);
+        assert_eq!(report.to_relay_json(), None);
+        assert_eq!(report.cancellation, None);
+    }
+
+    #[tokio::test]
+    async fn run_withdraw_bundle_cancelled_in_flight() {
+        let (provider, mock) = Provider::mocked();
+        let sent = Arc::new(AtomicBool::new(false));
+        let (relay, requests) = hanging_relay(sent.clone());
+        let submitter =
+            BundleSubmitter::flashbots(relay.clone(), LocalWallet::new(&mut thread_rng()))
+                .skip_gate();
+        let signer = LocalWallet::new(&mut thread_rng()).with_chain_id(1_u64);
+
+        // Cancelled once the relay has the bundle, before it answered.
+        mock.push(latest_block(100, 10)).unwrap();
+        let report = SubmissionLoop::new(&provider, &submitter, &signer)
+            .path(SubmissionPath::Bundle)
+            .run(verified_queue(10_000_000), async {
+                while !sent.load(Ordering::SeqCst) {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+            .await
+            .unwrap();
+        assert_eq!(report.end, SubmissionEnd::Cancelled);
+        assert!(report.attempts.is_empty());
+        assert_eq!(report.cancellation.unwrap().outcomes[&relay], Ok(()));
+
+        let requests = requests.join().unwrap();
+        assert_eq!(requests[0]["method"], "eth_sendBundle");
+        assert_eq!(requests[1]["method"], "eth_cancelBundle");
+        assert_eq!(
+            requests[1]["params"],
+            json!([{ "replacementUuid": report.replacement_uuid }])
+        );
+    }
+
+    #[tokio::test]
+    async fn fetch_stats_ask_again_every_block() {
+        let (provider, mock) = Provider::mocked();
+        // Nothing listens, the stats are never ready.
+        let relay = Url::parse("http://127.0.0.1:1").unwrap();
+        let submitter =
+            BundleSubmitter::flashbots(relay.clone(), LocalWallet::new(&mut thread_rng()));
+        let signer = LocalWallet::new(&mut thread_rng());
+        let submission = SubmissionLoop::new(&provider, &submitter, &signer)
+            .poll_interval(Duration::ZERO)
+            .stats(2);
+        let tx_queue = verified_queue(10_000_000);
+        let attempts = vec![SubmissionAttempt {
+            target_block: U64::from(100),
+            base_fee: None,
+            economics: tx_queue.economics.unwrap(),
+            submission: Ok(BundleSubmission {
+                outcomes: HashMap::from([(relay, RelayOutcome::Accepted(H256::random()))]),
+                succeeded: true,
+                bundle: None,
+            }),
+            tx_hashes: vec![TxHash::random()],
+            bundle: None,
+        }];
+
+        // Asked at 100, 101 and 102.
+        mock.push(U64::from(102)).unwrap();
+        mock.push(U64::from(101)).unwrap();
+        mock.push(U64::from(100)).unwrap();
+        let stats = submission.fetch_stats(&attempts, false, 2).await.unwrap();
+        assert_eq!(stats, (None, None));
+        for _ in 0..3 {
+            mock.assert_request("eth_blockNumber", ()).unwrap();
+        }
+        assert!(mock.assert_request("eth_blockNumber", ()).is_err());
+
+        // Nothing to ask for a private tx.
+        mock.push(U64::from(102)).unwrap();
+        let stats = submission.fetch_stats(&attempts, true, 2).await.unwrap();
+        assert_eq!(stats, (None, None));
+    }
+
+    #[tokio::test]
+    async fn compete_escalate_tip_up_to_break_even() {
+        let (provider, mock) = Provider::mocked();
+        let client = SignerMiddleware::new(provider, LocalWallet::new(&mut thread_rng()));
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let submitter = BundleSubmitter::flashbots(
+            Url::parse("http://127.0.0.1:1").unwrap(),
+            LocalWallet::new(&mut thread_rng()),
+        )
+        .skip_gate();
+
+        // 10_000_000 over 100000 gas is 100 per gas, at a base fee of 10 the tip can't reach 90.
+        mock.push(latest_block(103, 10)).unwrap();
+        for block in (100..103).rev() {
+            mock.push(Option::<TransactionReceipt>::None).unwrap();
+            mock.push(U64::from(block + 1)).unwrap();
+            // The gate's replay on the parent of the target block.
+            mock.push(latest_block(block, 10)).unwrap();
+            mock.push::<Vec<BlockTrace>, _>(vec![replayed()]).unwrap();
+            mock.push(latest_block(block, 10)).unwrap();
+        }
+
+        let report = simulate
+            .compete(
+                &submitter,
+                verified_queue(10_000_000),
+                U256::from(1000),
+                U256::from(40),
+                pending(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(report.end, SubmissionEnd::Unprofitable);
+        // Tips of 2, 42 and 82, the next 122 is past the break-even.
+        let gas_costs = report
+            .attempts
+            .iter()
+            .map(|attempt| attempt.economics.gas_cost)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            gas_costs,
+            [12, 52, 92]
+                .map(|fee: u64| U256::from(100000 * fee))
+                .to_vec()
+        );
+
+        // A lower cap ends it first.
+        mock.push(latest_block(101, 10)).unwrap();
+        mock.push(Option::<TransactionReceipt>::None).unwrap();
+        mock.push(U64::from(101)).unwrap();
+        mock.push(latest_block(100, 10)).unwrap();
+        mock.push::<Vec<BlockTrace>, _>(vec![replayed()]).unwrap();
+        mock.push(latest_block(100, 10)).unwrap();
+        let report = simulate
+            .compete(
+                &submitter,
+                verified_queue(10_000_000),
+                U256::from(30),
+                U256::from(40),
+                pending(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(report.end, SubmissionEnd::TipCapped);
+        assert_eq!(report.attempts.len(), 1);
+
+        // Reverting on the parent of the target block, nothing is sent.
+        let mut reverted = replayed();
+        reverted.trace = Some(vec![TransactionTrace {
+            trace_address: vec![],
+            subtraces: 0,
+            action: Action::Call(Call {
+                from: Address::zero(),
+                to: Address::zero(),
+                value: U256::zero(),
+                gas: U256::zero(),
+                input: Bytes::default(),
+                call_type: CallType::Call,
+            }),
+            action_type: ActionType::Call,
+            result: None,
+            error: Some("Reverted".into()),
+        }]);
+        mock.push(latest_block(100, 10)).unwrap();
+        mock.push::<Vec<BlockTrace>, _>(vec![reverted]).unwrap();
+        mock.push(latest_block(100, 10)).unwrap();
+        let report = simulate
+            .compete(
+                &submitter,
+                verified_queue(10_000_000),
+                U256::from(1000),
+                U256::from(40),
+                pending(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(report.end, SubmissionEnd::Refused);
+        assert!(matches!(
+            &report.attempts[0].submission,
+            Err(reason) if reason.contains("entry 0 reverts: Reverted")
+        ));
+        assert_eq!(report.attempts[0].bundle, None);
+        assert_eq!(report.to_relay_json(), None);
+    }
+
+    #[tokio::test]
+    async fn reconcile_landed_against_simulated_profit() {
+        let (provider, mock) = Provider::mocked();
+        let client = SignerMiddleware::new(provider, LocalWallet::new(&mut thread_rng()));
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let tx = Transaction {
+            hash: TxHash::random(),
+            from: client.address(),
+            to: Some(Address::random()),
+            ..Default::default()
+        };
+        let tx_queue = verified_queue(10_000_000);
+        let id = OpportunityId::new();
+        let attempt = SubmissionAttempt {
+            target_block: U64::from(101),
+            base_fee: Some(U256::from(10)),
+            economics: tx_queue.economics.unwrap(),
+            submission: Err("relay down".into()),
+            tx_hashes: vec![tx.hash],
+            bundle: None,
+        };
+        let mut report = SubmissionReport {
+            attempts: vec![attempt],
+            end: SubmissionEnd::Cancelled,
+            replacement_uuid: None,
+            cancellation: None,
+            bundle_stats: None,
+            user_stats: None,
+            fee_trajectory: vec![],
+            reputation_key: Address::zero(),
+            opportunity_id: Some(id),
+        };
+        let store = RecordingStore::default();
+        assert_eq!(
+            report.reconcile(&simulate, Some(&store)).await.unwrap(),
+            None
+        );
+        report.end = SubmissionEnd::Included(U64::from(101));
+
+        // The pool moved, the landed tx earned less than simulated.
+        let landed = BlockTrace {
+            state_diff: Some(StateDiff(BTreeMap::from([(
+                tx.from,
+                AccountDiff {
+                    balance: Diff::Changed(ChangedType {
+                        from: U256::from(1_000_000),
+                        to: U256::from(9_000_000),
+                    }),
+                    nonce: Diff::Same,
+                    code: Diff::Same,
+                    storage: BTreeMap::new(),
+                },
+            )]))),
+            ..replayed()
+        };
+        let receipt = TransactionReceipt {
+            gas_used: Some(U256::from(100000)),
+            effective_gas_price: Some(U256::from(12)),
+            block_number: Some(U64::from(101)),
+            ..Default::default()
+        };
+        mock.push(landed).unwrap();
+        mock.push(receipt).unwrap();
+        mock.push(tx.clone()).unwrap();
+
+        let reconciliation = report
+            .reconcile(&simulate, Some(&store))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            *store.0.lock().unwrap(),
+            vec![(id, "reconciliation".to_string())]
+        );
+        // 10000000 less the 1200000 of gas at the max fee.
+        assert_eq!(reconciliation.simulated_profit, I256::from(8_800_000));
+        assert_eq!(reconciliation.realized_profit, U256::from(8_000_000));
+        assert_eq!(reconciliation.gas_paid, U256::from(1_200_000));
+        assert_eq!(reconciliation.slippage, I256::from(800_000));
+        let json = serde_json::to_value(reconciliation).unwrap();
+        assert_eq!(json["simulated_profit"], json!("8800000"));
+        assert_eq!(json["slippage"], json!("800000"));
+        assert_eq!(
+            serde_json::from_value::<Reconciliation>(json).unwrap(),
+            reconciliation
+        );
+        mock.assert_request("eth_getTransactionByHash", [tx.hash])
+            .unwrap();
+        mock.assert_request("eth_getTransactionReceipt", [tx.hash])
+            .unwrap();
+        mock.assert_request(
+            "trace_replayTransaction",
+            json!([tx.hash, ["trace", "stateDiff"]]),
+        )
+        .unwrap();
+    }
+}
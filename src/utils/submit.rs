@@ -0,0 +1,1625 @@
+use crate::utils::{
+    retry_after, BundlePayload, FlashbotsAdapter, MevShareBundle, RateLimit, RateLimiter,
+    RelayAdapter, SimulateError, TxQueue,
+};
+use ethers::{
+    prelude::*,
+    utils::{hex, keccak256},
+};
+use futures::future::join_all;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use url::Url;
+
+// The block a bundle targets and the conditions the relay includes it under.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BundleOptions {
+    pub target_block: U64,
+    pub min_timestamp: Option<u64>,
+    pub max_timestamp: Option<u64>,
+    // Queue entries (by index) the bundle still lands with if they revert, e.g. a bribe that may
+    // fail once the profit is gone.
+    pub reverting: Vec<usize>,
+    // The victim's signed tx, sent first so the queue lands right behind it. It must not revert,
+    // the entries of the queue all may then.
+    pub backrun: Option<Bytes>,
+    // Sent as `replacementUuid`: a later bundle with it replaces this one, and
+    // `BundleSubmitter::cancel` withdraws it.
+    pub replacement_uuid: Option<String>,
+}
+
+impl BundleOptions {
+    pub fn new(target_block: U64) -> Self {
+        Self {
+            target_block,
+            ..Default::default()
+        }
+    }
+
+    pub fn min_timestamp(mut self, min_timestamp: u64) -> Self {
+        self.min_timestamp = Some(min_timestamp);
+        self
+    }
+
+    pub fn max_timestamp(mut self, max_timestamp: u64) -> Self {
+        self.max_timestamp = Some(max_timestamp);
+        self
+    }
+
+    pub fn reverting(mut self, index: usize) -> Self {
+        self.reverting.push(index);
+        self
+    }
+
+    // Also let the revertible entries of `tx_queue` revert, see `QueueEntry::revertible`.
+    pub fn reverting_entries(mut self, tx_queue: &TxQueue) -> Self {
+        for index in tx_queue.revertible() {
+            if !self.reverting.contains(&index) {
+                self.reverting.push(index);
+            }
+        }
+        self
+    }
+
+    // Backrun `victim_raw_tx`, see `Simulate::victim_raw_tx`.
+    pub fn backrun(mut self, victim_raw_tx: Bytes) -> Self {
+        self.backrun = Some(victim_raw_tx);
+        self
+    }
+
+    pub fn replacement_uuid(mut self, replacement_uuid: String) -> Self {
+        self.replacement_uuid = Some(replacement_uuid);
+        self
+    }
+}
+
+// A random (version 4) uuid, for `BundleOptions::replacement_uuid`.
+pub fn new_replacement_uuid() -> String {
+    let mut bytes = ethers::core::rand::random::<[u8; 16]>();
+    bytes[6] = bytes[6] & 0x0f | 0x40;
+    bytes[8] = bytes[8] & 0x3f | 0x80;
+    let hex = hex::encode(bytes);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+// The preferences of an `eth_sendPrivateTransaction`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrivateTxOptions {
+    // The last block the relay tries to include the tx in, the queue's `valid_until_block` if
+    // unset.
+    pub max_block_number: Option<U64>,
+    // Shared with every builder for the fastest inclusion, not only the relay's own.
+    pub fast: bool,
+}
+
+impl PrivateTxOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn max_block_number(mut self, max_block_number: U64) -> Self {
+        self.max_block_number = Some(max_block_number);
+        self
+    }
+
+    pub fn fast(mut self) -> Self {
+        self.fast = true;
+        self
+    }
+}
+
+// Whether a queue is sent as a bundle or as a private tx.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SubmissionPath {
+    // A private tx for a queue of one entry, a bundle otherwise.
+    #[default]
+    Auto,
+    Bundle,
+    Private,
+}
+
+impl SubmissionPath {
+    pub fn is_private(&self, tx_queue: &TxQueue) -> bool {
+        match self {
+            Self::Auto => tx_queue.entries.len() == 1,
+            Self::Bundle => false,
+            Self::Private => true,
+        }
+    }
+}
+
+// An endpoint of the relay list, with the adapter of the payload dialect it speaks.
+#[derive(Debug, Clone)]
+pub struct Relay {
+    pub url: Url,
+    pub adapter: Arc<dyn RelayAdapter>,
+}
+
+impl Relay {
+    pub fn new(url: Url, adapter: impl RelayAdapter + 'static) -> Self {
+        Self {
+            url,
+            adapter: Arc::new(adapter),
+        }
+    }
+
+    pub fn flashbots(url: Url) -> Self {
+        Self::new(url, FlashbotsAdapter::default())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RelayOutcome {
+    Accepted(H256),
+    // The relay answered with a json-rpc error.
+    Rejected(String),
+    // The relay couldn't be reached or answered garbage.
+    Error(String),
+    // The relay answered HTTP 429, nothing more is sent to it for a while.
+    RateLimited(String),
+    // Not sent, the turn of the request at the relay was further away than `RateLimit::max_wait`.
+    Throttled(Duration),
+}
+
+// The outcome of `BundleSubmitter::submit_all` at every relay.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BundleSubmission {
+    pub outcomes: HashMap<Url, RelayOutcome>,
+    // At least `min_accepted` relays accepted the bundle.
+    pub succeeded: bool,
+    // What was sent to the relays, `None` for a private tx or a MEV-Share bundle.
+    pub bundle: Option<BundlePayload>,
+}
+
+impl BundleSubmission {
+    pub fn accepted(&self) -> usize {
+        self.outcomes
+            .values()
+            .filter(|outcome| matches!(outcome, RelayOutcome::Accepted(_)))
+            .count()
+    }
+}
+
+// The acknowledgement of every relay asked to withdraw a bundle or private tx, or its error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BundleCancellation {
+    pub outcomes: HashMap<Url, Result<(), String>>,
+}
+
+impl BundleCancellation {
+    pub fn acknowledged(&self) -> usize {
+        self.outcomes
+            .values()
+            .filter(|outcome| outcome.is_ok())
+            .count()
+    }
+}
+
+// Sends signed queues to relays as `eth_sendBundle`, the requests are authenticated with the
+// reputation key of `auth_signer`, not the key the txs are signed with.
+pub struct BundleSubmitter<A> {
+    client: reqwest::Client,
+    relays: Vec<Relay>,
+    auth_signer: A,
+    min_accepted: usize,
+    rate_limiter: Option<RateLimiter>,
+    // Simulate every submission at a relay before sending it, see `skip_gate`.
+    gate: bool,
+    allow_shared_key: bool,
+    refuse_unprofitable: bool,
+}
+
+impl<A: Signer> BundleSubmitter<A> {
+    pub fn flashbots(relay_url: Url, auth_signer: A) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            relays: vec![Relay::flashbots(relay_url)],
+            auth_signer,
+            min_accepted: 1,
+            rate_limiter: None,
+            gate: true,
+            allow_shared_key: false,
+            refuse_unprofitable: true,
+        }
+    }
+
+    // Sign the relay requests with another reputation key than the one the submitter was built
+    // with. It should never be the key the txs are signed with, see `ensure_own_key`.
+    pub fn auth_signer(mut self, auth_signer: A) -> Self {
+        self.auth_signer = auth_signer;
+        self
+    }
+
+    // The identity relays know the submitter by.
+    pub fn auth_address(&self) -> Address {
+        self.auth_signer.address()
+    }
+
+    // Let the tx signer also sign the relay requests, refused by every submission otherwise: a
+    // hot trading key shouldn't carry the submitter's reputation.
+    pub fn allow_shared_key(mut self) -> Self {
+        self.allow_shared_key = true;
+        self
+    }
+
+    // `SharedReputationKey` if txs signed by `signer` would go out under the reputation key.
+    pub fn ensure_own_key(&self, signer: Address) -> Result<(), SimulateError> {
+        match signer == self.auth_address() && !self.allow_shared_key {
+            true => Err(SimulateError::SharedReputationKey { address: signer }),
+            false => Ok(()),
+        }
+    }
+
+    // Also send to `relay`, builders only include bundles of their own relay.
+    pub fn relay(mut self, relay: Relay) -> Self {
+        self.relays.push(relay);
+        self
+    }
+
+    // How many relays must accept the bundle for the submission to count as a success.
+    pub fn min_accepted(mut self, min_accepted: usize) -> Self {
+        self.min_accepted = min_accepted;
+        self
+    }
+
+    // Pace the requests to every relay, whichever submission they come from, so resubmitting
+    // doesn't get the reputation key throttled.
+    pub fn rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limiter = Some(RateLimiter::new(rate_limit));
+        self
+    }
+
+    // Send without simulating at the relay first, a queue that reverts (or backruns a victim that
+    // was mined already) then still goes out and costs the reputation key.
+    pub fn skip_gate(mut self) -> Self {
+        self.gate = false;
+        self
+    }
+
+    // Whether the submissions refuse queues whose economics net no profit, on by default like
+    // `Simulate::refuse_unprofitable`.
+    pub fn refuse_unprofitable(mut self, refuse_unprofitable: bool) -> Self {
+        self.refuse_unprofitable = refuse_unprofitable;
+        self
+    }
+
+    fn ensure_profitable(&self, tx_queue: &TxQueue) -> Result<(), SimulateError> {
+        match self.refuse_unprofitable {
+            true => tx_queue.ensure_profitable(),
+            false => Ok(()),
+        }
+    }
+
+    // The `eth_sendBundle` params of the signed queue.
+    pub fn bundle_params(raw_tx_list: &[Bytes], options: &BundleOptions) -> Value {
+        FlashbotsAdapter::default()
+            .bundle_request(&BundlePayload::new(raw_tx_list, options))
+            .1
+    }
+
+    // `submit_all`, the bundle hash of the first relay (in the order they were added) that
+    // accepted it, an error if fewer than `min_accepted` did.
+    pub async fn submit<S: Signer>(
+        &self,
+        tx_queue: &TxQueue,
+        signer: &S,
+        options: &BundleOptions,
+    ) -> Result<H256, SimulateError> {
+        let submission = self.submit_all(tx_queue, signer, options).await?;
+        let first_accepted =
+            self.relays
+                .iter()
+                .find_map(|relay| match submission.outcomes.get(&relay.url) {
+                    Some(RelayOutcome::Accepted(bundle_hash)) => Some(*bundle_hash),
+                    _ => None,
+                });
+        match first_accepted {
+            Some(bundle_hash) if submission.succeeded => Ok(bundle_hash),
+            _ => Err(SimulateError::BundleNotAccepted {
+                accepted: submission.accepted(),
+                min_accepted: self.min_accepted,
+                outcomes: submission.outcomes,
+            }),
+        }
+    }
+
+    // Sign the queue with `signer` once and send it to every relay at the same time for
+    // `options.target_block`, a failing relay doesn't hold up the others. Refused before anything
+    // is sent if `signer` holds the reputation key (see `ensure_own_key`), the queue is expired by
+    // the target block, nets no profit (see `refuse_unprofitable`) or doesn't pass `gate_bundle`.
+    pub async fn submit_all<S: Signer>(
+        &self,
+        tx_queue: &TxQueue,
+        signer: &S,
+        options: &BundleOptions,
+    ) -> Result<BundleSubmission, SimulateError> {
+        self.ensure_own_key(signer.address())?;
+        self.ensure_profitable(tx_queue)?;
+        // Included in the target block means the chain was at the block before.
+        tx_queue.ensure_valid(options.target_block.saturating_sub(U64::one()))?;
+
+        let raw_tx_list = tx_queue.sign_with(signer).await?;
+        let options = options.clone().reverting_entries(tx_queue);
+        let bundle = &match self.gate {
+            true => self.gate_bundle(tx_queue, &raw_tx_list, &options).await?,
+            false => BundlePayload::new(&raw_tx_list, &options),
+        };
+        let outcomes = join_all(self.relays.iter().map(|relay| async move {
+            let (method, params) = relay.adapter.bundle_request(bundle);
+            let outcome = self.send_bundle(relay, &method, params).await;
+            (relay.url.clone(), outcome)
+        }))
+        .await
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+
+        Ok(BundleSubmission {
+            bundle: Some(bundle.clone()),
+            ..self.submission(outcomes)
+        })
+    }
+
+    // Backrun the hinted tx of `bundle` through the MEV-Share matchmaker, at the relays of the
+    // flashbots dialect, the others don't speak `mev_sendBundle`. Refused like `submit_all`.
+    pub async fn submit_mev_share<S: Signer>(
+        &self,
+        tx_queue: &TxQueue,
+        signer: &S,
+        bundle: &MevShareBundle,
+    ) -> Result<BundleSubmission, SimulateError> {
+        self.ensure_own_key(signer.address())?;
+        self.ensure_profitable(tx_queue)?;
+        tx_queue.ensure_valid(bundle.block.saturating_sub(U64::one()))?;
+
+        let bundle = bundle.clone().reverting_entries(tx_queue);
+        let params = &bundle.to_params(&tx_queue.sign_with(signer).await?);
+        if self.gate {
+            self.gate_mev_share(tx_queue, bundle.block, params).await?;
+        }
+        let outcomes = join_all(self.flashbots_relays().map(|relay| async move {
+            let outcome = self
+                .send_bundle(relay, "mev_sendBundle", params.clone())
+                .await;
+            (relay.url.clone(), outcome)
+        }))
+        .await
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+
+        Ok(self.submission(outcomes))
+    }
+
+    // Sign the single entry of the queue and send it to the relays of the flashbots dialect as
+    // `eth_sendPrivateTransaction`, accepted outcomes hold the tx hash. Refused before anything is
+    // sent for a queue of more entries, a shared key or no profit, and by the gate for the max block (without
+    // one there's no block to simulate it for).
+    pub async fn submit_private<S: Signer>(
+        &self,
+        tx_queue: &TxQueue,
+        signer: &S,
+        options: &PrivateTxOptions,
+    ) -> Result<BundleSubmission, SimulateError> {
+        if tx_queue.entries.len() != 1 {
+            return Err(SimulateError::PrivateTxNotSingle {
+                entries: tx_queue.entries.len(),
+            });
+        }
+        self.ensure_own_key(signer.address())?;
+        self.ensure_profitable(tx_queue)?;
+
+        // Never past the queue's validity.
+        let max_block_number = match (options.max_block_number, tx_queue.valid_until_block) {
+            (Some(max_block), Some(valid_until)) => Some(max_block.min(valid_until)),
+            (max_block, valid_until) => max_block.or(valid_until),
+        };
+        let raw_tx = tx_queue.sign_with(signer).await?.remove(0);
+        if self.gate {
+            let target_block = max_block_number.ok_or_else(|| SimulateError::GateRefused {
+                block: U64::zero(),
+                reason: "private tx without a max block".into(),
+            })?;
+            let options = BundleOptions::new(target_block).reverting_entries(tx_queue);
+            let bundle = BundlePayload::new(&[raw_tx.clone()], &options);
+            self.call_bundle(tx_queue, &bundle, false).await?;
+        }
+        let mut private_tx = json!({
+            "tx": raw_tx,
+            "preferences": { "fast": options.fast },
+        });
+        if let Some(max_block_number) = max_block_number {
+            private_tx["maxBlockNumber"] = json!(max_block_number);
+        }
+        let params = &json!([private_tx]);
+
+        let outcomes = join_all(self.flashbots_relays().map(|relay| async move {
+            let outcome = match self
+                .request(relay, "eth_sendPrivateTransaction", params.clone())
+                .await
+            {
+                Ok(result) => match serde_json::from_value(result) {
+                    Ok(tx_hash) => RelayOutcome::Accepted(tx_hash),
+                    Err(e) => RelayOutcome::Error(e.to_string()),
+                },
+                Err(outcome) => outcome,
+            };
+            (relay.url.clone(), outcome)
+        }))
+        .await
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+        Ok(self.submission(outcomes))
+    }
+
+    // Withdraw a private tx of `submit_private` that didn't land yet.
+    pub async fn cancel_private(&self, tx_hash: TxHash) -> BundleCancellation {
+        let params = &json!([{ "txHash": tx_hash }]);
+        let outcomes = join_all(self.flashbots_relays().map(|relay| async move {
+            let outcome = match self
+                .request(relay, "eth_cancelPrivateTransaction", params.clone())
+                .await
+            {
+                Ok(Value::Bool(true)) => Ok(()),
+                Ok(result) => Err(format!("not cancelled: {result}")),
+                Err(outcome) => Err(format!("{outcome:?}")),
+            };
+            (relay.url.clone(), outcome)
+        }))
+        .await
+        .into_iter()
+        .collect();
+        BundleCancellation { outcomes }
+    }
+
+    // Withdraw the bundles sent with `replacement_uuid` from the relays of the flashbots dialect
+    // that `submission` reached, the minimal one never got the uuid and a relay that throttled or
+    // rate limited the bundle never took it. `None` while the relays were still answering, it's
+    // withdrawn from every one of them then. `mev_cancelBundleByUuid` is tried at a relay that
+    // doesn't know `eth_cancelBundle`.
+    pub async fn cancel(
+        &self,
+        replacement_uuid: &str,
+        submission: Option<&BundleSubmission>,
+    ) -> BundleCancellation {
+        let relays = self.flashbots_relays().filter(|relay| match submission {
+            Some(submission) => matches!(
+                submission.outcomes.get(&relay.url),
+                Some(
+                    RelayOutcome::Accepted(_) | RelayOutcome::Rejected(_) | RelayOutcome::Error(_)
+                )
+            ),
+            None => true,
+        });
+        let outcomes = join_all(relays.map(|relay| async move {
+            let mut outcome = self
+                .request(
+                    relay,
+                    "eth_cancelBundle",
+                    json!([{ "replacementUuid": replacement_uuid }]),
+                )
+                .await;
+            if matches!(&outcome, Err(RelayOutcome::Rejected(error)) if error.contains("-32601")) {
+                outcome = self
+                    .request(relay, "mev_cancelBundleByUuid", json!([replacement_uuid]))
+                    .await;
+            }
+            let outcome = outcome
+                .map(|_| ())
+                .map_err(|outcome| format!("{outcome:?}"));
+            (relay.url.clone(), outcome)
+        }))
+        .await
+        .into_iter()
+        .collect();
+        BundleCancellation { outcomes }
+    }
+
+    // The relays that speak more than `eth_sendBundle`.
+    pub(crate) fn flashbots_relays(&self) -> impl Iterator<Item = &Relay> {
+        self.relays
+            .iter()
+            .filter(|relay| relay.adapter.flashbots_methods())
+    }
+
+    fn submission(&self, outcomes: HashMap<Url, RelayOutcome>) -> BundleSubmission {
+        let submission = BundleSubmission {
+            outcomes,
+            succeeded: false,
+            bundle: None,
+        };
+        BundleSubmission {
+            succeeded: submission.accepted() >= self.min_accepted,
+            ..submission
+        }
+    }
+
+    async fn send_bundle(&self, relay: &Relay, method: &str, params: Value) -> RelayOutcome {
+        match self.request(relay, method, params).await {
+            Ok(result) => match relay.adapter.bundle_hash(&result) {
+                Ok(bundle_hash) => RelayOutcome::Accepted(bundle_hash),
+                Err(e) => RelayOutcome::Error(e),
+            },
+            Err(outcome) => outcome,
+        }
+    }
+
+    // The `result` of the relay's answer, the outcome if there is none.
+    pub(crate) async fn request(
+        &self,
+        relay: &Relay,
+        method: &str,
+        params: Value,
+    ) -> Result<Value, RelayOutcome> {
+        let body = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": params,
+        })
+        .to_string();
+
+        let mut request = self
+            .client
+            .post(relay.url.clone())
+            .header("Content-Type", "application/json");
+        if relay.adapter.signs_requests() {
+            match self.signature(&body).await {
+                Ok(signature) => request = request.header("X-Flashbots-Signature", signature),
+                Err(e) => return Err(RelayOutcome::Error(e.to_string())),
+            }
+        }
+        for (name, value) in relay.adapter.headers() {
+            request = request.header(name, value);
+        }
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter
+                .acquire(&relay.url)
+                .await
+                .map_err(RelayOutcome::Throttled)?;
+        }
+        let response = match request.body(body).send().await {
+            Ok(response) if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS => {
+                let retry_after = response
+                    .headers()
+                    .get(reqwest::header::RETRY_AFTER)
+                    .and_then(|value| retry_after(value.to_str().ok()?, SystemTime::now()));
+                if let Some(rate_limiter) = &self.rate_limiter {
+                    rate_limiter.cool_down(&relay.url, retry_after);
+                }
+                return Err(RelayOutcome::RateLimited(
+                    response.text().await.unwrap_or_default(),
+                ));
+            }
+            Ok(response) => response.json::<Value>().await,
+            Err(e) => return Err(RelayOutcome::Error(e.to_string())),
+        };
+
+        match response {
+            Ok(response) => match response.get("error") {
+                Some(error) => {
+                    // A relay whose limit comes as an error (e.g. bloXroute) cools down all the
+                    // same, for as long as configured.
+                    let outcome = relay.adapter.map_error(error);
+                    if let (RelayOutcome::RateLimited(_), Some(rate_limiter)) =
+                        (&outcome, &self.rate_limiter)
+                    {
+                        rate_limiter.cool_down(&relay.url, None);
+                    }
+                    Err(outcome)
+                }
+                None => Ok(response["result"].clone()),
+            },
+            Err(e) => Err(RelayOutcome::Error(e.to_string())),
+        }
+    }
+
+    // The bundle of the signed queue that passes `call_bundle` under `options`. A backrun victim
+    // that was mined already (its nonce is used) is left out, the queue then has to pass on top
+    // of the victim's block or is `VictimAlreadyIncluded`.
+    async fn gate_bundle(
+        &self,
+        tx_queue: &TxQueue,
+        raw_tx_list: &[Bytes],
+        options: &BundleOptions,
+    ) -> Result<BundlePayload, SimulateError> {
+        let bundle = BundlePayload::new(raw_tx_list, options);
+        match self
+            .call_bundle(tx_queue, &bundle, options.backrun.is_some())
+            .await
+        {
+            Err(SimulateError::GateRefused { block, reason })
+                if options.backrun.is_some() && reason.contains("nonce too low") =>
+            {
+                let options = BundleOptions {
+                    backrun: None,
+                    ..options.clone()
+                };
+                let bundle = BundlePayload::new(raw_tx_list, &options);
+                match self.call_bundle(tx_queue, &bundle, false).await {
+                    Ok(()) => Ok(bundle),
+                    Err(SimulateError::GateRefused { reason, .. }) => {
+                        Err(SimulateError::VictimAlreadyIncluded { block, reason })
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            result => result.map(|_| bundle),
+        }
+    }
+
+    // The check before `bundle` of the signed queue is sent: `eth_callBundle` at the first relay
+    // of the flashbots dialect on the target block's parent state. No entry may revert that the
+    // bundle doesn't allow to (with the victim first, all of ours may), the victim's never, and
+    // the coinbase must gain at least the queue's bribe from ours. `GateRefused` with what failed.
+    async fn call_bundle(
+        &self,
+        tx_queue: &TxQueue,
+        bundle: &BundlePayload,
+        backrun: bool,
+    ) -> Result<(), SimulateError> {
+        let block = bundle.target_block.saturating_sub(U64::one());
+        let refuse = |reason: String| SimulateError::GateRefused { block, reason };
+        let relay = self
+            .flashbots_relays()
+            .next()
+            .ok_or_else(|| refuse("no relay simulates bundles".into()))?;
+        let params = json!([{
+            "txs": bundle.txs,
+            "blockNumber": bundle.target_block,
+            "stateBlockNumber": block,
+        }]);
+        let result = self
+            .request(relay, "eth_callBundle", params)
+            .await
+            .map_err(|outcome| refuse(format!("eth_callBundle failed: {outcome:?}")))?;
+        let results = result["results"].as_array().cloned().unwrap_or_default();
+        if results.len() != bundle.txs.len() {
+            return Err(refuse(format!(
+                "{} results for {} txs",
+                results.len(),
+                bundle.txs.len()
+            )));
+        }
+
+        let mut coinbase_diff = U256::zero();
+        for (i, (tx, raw_tx)) in results.iter().zip(&bundle.txs).enumerate() {
+            // `None` for the victim.
+            let index = i.checked_sub(usize::from(backrun));
+            let error = tx["revert"]
+                .as_str()
+                .filter(|revert| !revert.is_empty())
+                .or_else(|| tx["error"].as_str());
+            match (index, error) {
+                (None, Some(error)) => return Err(refuse(format!("victim tx reverts: {error}"))),
+                (Some(index), Some(error))
+                    if !bundle
+                        .reverting_tx_hashes
+                        .contains(&H256(keccak256(raw_tx))) =>
+                {
+                    return Err(refuse(format!("entry {index} reverts: {error}")));
+                }
+                _ => {}
+            }
+            if index.is_some() {
+                coinbase_diff = coinbase_diff.saturating_add(relay_amount(&tx["coinbaseDiff"]));
+            }
+        }
+        let bribe = tx_queue.economics.unwrap_or_default().bribe;
+        if coinbase_diff < bribe {
+            return Err(refuse(format!(
+                "coinbase gains {coinbase_diff}, less than the bribe {bribe}"
+            )));
+        }
+
+        Ok(())
+    }
+
+    // `call_bundle` of a MEV-Share bundle, `mev_simBundle` of its `params` at the first relay of
+    // the flashbots dialect: the matched bundle must succeed and pay the coinbase the bribe.
+    async fn gate_mev_share(
+        &self,
+        tx_queue: &TxQueue,
+        target_block: U64,
+        params: &Value,
+    ) -> Result<(), SimulateError> {
+        let block = target_block.saturating_sub(U64::one());
+        let refuse = |reason: String| SimulateError::GateRefused { block, reason };
+        let relay = self
+            .flashbots_relays()
+            .next()
+            .ok_or_else(|| refuse("no relay simulates bundles".into()))?;
+        let result = self
+            .request(relay, "mev_simBundle", params.clone())
+            .await
+            .map_err(|outcome| refuse(format!("mev_simBundle failed: {outcome:?}")))?;
+        if result["success"] != json!(true) {
+            return Err(refuse(format!(
+                "bundle fails: {}",
+                result["error"].as_str().unwrap_or("no error given")
+            )));
+        }
+        let (profit, bribe) = (
+            relay_amount(&result["profit"]),
+            tx_queue.economics.unwrap_or_default().bribe,
+        );
+        if profit < bribe {
+            return Err(refuse(format!(
+                "coinbase gains {profit}, less than the bribe {bribe}"
+            )));
+        }
+
+        Ok(())
+    }
+
+    // `address:signature` of the hex keccak of the body, signed as a personal message.
+    async fn signature(&self, body: &str) -> Result<String, SimulateError> {
+        let signature = self
+            .auth_signer
+            .sign_message(format!("{:?}", H256(keccak256(body))))
+            .await
+            .map_err(|e| SimulateError::AuthSigning(e.to_string()))?;
+        Ok(format!("{:?}:0x{signature}", self.auth_signer.address()))
+    }
+}
+
+// An amount of a relay's answer, a decimal or `0x` hex string (or a number), zero otherwise.
+fn relay_amount(value: &Value) -> U256 {
+    match value {
+        Value::String(amount) => match amount.strip_prefix("0x") {
+            Some(hex) => U256::from_str_radix(hex, 16).ok(),
+            None => U256::from_dec_str(amount).ok(),
+        },
+        Value::Number(amount) => amount.as_u64().map(U256::from),
+        _ => None,
+    }
+    .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        new_replacement_uuid, BundleOptions, BundleSubmission, BundleSubmitter, PrivateTxOptions,
+        Relay, RelayOutcome, SubmissionPath,
+    };
+    use crate::utils::{
+        BloxrouteAdapter, FlashbotsAdapter, MevShareBundle, QueueEconomics, RateLimit, RelayAuth,
+        RelayDialect, SimulateError, TxQueue,
+    };
+    use ethers::{
+        core::rand::thread_rng,
+        prelude::*,
+        utils::{hash_message, keccak256},
+    };
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+    use url::Url;
+
+    // A relay answering one request with `result`, hands back the request's headers and body.
+    fn mock_relay(result: Value) -> (Url, thread::JoinHandle<(Vec<String>, Value)>) {
+        mock_relay_with("200 OK", result)
+    }
+
+    fn mock_relay_with(
+        status: &'static str,
+        result: Value,
+    ) -> (Url, thread::JoinHandle<(Vec<String>, Value)>) {
+        let (url, relay) = mock_relay_answers(vec![(status, result)]);
+        (url, thread::spawn(move || relay.join().unwrap().remove(0)))
+    }
+
+    // A relay answering a request with each of `answers` in turn, hands back every request.
+    fn mock_relay_answers(
+        answers: Vec<(&'static str, Value)>,
+    ) -> (Url, thread::JoinHandle<Vec<(Vec<String>, Value)>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+        let handle = thread::spawn(move || {
+            answers
+                .into_iter()
+                .map(|(status, result)| answer(&listener, status, result))
+                .collect()
+        });
+        (url, handle)
+    }
+
+    fn answer(listener: &TcpListener, status: &str, result: Value) -> (Vec<String>, Value) {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut headers = Vec::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line.trim().is_empty() {
+                break;
+            }
+            headers.push(line.trim().to_string());
+        }
+        let content_length = headers
+            .iter()
+            .find_map(|header| {
+                let (name, value) = header.split_once(':')?;
+                name.eq_ignore_ascii_case("content-length")
+                    .then(|| value.trim().parse::<usize>().unwrap())
+            })
+            .unwrap();
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+
+        let mut response = json!({ "jsonrpc": "2.0", "id": 1 });
+        response
+            .as_object_mut()
+            .unwrap()
+            .extend(result.as_object().unwrap().clone());
+        let response = response.to_string();
+        write!(
+            reader.get_mut(),
+            "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{response}",
+            response.len()
+        )
+        .unwrap();
+        (headers, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn submit_send_signed_bundle() {
+        let bundle_hash = H256::random();
+        let (url, relay) = mock_relay(json!({ "result": { "bundleHash": bundle_hash } }));
+        let auth_signer = LocalWallet::new(&mut thread_rng());
+        let signer = LocalWallet::new(&mut thread_rng()).with_chain_id(1_u64);
+        let submitter = BundleSubmitter::flashbots(url, auth_signer.clone()).skip_gate();
+        let tx_queue = TxQueue::from(vec![
+            TransactionRequest::new()
+                .to(Address::random())
+                .nonce(0)
+                .gas(100000)
+                .gas_price(10),
+            TransactionRequest::new()
+                .to(Address::random())
+                .nonce(1)
+                .gas(21000)
+                .gas_price(10)
+                .value(5),
+        ]);
+        let options = BundleOptions::new(U64::from(100))
+            .min_timestamp(1000)
+            .max_timestamp(2000)
+            .reverting(1);
+
+        assert_eq!(
+            submitter
+                .submit(&tx_queue, &signer, &options)
+                .await
+                .unwrap(),
+            bundle_hash
+        );
+
+        let (headers, body) = relay.join().unwrap();
+        assert_eq!(body["method"], "eth_sendBundle");
+        let bundle = &body["params"][0];
+        let raw_tx_list = tx_queue.sign_with(&signer).await.unwrap();
+        assert_eq!(bundle["txs"], json!(raw_tx_list));
+        assert_eq!(bundle["blockNumber"], "0x64");
+        assert_eq!(bundle["minTimestamp"], 1000);
+        assert_eq!(bundle["maxTimestamp"], 2000);
+        assert_eq!(
+            bundle["revertingTxHashes"],
+            json!([H256(keccak256(&raw_tx_list[1]))])
+        );
+
+        // Signed over the exact body by the reputation key.
+        let signature_header = headers
+            .iter()
+            .find_map(|header| {
+                let (name, value) = header.split_once(':')?;
+                name.eq_ignore_ascii_case("x-flashbots-signature")
+                    .then(|| value.trim().to_string())
+            })
+            .unwrap();
+        let (address, signature) = signature_header.split_once(':').unwrap();
+        assert_eq!(address.parse::<Address>().unwrap(), auth_signer.address());
+        let body_hash = format!("{:?}", H256(keccak256(body.to_string())));
+        let signer_address = signature
+            .parse::<Signature>()
+            .unwrap()
+            .recover(hash_message(body_hash))
+            .unwrap();
+        assert_eq!(signer_address, auth_signer.address());
+    }
+
+    #[tokio::test]
+    async fn submit_all_gate_on_call_bundle() {
+        let signer = LocalWallet::new(&mut thread_rng()).with_chain_id(1_u64);
+        let mut tx_queue = TxQueue::from(vec![
+            TransactionRequest::new()
+                .to(Address::random())
+                .nonce(0)
+                .gas(100000)
+                .gas_price(10),
+            TransactionRequest::new()
+                .to(Address::random())
+                .nonce(1)
+                .gas(21000)
+                .gas_price(10),
+        ]);
+        tx_queue.economics = Some(QueueEconomics {
+            bribe: U256::from(50),
+            ..Default::default()
+        });
+        let raw_tx_list = tx_queue.sign_with(&signer).await.unwrap();
+        let options = BundleOptions::new(U64::from(100));
+        let simulated =
+            |second: Value| json!({ "result": { "results": [{ "coinbaseDiff": "30" }, second] } });
+        let (tx_queue, signer, options) = (&tx_queue, &signer, &options);
+        let submit = |answers| async move {
+            let (url, relay) = mock_relay_answers(answers);
+            let submitter = BundleSubmitter::flashbots(url, LocalWallet::new(&mut thread_rng()));
+            let submission = submitter.submit_all(tx_queue, signer, options).await;
+            (submission, relay.join().unwrap())
+        };
+
+        let (submission, requests) = submit(vec![
+            ("200 OK", simulated(json!({ "coinbaseDiff": "0x14" }))),
+            (
+                "200 OK",
+                json!({ "result": { "bundleHash": H256::random() } }),
+            ),
+        ])
+        .await;
+        assert!(submission.unwrap().succeeded);
+        let (_, call_bundle) = &requests[0];
+        assert_eq!(call_bundle["method"], "eth_callBundle");
+        assert_eq!(
+            call_bundle["params"],
+            json!([{ "txs": raw_tx_list, "blockNumber": "0x64", "stateBlockNumber": "0x63" }])
+        );
+        assert_eq!(requests[1].1["method"], "eth_sendBundle");
+
+        // Nothing is sent after a refusal.
+        let (submission, requests) =
+            submit(vec![("200 OK", simulated(json!({ "coinbaseDiff": "10" })))]).await;
+        assert!(matches!(
+            submission,
+            Err(SimulateError::GateRefused { block, reason })
+                if block == U64::from(99) && reason.contains("less than the bribe 50")
+        ));
+        assert_eq!(requests.len(), 1);
+        let (submission, _) = submit(vec![(
+            "200 OK",
+            simulated(json!({ "coinbaseDiff": "20", "error": "execution reverted" })),
+        )])
+        .await;
+        assert!(matches!(
+            submission,
+            Err(SimulateError::GateRefused { reason, .. }) if reason.contains("entry 1 reverts")
+        ));
+    }
+
+    #[tokio::test]
+    async fn submit_all_strip_mined_victim() {
+        let signer = LocalWallet::new(&mut thread_rng()).with_chain_id(1_u64);
+        let tx_queue = TxQueue::from(vec![TransactionRequest::new()
+            .to(Address::random())
+            .nonce(0)
+            .gas(21000)
+            .gas_price(10)]);
+        let raw_tx_list = tx_queue.sign_with(&signer).await.unwrap();
+        let victim = Bytes::from(vec![0x02, 0x01]);
+        let options = BundleOptions::new(U64::from(100)).backrun(victim.clone());
+        let (tx_queue, signer, options) = (&tx_queue, &signer, &options);
+        let submit = |answers| async move {
+            let (url, relay) = mock_relay_answers(answers);
+            let submitter = BundleSubmitter::flashbots(url, LocalWallet::new(&mut thread_rng()));
+            let submission = submitter.submit_all(tx_queue, signer, options).await;
+            (submission, relay.join().unwrap())
+        };
+        let nonce_too_low = json!({ "result": { "results": [
+            { "error": "nonce too low" },
+            { "coinbaseDiff": "0" },
+        ] } });
+
+        let (submission, requests) = submit(vec![
+            ("200 OK", nonce_too_low.clone()),
+            (
+                "200 OK",
+                json!({ "result": { "results": [{ "coinbaseDiff": "0" }] } }),
+            ),
+            (
+                "200 OK",
+                json!({ "result": { "bundleHash": H256::random() } }),
+            ),
+        ])
+        .await;
+        let submission = submission.unwrap();
+        assert!(submission.succeeded);
+        assert_eq!(requests[0].1["params"][0]["txs"][0], json!(victim));
+        // Re-simulated and sent without the victim.
+        assert_eq!(requests[1].1["params"][0]["txs"], json!(raw_tx_list));
+        assert_eq!(requests[2].1["method"], "eth_sendBundle");
+        assert_eq!(requests[2].1["params"][0]["txs"], json!(raw_tx_list));
+        assert_eq!(submission.bundle.unwrap().txs, raw_tx_list);
+
+        // The queue doesn't pass on top of the victim's block either.
+        let (submission, requests) = submit(vec![
+            ("200 OK", nonce_too_low),
+            (
+                "200 OK",
+                json!({ "result": { "results": [{ "error": "execution reverted" }] } }),
+            ),
+        ])
+        .await;
+        assert!(matches!(
+            submission,
+            Err(SimulateError::VictimAlreadyIncluded { block, reason })
+                if block == U64::from(99) && reason.contains("entry 0 reverts")
+        ));
+        assert_eq!(requests.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn submit_refuse_tx_signer_as_reputation_key() {
+        let signer = LocalWallet::new(&mut thread_rng());
+        let submitter =
+            BundleSubmitter::flashbots(Url::parse("http://127.0.0.1:1").unwrap(), signer.clone())
+                .skip_gate();
+        let tx_queue = TxQueue::from(vec![TransactionRequest::new()
+            .nonce(0)
+            .gas(21000)
+            .gas_price(1)]);
+        let options = BundleOptions::new(U64::from(100));
+
+        for submission in [
+            submitter.submit_all(&tx_queue, &signer, &options).await,
+            submitter
+                .submit_private(&tx_queue, &signer, &PrivateTxOptions::new())
+                .await,
+            submitter
+                .submit_mev_share(
+                    &tx_queue,
+                    &signer,
+                    &MevShareBundle::new(H256::random(), U64::from(100)),
+                )
+                .await,
+        ] {
+            assert!(matches!(
+                submission,
+                Err(SimulateError::SharedReputationKey { address }) if address == signer.address()
+            ));
+        }
+
+        // Sent, nothing listens at the relay.
+        let submission = submitter
+            .allow_shared_key()
+            .submit_all(&tx_queue, &signer, &options)
+            .await
+            .unwrap();
+        assert_eq!(submission.accepted(), 0);
+    }
+
+    #[tokio::test]
+    async fn submit_let_revertible_entries_revert() {
+        let signer = LocalWallet::new(&mut thread_rng()).with_chain_id(1_u64);
+        let mut tx_queue = TxQueue::from(vec![
+            TransactionRequest::new()
+                .to(Address::random())
+                .nonce(0)
+                .gas(100000)
+                .gas_price(10),
+            TransactionRequest::new()
+                .to(Address::random())
+                .nonce(1)
+                .gas(21000)
+                .gas_price(10),
+        ]);
+        // Sweeping the dust left over may fail.
+        tx_queue.mark_revertible(1);
+        let raw_tx_list = tx_queue.sign_with(&signer).await.unwrap();
+
+        let (url, relay) = mock_relay(json!({ "result": { "bundleHash": H256::random() } }));
+        let submitter =
+            BundleSubmitter::flashbots(url, LocalWallet::new(&mut thread_rng())).skip_gate();
+        submitter
+            .submit(&tx_queue, &signer, &BundleOptions::new(U64::from(100)))
+            .await
+            .unwrap();
+        let (_, body) = relay.join().unwrap();
+        assert_eq!(
+            body["params"][0]["revertingTxHashes"],
+            json!([H256(keccak256(&raw_tx_list[1]))])
+        );
+
+        let (url, relay) = mock_relay(json!({ "result": { "bundleHash": H256::random() } }));
+        let submitter =
+            BundleSubmitter::flashbots(url, LocalWallet::new(&mut thread_rng())).skip_gate();
+        submitter
+            .submit_mev_share(
+                &tx_queue,
+                &signer,
+                &MevShareBundle::new(H256::random(), U64::from(100)),
+            )
+            .await
+            .unwrap();
+        let (_, body) = relay.join().unwrap();
+        let can_revert = body["params"][0]["body"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|tx| tx["canRevert"].clone())
+            .collect::<Vec<_>>();
+        // The hinted tx has no flag.
+        assert_eq!(can_revert, vec![json!(null), json!(false), json!(true)]);
+    }
+
+    #[test]
+    fn bundle_params_put_victim_tx_first_in_backrun() {
+        let victim_raw_tx = Bytes::from(vec![0xf8, 0x01]);
+        let raw_tx_list = vec![Bytes::from(vec![0x02, 0x01]), Bytes::from(vec![0x02, 0x02])];
+        let options = BundleOptions::new(U64::from(100))
+            .reverting(0)
+            .backrun(victim_raw_tx.clone());
+
+        let params = BundleSubmitter::<LocalWallet>::bundle_params(&raw_tx_list, &options);
+        let bundle = &params[0];
+        assert_eq!(
+            bundle["txs"],
+            json!([victim_raw_tx, raw_tx_list[0], raw_tx_list[1]])
+        );
+        // All of ours may revert, the victim's may not.
+        assert_eq!(
+            bundle["revertingTxHashes"],
+            json!([
+                H256(keccak256(&raw_tx_list[0])),
+                H256(keccak256(&raw_tx_list[1]))
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn submit_refuse_queue_expired_by_target_block() {
+        let submitter = BundleSubmitter::flashbots(
+            Url::parse("http://127.0.0.1:1").unwrap(),
+            LocalWallet::new(&mut thread_rng()),
+        )
+        .skip_gate();
+        let mut tx_queue = TxQueue::from(vec![TransactionRequest::new().nonce(0)]);
+        tx_queue.valid_until_block = Some(U64::from(100));
+
+        let signer = LocalWallet::new(&mut thread_rng());
+        let err = submitter
+            .submit(&tx_queue, &signer, &BundleOptions::new(U64::from(101)))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SimulateError::QueueExpired { .. }));
+
+        // Still valid for block 100, nothing listens at the relay url.
+        let err = submitter
+            .submit(&tx_queue, &signer, &BundleOptions::new(U64::from(100)))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            SimulateError::BundleNotAccepted { accepted: 0, .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn submit_mev_share_backrun_hinted_tx() {
+        let bundle_hash = H256::random();
+        let (url, relay) = mock_relay(json!({ "result": { "bundleHash": bundle_hash } }));
+        let submitter =
+            BundleSubmitter::flashbots(url.clone(), LocalWallet::new(&mut thread_rng()))
+                .relay(Relay::new(
+                    Url::parse("http://127.0.0.1:1").unwrap(),
+                    FlashbotsAdapter::new(RelayAuth::None, RelayDialect::Minimal),
+                ))
+                .skip_gate();
+        let tx_queue = TxQueue::from(vec![TransactionRequest::new()
+            .nonce(0)
+            .gas(21000)
+            .gas_price(1)]);
+        let signer = LocalWallet::new(&mut thread_rng());
+        let hinted = H256::random();
+        let bundle = MevShareBundle::new(hinted, U64::from(100)).max_block(U64::from(101));
+
+        let submission = submitter
+            .submit_mev_share(&tx_queue, &signer, &bundle)
+            .await
+            .unwrap();
+        // The minimal relay isn't asked.
+        assert_eq!(submission.outcomes.len(), 1);
+        assert_eq!(
+            submission.outcomes[&url],
+            RelayOutcome::Accepted(bundle_hash)
+        );
+        assert!(submission.succeeded);
+
+        let (_, body) = relay.join().unwrap();
+        assert_eq!(body["method"], "mev_sendBundle");
+        assert_eq!(
+            body["params"],
+            bundle.to_params(&tx_queue.sign_with(&signer).await.unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn submit_private_send_single_entry_and_cancel() {
+        let (url, relay) = mock_relay(json!({ "result": H256::zero() }));
+        let submitter =
+            BundleSubmitter::flashbots(url.clone(), LocalWallet::new(&mut thread_rng()))
+                .skip_gate();
+        let signer = LocalWallet::new(&mut thread_rng());
+        let mut tx_queue = TxQueue::from(vec![TransactionRequest::new()
+            .nonce(0)
+            .gas(21000)
+            .gas_price(1)]);
+        tx_queue.valid_until_block = Some(U64::from(105));
+        assert!(SubmissionPath::Auto.is_private(&tx_queue));
+        assert!(!SubmissionPath::Bundle.is_private(&tx_queue));
+
+        let options = PrivateTxOptions::new()
+            .max_block_number(U64::from(110))
+            .fast();
+        let submission = submitter
+            .submit_private(&tx_queue, &signer, &options)
+            .await
+            .unwrap();
+        assert_eq!(
+            submission.outcomes[&url],
+            RelayOutcome::Accepted(H256::zero())
+        );
+        assert!(submission.succeeded);
+
+        let (_, body) = relay.join().unwrap();
+        assert_eq!(body["method"], "eth_sendPrivateTransaction");
+        let raw_tx = tx_queue.sign_with(&signer).await.unwrap().remove(0);
+        // Capped at the queue's validity.
+        assert_eq!(
+            body["params"],
+            json!([{ "tx": raw_tx, "maxBlockNumber": "0x69", "preferences": { "fast": true } }])
+        );
+
+        let (url, relay) = mock_relay(json!({ "result": true }));
+        let submitter =
+            BundleSubmitter::flashbots(url.clone(), LocalWallet::new(&mut thread_rng()));
+        let tx_hash = H256(keccak256(&raw_tx));
+        let cancellation = submitter.cancel_private(tx_hash).await;
+        assert_eq!(cancellation.outcomes[&url], Ok(()));
+        let (_, body) = relay.join().unwrap();
+        assert_eq!(body["method"], "eth_cancelPrivateTransaction");
+        assert_eq!(body["params"], json!([{ "txHash": tx_hash }]));
+
+        // More than one entry is a bundle.
+        tx_queue.entries.push(tx_queue.entries[0].clone());
+        assert!(!SubmissionPath::Auto.is_private(&tx_queue));
+        assert!(matches!(
+            submitter.submit_private(&tx_queue, &signer, &options).await,
+            Err(SimulateError::PrivateTxNotSingle { entries: 2 })
+        ));
+    }
+
+    #[tokio::test]
+    async fn cancel_bundle_by_replacement_uuid() {
+        let replacement_uuid = new_replacement_uuid();
+        assert_eq!(replacement_uuid.len(), 36);
+        assert_eq!(&replacement_uuid[14..15], "4");
+        assert_ne!(replacement_uuid, new_replacement_uuid());
+
+        let options = BundleOptions::new(U64::from(100)).replacement_uuid(replacement_uuid.clone());
+        let params = BundleSubmitter::<LocalWallet>::bundle_params(&[], &options);
+        assert_eq!(params[0]["replacementUuid"], json!(replacement_uuid));
+
+        let (url, relay) = mock_relay(json!({ "result": null }));
+        let unreachable = Url::parse("http://127.0.0.1:1").unwrap();
+        let minimal = Url::parse("http://127.0.0.1:2").unwrap();
+        let (throttled, unsent) = (
+            Url::parse("http://127.0.0.1:3").unwrap(),
+            Url::parse("http://127.0.0.1:4").unwrap(),
+        );
+        let submitter =
+            BundleSubmitter::flashbots(url.clone(), LocalWallet::new(&mut thread_rng()))
+                .relay(Relay::flashbots(unreachable.clone()))
+                .relay(Relay::new(
+                    minimal.clone(),
+                    FlashbotsAdapter::new(RelayAuth::None, RelayDialect::Minimal),
+                ))
+                .relay(Relay::flashbots(throttled.clone()))
+                .relay(Relay::flashbots(unsent));
+        let submission = BundleSubmission {
+            outcomes: HashMap::from([
+                (url.clone(), RelayOutcome::Accepted(H256::random())),
+                (
+                    unreachable.clone(),
+                    RelayOutcome::Error("unreachable".into()),
+                ),
+                (minimal, RelayOutcome::Accepted(H256::random())),
+                (throttled, RelayOutcome::Throttled(Duration::from_secs(1))),
+            ]),
+            succeeded: true,
+            bundle: None,
+        };
+
+        let cancellation = submitter.cancel(&replacement_uuid, Some(&submission)).await;
+        // Neither the minimal relay nor those that didn't take the bundle are asked.
+        assert_eq!(cancellation.outcomes.len(), 2);
+        assert_eq!(cancellation.outcomes[&url], Ok(()));
+        assert!(cancellation.outcomes[&unreachable].is_err());
+        assert_eq!(cancellation.acknowledged(), 1);
+
+        let (_, body) = relay.join().unwrap();
+        assert_eq!(body["method"], "eth_cancelBundle");
+        assert_eq!(
+            body["params"],
+            json!([{ "replacementUuid": replacement_uuid }])
+        );
+    }
+
+    #[tokio::test]
+    async fn submit_all_report_outcome_per_relay() {
+        let bundle_hash = H256::random();
+        let (accepting, accepting_relay) =
+            mock_relay(json!({ "result": { "bundleHash": bundle_hash } }));
+        let (rejecting, rejecting_relay) =
+            mock_relay(json!({ "error": { "code": -32000, "message": "bundle too old" } }));
+        let unreachable = Url::parse("http://127.0.0.1:1").unwrap();
+        let submitter =
+            BundleSubmitter::flashbots(accepting.clone(), LocalWallet::new(&mut thread_rng()))
+                .relay(Relay::new(
+                    rejecting.clone(),
+                    FlashbotsAdapter::new(RelayAuth::None, RelayDialect::Minimal),
+                ))
+                .relay(Relay::flashbots(unreachable.clone()))
+                .min_accepted(2)
+                .skip_gate();
+        let tx_queue = TxQueue::from(vec![TransactionRequest::new()
+            .nonce(0)
+            .gas(21000)
+            .gas_price(1)]);
+        let options = BundleOptions::new(U64::from(100)).min_timestamp(1000);
+        let signer = LocalWallet::new(&mut thread_rng());
+
+        let submission = submitter
+            .submit_all(&tx_queue, &signer, &options)
+            .await
+            .unwrap();
+        assert_eq!(
+            submission.outcomes[&accepting],
+            RelayOutcome::Accepted(bundle_hash)
+        );
+        assert!(matches!(
+            &submission.outcomes[&rejecting],
+            RelayOutcome::Rejected(error) if error.contains("bundle too old")
+        ));
+        assert!(matches!(
+            submission.outcomes[&unreachable],
+            RelayOutcome::Error(_)
+        ));
+        // One of the two required.
+        assert!(!submission.succeeded);
+
+        let (headers, body) = accepting_relay.join().unwrap();
+        assert!(headers
+            .iter()
+            .any(|header| header.to_lowercase().starts_with("x-flashbots-signature")));
+        assert_eq!(body["params"][0]["minTimestamp"], 1000);
+        // No auth and only the fields of the minimal dialect.
+        let (headers, body) = rejecting_relay.join().unwrap();
+        assert!(!headers
+            .iter()
+            .any(|header| header.to_lowercase().starts_with("x-flashbots-signature")));
+        let mut fields = body["params"][0]
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        fields.sort();
+        assert_eq!(fields, vec!["blockNumber", "txs"]);
+    }
+
+    #[tokio::test]
+    async fn submit_all_speak_bloxroute_dialect() {
+        let bundle_hash = H256::random();
+        let (url, relay) = mock_relay(json!({ "result": { "bundleHash": bundle_hash } }));
+        let unreachable = Url::parse("http://127.0.0.1:1").unwrap();
+        let submitter =
+            BundleSubmitter::flashbots(unreachable, LocalWallet::new(&mut thread_rng()))
+                .relay(Relay::new(url.clone(), BloxrouteAdapter::new("c2VjcmV0")))
+                .skip_gate();
+        let tx_queue = TxQueue::from(vec![TransactionRequest::new()
+            .nonce(0)
+            .gas(21000)
+            .gas_price(1)]);
+        let signer = LocalWallet::new(&mut thread_rng());
+
+        let submission = submitter
+            .submit_all(&tx_queue, &signer, &BundleOptions::new(U64::from(100)))
+            .await
+            .unwrap();
+        assert_eq!(
+            submission.outcomes[&url],
+            RelayOutcome::Accepted(bundle_hash)
+        );
+        // Neither a stats nor a cancellation relay.
+        assert_eq!(submitter.flashbots_relays().count(), 1);
+
+        let (headers, body) = relay.join().unwrap();
+        assert!(headers
+            .iter()
+            .any(|header| header.eq_ignore_ascii_case("authorization: c2VjcmV0")));
+        assert!(!headers
+            .iter()
+            .any(|header| header.to_lowercase().starts_with("x-flashbots-signature")));
+        assert_eq!(body["method"], "blxr_submit_bundle");
+        let raw_tx = &tx_queue.sign_with(&signer).await.unwrap()[0];
+        assert_eq!(
+            body["params"],
+            json!({
+                "transaction": [ethers::utils::hex::encode(raw_tx)],
+                "block_number": "0x64",
+                "mev_builders": { "all": "" },
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn submit_all_cool_down_after_rate_limited() {
+        let (url, relay) = mock_relay_with(
+            "429 Too Many Requests",
+            json!({ "error": { "code": 429, "message": "rate limited" } }),
+        );
+        let submitter =
+            BundleSubmitter::flashbots(url.clone(), LocalWallet::new(&mut thread_rng()))
+                .rate_limit(
+                    RateLimit::new(5.0, 5)
+                        .unwrap()
+                        .cool_down(Duration::from_secs(60)),
+                )
+                .skip_gate();
+        let tx_queue = TxQueue::from(vec![TransactionRequest::new()
+            .nonce(0)
+            .gas(21000)
+            .gas_price(1)]);
+        let options = BundleOptions::new(U64::from(100));
+        let signer = LocalWallet::new(&mut thread_rng());
+
+        let submission = submitter
+            .submit_all(&tx_queue, &signer, &options)
+            .await
+            .unwrap();
+        assert!(matches!(
+            &submission.outcomes[&url],
+            RelayOutcome::RateLimited(body) if body.contains("rate limited")
+        ));
+        relay.join().unwrap();
+
+        // Cooling down for longer than a request waits, the relay isn't asked again.
+        let submission = submitter
+            .submit_all(&tx_queue, &signer, &options)
+            .await
+            .unwrap();
+        assert!(matches!(
+            submission.outcomes[&url],
+            RelayOutcome::Throttled(wait) if wait > Duration::from_secs(50)
+        ));
+        assert!(!submission.succeeded);
+    }
+
+    #[tokio::test]
+    async fn submit_all_unprofitable_unless_allowed() {
+        let bundle_hash = H256::random();
+        let (url, relay) = mock_relay(json!({ "result": { "bundleHash": bundle_hash } }));
+        let submitter =
+            BundleSubmitter::flashbots(url.clone(), LocalWallet::new(&mut thread_rng()))
+                .skip_gate();
+        let mut tx_queue = TxQueue::from(vec![TransactionRequest::new()
+            .nonce(0)
+            .gas(21000)
+            .gas_price(1)]);
+        // The gas costs more than the nothing it earns.
+        tx_queue.fill_economics(U256::zero(), None, U256::zero());
+        let options = BundleOptions::new(U64::from(100));
+        let signer = LocalWallet::new(&mut thread_rng());
+
+        assert!(matches!(
+            submitter.submit_all(&tx_queue, &signer, &options).await,
+            Err(SimulateError::Unprofitable { .. })
+        ));
+
+        let submitter = submitter.refuse_unprofitable(false);
+        let submission = submitter
+            .submit_all(&tx_queue, &signer, &options)
+            .await
+            .unwrap();
+        assert_eq!(
+            submission.outcomes[&url],
+            RelayOutcome::Accepted(bundle_hash)
+        );
+        relay.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn bundle_stats_ask_accepting_relay() {
+        let bundle_hash = H256::random();
+        let (url, relay) = mock_relay(json!({
+            "result": { "isSimulated": true, "receivedAt": "2022-10-06T21:36:06.250Z" }
+        }));
+        // The first relay never saw the bundle, and doesn't answer.
+        let unreachable = Url::parse("http://127.0.0.1:1").unwrap();
+        let submitter =
+            BundleSubmitter::flashbots(unreachable, LocalWallet::new(&mut thread_rng()))
+                .relay(Relay::flashbots(url.clone()));
+
+        let stats = submitter
+            .bundle_stats(&url, bundle_hash, U64::from(100))
+            .await
+            .unwrap();
+        assert!(stats.is_simulated);
+        let (_, body) = relay.join().unwrap();
+        assert_eq!(body["method"], "flashbots_getBundleStatsV2");
+        assert_eq!(
+            body["params"],
+            json!([{ "bundleHash": bundle_hash, "blockNumber": "0x64" }])
+        );
+
+        // Not a relay of the submitter.
+        let other = Url::parse("http://127.0.0.1:2").unwrap();
+        assert!(submitter
+            .bundle_stats(&other, bundle_hash, U64::from(100))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn submit_all_cool_down_after_rate_limit_error() {
+        // bloXroute answers 200 with the limit as the error.
+        let (url, relay) = mock_relay(json!({
+            "error": { "code": -32000, "message": "Rate limit exceeded" }
+        }));
+        let unreachable = Url::parse("http://127.0.0.1:1").unwrap();
+        let submitter =
+            BundleSubmitter::flashbots(unreachable, LocalWallet::new(&mut thread_rng()))
+                .relay(Relay::new(url.clone(), BloxrouteAdapter::new("c2VjcmV0")))
+                .rate_limit(
+                    RateLimit::new(5.0, 5)
+                        .unwrap()
+                        .cool_down(Duration::from_secs(60)),
+                )
+                .skip_gate();
+        let tx_queue = TxQueue::from(vec![TransactionRequest::new()
+            .nonce(0)
+            .gas(21000)
+            .gas_price(1)]);
+        let options = BundleOptions::new(U64::from(100));
+        let signer = LocalWallet::new(&mut thread_rng());
+
+        let submission = submitter
+            .submit_all(&tx_queue, &signer, &options)
+            .await
+            .unwrap();
+        assert_eq!(
+            submission.outcomes[&url],
+            RelayOutcome::RateLimited("Rate limit exceeded".into())
+        );
+        relay.join().unwrap();
+
+        let submission = submitter
+            .submit_all(&tx_queue, &signer, &options)
+            .await
+            .unwrap();
+        assert!(matches!(
+            submission.outcomes[&url],
+            RelayOutcome::Throttled(wait) if wait > Duration::from_secs(50)
+        ));
+    }
+}
@@ -0,0 +1,405 @@
+use crate::utils::{Opportunity, Simulate, SimulateError};
+use ethers::prelude::*;
+use futures::stream::{LocalBoxStream, Stream, StreamExt};
+use serde_json::{json, Value};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::{sleep, timeout};
+use tracing::debug;
+
+// How an `OpportunityStream` fetches and simulates the pending txs.
+#[derive(Debug, Clone)]
+pub struct WatchOptions {
+    concurrency: usize,
+    tx_timeout: Duration,
+    fetch_retries: u32,
+    retry_delay: Duration,
+    to_addresses: Vec<Address>,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 16,
+            tx_timeout: Duration::from_secs(2),
+            fetch_retries: 3,
+            retry_delay: Duration::from_millis(250),
+            to_addresses: Vec::new(),
+        }
+    }
+}
+
+impl WatchOptions {
+    // Txs fetched and simulated at the same time. No tx is taken from the subscription while
+    // that many are in flight and the opportunities wait to be polled, so a slow consumer slows
+    // the stream down rather than piling up simulations.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    // For the fetch and the simulation of one tx together, a tx that takes longer is dropped.
+    pub fn tx_timeout(mut self, tx_timeout: Duration) -> Self {
+        self.tx_timeout = tx_timeout;
+        self
+    }
+
+    // The node announces a hash before it serves the tx, a tx not found is fetched again
+    // `fetch_retries` times, `retry_delay` apart.
+    pub fn fetch_retries(mut self, fetch_retries: u32, retry_delay: Duration) -> Self {
+        self.fetch_retries = fetch_retries;
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    // Only simulate txs to one of these, all of them if empty. Passed to the subscription when it
+    // filters (`alchemy_pendingTransactions`), checked on every tx otherwise.
+    pub fn to_addresses(mut self, to_addresses: Vec<Address>) -> Self {
+        self.to_addresses = to_addresses;
+        self
+    }
+
+    fn admits(&self, tx: &Transaction) -> bool {
+        self.to_addresses.is_empty() || matches!(tx.to, Some(to) if self.to_addresses.contains(&to))
+    }
+}
+
+// The pending tx subscriptions `OpportunityStream::new` knows, in the order they're tried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PendingSubscription {
+    // Alchemy's, full txs filtered by `to` on their side.
+    AlchemyPendingTransactions,
+    // geth's hash subscription with its full tx flag.
+    NewPendingTransactionsFull,
+    // Full txs, with no filter.
+    NewPendingTransactionsWithBody,
+    // Only hashes, every tx is fetched after.
+    NewPendingTransactions,
+}
+
+impl PendingSubscription {
+    pub const ALL: [Self; 4] = [
+        Self::AlchemyPendingTransactions,
+        Self::NewPendingTransactionsFull,
+        Self::NewPendingTransactionsWithBody,
+        Self::NewPendingTransactions,
+    ];
+
+    // The `eth_subscribe` params, the `to` filter only where the subscription takes one.
+    pub fn params(&self, to_addresses: &[Address]) -> Value {
+        match self {
+            Self::AlchemyPendingTransactions => {
+                let mut filter = json!({ "hashesOnly": false });
+                if !to_addresses.is_empty() {
+                    filter["toAddress"] = json!(to_addresses);
+                }
+                json!(["alchemy_pendingTransactions", filter])
+            }
+            Self::NewPendingTransactionsFull => json!(["newPendingTransactions", true]),
+            Self::NewPendingTransactionsWithBody => json!(["newPendingTransactionsWithBody"]),
+            Self::NewPendingTransactions => json!(["newPendingTransactions"]),
+        }
+    }
+
+    pub fn with_body(&self) -> bool {
+        *self != Self::NewPendingTransactions
+    }
+}
+
+// A pending tx as a subscription pushes it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PendingTx {
+    Hash(TxHash),
+    Body(Box<Transaction>),
+}
+
+impl PendingTx {
+    pub fn hash(&self) -> TxHash {
+        match self {
+            Self::Hash(tx_hash) => *tx_hash,
+            Self::Body(tx) => tx.hash,
+        }
+    }
+}
+
+// The opportunities of the pending txs, see `Simulate::run_pending`. Txs that can't be fetched,
+// fail to simulate or time out are logged and skipped.
+pub struct OpportunityStream<'a> {
+    inner: LocalBoxStream<'a, Opportunity>,
+    subscription: Option<PendingSubscription>,
+}
+
+impl<'a> OpportunityStream<'a> {
+    // Subscribe on `ws_provider` with the first of `PendingSubscription::ALL` it supports, the
+    // ones with bodies first: they save the fetch, which often misses for a second or two.
+    pub async fn new<'s: 'a, P: PubsubClient, M: Middleware + 's, S: Signer + 's>(
+        ws_provider: &'a Provider<P>,
+        simulate: &'a Simulate<'s, M, S>,
+        options: WatchOptions,
+    ) -> Result<OpportunityStream<'a>, SimulateError> {
+        for subscription in PendingSubscription::ALL
+            .into_iter()
+            .filter(PendingSubscription::with_body)
+        {
+            let params = subscription.params(&options.to_addresses);
+            match ws_provider.subscribe::<_, Transaction>(params).await {
+                Ok(txs) => {
+                    debug!(?subscription, "subscribed to pending txs");
+                    let pending = txs.map(|tx| PendingTx::Body(Box::new(tx)));
+                    return Ok(Self {
+                        subscription: Some(subscription),
+                        ..Self::from_pending(pending, simulate, options)
+                    });
+                }
+                Err(e) => debug!(?subscription, "pending tx subscription unsupported: {e}"),
+            }
+        }
+
+        // What every node has, its error is the node's.
+        let subscription = PendingSubscription::NewPendingTransactions;
+        let hashes = ws_provider
+            .subscribe::<_, TxHash>(subscription.params(&options.to_addresses))
+            .await
+            .map_err(SimulateError::middleware)?;
+        debug!(?subscription, "subscribed to pending txs");
+        Ok(Self {
+            subscription: Some(subscription),
+            ..Self::from_hashes(hashes, simulate, options)
+        })
+    }
+
+    // The opportunities of the txs of `hashes`, e.g. of another subscription or a replay.
+    pub fn from_hashes<'s: 'a, M: Middleware + 's, S: Signer + 's>(
+        hashes: impl Stream<Item = TxHash> + 'a,
+        simulate: &'a Simulate<'s, M, S>,
+        options: WatchOptions,
+    ) -> OpportunityStream<'a> {
+        Self::from_pending(hashes.map(PendingTx::Hash), simulate, options)
+    }
+
+    // The opportunities of `pending`, a tx pushed with its body isn't fetched again.
+    pub fn from_pending<'s: 'a, M: Middleware + 's, S: Signer + 's>(
+        pending: impl Stream<Item = PendingTx> + 'a,
+        simulate: &'a Simulate<'s, M, S>,
+        options: WatchOptions,
+    ) -> OpportunityStream<'a> {
+        let concurrency = options.concurrency;
+        let options = Arc::new(options);
+        let inner = pending
+            .map(move |pending| watch_tx(simulate, pending, options.clone()))
+            .buffer_unordered(concurrency)
+            .filter_map(|opportunity| async move { opportunity })
+            .boxed_local();
+        Self {
+            inner,
+            subscription: None,
+        }
+    }
+
+    // What `new` subscribed with, `None` for a stream of other txs.
+    pub fn subscription(&self) -> Option<PendingSubscription> {
+        self.subscription
+    }
+}
+
+impl<'a> Stream for OpportunityStream<'a> {
+    type Item = Opportunity;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.poll_next_unpin(cx)
+    }
+}
+
+async fn watch_tx<'a, M: Middleware + 'a, S: Signer + 'a>(
+    simulate: &Simulate<'a, M, S>,
+    pending: PendingTx,
+    options: Arc<WatchOptions>,
+) -> Option<Opportunity> {
+    let tx_hash = pending.hash();
+    let run = async {
+        let tx = match pending {
+            PendingTx::Hash(tx_hash) => fetch_pending(simulate, tx_hash, &options).await?,
+            PendingTx::Body(tx) => *tx,
+        };
+        if !options.admits(&tx) {
+            return Ok(None);
+        }
+        simulate.run_pending(tx).await
+    };
+    match timeout(options.tx_timeout, run).await {
+        Ok(Ok(opportunity)) => opportunity,
+        Ok(Err(e)) => {
+            debug!(?tx_hash, "pending tx skipped: {e}");
+            None
+        }
+        Err(_) => {
+            debug!(?tx_hash, timeout = ?options.tx_timeout, "pending tx timed out");
+            None
+        }
+    }
+}
+
+async fn fetch_pending<'a, M: Middleware + 'a, S: Signer + 'a>(
+    simulate: &Simulate<'a, M, S>,
+    tx_hash: TxHash,
+    options: &WatchOptions,
+) -> Result<Transaction, SimulateError> {
+    for attempt in 0..=options.fetch_retries {
+        if attempt > 0 {
+            sleep(options.retry_delay).await;
+        }
+        if let Some(tx) = simulate
+            .get_transaction(tx_hash)
+            .await
+            .map_err(SimulateError::middleware)?
+        {
+            return Ok(tx);
+        }
+    }
+    Err(SimulateError::PendingTxNotFound {
+        tx_hash,
+        tries: options.fetch_retries.saturating_add(1),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OpportunityStream, PendingSubscription, PendingTx, WatchOptions};
+    use crate::utils::simulate::mock::{balance_diff, block_trace, call_trace, mock_client};
+    use crate::utils::Simulate;
+    use ethers::prelude::*;
+    use futures::stream::{self, StreamExt};
+    use serde_json::json;
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn opportunity_stream_yield_profitable_pending_txs() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let profitable = Transaction {
+            hash: TxHash::random(),
+            input: "0x00000001".parse().unwrap(),
+            ..Default::default()
+        };
+        // A plain transfer, pruned before it's traced.
+        let transfer = Transaction {
+            hash: TxHash::random(),
+            to: Some(Address::random()),
+            ..Default::default()
+        };
+        let trace = block_trace(
+            vec![call_trace(vec![], 0, U256::zero())],
+            Some(StateDiff(BTreeMap::from([(
+                profitable.from,
+                balance_diff(U256::zero(), U256::from(1000)),
+            )]))),
+        );
+
+        // The first tx isn't served yet, then it is and
+        // is traced, then the transfer.
+        mock.push(transfer.clone()).unwrap();
+        mock.push(U256::one()).unwrap();
+        mock.push(trace).unwrap();
+        mock.push(profitable.clone()).unwrap();
+        mock.push(Option::<Transaction>::None).unwrap();
+
+        // One at a time, the mock answers in order.
+        let options = WatchOptions::default()
+            .concurrency(1)
+            .fetch_retries(1, Duration::ZERO);
+        let opportunities = OpportunityStream::from_hashes(
+            stream::iter([profitable.hash, transfer.hash]),
+            &simulate,
+            options,
+        )
+        .collect::<Vec<_>>()
+        .await;
+        assert_eq!(opportunities.len(), 1);
+        assert_eq!(opportunities[0].victim, Some(profitable.hash));
+        // Fetched again once the node serves it.
+        for _ in 0..2 {
+            mock.assert_request("eth_getTransactionByHash", [profitable.hash])
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn opportunity_stream_skip_fetch_of_pushed_bodies() {
+        let (client, mock) = mock_client();
+        let simulate = Simulate::init(&client, None).await.unwrap();
+        let target = Address::random();
+        let profitable = Transaction {
+            hash: TxHash::random(),
+            to: Some(target),
+            input: "0x00000001".parse().unwrap(),
+            ..Default::default()
+        };
+        // Pushed by a subscription that doesn't filter, dropped here.
+        let elsewhere = Transaction {
+            hash: TxHash::random(),
+            to: Some(Address::random()),
+            ..profitable.clone()
+        };
+        let trace = block_trace(
+            vec![call_trace(vec![], 0, U256::zero())],
+            Some(StateDiff(BTreeMap::from([(
+                profitable.from,
+                balance_diff(U256::zero(), U256::from(1000)),
+            )]))),
+        );
+
+        // No fetch, the trace then the chain id.
+        mock.push(U256::one()).unwrap();
+        mock.push(trace).unwrap();
+
+        let options = WatchOptions::default()
+            .concurrency(1)
+            .to_addresses(vec![target]);
+        let stream = OpportunityStream::from_pending(
+            stream::iter([
+                PendingTx::Body(Box::new(elsewhere)),
+                PendingTx::Body(Box::new(profitable.clone())),
+            ]),
+            &simulate,
+            options,
+        );
+        assert_eq!(stream.subscription(), None);
+        let opportunities = stream.collect::<Vec<_>>().await;
+        assert_eq!(opportunities.len(), 1);
+        assert_eq!(opportunities[0].victim, Some(profitable.hash));
+        assert!(mock
+            .assert_request("eth_getTransactionByHash", [profitable.hash])
+            .is_err());
+    }
+
+    #[test]
+    fn pending_subscription_push_filter_down() {
+        let to = Address::random();
+        assert_eq!(
+            PendingSubscription::AlchemyPendingTransactions.params(&[to]),
+            json!(["alchemy_pendingTransactions", { "toAddress": [to], "hashesOnly": false }])
+        );
+        assert_eq!(
+            PendingSubscription::AlchemyPendingTransactions.params(&[]),
+            json!(["alchemy_pendingTransactions", { "hashesOnly": false }])
+        );
+        // The others take no filter, it's applied on the txs.
+        assert_eq!(
+            PendingSubscription::NewPendingTransactionsFull.params(&[to]),
+            json!(["newPendingTransactions", true])
+        );
+        assert!(PendingSubscription::NewPendingTransactionsFull.with_body());
+        assert_eq!(
+            PendingSubscription::NewPendingTransactionsWithBody.params(&[to]),
+            json!(["newPendingTransactionsWithBody"])
+        );
+        assert_eq!(
+            PendingSubscription::NewPendingTransactions.params(&[to]),
+            json!(["newPendingTransactions"])
+        );
+        assert!(!PendingSubscription::NewPendingTransactions.with_body());
+    }
+}
@@ -30,7 +30,10 @@ async fn t_16298449() {
         .await
         .unwrap();
     let tx_hash = TX_HASH.parse::<TxHash>().unwrap();
-    let (tx_queue, profit) = simulate.run(tx_hash, true).await.unwrap().unwrap();
+    let Opportunity {
+        tx_queue, reports, ..
+    } = simulate.run(tx_hash, true).await.unwrap().unwrap();
+    let profit = ProfitReport::total_native(&reports);
     log_profit(
         &anvil_client,
         arbitrage.address(),
@@ -31,5 +31,5 @@ async fn t_16384470() {
     let tx_hash = TX_HASH.parse::<TxHash>().unwrap();
 
     // Unable to detect contract creation and arbitrage in the same block
-    assert_eq!(simulate.run(tx_hash, true).await.unwrap(), None);
+    assert!(simulate.run(tx_hash, true).await.unwrap().is_none());
 }
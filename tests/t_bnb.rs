@@ -3,7 +3,7 @@ use dotenv::dotenv;
 use ethers::{prelude::*, utils::Anvil};
 
 #[tokio::test]
-#[should_panic(expected = "the method trace_call does not exist/is not available")]
+#[should_panic(expected = "TraceApiUnsupported")]
 async fn t_bnb() {
     dotenv().ok();
     const HTTP_RPC_URL: &str = "https://rpc.ankr.com/bsc";
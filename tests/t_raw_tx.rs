@@ -0,0 +1,35 @@
+use arbitrage::utils::*;
+use ethers::prelude::*;
+
+#[tokio::test]
+async fn t_raw_tx() {
+    const HTTP_RPC_URL: &str = "https://rpc.ankr.com/eth";
+    const TX_HASHES: [&str; 2] = [
+        "0x12d867ee837cec251b067319e2802c15b01dc2e18b052b95fcd6657e19ff2a5e",
+        "0x927b784148b60d5233e57287671cdf67d38e3e69e5b6d0ecacc7c1aeaa98985b",
+    ];
+
+    let provider = Provider::<Http>::connect(HTTP_RPC_URL).await;
+    for tx_hash in TX_HASHES {
+        let tx_hash = tx_hash.parse::<TxHash>().unwrap();
+        let raw: Bytes = provider
+            .request("eth_getRawTransactionByHash", [tx_hash])
+            .await
+            .unwrap();
+        let expected = provider.get_transaction(tx_hash).await.unwrap().unwrap();
+        let decoded = decode_raw_tx(&raw).unwrap();
+
+        assert_eq!(decoded.hash, expected.hash);
+        assert_eq!(decoded.from, expected.from);
+        assert_eq!(decoded.to, expected.to);
+        assert_eq!(decoded.nonce, expected.nonce);
+        assert_eq!(decoded.value, expected.value);
+        assert_eq!(decoded.gas, expected.gas);
+        assert_eq!(decoded.input, expected.input);
+        assert_eq!(decoded.transaction_type, expected.transaction_type);
+        assert_eq!(decoded.max_fee_per_gas, expected.max_fee_per_gas);
+        assert_eq!(decoded.v, expected.v);
+        assert_eq!(decoded.r, expected.r);
+        assert_eq!(decoded.s, expected.s);
+    }
+}